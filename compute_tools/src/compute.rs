@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::io::BufRead;
+use std::io::{BufRead, Read};
 use std::os::unix::fs::{symlink, PermissionsExt};
 use std::path::Path;
 use std::process::{Command, Stdio};
@@ -205,6 +205,13 @@ fn maybe_cgexec(cmd: &str) -> Command {
     }
 }
 
+/// Did the pageserver reject the options after the LSN of a basebackup command?
+/// Pageservers that predate `--compression` only know `--gzip`.
+fn is_unknown_basebackup_option(e: &postgres::Error) -> bool {
+    e.as_db_error()
+        .is_some_and(|e| e.message().contains("Parameter in position 3 unknown"))
+}
+
 /// Create special neon_superuser role, that's a slightly nerfed version of a real superuser
 /// that we give to customers
 #[instrument(skip_all)]
@@ -319,8 +326,16 @@ impl ComputeNode {
 
     // Get basebackup from the libpq connection to pageserver using `connstr` and
     // unarchive it to `pgdata` directory overriding all its previous content.
-    #[instrument(skip_all, fields(%lsn))]
-    fn try_get_basebackup(&self, compute_state: &ComputeState, lsn: Lsn) -> Result<()> {
+    //
+    // `unpacked_entries` counts the tar entries that were unpacked so far, to
+    // resume from there if a previous attempt was interrupted.
+    #[instrument(skip_all, fields(%lsn, %unpacked_entries))]
+    fn try_get_basebackup(
+        &self,
+        compute_state: &ComputeState,
+        lsn: Lsn,
+        unpacked_entries: &mut usize,
+    ) -> Result<()> {
         let spec = compute_state.pspec.as_ref().expect("spec must be set");
         let start_time = Instant::now();
 
@@ -341,48 +356,76 @@ impl ComputeNode {
         let pageserver_connect_micros = start_time.elapsed().as_micros() as u64;
 
         let basebackup_cmd = match lsn {
-            // HACK We don't use compression on first start (Lsn(0)) because there's no API for it
+            // HACK We don't use compression on first start (Lsn(0)) because there's no API for it.
+            // Without an LSN, the basebackup can't be resumed either.
             Lsn(0) => format!("basebackup {} {}", spec.tenant_id, spec.timeline_id),
+            _ if *unpacked_entries > 0 => format!(
+                "basebackup {} {} {} --compression=zstd,gzip --resume-from={}",
+                spec.tenant_id, spec.timeline_id, lsn, unpacked_entries
+            ),
             _ => format!(
-                "basebackup {} {} {} --gzip",
+                "basebackup {} {} {} --compression=zstd,gzip",
                 spec.tenant_id, spec.timeline_id, lsn
             ),
         };
 
-        let copyreader = client.copy_out(basebackup_cmd.as_str())?;
+        let copyreader = match client.copy_out(basebackup_cmd.as_str()) {
+            // A pageserver that doesn't know the --compression option can't resume
+            // either: ask it for a gzip compressed tarball, from the start.
+            Err(e) if lsn != Lsn(0) && is_unknown_basebackup_option(&e) => {
+                info!("pageserver does not support --compression, falling back to --gzip");
+                *unpacked_entries = 0;
+                let basebackup_cmd = format!(
+                    "basebackup {} {} {} --gzip",
+                    spec.tenant_id, spec.timeline_id, lsn
+                );
+                client.copy_out(basebackup_cmd.as_str())?
+            }
+            result => result?,
+        };
         let mut measured_reader = MeasuredReader::new(copyreader);
 
-        // Check the magic number to see if it's a gzip or not. Even though
-        // we might explicitly ask for gzip, an old pageserver with no implementation
-        // of gzip compression might send us uncompressed data. After some time
-        // passes we can assume all pageservers know how to compress and we can
-        // delete this check.
+        // Check the magic number to see which compression the pageserver chose.
+        // An old pageserver with no implementation of zstd compression might send
+        // us gzip, and one that doesn't know compression at all might send us
+        // uncompressed data. After some time passes we can assume all pageservers
+        // know how to compress and we can delete this check.
         //
-        // If the data is not gzip, it will be tar. It will not be mistakenly
-        // recognized as gzip because tar starts with an ascii encoding of a filename,
-        // and 0x1f and 0x8b are unlikely first characters for any filename. Moreover,
-        // we send the "global" directory first from the pageserver, so it definitely
-        // won't be recognized as gzip.
+        // If the data is not compressed, it will be tar. It will not be mistakenly
+        // recognized as compressed because tar starts with an ascii encoding of a
+        // filename, and the magic numbers are unlikely first characters for any
+        // filename. Moreover, we send the "global" directory first from the
+        // pageserver, so it definitely won't be recognized as compressed.
         let mut bufreader = std::io::BufReader::new(&mut measured_reader);
-        let gzip = {
-            let peek = bufreader.fill_buf().unwrap();
-            peek[0] == 0x1f && peek[1] == 0x8b
+        let (gzip, zstd) = {
+            let peek = bufreader.fill_buf()?;
+            (
+                peek.starts_with(&[0x1f, 0x8b]),
+                peek.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]),
+            )
+        };
+        let reader: Box<dyn Read + '_> = if zstd {
+            Box::new(zstd::stream::read::Decoder::with_buffer(bufreader)?)
+        } else if gzip {
+            Box::new(flate2::read::GzDecoder::new(bufreader))
+        } else {
+            Box::new(bufreader)
         };
 
         // Read the archive directly from the `CopyOutReader`
         //
-        // Set `ignore_zeros` so that unpack() reads all the Copy data and
-        // doesn't stop at the end-of-archive marker. Otherwise, if the server
-        // sends an Error after finishing the tarball, we will not notice it.
-        if gzip {
-            let mut ar = tar::Archive::new(flate2::read::GzDecoder::new(&mut bufreader));
-            ar.set_ignore_zeros(true);
-            ar.unpack(&self.pgdata)?;
-        } else {
-            let mut ar = tar::Archive::new(&mut bufreader);
-            ar.set_ignore_zeros(true);
-            ar.unpack(&self.pgdata)?;
-        };
+        // Set `ignore_zeros` so that we read all the Copy data and don't stop
+        // at the end-of-archive marker. Otherwise, if the server sends an
+        // Error after finishing the tarball, we will not notice it.
+        let mut ar = tar::Archive::new(reader);
+        ar.set_ignore_zeros(true);
+        for entry in ar.entries()? {
+            entry?.unpack_in(&self.pgdata)?;
+            if lsn != Lsn(0) {
+                *unpacked_entries += 1;
+            }
+        }
+        drop(ar);
 
         // Report metrics
         let mut state = self.state.lock().unwrap();
@@ -398,8 +441,9 @@ impl ComputeNode {
         let mut retry_period_ms = 500;
         let mut attempts = 0;
         let max_attempts = 5;
+        let mut unpacked_entries = 0;
         loop {
-            let result = self.try_get_basebackup(compute_state, lsn);
+            let result = self.try_get_basebackup(compute_state, lsn, &mut unpacked_entries);
             match result {
                 Ok(_) => {
                     return result;
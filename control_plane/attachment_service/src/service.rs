@@ -1297,11 +1297,13 @@ impl Service {
             if timeline_info.is_none() {
                 // If the caller specified an ancestor but no ancestor LSN, we are responsible for
                 // propagating the LSN chosen by the first shard to the other shards: it is important
-                // that all shards end up with the same ancestor_start_lsn.
+                // that all shards end up with the same ancestor_start_lsn. This also covers an
+                // ancestor_start_timestamp, which only shard zero can resolve.
                 if create_req.ancestor_timeline_id.is_some()
                     && create_req.ancestor_start_lsn.is_none()
                 {
                     create_req.ancestor_start_lsn = shard_timeline_info.ancestor_lsn;
+                    create_req.ancestor_start_timestamp = None;
                 }
 
                 // We will return the TimelineInfo from the first shard
@@ -478,6 +478,7 @@ async fn handle_tenant(
                         new_timeline_id,
                         ancestor_timeline_id: None,
                         ancestor_start_lsn: None,
+                        ancestor_start_timestamp: None,
                         existing_initdb_timeline_id: None,
                         pg_version: Some(pg_version),
                     },
@@ -632,6 +633,7 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
                 ancestor_timeline_id: None,
                 existing_initdb_timeline_id: None,
                 ancestor_start_lsn: None,
+                ancestor_start_timestamp: None,
                 pg_version: Some(pg_version),
             };
             let timeline_info = attachment_service
@@ -724,6 +726,7 @@ async fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::Local
                 ancestor_timeline_id: Some(ancestor_timeline_id),
                 existing_initdb_timeline_id: None,
                 ancestor_start_lsn: start_lsn,
+                ancestor_start_timestamp: None,
                 pg_version: None,
             };
             let timeline_info = attachment_service
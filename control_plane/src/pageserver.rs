@@ -405,6 +405,41 @@ impl PageServerNode {
                 .map(serde_json::from_str)
                 .transpose()
                 .context("parse `timeline_get_throttle` from json")?,
+            layer_compression: settings
+                .remove("layer_compression")
+                .map(serde_json::from_str)
+                .transpose()
+                .context("parse `layer_compression` from json")?,
+            compaction_algorithm: settings
+                .remove("compaction_algorithm")
+                .map(serde_json::from_str)
+                .transpose()
+                .context("parse `compaction_algorithm` from json")?,
+            gc_branch_images: settings
+                .remove("gc_branch_images")
+                .map(|x| x.parse::<bool>())
+                .transpose()
+                .context("Failed to parse 'gc_branch_images' as bool")?,
+            resident_size_quota: settings
+                .remove("resident_size_quota")
+                .map(|x| x.parse::<u64>())
+                .transpose()
+                .context("Failed to parse 'resident_size_quota' as integer")?,
+            write_throttle: settings
+                .remove("write_throttle")
+                .map(serde_json::from_str)
+                .transpose()
+                .context("parse `write_throttle` from json")?,
+            image_creation_read_heat: settings
+                .remove("image_creation_read_heat")
+                .map(|x| x.parse::<bool>())
+                .transpose()
+                .context("Failed to parse 'image_creation_read_heat' as bool")?,
+            layer_checksums: settings
+                .remove("layer_checksums")
+                .map(|x| x.parse::<bool>())
+                .transpose()
+                .context("Failed to parse 'layer_checksums' as bool")?,
         };
         if !settings.is_empty() {
             bail!("Unrecognized tenant settings: {settings:?}")
@@ -515,6 +550,41 @@ impl PageServerNode {
                     .map(serde_json::from_str)
                     .transpose()
                     .context("parse `timeline_get_throttle` from json")?,
+                layer_compression: settings
+                    .remove("layer_compression")
+                    .map(serde_json::from_str)
+                    .transpose()
+                    .context("parse `layer_compression` from json")?,
+                compaction_algorithm: settings
+                    .remove("compaction_algorithm")
+                    .map(serde_json::from_str)
+                    .transpose()
+                    .context("parse `compaction_algorithm` from json")?,
+                gc_branch_images: settings
+                    .remove("gc_branch_images")
+                    .map(|x| x.parse::<bool>())
+                    .transpose()
+                    .context("Failed to parse 'gc_branch_images' as bool")?,
+                resident_size_quota: settings
+                    .remove("resident_size_quota")
+                    .map(|x| x.parse::<u64>())
+                    .transpose()
+                    .context("Failed to parse 'resident_size_quota' as an integer")?,
+                write_throttle: settings
+                    .remove("write_throttle")
+                    .map(serde_json::from_str)
+                    .transpose()
+                    .context("parse `write_throttle` from json")?,
+                image_creation_read_heat: settings
+                    .remove("image_creation_read_heat")
+                    .map(|x| x.parse::<bool>())
+                    .transpose()
+                    .context("Failed to parse 'image_creation_read_heat' as bool")?,
+                layer_checksums: settings
+                    .remove("layer_checksums")
+                    .map(|x| x.parse::<bool>())
+                    .transpose()
+                    .context("Failed to parse 'layer_checksums' as bool")?,
             }
         };
 
@@ -567,6 +637,7 @@ impl PageServerNode {
         let req = models::TimelineCreateRequest {
             new_timeline_id,
             ancestor_start_lsn,
+            ancestor_start_timestamp: None,
             ancestor_timeline_id,
             pg_version,
             existing_initdb_timeline_id,
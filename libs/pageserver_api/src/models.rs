@@ -189,9 +189,31 @@ pub struct TimelineCreateRequest {
     pub existing_initdb_timeline_id: Option<TimelineId>,
     #[serde(default)]
     pub ancestor_start_lsn: Option<Lsn>,
+    /// Branch at the LSN of the last commit at or before this time, instead of at
+    /// `ancestor_start_lsn`. Resolved like the `get_lsn_by_timestamp` API does.
+    #[serde(default, with = "humantime_serde")]
+    pub ancestor_start_timestamp: Option<SystemTime>,
     pub pg_version: Option<u32>,
 }
 
+/// The response of the "timeline_create" API call: a [`TimelineInfo`] of the new
+/// timeline, plus the branch point that an `ancestor_start_timestamp` resolved to.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimelineCreateResponse {
+    #[serde(flatten)]
+    pub timeline_info: TimelineInfo,
+    /// Only set if the request had an `ancestor_start_timestamp`
+    pub ancestor_start: Option<AncestorStart>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AncestorStart {
+    pub lsn: Lsn,
+    /// Time of the latest commit at or before `lsn`, if there is any
+    #[serde(default, with = "humantime_serde")]
+    pub commit_timestamp: Option<SystemTime>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TenantShardSplitRequest {
     pub new_shard_count: u8,
@@ -284,6 +306,13 @@ pub struct TenantConfig {
     pub heatmap_period: Option<String>,
     pub lazy_slru_download: Option<bool>,
     pub timeline_get_throttle: Option<ThrottleConfig>,
+    pub layer_compression: Option<CompressionAlgorithm>,
+    pub compaction_algorithm: Option<CompactionAlgorithm>,
+    pub gc_branch_images: Option<bool>,
+    pub resident_size_quota: Option<u64>,
+    pub write_throttle: Option<ThrottleConfig>,
+    pub image_creation_read_heat: Option<bool>,
+    pub layer_checksums: Option<bool>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -310,6 +339,35 @@ pub struct EvictionPolicyLayerAccessThreshold {
     pub threshold: Duration,
 }
 
+/// Compression applied to the values (page images and WAL records) of newly
+/// written image and delta layers. Existing layers are not rewritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum CompressionAlgorithm {
+    Disabled,
+    Zstd {
+        /// Compression level passed to zstd; the zstd default if omitted.
+        level: Option<i8>,
+    },
+}
+
+/// How delta layers are compacted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum CompactionAlgorithm {
+    /// Merge all L0 delta layers into L1 layers covering their whole LSN range.
+    Legacy,
+    /// Merge runs of delta layers of similar size, so that each delta is
+    /// rewritten a logarithmic number of times.
+    Tiered,
+}
+
+impl CompressionAlgorithm {
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, CompressionAlgorithm::Disabled)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct ThrottleConfig {
     pub task_kinds: Vec<String>, // TaskKind
@@ -497,6 +555,12 @@ pub struct TenantDetails {
     pub walredo: Option<WalRedoManagerStatus>,
 
     pub timelines: Vec<TimelineId>,
+
+    /// Size of the layer files of the tenant on local disk.
+    #[serde(default)]
+    pub resident_size: u64,
+    /// The `resident_size_quota` of the tenant, if it has one.
+    pub resident_size_quota: Option<u64>,
 }
 
 /// This represents the output of the "timeline_detail" and "timeline_list" API calls.
@@ -680,6 +744,74 @@ pub enum DownloadRemoteLayersTaskState {
     ShutDown,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimelineImportRequest {
+    /// Path of the basebackup in the pageserver's remote storage, below
+    /// `imports/<tenant_id>/`: either a tarball, or a directory with the output of
+    /// `pg_basebackup --format=tar`, i.e. a `base.tar` and a `pg_wal.tar` with the WAL
+    /// from `base_lsn` to `end_lsn`.
+    pub remote_path: String,
+    pub base_lsn: Lsn,
+    pub end_lsn: Lsn,
+    pub pg_version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimelineImportTaskInfo {
+    pub task_id: String,
+    pub state: TimelineImportTaskState,
+    pub downloaded_bytes: u64,
+    /// Set when `state` is `Failed`
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum TimelineImportTaskState {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Logical size of each database and relation of a timeline at an LSN.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimelineRelationSizes {
+    pub lsn: Lsn,
+    pub calculated_at: chrono::DateTime<chrono::Utc>,
+    /// Ordered by tablespace and database oid
+    pub databases: Vec<DatabaseSize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DatabaseSize {
+    pub spcnode: u32,
+    pub dbnode: u32,
+    /// Sum of the sizes of `relations`, in bytes
+    pub size: u64,
+    /// Largest first
+    pub relations: Vec<RelationSize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RelationSize {
+    pub relnode: u32,
+    /// Size of all forks of the relation, in bytes
+    pub size: u64,
+}
+
+/// Sampled read and write counts of a key range of a timeline, halved every
+/// ten minutes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyRangeStats {
+    pub key_start: String,
+    pub key_end: String,
+    pub reads: u64,
+    pub writes: u64,
+    /// Layers visited by the reads, in total
+    pub layers_visited: u64,
+    /// WAL records applied by the reads, in total
+    pub records_applied: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TimelineGcRequest {
     pub gc_horizon: Option<u64>,
@@ -689,6 +821,12 @@ pub struct TimelineGcRequest {
 pub struct WalRedoManagerStatus {
     pub last_redo_at: Option<chrono::DateTime<chrono::Utc>>,
     pub pid: Option<u32>,
+    /// Number of running walredo processes
+    #[serde(default)]
+    pub pool_size: usize,
+    /// Number of requests waiting for a busy walredo process
+    #[serde(default)]
+    pub queue_depth: usize,
 }
 
 pub mod virtual_file {
@@ -710,8 +848,44 @@ pub mod virtual_file {
         #[cfg(target_os = "linux")]
         TokioEpollUring,
     }
+
+    /// Whether layer files are read through the kernel page cache.
+    #[derive(
+        Copy,
+        Clone,
+        PartialEq,
+        Eq,
+        Hash,
+        strum_macros::EnumString,
+        strum_macros::Display,
+        strum_macros::FromRepr,
+        serde_with::DeserializeFromStr,
+        serde_with::SerializeDisplay,
+        Debug,
+    )]
+    #[strum(serialize_all = "kebab-case")]
+    #[repr(u8)]
+    pub enum IoMode {
+        Buffered,
+        /// Read layer files with `O_DIRECT`, so that their pages are only
+        /// cached in the pageserver's own page cache.
+        #[cfg(target_os = "linux")]
+        Direct,
+    }
+}
+
+/// Version of the pagestream protocol, negotiated by the command that the client
+/// uses to enter pagestream mode (`pagestream` or `pagestream_v2`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagestreamProtocolVersion {
+    V1,
+    /// Adds [`PagestreamFeMessage::GetPageBatch`].
+    V2,
 }
 
+/// Upper bound on the number of pages in a single [`PagestreamGetPageBatchRequest`].
+pub const PAGESTREAM_MAX_BATCH_PAGES: usize = 1024;
+
 // Wrapped in libpq CopyData
 #[derive(PartialEq, Eq, Debug)]
 pub enum PagestreamFeMessage {
@@ -720,6 +894,7 @@ pub enum PagestreamFeMessage {
     GetPage(PagestreamGetPageRequest),
     DbSize(PagestreamDbSizeRequest),
     GetSlruSegment(PagestreamGetSlruSegmentRequest),
+    GetPageBatch(PagestreamGetPageBatchRequest),
 }
 
 // Wrapped in libpq CopyData
@@ -731,9 +906,12 @@ pub enum PagestreamBeMessage {
     Error(PagestreamErrorResponse),
     DbSize(PagestreamDbSizeResponse),
     GetSlruSegment(PagestreamGetSlruSegmentResponse),
+    GetPageBatch(PagestreamGetPageBatchResponse),
 }
 
-// Keep in sync with `pagestore_client.h`
+// Keep in sync with `pagestore_client.h`, except for `GetPageBatch`: the compute
+// only speaks protocol version 1 (see [`PagestreamProtocolVersion`]), so it has no
+// use for the batch messages yet.
 #[repr(u8)]
 enum PagestreamBeMessageTag {
     Exists = 100,
@@ -742,6 +920,7 @@ enum PagestreamBeMessageTag {
     Error = 103,
     DbSize = 104,
     GetSlruSegment = 105,
+    GetPageBatch = 106,
 }
 impl TryFrom<u8> for PagestreamBeMessageTag {
     type Error = u8;
@@ -753,6 +932,7 @@ impl TryFrom<u8> for PagestreamBeMessageTag {
             103 => Ok(PagestreamBeMessageTag::Error),
             104 => Ok(PagestreamBeMessageTag::DbSize),
             105 => Ok(PagestreamBeMessageTag::GetSlruSegment),
+            106 => Ok(PagestreamBeMessageTag::GetPageBatch),
             _ => Err(value),
         }
     }
@@ -795,6 +975,14 @@ pub struct PagestreamGetSlruSegmentRequest {
     pub segno: u32,
 }
 
+/// Many pages, possibly of different relations, all requested at the same LSN.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PagestreamGetPageBatchRequest {
+    pub latest: bool,
+    pub lsn: Lsn,
+    pub pages: Vec<(RelTag, u32)>,
+}
+
 #[derive(Debug)]
 pub struct PagestreamExistsResponse {
     pub exists: bool,
@@ -820,6 +1008,13 @@ pub struct PagestreamErrorResponse {
     pub message: String,
 }
 
+/// Response to a [`PagestreamGetPageBatchRequest`]: one entry per requested page, in
+/// request order. Failing to read one page does not fail the others.
+#[derive(Debug)]
+pub struct PagestreamGetPageBatchResponse {
+    pub pages: Vec<Result<Bytes, PagestreamErrorResponse>>,
+}
+
 #[derive(Debug)]
 pub struct PagestreamDbSizeResponse {
     pub db_size: i64,
@@ -886,15 +1081,33 @@ impl PagestreamFeMessage {
                 bytes.put_u8(req.kind);
                 bytes.put_u32(req.segno);
             }
+
+            Self::GetPageBatch(req) => {
+                bytes.put_u8(5);
+                bytes.put_u8(u8::from(req.latest));
+                bytes.put_u64(req.lsn.0);
+                bytes.put_u32(req.pages.len() as u32);
+                for (rel, blkno) in &req.pages {
+                    bytes.put_u32(rel.spcnode);
+                    bytes.put_u32(rel.dbnode);
+                    bytes.put_u32(rel.relnode);
+                    bytes.put_u8(rel.forknum);
+                    bytes.put_u32(*blkno);
+                }
+            }
         }
 
         bytes.into()
     }
 
-    pub fn parse<R: std::io::Read>(body: &mut R) -> anyhow::Result<PagestreamFeMessage> {
+    pub fn parse<R: std::io::Read>(
+        body: &mut R,
+        protocol_version: PagestreamProtocolVersion,
+    ) -> anyhow::Result<PagestreamFeMessage> {
         // TODO these gets can fail
 
-        // these correspond to the NeonMessageTag enum in pagestore_client.h
+        // these correspond to the NeonMessageTag enum in pagestore_client.h, apart
+        // from GetPageBatch, which the compute does not send.
         //
         // TODO: consider using protobuf or serde bincode for less error prone
         // serialization.
@@ -944,6 +1157,33 @@ impl PagestreamFeMessage {
                     segno: body.read_u32::<BigEndian>()?,
                 },
             )),
+            5 if protocol_version == PagestreamProtocolVersion::V1 => {
+                bail!("GetPageBatch requires pagestream protocol version 2")
+            }
+            5 => {
+                let latest = body.read_u8()? != 0;
+                let lsn = Lsn::from(body.read_u64::<BigEndian>()?);
+                let n_pages = body.read_u32::<BigEndian>()? as usize;
+                if n_pages > PAGESTREAM_MAX_BATCH_PAGES {
+                    bail!(
+                        "too many pages in GetPageBatch: {n_pages} > {PAGESTREAM_MAX_BATCH_PAGES}"
+                    );
+                }
+                let mut pages = Vec::with_capacity(n_pages);
+                for _ in 0..n_pages {
+                    let rel = RelTag {
+                        spcnode: body.read_u32::<BigEndian>()?,
+                        dbnode: body.read_u32::<BigEndian>()?,
+                        relnode: body.read_u32::<BigEndian>()?,
+                        forknum: body.read_u8()?,
+                    };
+                    let blkno = body.read_u32::<BigEndian>()?;
+                    pages.push((rel, blkno));
+                }
+                Ok(PagestreamFeMessage::GetPageBatch(
+                    PagestreamGetPageBatchRequest { latest, lsn, pages },
+                ))
+            }
             _ => bail!("unknown smgr message tag: {:?}", msg_tag),
         }
     }
@@ -985,6 +1225,24 @@ impl PagestreamBeMessage {
                 bytes.put_u32((resp.segment.len() / BLCKSZ as usize) as u32);
                 bytes.put(&resp.segment[..]);
             }
+
+            Self::GetPageBatch(resp) => {
+                bytes.put_u8(Tag::GetPageBatch as u8);
+                bytes.put_u32(resp.pages.len() as u32);
+                for page in &resp.pages {
+                    match page {
+                        Ok(page) => {
+                            bytes.put_u8(0);
+                            bytes.put(&page[..]);
+                        }
+                        Err(err) => {
+                            bytes.put_u8(1);
+                            bytes.put(err.message.as_bytes());
+                            bytes.put_u8(0); // null terminator
+                        }
+                    }
+                }
+            }
         }
 
         bytes.into()
@@ -1033,6 +1291,30 @@ impl PagestreamBeMessage {
                         segment: segment.into(),
                     })
                 }
+                Tag::GetPageBatch => {
+                    let n_pages = buf.read_u32::<BigEndian>()?;
+                    let mut pages = Vec::with_capacity(n_pages as usize);
+                    for _ in 0..n_pages {
+                        let page = match buf.read_u8()? {
+                            0 => {
+                                let mut page = vec![0; BLCKSZ as usize];
+                                buf.read_exact(&mut page)?;
+                                Ok(page.into())
+                            }
+                            1 => {
+                                let mut msg = Vec::new();
+                                buf.read_until(0, &mut msg)?;
+                                let cstring = std::ffi::CString::from_vec_with_nul(msg)?;
+                                Err(PagestreamErrorResponse {
+                                    message: cstring.to_str()?.to_owned(),
+                                })
+                            }
+                            status => anyhow::bail!("invalid page status {status}"),
+                        };
+                        pages.push(page);
+                    }
+                    Self::GetPageBatch(PagestreamGetPageBatchResponse { pages })
+                }
             };
         let remaining = buf.into_inner();
         if !remaining.is_empty() {
@@ -1052,6 +1334,7 @@ impl PagestreamBeMessage {
             Self::Error(_) => "Error",
             Self::DbSize(_) => "DbSize",
             Self::GetSlruSegment(_) => "GetSlruSegment",
+            Self::GetPageBatch(_) => "GetPageBatch",
         }
     }
 }
@@ -1106,11 +1389,68 @@ mod tests {
         ];
         for msg in messages {
             let bytes = msg.serialize();
-            let reconstructed = PagestreamFeMessage::parse(&mut bytes.reader()).unwrap();
-            assert!(msg == reconstructed);
+            for protocol_version in [PagestreamProtocolVersion::V1, PagestreamProtocolVersion::V2] {
+                let reconstructed =
+                    PagestreamFeMessage::parse(&mut bytes.reader(), protocol_version).unwrap();
+                assert!(msg == reconstructed);
+            }
         }
     }
 
+    #[test]
+    fn test_pagestream_get_page_batch() {
+        let rel = RelTag {
+            forknum: 0,
+            spcnode: 2,
+            dbnode: 3,
+            relnode: 4,
+        };
+        let msg = PagestreamFeMessage::GetPageBatch(PagestreamGetPageBatchRequest {
+            latest: false,
+            lsn: Lsn(4),
+            pages: vec![(rel, 7), (rel, 8), (RelTag { forknum: 1, ..rel }, 0)],
+        });
+        let bytes = msg.serialize();
+
+        // Batches are only understood from protocol version 2 on
+        assert!(PagestreamFeMessage::parse(
+            &mut bytes.clone().reader(),
+            PagestreamProtocolVersion::V1
+        )
+        .is_err());
+        let reconstructed =
+            PagestreamFeMessage::parse(&mut bytes.reader(), PagestreamProtocolVersion::V2).unwrap();
+        assert!(msg == reconstructed);
+
+        let resp = PagestreamBeMessage::GetPageBatch(PagestreamGetPageBatchResponse {
+            pages: vec![
+                Ok(Bytes::from(vec![1; BLCKSZ as usize])),
+                Err(PagestreamErrorResponse {
+                    message: "could not read page".to_string(),
+                }),
+                Ok(Bytes::from(vec![3; BLCKSZ as usize])),
+            ],
+        });
+        let PagestreamBeMessage::GetPageBatch(reconstructed) =
+            PagestreamBeMessage::deserialize(resp.serialize()).unwrap()
+        else {
+            panic!("unexpected response kind");
+        };
+        assert_eq!(reconstructed.pages.len(), 3);
+        assert_eq!(
+            reconstructed.pages[0].as_ref().unwrap()[..],
+            [1; BLCKSZ as usize]
+        );
+        assert_eq!(
+            reconstructed.pages[1].as_ref().unwrap_err().message,
+            "could not read page"
+        );
+        assert_eq!(
+            reconstructed.pages[2].as_ref().unwrap()[..],
+            [3; BLCKSZ as usize]
+        );
+    }
+
     #[test]
     fn test_tenantinfo_serde() {
         // Test serialization/deserialization of TenantInfo
@@ -148,6 +148,47 @@ pub const XLH_INSERT_ALL_VISIBLE_CLEARED: u8 = (1 << 0) as u8;
 pub const XLH_UPDATE_OLD_ALL_VISIBLE_CLEARED: u8 = (1 << 0) as u8;
 pub const XLH_UPDATE_NEW_ALL_VISIBLE_CLEARED: u8 = (1 << 1) as u8;
 pub const XLH_DELETE_ALL_VISIBLE_CLEARED: u8 = (1 << 0) as u8;
+pub const XLH_DELETE_IS_SUPER: u8 = (1 << 3) as u8;
+pub const XLH_DELETE_IS_PARTITION_MOVE: u8 = (1 << 4) as u8;
+pub const XLH_UPDATE_PREFIX_FROM_OLD: u8 = (1 << 5) as u8;
+pub const XLH_UPDATE_SUFFIX_FROM_OLD: u8 = (1 << 6) as u8;
+pub const XLHL_XMAX_IS_MULTI: u8 = 0x01;
+pub const XLHL_XMAX_LOCK_ONLY: u8 = 0x02;
+pub const XLHL_XMAX_EXCL_LOCK: u8 = 0x04;
+pub const XLHL_XMAX_KEYSHR_LOCK: u8 = 0x08;
+pub const XLHL_KEYS_UPDATED: u8 = 0x10;
+
+// From nbtxlog.h
+pub const XLOG_BTREE_INSERT_LEAF: u8 = 0x00;
+
+// From htup_details.h
+pub const SIZEOF_HEAP_TUPLE_HEADER: usize = 23;
+pub const HEAP_XMAX_KEYSHR_LOCK: u16 = 0x0010;
+pub const HEAP_COMBOCID: u16 = 0x0020;
+pub const HEAP_XMAX_EXCL_LOCK: u16 = 0x0040;
+pub const HEAP_XMAX_LOCK_ONLY: u16 = 0x0080;
+pub const HEAP_XMAX_COMMITTED: u16 = 0x0400;
+pub const HEAP_XMAX_INVALID: u16 = 0x0800;
+pub const HEAP_XMAX_IS_MULTI: u16 = 0x1000;
+pub const HEAP_MOVED_OFF: u16 = 0x4000;
+pub const HEAP_MOVED_IN: u16 = 0x8000;
+pub const HEAP_MOVED: u16 = HEAP_MOVED_OFF | HEAP_MOVED_IN;
+pub const HEAP_LOCK_MASK: u16 = HEAP_XMAX_EXCL_LOCK | HEAP_XMAX_KEYSHR_LOCK;
+pub const HEAP_XMAX_BITS: u16 = HEAP_XMAX_COMMITTED
+    | HEAP_XMAX_INVALID
+    | HEAP_XMAX_IS_MULTI
+    | HEAP_LOCK_MASK
+    | HEAP_XMAX_LOCK_ONLY;
+pub const HEAP_KEYS_UPDATED: u16 = 0x2000;
+pub const HEAP_HOT_UPDATED: u16 = 0x4000;
+pub const MAX_HEAP_TUPLES_PER_PAGE: u16 = ((BLCKSZ as usize - SIZEOF_PAGE_HEADER_DATA)
+    / (((SIZEOF_HEAP_TUPLE_HEADER + 7) & !7) + 4)) as u16;
+
+// From bufpage.h and itemid.h
+pub const PD_ALL_VISIBLE: u16 = 0x0004;
+pub const PG_PAGE_LAYOUT_VERSION: u16 = 4;
+pub const LP_UNUSED: u8 = 0;
+pub const LP_NORMAL: u8 = 1;
 
 // From replication/message.h
 pub const XLOG_LOGICAL_MESSAGE: u8 = 0x00;
@@ -164,6 +205,7 @@ pub const RM_RELMAP_ID: u8 = 7;
 pub const RM_STANDBY_ID: u8 = 8;
 pub const RM_HEAP2_ID: u8 = 9;
 pub const RM_HEAP_ID: u8 = 10;
+pub const RM_BTREE_ID: u8 = 11;
 pub const RM_LOGICALMSG_ID: u8 = 21;
 
 // from neon_rmgr.h
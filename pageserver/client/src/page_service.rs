@@ -3,8 +3,8 @@ use std::pin::Pin;
 use futures::SinkExt;
 use pageserver_api::{
     models::{
-        PagestreamBeMessage, PagestreamFeMessage, PagestreamGetPageRequest,
-        PagestreamGetPageResponse,
+        PagestreamBeMessage, PagestreamFeMessage, PagestreamGetPageBatchRequest,
+        PagestreamGetPageBatchResponse, PagestreamGetPageRequest, PagestreamGetPageResponse,
     },
     reltag::RelTag,
 };
@@ -57,10 +57,31 @@ impl Client {
         self,
         tenant_id: TenantId,
         timeline_id: TimelineId,
+    ) -> anyhow::Result<PagestreamClient> {
+        self.pagestream_impl("pagestream", tenant_id, timeline_id)
+            .await
+    }
+
+    /// Like [`Self::pagestream`], but speaks version 2 of the protocol, which allows
+    /// using [`PagestreamClient::getpage_batch`].
+    pub async fn pagestream_v2(
+        self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> anyhow::Result<PagestreamClient> {
+        self.pagestream_impl("pagestream_v2", tenant_id, timeline_id)
+            .await
+    }
+
+    async fn pagestream_impl(
+        self,
+        command: &str,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
     ) -> anyhow::Result<PagestreamClient> {
         let copy_both: tokio_postgres::CopyBothDuplex<bytes::Bytes> = self
             .client
-            .copy_both_simple(&format!("pagestream {tenant_id} {timeline_id}"))
+            .copy_both_simple(&format!("{command} {tenant_id} {timeline_id}"))
             .await?;
         let Client {
             cancel_on_client_drop,
@@ -140,24 +161,15 @@ impl PagestreamClient {
         &mut self,
         req: PagestreamGetPageRequest,
     ) -> anyhow::Result<PagestreamGetPageResponse> {
-        let req = PagestreamFeMessage::GetPage(req);
-        let req: bytes::Bytes = req.serialize();
-        // let mut req = tokio_util::io::ReaderStream::new(&req);
-        let mut req = tokio_stream::once(Ok(req));
-
-        self.copy_both.send_all(&mut req).await?;
-
-        let next: Option<Result<bytes::Bytes, _>> = self.copy_both.next().await;
-        let next: bytes::Bytes = next.unwrap()?;
-
-        let msg = PagestreamBeMessage::deserialize(next)?;
+        let msg = self.request(PagestreamFeMessage::GetPage(req)).await?;
         match msg {
             PagestreamBeMessage::GetPage(p) => Ok(p),
             PagestreamBeMessage::Error(e) => anyhow::bail!("Error: {:?}", e),
             PagestreamBeMessage::Exists(_)
             | PagestreamBeMessage::Nblocks(_)
             | PagestreamBeMessage::DbSize(_)
-            | PagestreamBeMessage::GetSlruSegment(_) => {
+            | PagestreamBeMessage::GetSlruSegment(_)
+            | PagestreamBeMessage::GetPageBatch(_) => {
                 anyhow::bail!(
                     "unexpected be message kind in response to getpage request: {}",
                     msg.kind()
@@ -165,4 +177,41 @@ impl PagestreamClient {
             }
         }
     }
+
+    /// Requires a client created with [`Client::pagestream_v2`].
+    ///
+    /// Errors reading individual pages are returned in the response, not as an `Err`.
+    pub async fn getpage_batch(
+        &mut self,
+        req: PagestreamGetPageBatchRequest,
+    ) -> anyhow::Result<PagestreamGetPageBatchResponse> {
+        let msg = self.request(PagestreamFeMessage::GetPageBatch(req)).await?;
+        match msg {
+            PagestreamBeMessage::GetPageBatch(p) => Ok(p),
+            PagestreamBeMessage::Error(e) => anyhow::bail!("Error: {:?}", e),
+            PagestreamBeMessage::Exists(_)
+            | PagestreamBeMessage::Nblocks(_)
+            | PagestreamBeMessage::GetPage(_)
+            | PagestreamBeMessage::DbSize(_)
+            | PagestreamBeMessage::GetSlruSegment(_) => {
+                anyhow::bail!(
+                    "unexpected be message kind in response to getpage batch request: {}",
+                    msg.kind()
+                )
+            }
+        }
+    }
+
+    async fn request(&mut self, req: PagestreamFeMessage) -> anyhow::Result<PagestreamBeMessage> {
+        let req: bytes::Bytes = req.serialize();
+        // let mut req = tokio_util::io::ReaderStream::new(&req);
+        let mut req = tokio_stream::once(Ok(req));
+
+        self.copy_both.send_all(&mut req).await?;
+
+        let next: Option<Result<bytes::Bytes, _>> = self.copy_both.next().await;
+        let next: bytes::Bytes = next.unwrap()?;
+
+        PagestreamBeMessage::deserialize(next)
+    }
 }
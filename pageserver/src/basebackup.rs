@@ -13,12 +13,13 @@
 use anyhow::{anyhow, bail, ensure, Context};
 use bytes::{BufMut, Bytes, BytesMut};
 use fail::fail_point;
-use pageserver_api::key::{key_to_slru_block, Key};
+use pageserver_api::key::{key_to_rel_block, key_to_slru_block, rel_block_to_key, Key};
 use postgres_ffi::pg_constants;
+use std::collections::BTreeSet;
 use std::fmt::Write as FmtWrite;
 use std::time::SystemTime;
 use tokio::io;
-use tokio::io::AsyncWrite;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tracing::*;
 
 use tokio_tar::{Builder, EntryType, Header};
@@ -38,9 +39,23 @@ use postgres_ffi::PG_TLI;
 use postgres_ffi::{BLCKSZ, RELSEG_SIZE, WAL_SEGMENT_SIZE};
 use utils::lsn::Lsn;
 
+/// Magic number at the start of the `INCREMENTAL.` relation segment files of an
+/// incremental backup, the same as in PostgreSQL 17's incremental backups.
+const INCREMENTAL_MAGIC: u32 = 0xd3ae1f0d;
+
 /// Create basebackup with non-rel data in it.
 /// Only include relational data if 'full_backup' is true.
 ///
+/// With `incremental_from`, a full backup only includes what changed after
+/// that LSN, see `Basebackup::add_rel_incremental`: every relation segment is
+/// sent as an `INCREMENTAL.` file with the modified blocks, and only modified
+/// SLRU segments are sent. The other non-relational files are small, they are
+/// always sent in full.
+///
+/// The first `skip_entries` entries of the archive are left out, to resume a
+/// transfer that was interrupted after the client had received them. The
+/// entries are generated in the same order every time for a given LSN.
+///
 /// Currently we use empty 'req_lsn' in two cases:
 ///  * During the basebackup right after timeline creation
 ///  * When working without safekeepers. In this situation it is important to match the lsn
@@ -52,6 +67,8 @@ pub async fn send_basebackup_tarball<'a, W>(
     req_lsn: Option<Lsn>,
     prev_lsn: Option<Lsn>,
     full_backup: bool,
+    incremental_from: Option<Lsn>,
+    skip_entries: usize,
     ctx: &'a RequestContext,
 ) -> anyhow::Result<()>
 where
@@ -101,16 +118,33 @@ where
     };
 
     info!(
-        "taking basebackup lsn={}, prev_lsn={} (full_backup={})",
-        backup_lsn, prev_lsn, full_backup
+        "taking basebackup lsn={}, prev_lsn={} (full_backup={}, incremental_from={:?}, skip_entries={})",
+        backup_lsn, prev_lsn, full_backup, incremental_from, skip_entries
     );
 
+    let modified_keys = match incremental_from {
+        Some(from_lsn) => {
+            ensure!(full_backup, "only full backups can be incremental");
+            Some(
+                timeline
+                    .collect_modified_keys(from_lsn, backup_lsn, ctx)
+                    .await
+                    .context("failed to collect keys modified since the previous backup")?,
+            )
+        }
+        None => None,
+    };
+
     let basebackup = Basebackup {
-        ar: Builder::new_non_terminated(write),
+        ar: TarBuilder {
+            inner: Builder::new_non_terminated(write),
+            skip_entries,
+        },
         timeline,
         lsn: backup_lsn,
         prev_record_lsn: prev_lsn,
         full_backup,
+        modified_keys,
         ctx,
     };
     basebackup
@@ -126,21 +160,50 @@ struct Basebackup<'a, W>
 where
     W: AsyncWrite + Send + Sync + Unpin,
 {
-    ar: Builder<&'a mut W>,
+    ar: TarBuilder<'a, W>,
     timeline: &'a Timeline,
     lsn: Lsn,
     prev_record_lsn: Lsn,
     full_backup: bool,
+    /// For an incremental backup, the keys modified since the previous backup.
+    modified_keys: Option<BTreeSet<Key>>,
     ctx: &'a RequestContext,
 }
 
+/// The tar [`Builder`] that the basebackup is written to, minus the entries
+/// that the client already has.
+struct TarBuilder<'a, W>
+where
+    W: AsyncWrite + Send + Sync + Unpin,
+{
+    inner: Builder<&'a mut W>,
+    skip_entries: usize,
+}
+
+impl<'a, W> TarBuilder<'a, W>
+where
+    W: AsyncWrite + Send + Sync + Unpin,
+{
+    async fn append<R: AsyncRead + Unpin>(&mut self, header: &Header, data: R) -> io::Result<()> {
+        if self.skip_entries > 0 {
+            self.skip_entries -= 1;
+            return Ok(());
+        }
+        self.inner.append(header, data).await
+    }
+
+    async fn finish(&mut self) -> io::Result<()> {
+        self.inner.finish().await
+    }
+}
+
 /// A sink that accepts SLRU blocks ordered by key and forwards
 /// full segments to the archive.
 struct SlruSegmentsBuilder<'a, 'b, W>
 where
     W: AsyncWrite + Send + Sync + Unpin,
 {
-    ar: &'a mut Builder<&'b mut W>,
+    ar: &'a mut TarBuilder<'b, W>,
     buf: Vec<u8>,
     current_segment: Option<(SlruKind, u32)>,
 }
@@ -149,7 +212,7 @@ impl<'a, 'b, W> SlruSegmentsBuilder<'a, 'b, W>
 where
     W: AsyncWrite + Send + Sync + Unpin,
 {
-    fn new(ar: &'a mut Builder<&'b mut W>) -> Self {
+    fn new(ar: &'a mut TarBuilder<'b, W>) -> Self {
         Self {
             ar,
             buf: Vec::new(),
@@ -252,11 +315,18 @@ where
         }
         if !lazy_slru_download {
             // Gather non-relational files from object storage pages.
-            let slru_partitions = self
+            let mut slru_keyspace = self
                 .timeline
                 .get_slru_keyspace(Version::Lsn(self.lsn), self.ctx)
-                .await?
-                .partition(Timeline::MAX_GET_VECTORED_KEYS * BLCKSZ as u64);
+                .await?;
+            if let Some(modified_keys) = &self.modified_keys {
+                // Each range covers whole segments
+                slru_keyspace
+                    .ranges
+                    .retain(|range| modified_keys.range(range.clone()).next().is_some());
+            }
+            let slru_partitions =
+                slru_keyspace.partition(Timeline::MAX_GET_VECTORED_KEYS * BLCKSZ as u64);
 
             let mut slru_builder = SlruSegmentsBuilder::new(&mut self.ar);
 
@@ -273,11 +343,11 @@ where
             slru_builder.finish().await?;
         }
 
-        let mut min_restart_lsn: Lsn = Lsn::MAX;
-        // Create tablespace directories
-        for ((spcnode, dbnode), has_relmap_file) in
-            self.timeline.list_dbdirs(self.lsn, self.ctx).await?
-        {
+        // Create tablespace directories. Databases, relations and twophase files
+        // are sorted, so that a resumed basebackup produces the same entries.
+        let mut dbdirs = Vec::from_iter(self.timeline.list_dbdirs(self.lsn, self.ctx).await?);
+        dbdirs.sort_unstable();
+        for ((spcnode, dbnode), has_relmap_file) in dbdirs {
             self.add_dbdir(spcnode, dbnode, has_relmap_file).await?;
 
             // If full backup is requested, include all relation files.
@@ -286,7 +356,9 @@ where
                 .timeline
                 .list_rels(spcnode, dbnode, Version::Lsn(self.lsn), self.ctx)
                 .await?;
-            for &rel in rels.iter() {
+            let mut sorted_rels = Vec::from_iter(rels.iter().copied());
+            sorted_rels.sort_unstable();
+            for rel in sorted_rels {
                 // Send init fork as main fork to provide well formed empty
                 // contents of UNLOGGED relations. Postgres copies it in
                 // `reinit.c` during recovery.
@@ -307,40 +379,17 @@ where
                     self.add_rel(rel, rel).await?;
                 }
             }
-
-            for (path, content) in self.timeline.list_aux_files(self.lsn, self.ctx).await? {
-                if path.starts_with("pg_replslot") {
-                    let offs = pg_constants::REPL_SLOT_ON_DISK_OFFSETOF_RESTART_LSN;
-                    let restart_lsn = Lsn(u64::from_le_bytes(
-                        content[offs..offs + 8].try_into().unwrap(),
-                    ));
-                    info!("Replication slot {} restart LSN={}", path, restart_lsn);
-                    min_restart_lsn = Lsn::min(min_restart_lsn, restart_lsn);
-                }
-                let header = new_tar_header(&path, content.len() as u64)?;
-                self.ar
-                    .append(&header, &*content)
-                    .await
-                    .context("could not add aux file to basebackup tarball")?;
-            }
         }
-        if min_restart_lsn != Lsn::MAX {
-            info!(
-                "Min restart LSN for logical replication is {}",
-                min_restart_lsn
-            );
-            let data = min_restart_lsn.0.to_le_bytes();
-            let header = new_tar_header("restart.lsn", data.len() as u64)?;
-            self.ar
-                .append(&header, &data[..])
-                .await
-                .context("could not add restart.lsn file to basebackup tarball")?;
-        }
-        for xid in self
-            .timeline
-            .list_twophase_files(self.lsn, self.ctx)
-            .await?
-        {
+
+        self.add_aux_files().await?;
+
+        let mut twophase_files = Vec::from_iter(
+            self.timeline
+                .list_twophase_files(self.lsn, self.ctx)
+                .await?,
+        );
+        twophase_files.sort_unstable();
+        for xid in twophase_files {
             self.add_twophase_file(xid).await?;
         }
 
@@ -362,6 +411,10 @@ where
             .get_rel_size(src, Version::Lsn(self.lsn), false, self.ctx)
             .await?;
 
+        if self.modified_keys.is_some() {
+            return self.add_rel_incremental(src, dst, nblocks).await;
+        }
+
         // If the relation is empty, create an empty file
         if nblocks == 0 {
             let file_name = dst.to_segfile_name(0);
@@ -396,6 +449,81 @@ where
         Ok(())
     }
 
+    /// Add the blocks of relfilenode `src` modified since the previous backup,
+    /// naming it as `dst`.
+    ///
+    /// Each segment of the relation is sent as a file named like the segment
+    /// with an `INCREMENTAL.` prefix, even if none of its blocks changed, so
+    /// that segment files missing from the backup can be removed. The file
+    /// has the format of PostgreSQL 17's incremental files, which
+    /// `pg_combinebackup` reads. It consists of, in little-endian:
+    ///
+    /// * the magic number [`INCREMENTAL_MAGIC`]
+    /// * the number of modified blocks
+    /// * the length of the segment in blocks, to truncate or extend it to
+    /// * the modified block numbers, relative to the start of the segment
+    /// * if any blocks were modified, zeros up to a multiple of `BLCKSZ`
+    /// * the contents of the modified blocks, in the same order
+    async fn add_rel_incremental(
+        &mut self,
+        src: RelTag,
+        dst: RelTag,
+        nblocks: u32,
+    ) -> anyhow::Result<()> {
+        let modified_keys = self.modified_keys.as_ref().unwrap();
+
+        let mut startblk = 0;
+        let mut seg = 0;
+        loop {
+            let endblk = std::cmp::min(startblk + RELSEG_SIZE, nblocks);
+
+            let mut modified_blocks = Vec::new();
+            for key in
+                modified_keys.range(rel_block_to_key(src, startblk)..rel_block_to_key(src, endblk))
+            {
+                let (_, blknum) = key_to_rel_block(*key)?;
+                modified_blocks.push(blknum);
+            }
+
+            let mut header_len = 12 + 4 * modified_blocks.len();
+            if !modified_blocks.is_empty() {
+                header_len = header_len.next_multiple_of(BLCKSZ as usize);
+            }
+            let mut data =
+                BytesMut::with_capacity(header_len + modified_blocks.len() * BLCKSZ as usize);
+            data.put_u32_le(INCREMENTAL_MAGIC);
+            data.put_u32_le(modified_blocks.len() as u32);
+            data.put_u32_le(endblk - startblk);
+            for blknum in &modified_blocks {
+                data.put_u32_le(blknum - startblk);
+            }
+            data.resize(header_len, 0);
+            for blknum in modified_blocks {
+                let img = self
+                    .timeline
+                    .get_rel_page_at_lsn(src, blknum, Version::Lsn(self.lsn), false, self.ctx)
+                    .await?;
+                data.extend_from_slice(&img[..]);
+            }
+
+            let segfile_name = dst.to_segfile_name(seg);
+            let file_name = match segfile_name.rsplit_once('/') {
+                Some((dir, name)) => format!("{dir}/INCREMENTAL.{name}"),
+                None => format!("INCREMENTAL.{segfile_name}"),
+            };
+            let header = new_tar_header(&file_name, data.len() as u64)?;
+            self.ar.append(&header, &data[..]).await?;
+
+            seg += 1;
+            startblk = endblk;
+            if startblk >= nblocks {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
     //
     // Include database/tablespace directories.
     //
@@ -490,6 +618,65 @@ where
         Ok(())
     }
 
+    //
+    // Add the files stored in the aux files keyspace: the state of logical
+    // replication slots, logical rewrite mappings and replication origins.
+    //
+    // Also add a `restart.lsn` file with the oldest restart LSN of the
+    // replication slots, so that the compute knows how far back it needs WAL.
+    //
+    async fn add_aux_files(&mut self) -> anyhow::Result<()> {
+        let mut min_restart_lsn: Lsn = Lsn::MAX;
+
+        let mut aux_files = self
+            .timeline
+            .list_aux_files(self.lsn, self.ctx)
+            .await?
+            .into_iter()
+            .collect::<Vec<_>>();
+        // Make the tarball deterministic
+        aux_files.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        for (path, content) in aux_files {
+            if path.starts_with("pg_replslot") {
+                let offs = pg_constants::REPL_SLOT_ON_DISK_OFFSETOF_RESTART_LSN;
+                if let Some(restart_lsn) = content.get(offs..offs + 8) {
+                    let restart_lsn = Lsn(u64::from_le_bytes(restart_lsn.try_into().unwrap()));
+                    info!("Replication slot {} restart LSN={}", path, restart_lsn);
+                    min_restart_lsn = Lsn::min(min_restart_lsn, restart_lsn);
+                } else {
+                    // Don't fail the whole basebackup on a malformed slot file, the
+                    // compute will complain about it when it loads the slots.
+                    warn!(
+                        "Replication slot file {} is too short ({} bytes) to contain a restart LSN",
+                        path,
+                        content.len()
+                    );
+                }
+            }
+            let header = new_tar_header(&path, content.len() as u64)?;
+            self.ar
+                .append(&header, &*content)
+                .await
+                .context("could not add aux file to basebackup tarball")?;
+        }
+
+        if min_restart_lsn != Lsn::MAX {
+            info!(
+                "Min restart LSN for logical replication is {}",
+                min_restart_lsn
+            );
+            let data = min_restart_lsn.0.to_le_bytes();
+            let header = new_tar_header("restart.lsn", data.len() as u64)?;
+            self.ar
+                .append(&header, &data[..])
+                .await
+                .context("could not add restart.lsn file to basebackup tarball")?;
+        }
+
+        Ok(())
+    }
+
     //
     // Extract twophase state files
     //
@@ -0,0 +1,664 @@
+//!
+//! A bounded on-disk cache of basebackup tarballs.
+//!
+//! When many computes start on the same quiescent timeline, they all request
+//! the same basebackup, at the same `last_record_lsn`. Instead of generating
+//! an identical tarball from scratch for each of them, the tarball produced
+//! for the first request is kept in a file under the `basebackup_cache`
+//! directory of the pageserver workdir, and streamed as-is to the next ones.
+//!
+//! Entries are keyed by tenant shard, timeline, requested LSN and compression.
+//! Each entry remembers the `last_record_lsn` of the timeline at the time the
+//! tarball was generated, and it is invalidated when the timeline has advanced
+//! past that: a basebackup at the end of the timeline would start at a
+//! different LSN, and even one at a fixed LSN carries the previous record LSN
+//! only when taken at the end of the timeline.
+//!
+//! An entry is added to the cache as soon as its tarball starts being
+//! generated, by a task of its own that holds the [`TarballWriter`]. Requests
+//! that come in meanwhile don't generate it again: like the request that
+//! started it, they stream the file as it is being written, see
+//! [`CachedTarball`].
+//!
+//! The tarballs take up at most `basebackup_cache_max_size` bytes, evicting
+//! the least recently used ones to make room for a new one. The entries of a
+//! timeline are removed when it is deleted, and those of a tenant shard when
+//! it is detached. The cache doesn't survive a restart: the directory is
+//! cleared at startup.
+//!
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::task::{ready, Context as TaskContext, Poll};
+use std::time::Instant;
+
+use anyhow::Context;
+use camino::{Utf8Path, Utf8PathBuf};
+use once_cell::sync::OnceCell;
+use pageserver_api::shard::TenantShardId;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+use tracing::*;
+use utils::{id::TimelineId, lsn::Lsn};
+
+use crate::config::PageServerConf;
+use crate::metrics::BASEBACKUP_CACHE;
+use crate::page_service::BasebackupCompression;
+
+static BASEBACKUP_CACHE_INSTANCE: OnceCell<BasebackupCache> = OnceCell::new();
+
+///
+/// Initialize the basebackup cache. This must be called once at page server startup.
+///
+/// Leftovers from a previous run are removed. If the cache is disabled in the
+/// config, [`get`] returns `None` afterwards.
+///
+pub fn init(conf: &'static PageServerConf) -> anyhow::Result<()> {
+    let path = conf.basebackup_cache_path();
+    match std::fs::remove_dir_all(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("failed to clear {path}")),
+    }
+    if conf.basebackup_cache_max_size == 0 {
+        return Ok(());
+    }
+    std::fs::create_dir_all(&path).with_context(|| format!("failed to create {path}"))?;
+
+    let cache = BasebackupCache::new(path, conf.basebackup_cache_max_size);
+    if BASEBACKUP_CACHE_INSTANCE.set(cache).is_err() {
+        panic!("basebackup cache already initialized");
+    }
+    Ok(())
+}
+
+///
+/// Get a handle to the basebackup cache, if it is enabled.
+///
+pub(crate) fn get() -> Option<&'static BasebackupCache> {
+    BASEBACKUP_CACHE_INSTANCE.get()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct BasebackupCacheKey {
+    pub(crate) tenant_shard_id: TenantShardId,
+    pub(crate) timeline_id: TimelineId,
+    /// The LSN requested by the client, `None` for the end of the timeline.
+    pub(crate) lsn: Option<Lsn>,
+    pub(crate) compression: BasebackupCompression,
+}
+
+/// How much of a tarball has been written to its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Progress {
+    Writing(u64),
+    Done(u64),
+}
+
+struct CacheEntry {
+    path: Utf8PathBuf,
+    /// `last_record_lsn` of the timeline when the tarball was generated.
+    last_record_lsn: Lsn,
+    last_used: Instant,
+    progress: watch::Receiver<Progress>,
+}
+
+impl CacheEntry {
+    /// The size of the tarball, once it is complete
+    fn size(&self) -> Option<u64> {
+        match *self.progress.borrow() {
+            Progress::Writing(_) => None,
+            Progress::Done(size) => Some(size),
+        }
+    }
+}
+
+/// The result of [`BasebackupCache::get_or_start`].
+pub(crate) enum Lookup<'a> {
+    /// The tarball is in the cache, or being written to it by another request.
+    Hit(CachedTarball),
+    /// The tarball is not in the cache: the caller is to generate it with the
+    /// writer, and can send it to the client meanwhile.
+    Miss(TarballWriter<'a>, CachedTarball),
+}
+
+pub struct BasebackupCache {
+    path: Utf8PathBuf,
+    max_size: u64,
+    entries: Mutex<HashMap<BasebackupCacheKey, CacheEntry>>,
+    next_file_id: AtomicU64,
+}
+
+impl BasebackupCache {
+    fn new(path: Utf8PathBuf, max_size: u64) -> Self {
+        BasebackupCache {
+            path,
+            max_size,
+            entries: Mutex::new(HashMap::new()),
+            next_file_id: AtomicU64::new(0),
+        }
+    }
+
+    ///
+    /// Look up the tarball for `key`, generated when the timeline was at
+    /// `last_record_lsn`. An entry generated at any other LSN is stale, and is
+    /// dropped.
+    ///
+    /// On a miss, the new entry is added right away, so that concurrent
+    /// requests for the same tarball wait for this one instead of generating
+    /// it again.
+    ///
+    pub(crate) async fn get_or_start(
+        &self,
+        key: &BasebackupCacheKey,
+        last_record_lsn: Lsn,
+    ) -> anyhow::Result<Lookup<'_>> {
+        loop {
+            let (found, stale) = {
+                let mut entries = self.entries.lock().unwrap();
+                match entries.get_mut(key) {
+                    Some(entry) if entry.last_record_lsn == last_record_lsn => {
+                        entry.last_used = Instant::now();
+                        (Some((entry.path.clone(), entry.progress.clone())), None)
+                    }
+                    Some(_) => {
+                        let entry = entries.remove(key).unwrap();
+                        self.update_metrics(&entries);
+                        (None, Some(entry.path))
+                    }
+                    None => (None, None),
+                }
+            };
+            if let Some(stale) = stale {
+                remove_file(&stale).await;
+            }
+
+            match found {
+                Some((path, progress)) => match tokio::fs::File::open(&path).await {
+                    Ok(file) => {
+                        BASEBACKUP_CACHE.hits.inc();
+                        return Ok(Lookup::Hit(CachedTarball { file, progress }));
+                    }
+                    // Evicted before we opened it: look again, it's gone by now
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e).with_context(|| format!("failed to open {path}")),
+                },
+                None => {
+                    if let Some(lookup) = self.start(key, last_record_lsn).await? {
+                        BASEBACKUP_CACHE.misses.inc();
+                        return Ok(lookup);
+                    }
+                    // Someone else started the same entry meanwhile
+                }
+            }
+        }
+    }
+
+    /// Create the file and the entry for a tarball that is about to be
+    /// generated, unless there is an entry for `key` already.
+    async fn start(
+        &self,
+        key: &BasebackupCacheKey,
+        last_record_lsn: Lsn,
+    ) -> anyhow::Result<Option<Lookup<'_>>> {
+        let file_id = self.next_file_id.fetch_add(1, Ordering::Relaxed);
+        let path = self.path.join(format!("{file_id:016x}.tar"));
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("failed to create {path}"))?;
+        let reader = tokio::fs::File::open(&path)
+            .await
+            .with_context(|| format!("failed to open {path}"))?;
+
+        let (progress_tx, progress) = watch::channel(Progress::Writing(0));
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.contains_key(key) {
+                drop(entries);
+                remove_file(&path).await;
+                return Ok(None);
+            }
+            entries.insert(
+                *key,
+                CacheEntry {
+                    path: path.clone(),
+                    last_record_lsn,
+                    last_used: Instant::now(),
+                    progress: progress.clone(),
+                },
+            );
+        }
+
+        let writer = TarballWriter {
+            cache: self,
+            key: *key,
+            path,
+            file,
+            written: 0,
+            progress: progress_tx,
+            finished: false,
+        };
+        let tarball = CachedTarball {
+            file: reader,
+            progress,
+        };
+        Ok(Some(Lookup::Miss(writer, tarball)))
+    }
+
+    /// Evict the least recently used complete tarballs until the cache fits in
+    /// `max_size`.
+    async fn evict(&self) {
+        let mut removed = Vec::new();
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let mut total_size: u64 = entries.values().filter_map(CacheEntry::size).sum();
+            while total_size > self.max_size {
+                let Some((victim, size)) = entries
+                    .iter()
+                    .filter_map(|(key, entry)| Some((key, entry.size()?, entry.last_used)))
+                    .min_by_key(|(_, _, last_used)| *last_used)
+                    .map(|(key, size, _)| (*key, size))
+                else {
+                    break;
+                };
+                removed.push(entries.remove(&victim).unwrap().path);
+                total_size -= size;
+            }
+            self.update_metrics(&entries);
+        }
+        for path in removed {
+            remove_file(&path).await;
+        }
+    }
+
+    /// Remove the entries of a deleted timeline.
+    pub(crate) async fn remove_timeline(
+        &self,
+        tenant_shard_id: TenantShardId,
+        timeline_id: TimelineId,
+    ) {
+        self.remove_entries(|key| {
+            key.tenant_shard_id == tenant_shard_id && key.timeline_id == timeline_id
+        })
+        .await
+    }
+
+    /// Remove the entries of a detached tenant shard.
+    pub(crate) async fn remove_tenant(&self, tenant_shard_id: TenantShardId) {
+        self.remove_entries(|key| key.tenant_shard_id == tenant_shard_id)
+            .await
+    }
+
+    /// Remove the entries whose key matches, including those still being
+    /// written: their readers can still finish reading the file.
+    async fn remove_entries(&self, matches: impl Fn(&BasebackupCacheKey) -> bool) {
+        let removed: Vec<_> = {
+            let mut entries = self.entries.lock().unwrap();
+            let keys: Vec<_> = entries.keys().filter(|key| matches(key)).copied().collect();
+            let removed = keys
+                .iter()
+                .filter_map(|key| entries.remove(key))
+                .map(|entry| entry.path)
+                .collect();
+            self.update_metrics(&entries);
+            removed
+        };
+        for path in removed {
+            remove_file(&path).await;
+        }
+    }
+
+    /// Remove the entry for `key` if it's still the one with the file at `path`.
+    fn remove_entry(&self, key: &BasebackupCacheKey, path: &Utf8Path) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.path == path => {
+                entries.remove(key);
+                self.update_metrics(&entries);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn update_metrics(&self, entries: &HashMap<BasebackupCacheKey, CacheEntry>) {
+        BASEBACKUP_CACHE.entries.set(entries.len() as u64);
+        BASEBACKUP_CACHE
+            .size
+            .set(entries.values().filter_map(CacheEntry::size).sum());
+    }
+}
+
+///
+/// Writes a tarball into a new cache entry. Readers of the entry see the data
+/// as soon as it is in the file.
+///
+/// Call [`Self::finish`] once the whole tarball has been written. If the
+/// writer is dropped before that, the entry is removed and its readers fail.
+///
+pub(crate) struct TarballWriter<'a> {
+    cache: &'a BasebackupCache,
+    key: BasebackupCacheKey,
+    path: Utf8PathBuf,
+    file: tokio::fs::File,
+    /// Bytes accepted by `file`, which may still be writing the last of them
+    written: u64,
+    progress: watch::Sender<Progress>,
+    finished: bool,
+}
+
+impl TarballWriter<'_> {
+    ///
+    /// Mark the tarball as complete. The entry is kept only if `keep` is set,
+    /// i.e. if the timeline didn't advance while the tarball was generated: it
+    /// is still a valid basebackup to send, but not to reuse. A tarball larger
+    /// than the whole cache isn't kept either.
+    ///
+    pub(crate) async fn finish(mut self, keep: bool) -> anyhow::Result<()> {
+        self.file.flush().await?;
+        self.finished = true;
+        self.progress.send_replace(Progress::Done(self.written));
+
+        if keep && self.written <= self.cache.max_size {
+            // Count as used from now on: the eviction below makes room for it
+            let mut entries = self.cache.entries.lock().unwrap();
+            if let Some(entry) = entries.get_mut(&self.key) {
+                if entry.path == self.path {
+                    entry.last_used = Instant::now();
+                }
+            }
+            drop(entries);
+            self.cache.evict().await;
+        } else if self.cache.remove_entry(&self.key, &self.path) {
+            remove_file(&self.path).await;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TarballWriter<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        // Readers that have the file open fail once `progress` is dropped
+        if self.cache.remove_entry(&self.key, &self.path) {
+            let path = std::mem::take(&mut self.path);
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    warn!("failed to remove basebackup cache file {path}: {e}");
+                }
+            });
+        }
+    }
+}
+
+impl AsyncWrite for TarballWriter<'_> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        // Accepting a write means that the previous ones are in the file
+        let n = ready!(Pin::new(&mut self.file).poll_write(cx, buf))?;
+        let written = self.written;
+        self.progress.send_replace(Progress::Writing(written));
+        self.written += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        ready!(Pin::new(&mut self.file).poll_flush(cx))?;
+        let written = self.written;
+        self.progress.send_replace(Progress::Writing(written));
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+///
+/// A tarball in the cache, which may still be being written.
+///
+pub(crate) struct CachedTarball {
+    file: tokio::fs::File,
+    progress: watch::Receiver<Progress>,
+}
+
+impl CachedTarball {
+    /// Copy the whole tarball to `writer`, waiting for the rest of it to be
+    /// written as needed.
+    pub(crate) async fn copy_to<W>(mut self, writer: &mut W) -> anyhow::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut sent = 0;
+        loop {
+            let progress = *self.progress.borrow_and_update();
+            let (available, done) = match progress {
+                Progress::Writing(size) => (size, false),
+                Progress::Done(size) => (size, true),
+            };
+            if sent < available {
+                let mut chunk = (&mut self.file).take(available - sent);
+                let n = tokio::io::copy(&mut chunk, writer).await?;
+                anyhow::ensure!(n > 0, "basebackup cache file is truncated");
+                sent += n;
+            } else if done {
+                return Ok(());
+            } else if self.progress.changed().await.is_err() {
+                anyhow::bail!("generating the basebackup failed");
+            }
+        }
+    }
+}
+
+async fn remove_file(path: &Utf8Path) {
+    // Readers that have the file open can still finish reading it
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        warn!("failed to remove basebackup cache file {path}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use utils::id::TenantId;
+
+    fn key(lsn: Option<Lsn>) -> BasebackupCacheKey {
+        BasebackupCacheKey {
+            tenant_shard_id: TenantShardId::unsharded(
+                TenantId::from_str("11000000000000000000000000000000").unwrap(),
+            ),
+            timeline_id: TimelineId::from_str("22000000000000000000000000000000").unwrap(),
+            lsn,
+            compression: BasebackupCompression::None,
+        }
+    }
+
+    async fn start(
+        cache: &BasebackupCache,
+        key: BasebackupCacheKey,
+        lsn: Lsn,
+    ) -> (TarballWriter<'_>, CachedTarball) {
+        match cache.get_or_start(&key, lsn).await.unwrap() {
+            Lookup::Miss(writer, tarball) => (writer, tarball),
+            Lookup::Hit(_) => panic!("unexpected hit"),
+        }
+    }
+
+    async fn put(cache: &BasebackupCache, key: BasebackupCacheKey, lsn: Lsn, content: &[u8]) {
+        let (mut writer, _) = start(cache, key, lsn).await;
+        writer.write_all(content).await.unwrap();
+        writer.finish(true).await.unwrap();
+    }
+
+    async fn read(cache: &BasebackupCache, key: BasebackupCacheKey, lsn: Lsn) -> Option<Vec<u8>> {
+        let tarball = match cache.get_or_start(&key, lsn).await.unwrap() {
+            Lookup::Hit(tarball) => tarball,
+            // Drops the new entry again
+            Lookup::Miss(..) => return None,
+        };
+        let mut buf = Vec::new();
+        tarball.copy_to(&mut buf).await.unwrap();
+        Some(buf)
+    }
+
+    /// Wait for the files of dropped [`TarballWriter`]s to be removed in the
+    /// background.
+    async fn wait_for_file_count(dir: &Utf8Path, count: usize) {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while std::fs::read_dir(dir).unwrap().count() != count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalidation_and_eviction() {
+        let temp_dir = camino_tempfile::tempdir().unwrap();
+        let cache = BasebackupCache::new(temp_dir.path().to_owned(), 4);
+        let (a, b, c) = (key(None), key(Some(Lsn(0x10))), key(Some(Lsn(0x20))));
+
+        put(&cache, a, Lsn(0x100), b"aa").await;
+        assert_eq!(
+            read(&cache, a, Lsn(0x100)).await.as_deref(),
+            Some(&b"aa"[..])
+        );
+
+        // The timeline advanced: the entry is gone
+        assert_eq!(read(&cache, a, Lsn(0x200)).await, None);
+        assert_eq!(read(&cache, a, Lsn(0x100)).await, None);
+
+        put(&cache, a, Lsn(0x200), b"aa").await;
+        put(&cache, b, Lsn(0x200), b"bb").await;
+        // Make 'b' the least recently used one
+        assert!(read(&cache, a, Lsn(0x200)).await.is_some());
+        put(&cache, c, Lsn(0x200), b"c").await;
+
+        // 'c' doesn't fit next to the others
+        assert_eq!(read(&cache, b, Lsn(0x200)).await, None);
+        assert_eq!(
+            read(&cache, a, Lsn(0x200)).await.as_deref(),
+            Some(&b"aa"[..])
+        );
+        assert_eq!(
+            read(&cache, c, Lsn(0x200)).await.as_deref(),
+            Some(&b"c"[..])
+        );
+
+        // A tarball larger than the whole cache is not kept
+        put(&cache, b, Lsn(0x200), b"bbbbb").await;
+        assert_eq!(read(&cache, b, Lsn(0x200)).await, None);
+
+        // The files of evicted and stale entries are removed
+        wait_for_file_count(temp_dir.path(), 2).await;
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_the_tarball() {
+        let temp_dir = camino_tempfile::tempdir().unwrap();
+        let cache = BasebackupCache::new(temp_dir.path().to_owned(), 1024);
+        let a = key(None);
+
+        let (mut writer, first) = start(&cache, a, Lsn(0x100)).await;
+        let Lookup::Hit(second) = cache.get_or_start(&a, Lsn(0x100)).await.unwrap() else {
+            panic!("expected the entry that is being written");
+        };
+        let readers: Vec<_> = [first, second]
+            .into_iter()
+            .map(|tarball| {
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    tarball.copy_to(&mut buf).await.map(|()| buf)
+                })
+            })
+            .collect();
+
+        writer.write_all(b"foo").await.unwrap();
+        writer.flush().await.unwrap();
+        tokio::task::yield_now().await;
+        writer.write_all(b"bar").await.unwrap();
+        writer.finish(true).await.unwrap();
+
+        for reader in readers {
+            assert_eq!(reader.await.unwrap().unwrap(), b"foobar");
+        }
+        assert_eq!(
+            read(&cache, a, Lsn(0x100)).await.as_deref(),
+            Some(&b"foobar"[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_generation() {
+        let temp_dir = camino_tempfile::tempdir().unwrap();
+        let cache = BasebackupCache::new(temp_dir.path().to_owned(), 1024);
+        let a = key(None);
+
+        let (mut writer, tarball) = start(&cache, a, Lsn(0x100)).await;
+        writer.write_all(b"foo").await.unwrap();
+        drop(writer);
+
+        let mut buf = Vec::new();
+        assert!(tarball.copy_to(&mut buf).await.is_err());
+        assert_eq!(read(&cache, a, Lsn(0x100)).await, None);
+
+        // The file is removed in the background
+        wait_for_file_count(temp_dir.path(), 0).await;
+    }
+
+    #[tokio::test]
+    async fn timeline_and_tenant_removal() {
+        let temp_dir = camino_tempfile::tempdir().unwrap();
+        let cache = BasebackupCache::new(temp_dir.path().to_owned(), 1024);
+        let a = key(None);
+        let other_timeline = BasebackupCacheKey {
+            timeline_id: TimelineId::from_str("33000000000000000000000000000000").unwrap(),
+            ..a
+        };
+        let other_tenant = BasebackupCacheKey {
+            tenant_shard_id: TenantShardId::unsharded(
+                TenantId::from_str("44000000000000000000000000000000").unwrap(),
+            ),
+            ..a
+        };
+
+        put(&cache, a, Lsn(0x100), b"a").await;
+        put(&cache, other_timeline, Lsn(0x100), b"b").await;
+        put(&cache, other_tenant, Lsn(0x100), b"c").await;
+        // Still being written: the reader finishes reading it
+        let (mut writer, tarball) = start(&cache, key(Some(Lsn(0x10))), Lsn(0x100)).await;
+        writer.write_all(b"foo").await.unwrap();
+
+        cache
+            .remove_timeline(a.tenant_shard_id, a.timeline_id)
+            .await;
+        assert_eq!(read(&cache, a, Lsn(0x100)).await, None);
+        assert!(read(&cache, other_timeline, Lsn(0x100)).await.is_some());
+
+        writer.finish(true).await.unwrap();
+        let mut buf = Vec::new();
+        tarball.copy_to(&mut buf).await.unwrap();
+        assert_eq!(buf, b"foo");
+
+        cache.remove_tenant(a.tenant_shard_id).await;
+        assert_eq!(read(&cache, other_timeline, Lsn(0x100)).await, None);
+        assert_eq!(
+            read(&cache, other_tenant, Lsn(0x100)).await.as_deref(),
+            Some(&b"c"[..])
+        );
+        wait_for_file_count(temp_dir.path(), 1).await;
+    }
+}
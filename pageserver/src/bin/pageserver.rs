@@ -21,6 +21,7 @@ use tracing::*;
 
 use metrics::set_build_info_metric;
 use pageserver::{
+    basebackup_cache,
     config::{defaults::*, PageServerConf},
     context::{DownloadBehavior, RequestContext},
     deletion_queue::DeletionQueue,
@@ -129,7 +130,10 @@ fn main() -> anyhow::Result<()> {
 
     // Basic initialization of things that don't change after startup
     virtual_file::init(conf.max_file_descriptors, conf.virtual_file_io_engine);
+    virtual_file::init_io_scheduler(conf.io_scheduler.clone());
+    virtual_file::init_io_mode(conf.virtual_file_io_mode);
     page_cache::init(conf.page_cache_size);
+    basebackup_cache::init(conf).context("Failed to initialize basebackup cache")?;
 
     start_pageserver(launch_ts, conf).context("Failed to start pageserver")?;
 
@@ -45,6 +45,7 @@ use crate::{
 use self::defaults::DEFAULT_CONCURRENT_TENANT_WARMUP;
 
 use self::defaults::DEFAULT_VIRTUAL_FILE_IO_ENGINE;
+use self::defaults::DEFAULT_VIRTUAL_FILE_IO_MODE;
 
 pub mod defaults {
     use crate::tenant::config::defaults::*;
@@ -58,6 +59,8 @@ pub mod defaults {
 
     pub const DEFAULT_WAIT_LSN_TIMEOUT: &str = "60 s";
     pub const DEFAULT_WAL_REDO_TIMEOUT: &str = "60 s";
+    pub const DEFAULT_WAL_REDO_PROCESS_POOL_SIZE: usize = 1;
+    pub const DEFAULT_BASEBACKUP_CACHE_MAX_SIZE: u64 = 0;
 
     pub const DEFAULT_SUPERUSER: &str = "cloud_admin";
 
@@ -84,6 +87,8 @@ pub mod defaults {
 
     pub const DEFAULT_VIRTUAL_FILE_IO_ENGINE: &str = "std-fs";
 
+    pub const DEFAULT_VIRTUAL_FILE_IO_MODE: &str = "buffered";
+
     ///
     /// Default built-in configuration file.
     ///
@@ -95,8 +100,10 @@ pub mod defaults {
 
 #wait_lsn_timeout = '{DEFAULT_WAIT_LSN_TIMEOUT}'
 #wal_redo_timeout = '{DEFAULT_WAL_REDO_TIMEOUT}'
+#wal_redo_process_pool_size = {DEFAULT_WAL_REDO_PROCESS_POOL_SIZE}
 
 #page_cache_size = {DEFAULT_PAGE_CACHE_SIZE}
+#basebackup_cache_max_size = {DEFAULT_BASEBACKUP_CACHE_MAX_SIZE}
 #max_file_descriptors = {DEFAULT_MAX_FILE_DESCRIPTORS}
 
 # initial superuser role name to use when creating a new tenant
@@ -120,6 +127,8 @@ pub mod defaults {
 #ingest_batch_size = {DEFAULT_INGEST_BATCH_SIZE}
 
 #virtual_file_io_engine = '{DEFAULT_VIRTUAL_FILE_IO_ENGINE}'
+#virtual_file_io_mode = '{DEFAULT_VIRTUAL_FILE_IO_MODE}'
+#io_scheduler = {{ bandwidth = .., iops = .. }}
 
 [tenant_config]
 #checkpoint_distance = {DEFAULT_CHECKPOINT_DISTANCE} # in bytes
@@ -134,6 +143,8 @@ pub mod defaults {
 #pitr_interval = '{DEFAULT_PITR_INTERVAL}'
 
 #min_resident_size_override = .. # in bytes
+#resident_size_quota = .. # in bytes
+#write_throttle = .. # amounts in bytes
 #evictions_low_residence_duration_metric_threshold = '{DEFAULT_EVICTIONS_LOW_RESIDENCE_DURATION_METRIC_THRESHOLD}'
 #gc_feedback = false
 
@@ -164,10 +175,20 @@ pub struct PageServerConf {
     pub wait_lsn_timeout: Duration,
     // How long to wait for WAL redo to complete.
     pub wal_redo_timeout: Duration,
+    /// Replay the most common Postgres WAL records in the pageserver itself, rather
+    /// than in the walredo process.
+    pub native_wal_redo: bool,
+    /// Maximum number of walredo processes per tenant shard. More processes are
+    /// launched when all the existing ones are busy, and idle ones are shut down
+    /// again after a while.
+    pub wal_redo_process_pool_size: NonZeroUsize,
 
     pub superuser: String,
 
     pub page_cache_size: usize,
+    /// Maximum total size in bytes of the tarballs in the on-disk basebackup
+    /// cache. Zero disables the cache.
+    pub basebackup_cache_max_size: u64,
     pub max_file_descriptors: usize,
 
     // Repository directory, relative to current working directory.
@@ -256,6 +277,12 @@ pub struct PageServerConf {
     pub ingest_batch_size: u64,
 
     pub virtual_file_io_engine: virtual_file::IoEngineKind,
+
+    /// Whether layer files are read with O_DIRECT, bypassing the kernel page cache.
+    pub virtual_file_io_mode: virtual_file::IoMode,
+
+    /// Bandwidth and IOPS budgets for VirtualFile I/O, which foreground reads get first.
+    pub io_scheduler: virtual_file::IoSchedulerConfig,
 }
 
 /// We do not want to store this in a PageServerConf because the latter may be logged
@@ -292,10 +319,13 @@ struct PageServerConfigBuilder {
 
     wait_lsn_timeout: BuilderValue<Duration>,
     wal_redo_timeout: BuilderValue<Duration>,
+    native_wal_redo: BuilderValue<bool>,
+    wal_redo_process_pool_size: BuilderValue<NonZeroUsize>,
 
     superuser: BuilderValue<String>,
 
     page_cache_size: BuilderValue<usize>,
+    basebackup_cache_max_size: BuilderValue<u64>,
     max_file_descriptors: BuilderValue<usize>,
 
     workdir: BuilderValue<Utf8PathBuf>,
@@ -342,6 +372,10 @@ struct PageServerConfigBuilder {
     ingest_batch_size: BuilderValue<u64>,
 
     virtual_file_io_engine: BuilderValue<virtual_file::IoEngineKind>,
+
+    virtual_file_io_mode: BuilderValue<virtual_file::IoMode>,
+
+    io_scheduler: BuilderValue<virtual_file::IoSchedulerConfig>,
 }
 
 impl Default for PageServerConfigBuilder {
@@ -356,8 +390,12 @@ impl Default for PageServerConfigBuilder {
                 .expect("cannot parse default wait lsn timeout")),
             wal_redo_timeout: Set(humantime::parse_duration(DEFAULT_WAL_REDO_TIMEOUT)
                 .expect("cannot parse default wal redo timeout")),
+            native_wal_redo: Set(false),
+            wal_redo_process_pool_size: Set(NonZeroUsize::new(DEFAULT_WAL_REDO_PROCESS_POOL_SIZE)
+                .expect("Invalid default constant")),
             superuser: Set(DEFAULT_SUPERUSER.to_string()),
             page_cache_size: Set(DEFAULT_PAGE_CACHE_SIZE),
+            basebackup_cache_max_size: Set(DEFAULT_BASEBACKUP_CACHE_MAX_SIZE),
             max_file_descriptors: Set(DEFAULT_MAX_FILE_DESCRIPTORS),
             workdir: Set(Utf8PathBuf::new()),
             pg_distrib_dir: Set(Utf8PathBuf::from_path_buf(
@@ -419,6 +457,10 @@ impl Default for PageServerConfigBuilder {
             ingest_batch_size: Set(DEFAULT_INGEST_BATCH_SIZE),
 
             virtual_file_io_engine: Set(DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap()),
+
+            virtual_file_io_mode: Set(DEFAULT_VIRTUAL_FILE_IO_MODE.parse().unwrap()),
+
+            io_scheduler: Set(virtual_file::IoSchedulerConfig::default()),
         }
     }
 }
@@ -444,6 +486,14 @@ impl PageServerConfigBuilder {
         self.wal_redo_timeout = BuilderValue::Set(wal_redo_timeout)
     }
 
+    pub fn native_wal_redo(&mut self, enabled: bool) {
+        self.native_wal_redo = BuilderValue::Set(enabled)
+    }
+
+    pub fn wal_redo_process_pool_size(&mut self, size: NonZeroUsize) {
+        self.wal_redo_process_pool_size = BuilderValue::Set(size)
+    }
+
     pub fn superuser(&mut self, superuser: String) {
         self.superuser = BuilderValue::Set(superuser)
     }
@@ -452,6 +502,10 @@ impl PageServerConfigBuilder {
         self.page_cache_size = BuilderValue::Set(page_cache_size)
     }
 
+    pub fn basebackup_cache_max_size(&mut self, max_size: u64) {
+        self.basebackup_cache_max_size = BuilderValue::Set(max_size)
+    }
+
     pub fn max_file_descriptors(&mut self, max_file_descriptors: usize) {
         self.max_file_descriptors = BuilderValue::Set(max_file_descriptors)
     }
@@ -579,6 +633,14 @@ impl PageServerConfigBuilder {
         self.virtual_file_io_engine = BuilderValue::Set(value);
     }
 
+    pub fn virtual_file_io_mode(&mut self, value: virtual_file::IoMode) {
+        self.virtual_file_io_mode = BuilderValue::Set(value);
+    }
+
+    pub fn io_scheduler(&mut self, value: virtual_file::IoSchedulerConfig) {
+        self.io_scheduler = BuilderValue::Set(value);
+    }
+
     pub fn build(self) -> anyhow::Result<PageServerConf> {
         let concurrent_tenant_warmup = self
             .concurrent_tenant_warmup
@@ -604,10 +666,19 @@ impl PageServerConfigBuilder {
             wal_redo_timeout: self
                 .wal_redo_timeout
                 .ok_or(anyhow!("missing wal_redo_timeout"))?,
+            native_wal_redo: self
+                .native_wal_redo
+                .ok_or(anyhow!("missing native_wal_redo"))?,
+            wal_redo_process_pool_size: self
+                .wal_redo_process_pool_size
+                .ok_or(anyhow!("missing wal_redo_process_pool_size"))?,
             superuser: self.superuser.ok_or(anyhow!("missing superuser"))?,
             page_cache_size: self
                 .page_cache_size
                 .ok_or(anyhow!("missing page_cache_size"))?,
+            basebackup_cache_max_size: self
+                .basebackup_cache_max_size
+                .ok_or(anyhow!("missing basebackup_cache_max_size"))?,
             max_file_descriptors: self
                 .max_file_descriptors
                 .ok_or(anyhow!("missing max_file_descriptors"))?,
@@ -689,6 +760,10 @@ impl PageServerConfigBuilder {
             virtual_file_io_engine: self
                 .virtual_file_io_engine
                 .ok_or(anyhow!("missing virtual_file_io_engine"))?,
+            virtual_file_io_mode: self
+                .virtual_file_io_mode
+                .ok_or(anyhow!("missing virtual_file_io_mode"))?,
+            io_scheduler: self.io_scheduler.ok_or(anyhow!("missing io_scheduler"))?,
         })
     }
 }
@@ -706,6 +781,10 @@ impl PageServerConf {
         self.workdir.join("deletion")
     }
 
+    pub fn basebackup_cache_path(&self) -> Utf8PathBuf {
+        self.workdir.join("basebackup_cache")
+    }
+
     pub fn deletion_list_path(&self, sequence: u64) -> Utf8PathBuf {
         // Encode a version in the filename, so that if we ever switch away from JSON we can
         // increment this.
@@ -861,8 +940,16 @@ impl PageServerConf {
                 "availability_zone" => builder.availability_zone(Some(parse_toml_string(key, item)?)),
                 "wait_lsn_timeout" => builder.wait_lsn_timeout(parse_toml_duration(key, item)?),
                 "wal_redo_timeout" => builder.wal_redo_timeout(parse_toml_duration(key, item)?),
+                "native_wal_redo" => builder.native_wal_redo(parse_toml_bool(key, item)?),
+                "wal_redo_process_pool_size" => builder.wal_redo_process_pool_size(
+                    NonZeroUsize::new(parse_toml_u64(key, item)? as usize)
+                        .context("wal_redo_process_pool_size must be at least 1")?
+                ),
                 "initial_superuser_name" => builder.superuser(parse_toml_string(key, item)?),
                 "page_cache_size" => builder.page_cache_size(parse_toml_u64(key, item)? as usize),
+                "basebackup_cache_max_size" => {
+                    builder.basebackup_cache_max_size(parse_toml_u64(key, item)?)
+                }
                 "max_file_descriptors" => {
                     builder.max_file_descriptors(parse_toml_u64(key, item)? as usize)
                 }
@@ -943,6 +1030,15 @@ impl PageServerConf {
                 "virtual_file_io_engine" => {
                     builder.virtual_file_io_engine(parse_toml_from_str("virtual_file_io_engine", item)?)
                 }
+                "virtual_file_io_mode" => {
+                    builder.virtual_file_io_mode(parse_toml_from_str("virtual_file_io_mode", item)?)
+                }
+                "io_scheduler" => {
+                    builder.io_scheduler(
+                        deserialize_from_item("io_scheduler", item)
+                            .context("parse io_scheduler")?
+                    )
+                }
                 _ => bail!("unrecognized pageserver option '{key}'"),
             }
         }
@@ -979,7 +1075,13 @@ impl PageServerConf {
             id: NodeId(0),
             wait_lsn_timeout: Duration::from_secs(60),
             wal_redo_timeout: Duration::from_secs(60),
+            native_wal_redo: false,
+            wal_redo_process_pool_size: NonZeroUsize::new(
+                defaults::DEFAULT_WAL_REDO_PROCESS_POOL_SIZE,
+            )
+            .unwrap(),
             page_cache_size: defaults::DEFAULT_PAGE_CACHE_SIZE,
+            basebackup_cache_max_size: defaults::DEFAULT_BASEBACKUP_CACHE_MAX_SIZE,
             max_file_descriptors: defaults::DEFAULT_MAX_FILE_DESCRIPTORS,
             listen_pg_addr: defaults::DEFAULT_PG_LISTEN_ADDR.to_string(),
             listen_http_addr: defaults::DEFAULT_HTTP_LISTEN_ADDR.to_string(),
@@ -1017,6 +1119,8 @@ impl PageServerConf {
             secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
             ingest_batch_size: defaults::DEFAULT_INGEST_BATCH_SIZE,
             virtual_file_io_engine: DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap(),
+            virtual_file_io_mode: DEFAULT_VIRTUAL_FILE_IO_MODE.parse().unwrap(),
+            io_scheduler: virtual_file::IoSchedulerConfig::default(),
         }
     }
 }
@@ -1167,6 +1271,7 @@ wait_lsn_timeout = '111 s'
 wal_redo_timeout = '111 s'
 
 page_cache_size = 444
+basebackup_cache_max_size = 16777216
 max_file_descriptors = 333
 
 # initial superuser role name to use when creating a new tenant
@@ -1206,8 +1311,14 @@ background_task_maximum_delay = '334 s'
                 availability_zone: None,
                 wait_lsn_timeout: humantime::parse_duration(defaults::DEFAULT_WAIT_LSN_TIMEOUT)?,
                 wal_redo_timeout: humantime::parse_duration(defaults::DEFAULT_WAL_REDO_TIMEOUT)?,
+                native_wal_redo: false,
+                wal_redo_process_pool_size: NonZeroUsize::new(
+                    defaults::DEFAULT_WAL_REDO_PROCESS_POOL_SIZE
+                )
+                .unwrap(),
                 superuser: defaults::DEFAULT_SUPERUSER.to_string(),
                 page_cache_size: defaults::DEFAULT_PAGE_CACHE_SIZE,
+                basebackup_cache_max_size: defaults::DEFAULT_BASEBACKUP_CACHE_MAX_SIZE,
                 max_file_descriptors: defaults::DEFAULT_MAX_FILE_DESCRIPTORS,
                 workdir,
                 pg_distrib_dir,
@@ -1250,6 +1361,8 @@ background_task_maximum_delay = '334 s'
                 secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
                 ingest_batch_size: defaults::DEFAULT_INGEST_BATCH_SIZE,
                 virtual_file_io_engine: DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap(),
+                virtual_file_io_mode: DEFAULT_VIRTUAL_FILE_IO_MODE.parse().unwrap(),
+                io_scheduler: virtual_file::IoSchedulerConfig::default(),
             },
             "Correct defaults should be used when no config values are provided"
         );
@@ -1280,8 +1393,14 @@ background_task_maximum_delay = '334 s'
                 availability_zone: None,
                 wait_lsn_timeout: Duration::from_secs(111),
                 wal_redo_timeout: Duration::from_secs(111),
+                native_wal_redo: false,
+                wal_redo_process_pool_size: NonZeroUsize::new(
+                    defaults::DEFAULT_WAL_REDO_PROCESS_POOL_SIZE
+                )
+                .unwrap(),
                 superuser: "zzzz".to_string(),
                 page_cache_size: 444,
+                basebackup_cache_max_size: 16777216,
                 max_file_descriptors: 333,
                 workdir,
                 pg_distrib_dir,
@@ -1314,6 +1433,8 @@ background_task_maximum_delay = '334 s'
                 secondary_download_concurrency: defaults::DEFAULT_SECONDARY_DOWNLOAD_CONCURRENCY,
                 ingest_batch_size: 100,
                 virtual_file_io_engine: DEFAULT_VIRTUAL_FILE_IO_ENGINE.parse().unwrap(),
+                virtual_file_io_mode: DEFAULT_VIRTUAL_FILE_IO_MODE.parse().unwrap(),
+                io_scheduler: virtual_file::IoSchedulerConfig::default(),
             },
             "Should be able to parse all basic config values correctly"
         );
@@ -36,6 +36,13 @@
 //! during page reconstruction.
 //! An alternative default for all tenants can be specified in the `tenant_config` section of the config.
 //! Lastly, each tenant can have an override in their respective tenant config (`min_resident_size_override`).
+//!
+//! # Resident Size Quotas
+//!
+//! A tenant can also have a `resident_size_quota` in its tenant config. The least recently
+//! accessed layers of a tenant beyond its quota are evicted on every iteration, even without
+//! disk pressure. Under disk pressure, they are evicted before any other layers, so that a
+//! tenant over its quota does not push out the layers of other tenants.
 
 // Implementation notes:
 // - The `#[allow(dead_code)]` above various structs are to suppress warnings about only the Debug impl
@@ -64,7 +71,7 @@ use crate::{
         mgr::TenantManager,
         remote_timeline_client::LayerFileMetadata,
         secondary::SecondaryTenant,
-        storage_layer::{AsLayerDesc, EvictionError, Layer, LayerFileName},
+        storage_layer::{self, AsLayerDesc, EvictionError, Layer, LayerFileName},
         Timeline,
     },
 };
@@ -107,12 +114,38 @@ pub enum EvictionOrder {
         #[serde(default = "default_highest_layer_count_loses_first")]
         highest_layer_count_loses_first: bool,
     },
+
+    /// Order the layers to be evicted by the expected cost of downloading them again, per byte
+    /// freed by evicting them.
+    ///
+    /// The cost is how often a layer is accessed, going by its recent accesses, times how long it
+    /// takes to download it. A large layer that is read often can be cheaper to keep than many
+    /// small layers that are read rarely.
+    CostAware {
+        /// Latency of a layer download, regardless of the layer's size.
+        #[serde(
+            default = "default_cost_aware_download_latency",
+            with = "humantime_serde"
+        )]
+        download_latency: Duration,
+        /// Throughput of a layer download, in bytes per second.
+        #[serde(default = "default_cost_aware_download_bandwidth")]
+        download_bandwidth: u64,
+    },
 }
 
 fn default_highest_layer_count_loses_first() -> bool {
     true
 }
 
+fn default_cost_aware_download_latency() -> Duration {
+    Duration::from_millis(100)
+}
+
+fn default_cost_aware_download_bandwidth() -> u64 {
+    100 * 1024 * 1024
+}
+
 impl EvictionOrder {
     fn sort(&self, candidates: &mut [(MinResidentSizePartition, EvictionCandidate)]) {
         use EvictionOrder::*;
@@ -126,6 +159,40 @@ impl EvictionOrder {
             RelativeAccessed { .. } => candidates.sort_unstable_by_key(|(partition, candidate)| {
                 (*partition, candidate.relative_last_activity)
             }),
+            CostAware { .. } => candidates.sort_unstable_by_key(|(partition, candidate)| {
+                (
+                    *partition,
+                    candidate.eviction_cost,
+                    candidate.last_activity_ts,
+                )
+            }),
+        }
+    }
+
+    /// Called to fill in the [`EvictionCandidate::eviction_cost`] while iterating tenants layers.
+    fn eviction_cost(
+        &self,
+        candidate: &EvictionCandidate,
+        now: SystemTime,
+    ) -> finite_f32::FiniteF32 {
+        use EvictionOrder::*;
+
+        match self {
+            AbsoluteAccessed | RelativeAccessed { .. } => finite_f32::FiniteF32::ZERO,
+            CostAware {
+                download_latency,
+                download_bandwidth,
+            } => {
+                let access_frequency = candidate
+                    .layer
+                    .access_frequency(candidate.last_activity_ts, now);
+                redownload_cost(
+                    candidate.layer.get_file_size(),
+                    access_frequency,
+                    *download_latency,
+                    *download_bandwidth,
+                )
+            }
         }
     }
 
@@ -135,7 +202,7 @@ impl EvictionOrder {
         use EvictionOrder::*;
 
         match self {
-            AbsoluteAccessed => finite_f32::FiniteF32::ZERO,
+            AbsoluteAccessed | CostAware { .. } => finite_f32::FiniteF32::ZERO,
             RelativeAccessed {
                 highest_layer_count_loses_first,
             } => {
@@ -181,6 +248,23 @@ impl EvictionOrder {
     }
 }
 
+/// Seconds per second spent on downloading a layer again after evicting it, per byte freed.
+fn redownload_cost(
+    file_size: u64,
+    access_frequency: f32,
+    download_latency: Duration,
+    download_bandwidth: u64,
+) -> finite_f32::FiniteF32 {
+    let file_size = file_size.max(1) as f32;
+    let download_time =
+        download_latency.as_secs_f32() + file_size / download_bandwidth.max(1) as f32;
+    finite_f32::FiniteF32::try_from(access_frequency * download_time / file_size)
+        .unwrap_or_else(|val| {
+            tracing::warn!("calculated invalid eviction cost for size={file_size}, access_frequency={access_frequency}: {val}");
+            finite_f32::FiniteF32::ZERO
+        })
+}
+
 #[derive(Default)]
 pub struct State {
     /// Exclude http requests and background task from running at the same time.
@@ -329,8 +413,10 @@ async fn disk_usage_eviction_task_iteration(
                         // TODO: deltas between the three different usages would be helpful,
                         // consider MiB, GiB, TiB
                         warn!(?outcome, ?after, "disk usage still high");
-                    } else {
+                    } else if outcome.before.has_pressure() {
                         info!(?outcome, ?after, "disk usage pressure relieved");
+                    } else {
+                        info!(?outcome, ?after, "evicted layers over resident size quotas");
                     }
                 }
             }
@@ -400,15 +486,17 @@ pub(crate) async fn disk_usage_eviction_task_iteration_impl<U: Usage>(
 
     debug!(?usage_pre, "disk usage");
 
-    if !usage_pre.has_pressure() {
+    if usage_pre.has_pressure() {
+        warn!(
+            ?usage_pre,
+            "running disk usage based eviction due to pressure"
+        );
+    } else if any_tenant_over_resident_size_quota().await? {
+        info!("running disk usage based eviction for tenants over their resident size quota");
+    } else {
         return Ok(IterationOutcome::NoPressure);
     }
 
-    warn!(
-        ?usage_pre,
-        "running disk usage based eviction due to pressure"
-    );
-
     let candidates =
         match collect_eviction_candidates(tenant_manager, eviction_order, cancel).await? {
             EvictionCandidates::Cancelled => {
@@ -424,8 +512,9 @@ pub(crate) async fn disk_usage_eviction_task_iteration_impl<U: Usage>(
         let total_candidates = candidates.len();
         let size = candidate.layer.get_file_size();
         let rel = candidate.relative_last_activity;
+        let cost = candidate.eviction_cost;
         debug!(
-            "cand {nth}/{total_candidates}: size={size}, rel_last_activity={rel}, no_access_for={}us, partition={partition:?}, {}/{}/{}",
+            "cand {nth}/{total_candidates}: size={size}, rel_last_activity={rel}, cost={cost}, no_access_for={}us, partition={partition:?}, {}/{}/{}",
             now.duration_since(candidate.last_activity_ts)
                 .unwrap()
                 .as_micros(),
@@ -592,6 +681,14 @@ impl EvictionLayer {
         }
     }
 
+    /// Accesses per second of the layer. Secondary locations only know of the latest access.
+    fn access_frequency(&self, last_activity_ts: SystemTime, now: SystemTime) -> f32 {
+        match self {
+            Self::Attached(l) => l.access_stats().access_frequency(now),
+            Self::Secondary(_) => storage_layer::access_frequency(1, last_activity_ts, now),
+        }
+    }
+
     pub(crate) fn get_file_size(&self) -> u64 {
         match self {
             Self::Attached(l) => l.layer_desc().file_size,
@@ -605,6 +702,7 @@ pub(crate) struct EvictionCandidate {
     pub(crate) layer: EvictionLayer,
     pub(crate) last_activity_ts: SystemTime,
     pub(crate) relative_last_activity: finite_f32::FiniteF32,
+    pub(crate) eviction_cost: finite_f32::FiniteF32,
 }
 
 impl std::fmt::Display for EvictionLayer {
@@ -647,6 +745,9 @@ impl std::fmt::Debug for EvictionCandidate {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 enum MinResidentSizePartition {
+    /// Layers of a tenant that are beyond its `resident_size_quota`, evicted regardless of
+    /// disk pressure.
+    AboveQuota,
     Above,
     Below,
 }
@@ -758,6 +859,18 @@ enum EvictionCandidates {
 /// - tenant A 14 layers
 /// - tenant B 1 layer
 /// - tenant C 8 layers
+///
+/// # Example with EvictionOrder::CostAware
+///
+/// Within each partition, the layers with the lowest [`EvictionCandidate::eviction_cost`] are
+/// evicted first, and the least recently accessed among layers of equal cost. With the default
+/// download latency and bandwidth, a 256 MiB layer read once a minute costs about six times as
+/// much per byte to evict as a 1 MiB layer read once an hour, so the small layer goes first.
+///
+/// # Resident size quotas
+///
+/// With either order, the least recently accessed layers of a tenant that do not fit in its
+/// `resident_size_quota` go to the `AboveQuota` partition, which sorts before all others.
 async fn collect_eviction_candidates(
     tenant_manager: &Arc<TenantManager>,
     eviction_order: EvictionOrder,
@@ -772,6 +885,7 @@ async fn collect_eviction_candidates(
     // and the resulting data structure can be huge.
     // (https://github.com/neondatabase/neon/issues/6224)
     let mut candidates = Vec::new();
+    let now = SystemTime::now();
 
     for (tenant_id, _state, _gen) in tenants {
         if cancel.is_cancelled() {
@@ -842,8 +956,10 @@ async fn collect_eviction_candidates(
             max_layer_size
         };
 
+        let resident_size_quota = tenant.get_resident_size_quota();
+
         // Sort layers most-recently-used first, then partition by
-        // cumsum above/below min_resident_size.
+        // cumsum above/below min_resident_size, and above resident_size_quota.
         tenant_candidates
             .sort_unstable_by_key(|layer_info| std::cmp::Reverse(layer_info.last_activity_ts));
         let mut cumsum: i128 = 0;
@@ -859,13 +975,19 @@ async fn collect_eviction_candidates(
                     // be 1.0; this is for us to evict it last.
                     candidate.relative_last_activity =
                         eviction_order.relative_last_activity(total, i);
-
-                    let partition = if cumsum > min_resident_size as i128 {
+                    candidate.eviction_cost = eviction_order.eviction_cost(&candidate, now);
+
+                    let file_size = i128::from(candidate.layer.get_file_size());
+                    let partition = if resident_size_quota
+                        .is_some_and(|quota| cumsum + file_size > quota as i128)
+                    {
+                        MinResidentSizePartition::AboveQuota
+                    } else if cumsum > min_resident_size as i128 {
                         MinResidentSizePartition::Above
                     } else {
                         MinResidentSizePartition::Below
                     };
-                    cumsum += i128::from(candidate.layer.get_file_size());
+                    cumsum += file_size;
 
                     (partition, candidate)
                 });
@@ -909,6 +1031,7 @@ async fn collect_eviction_candidates(
                 .map(|(i, mut candidate)| {
                     candidate.relative_last_activity =
                         eviction_order.relative_last_activity(total_layers, i);
+                    candidate.eviction_cost = eviction_order.eviction_cost(&candidate, now);
                     (
                         // Secondary locations' layers are always considered above the min resident size,
                         // i.e. secondary locations are permitted to be trimmed to zero layers if all
@@ -925,14 +1048,49 @@ async fn collect_eviction_candidates(
 
     debug_assert!(MinResidentSizePartition::Above < MinResidentSizePartition::Below,
         "as explained in the function's doc comment, layers that aren't in the tenant's min_resident_size are evicted first");
+    debug_assert!(MinResidentSizePartition::AboveQuota < MinResidentSizePartition::Above,
+        "as explained in the function's doc comment, layers beyond the tenant's resident_size_quota are evicted first");
 
     eviction_order.sort(&mut candidates);
 
     Ok(EvictionCandidates::Finished(candidates))
 }
 
+/// Whether any attached tenant has more resident layers than its `resident_size_quota`.
+async fn any_tenant_over_resident_size_quota() -> anyhow::Result<bool> {
+    let tenants = tenant::mgr::list_tenants()
+        .await
+        .context("get list of tenants")?;
+
+    for (tenant_id, _state, _gen) in tenants {
+        let Ok(tenant) = tenant::mgr::get_tenant(tenant_id, true) else {
+            continue;
+        };
+        let Some(quota) = tenant.get_resident_size_quota() else {
+            continue;
+        };
+        let resident_size: u64 = tenant
+            .list_timelines()
+            .iter()
+            .map(|tl| tl.resident_physical_size())
+            .sum();
+        if resident_size > quota {
+            debug!(
+                tenant_id=%tenant_id.tenant_id,
+                shard_id=%tenant_id.shard_slug(),
+                resident_size,
+                quota,
+                "tenant is over its resident size quota"
+            );
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
 /// Given a pre-sorted vec of all layers in the system, select the first N which are enough to
-/// relieve pressure.
+/// relieve pressure, and at least all layers beyond the resident size quotas of their tenants.
 ///
 /// Returns the amount of candidates selected, with the planned usage.
 fn select_victims<U: Usage>(
@@ -944,7 +1102,7 @@ fn select_victims<U: Usage>(
     let mut evicted_amount = 0;
 
     for (i, (partition, candidate)) in candidates.iter().enumerate() {
-        if !usage_planned.has_pressure() {
+        if partition != &MinResidentSizePartition::AboveQuota && !usage_planned.has_pressure() {
             break;
         }
 
@@ -1246,4 +1404,135 @@ mod tests {
         assert_eq!(v.last(), Some(&0.1));
         assert!(v.windows(2).all(|slice| slice[0] > slice[1]));
     }
+
+    #[test]
+    fn cost_aware_defaults() {
+        let order: EvictionOrder =
+            serde_json::from_str(r#"{"type": "CostAware", "args": {}}"#).unwrap();
+        assert_eq!(
+            order,
+            EvictionOrder::CostAware {
+                download_latency: default_cost_aware_download_latency(),
+                download_bandwidth: default_cost_aware_download_bandwidth(),
+            }
+        );
+    }
+
+    #[test]
+    fn cost_aware_costs() {
+        let cost = |file_size: u64, accesses_per_hour: f32| {
+            redownload_cost(
+                file_size,
+                accesses_per_hour / 3600.0,
+                default_cost_aware_download_latency(),
+                default_cost_aware_download_bandwidth(),
+            )
+        };
+
+        assert_eq!(cost(1 << 20, 0.0), finite_f32::FiniteF32::ZERO);
+        // A large layer read once a minute is worth more than a small cold layer
+        assert!(cost(256 << 20, 60.0) > cost(1 << 20, 1.0));
+        // With the same access frequency, evicting the larger layer frees more per download
+        assert!(cost(256 << 20, 1.0) < cost(1 << 20, 1.0));
+        assert!(cost(1 << 20, 2.0) > cost(1 << 20, 1.0));
+    }
+
+    #[test]
+    fn cost_aware_sort_order() {
+        use pageserver_api::shard::{ShardIdentity, ShardIndex};
+        use utils::{generation::Generation, id::TenantId};
+
+        use crate::tenant::config::{SecondaryLocationConfig, TenantConfOpt};
+
+        let order = EvictionOrder::CostAware {
+            download_latency: default_cost_aware_download_latency(),
+            download_bandwidth: default_cost_aware_download_bandwidth(),
+        };
+        let now = SystemTime::now();
+
+        // Secondary layers count a single access since their last activity, which makes
+        // the access frequency easy to pick.
+        let secondary_tenant = SecondaryTenant::new(
+            TenantShardId::unsharded(TenantId::generate()),
+            ShardIdentity::unsharded(),
+            TenantConfOpt::default(),
+            &SecondaryLocationConfig { warm: false },
+        );
+        let name: LayerFileName = "000000000000000000000000000000000000-FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF__00000000016B59D8-00000000016B5A51".parse().unwrap();
+        let candidate = |file_size: u64, last_activity_ts: SystemTime| {
+            let mut candidate = EvictionCandidate {
+                layer: EvictionLayer::Secondary(EvictionSecondaryLayer {
+                    secondary_tenant: secondary_tenant.clone(),
+                    timeline_id: TimelineId::generate(),
+                    name: name.clone(),
+                    metadata: LayerFileMetadata::new(
+                        file_size,
+                        Generation::none(),
+                        ShardIndex::unsharded(),
+                    ),
+                }),
+                last_activity_ts,
+                relative_last_activity: finite_f32::FiniteF32::ZERO,
+                eviction_cost: finite_f32::FiniteF32::ZERO,
+            };
+            candidate.eviction_cost = order.eviction_cost(&candidate, now);
+            candidate
+        };
+
+        let hot_large = now - Duration::from_secs(1);
+        let cold_small = now - Duration::from_secs(3600);
+        let mut candidates = vec![
+            (
+                MinResidentSizePartition::Above,
+                candidate(256 << 20, hot_large),
+            ),
+            (
+                MinResidentSizePartition::Above,
+                candidate(1 << 20, cold_small),
+            ),
+            (
+                MinResidentSizePartition::Above,
+                candidate(2 << 20, cold_small),
+            ),
+            (
+                MinResidentSizePartition::Below,
+                candidate(1 << 20, cold_small),
+            ),
+            (
+                MinResidentSizePartition::AboveQuota,
+                candidate(256 << 20, hot_large),
+            ),
+        ];
+        order.sort(&mut candidates);
+
+        let sorted = candidates
+            .iter()
+            .map(|(partition, candidate)| (*partition, candidate.layer.get_file_size()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sorted,
+            vec![
+                // Layers above the tenant's quota go first, however costly
+                (MinResidentSizePartition::AboveQuota, 256 << 20),
+                // Then the cold small layers, cheapest per byte first, before the
+                // frequently read large one
+                (MinResidentSizePartition::Above, 2 << 20),
+                (MinResidentSizePartition::Above, 1 << 20),
+                (MinResidentSizePartition::Above, 256 << 20),
+                (MinResidentSizePartition::Below, 1 << 20),
+            ]
+        );
+    }
+
+    #[test]
+    fn access_frequency() {
+        let now = SystemTime::now();
+        let since = now - Duration::from_secs(16);
+        assert_eq!(storage_layer::access_frequency(16, since, now), 1.0);
+        assert_eq!(storage_layer::access_frequency(4, since, now), 0.25);
+        // Periods shorter than a second, or in the future, count as a second
+        assert_eq!(storage_layer::access_frequency(2, now, now), 2.0);
+        let future = now + Duration::from_secs(10);
+        assert_eq!(storage_layer::access_frequency(2, future, now), 2.0);
+    }
 }
@@ -24,7 +24,7 @@ use pageserver_api::models::TenantShardSplitResponse;
 use pageserver_api::models::TenantState;
 use pageserver_api::models::{
     DownloadRemoteLayersTaskSpawnRequest, LocationConfigMode, TenantAttachRequest,
-    TenantLoadRequest, TenantLocationConfigRequest,
+    TenantLoadRequest, TenantLocationConfigRequest, TimelineImportRequest,
 };
 use pageserver_api::shard::ShardCount;
 use pageserver_api::shard::TenantShardId;
@@ -42,7 +42,7 @@ use utils::http::request::{get_request_param, must_get_query_param, parse_query_
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::deletion_queue::DeletionQueueClient;
 use crate::metrics::{StorageTimeOperation, STORAGE_TIME_GLOBAL};
-use crate::pgdatadir_mapping::LsnForTimestamp;
+use crate::pgdatadir_mapping::{CalculateLogicalSizeError, LsnForTimestamp};
 use crate::task_mgr::TaskKind;
 use crate::tenant::config::{LocationConf, TenantConfOpt};
 use crate::tenant::mgr::GetActiveTenantError;
@@ -62,8 +62,8 @@ use crate::tenant::{LogicalSizeCalculationCause, PageReconstructError};
 use crate::{config::PageServerConf, tenant::mgr};
 use crate::{disk_usage_eviction_task, tenant};
 use pageserver_api::models::{
-    StatusResponse, TenantConfigRequest, TenantCreateRequest, TenantCreateResponse, TenantInfo,
-    TimelineCreateRequest, TimelineGcRequest, TimelineInfo,
+    AncestorStart, StatusResponse, TenantConfigRequest, TenantCreateRequest, TenantCreateResponse,
+    TenantInfo, TimelineCreateRequest, TimelineCreateResponse, TimelineGcRequest, TimelineInfo,
 };
 use utils::{
     auth::SwappableJwtAuth,
@@ -164,6 +164,7 @@ impl From<PageReconstructError> for ApiError {
             }
             PageReconstructError::AncestorLsnTimeout(e) => ApiError::Timeout(format!("{e}").into()),
             PageReconstructError::WalRedo(pre) => ApiError::InternalServerError(pre),
+            PageReconstructError::Corrupt(pre) => ApiError::InternalServerError(pre),
         }
     }
 }
@@ -303,6 +304,32 @@ impl From<crate::tenant::DeleteTimelineError> for ApiError {
     }
 }
 
+impl From<crate::tenant::timeline::detach_ancestor::Error> for ApiError {
+    fn from(value: crate::tenant::timeline::detach_ancestor::Error) -> Self {
+        use crate::tenant::timeline::detach_ancestor::Error::*;
+        match value {
+            e @ (NoAncestor | NoRemoteStorage) => {
+                ApiError::PreconditionFailed(e.to_string().into_boxed_str())
+            }
+            ShuttingDown => ApiError::ShuttingDown,
+            Other(e) => ApiError::InternalServerError(e),
+        }
+    }
+}
+
+impl From<crate::tenant::remote_import::Error> for ApiError {
+    fn from(value: crate::tenant::remote_import::Error) -> Self {
+        use crate::tenant::remote_import::Error::*;
+        match value {
+            e @ (NoRemoteStorage | Sharded) => {
+                ApiError::PreconditionFailed(e.to_string().into_boxed_str())
+            }
+            e @ InvalidPath(_) => ApiError::BadRequest(e.into()),
+            e @ (AlreadyExists | AlreadyInProgress) => ApiError::Conflict(e.to_string()),
+        }
+    }
+}
+
 impl From<crate::tenant::mgr::DeleteTimelineError> for ApiError {
     fn from(value: crate::tenant::mgr::DeleteTimelineError) -> Self {
         use crate::tenant::mgr::DeleteTimelineError::*;
@@ -484,7 +511,35 @@ async fn timeline_create_handler(
 
     let new_timeline_id = request_data.new_timeline_id;
 
-    let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Error);
+    let ancestor_start_timestamp = match request_data.ancestor_start_timestamp {
+        Some(_) if request_data.ancestor_timeline_id.is_none() => {
+            return Err(ApiError::BadRequest(anyhow!(
+                "ancestor_start_timestamp requires ancestor_timeline_id"
+            )));
+        }
+        Some(_) if request_data.ancestor_start_lsn.is_some() => {
+            return Err(ApiError::BadRequest(anyhow!(
+                "ancestor_start_lsn and ancestor_start_timestamp are mutually exclusive"
+            )));
+        }
+        Some(_) if !tenant_shard_id.is_zero() => {
+            // Requires SLRU contents, which are only stored on shard zero. Other shards
+            // must be given the LSN that shard zero resolved the timestamp to.
+            return Err(ApiError::BadRequest(anyhow!(
+                "ancestor_start_timestamp is only available on shard zero"
+            )));
+        }
+        Some(timestamp) => Some(postgres_ffi::to_pg_timestamp(timestamp)),
+        None => None,
+    };
+
+    // Resolving a timestamp reads commit records of the ancestor, which may have been evicted.
+    let download_behavior = if ancestor_start_timestamp.is_some() {
+        DownloadBehavior::Download
+    } else {
+        DownloadBehavior::Error
+    };
+    let ctx = RequestContext::new(TaskKind::MgmtRequest, download_behavior);
 
     let state = get_state(&request);
 
@@ -506,6 +561,7 @@ async fn timeline_create_handler(
                 new_timeline_id,
                 request_data.ancestor_timeline_id,
                 request_data.ancestor_start_lsn,
+                ancestor_start_timestamp,
                 request_data.pg_version.unwrap_or(crate::DEFAULT_PG_VERSION),
                 request_data.existing_initdb_timeline_id,
                 state.broker_client.clone(),
@@ -522,7 +578,29 @@ async fn timeline_create_handler(
                 )
                 .await
                 .map_err(ApiError::InternalServerError)?;
-                json_response(StatusCode::CREATED, timeline_info)
+
+                // Tell the caller which branch point the timestamp resolved to.
+                let ancestor_start = if ancestor_start_timestamp.is_some() {
+                    let lsn = new_timeline.get_ancestor_lsn();
+                    let commit_timestamp = new_timeline
+                        .get_timestamp_for_lsn(lsn, &ctx)
+                        .await?
+                        .map(postgres_ffi::from_pg_timestamp);
+                    Some(AncestorStart {
+                        lsn,
+                        commit_timestamp,
+                    })
+                } else {
+                    None
+                };
+
+                json_response(
+                    StatusCode::CREATED,
+                    TimelineCreateResponse {
+                        timeline_info,
+                        ancestor_start,
+                    },
+                )
             }
             Err(_) if tenant.cancel.is_cancelled() => {
                 // In case we get some ugly error type during shutdown, cast it into a clean 503.
@@ -645,6 +723,32 @@ async fn timeline_preserve_initdb_handler(
     json_response(StatusCode::OK, ())
 }
 
+async fn timeline_detach_ancestor_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
+
+    async {
+        let timeline = active_timeline_of_active_tenant(tenant_shard_id, timeline_id).await?;
+
+        timeline.detach_from_ancestor(&ctx).await?;
+
+        Ok::<_, ApiError>(())
+    }
+    .instrument(info_span!("timeline_detach_ancestor",
+                tenant_id = %tenant_shard_id.tenant_id,
+                shard_id = %tenant_shard_id.shard_slug(),
+                %timeline_id))
+    .await?;
+
+    json_response(StatusCode::OK, ())
+}
+
 async fn timeline_detail_handler(
     request: Request<Body>,
     _cancel: CancellationToken,
@@ -993,8 +1097,10 @@ async fn tenant_status(
 
         // Calculate total physical size of all timelines
         let mut current_physical_size = 0;
+        let mut resident_size = 0;
         for timeline in tenant.list_timelines().iter() {
             current_physical_size += timeline.layer_size_sum().await;
+            resident_size += timeline.resident_physical_size();
         }
 
         let state = tenant.current_state();
@@ -1008,6 +1114,8 @@ async fn tenant_status(
             },
             walredo: tenant.wal_redo_manager_status(),
             timelines: tenant.list_timeline_ids(),
+            resident_size,
+            resident_size_quota: tenant.get_resident_size_quota(),
         })
     }
     .instrument(info_span!("tenant_status_handler",
@@ -1690,6 +1798,44 @@ async fn timeline_download_remote_layers_handler_get(
     json_response(StatusCode::OK, info)
 }
 
+async fn timeline_import_handler_post(
+    mut request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    let body: TimelineImportRequest = json_request(&mut request).await?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let state = get_state(&request);
+    let tenant = state
+        .tenant_manager
+        .get_attached_tenant_shard(tenant_shard_id, false)?;
+    tenant.wait_to_become_active(ACTIVE_TENANT_TIMEOUT).await?;
+
+    let info =
+        tenant.spawn_import_timeline_from_remote(timeline_id, body, state.broker_client.clone())?;
+    json_response(StatusCode::ACCEPTED, info)
+}
+
+async fn timeline_import_handler_get(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+
+    let tenant = get_state(&request)
+        .tenant_manager
+        .get_attached_tenant_shard(tenant_shard_id, false)?;
+    let info = tenant
+        .get_timeline_import_task_info(timeline_id)
+        .context("no import of this timeline since pageserver process start")
+        .map_err(|e| ApiError::NotFound(e.into()))?;
+    json_response(StatusCode::OK, info)
+}
+
 async fn deletion_queue_flush(
     r: Request<Body>,
     cancel: CancellationToken,
@@ -1792,6 +1938,63 @@ async fn timeline_collect_keyspace(
     .await
 }
 
+async fn timeline_relation_sizes_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let lsn: Option<Lsn> = parse_query_param(&request, "lsn")?;
+
+    if !tenant_shard_id.is_zero() {
+        // Other shards don't have accurate relation sizes
+        return Err(ApiError::BadRequest(anyhow!(
+            "relation sizes are only available on shard zero"
+        )));
+    }
+
+    async {
+        let ctx = RequestContext::new(TaskKind::MgmtRequest, DownloadBehavior::Download);
+        let timeline = active_timeline_of_active_tenant(tenant_shard_id, timeline_id).await?;
+        if let Some(lsn) = lsn {
+            if lsn > timeline.get_last_record_lsn() {
+                return Err(ApiError::BadRequest(anyhow!(
+                    "LSN {lsn} is beyond the end of the timeline"
+                )));
+            }
+            timeline
+                .check_lsn_is_in_scope(lsn, &timeline.get_latest_gc_cutoff_lsn())
+                .map_err(ApiError::BadRequest)?;
+        }
+
+        let sizes = timeline
+            .get_relation_sizes(lsn, &ctx)
+            .await
+            .map_err(|e| match e {
+                CalculateLogicalSizeError::Cancelled => ApiError::ShuttingDown,
+                CalculateLogicalSizeError::Other(e) => ApiError::InternalServerError(e),
+            })?;
+
+        json_response(StatusCode::OK, &*sizes)
+    }
+    .instrument(info_span!("timeline_relation_sizes", tenant_id = %tenant_shard_id.tenant_id, shard_id = %tenant_shard_id.shard_slug(), %timeline_id))
+    .await
+}
+
+async fn timeline_key_range_stats_handler(
+    request: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_shard_id: TenantShardId = parse_request_param(&request, "tenant_shard_id")?;
+    let timeline_id: TimelineId = parse_request_param(&request, "timeline_id")?;
+    check_permission(&request, Some(tenant_shard_id.tenant_id))?;
+
+    let timeline = active_timeline_of_active_tenant(tenant_shard_id, timeline_id).await?;
+    json_response(StatusCode::OK, timeline.key_range_stats.snapshot())
+}
+
 async fn active_timeline_of_active_tenant(
     tenant_shard_id: TenantShardId,
     timeline_id: TimelineId,
@@ -1963,6 +2166,16 @@ async fn put_io_engine_handler(
     json_response(StatusCode::OK, ())
 }
 
+async fn put_io_mode_handler(
+    mut r: Request<Body>,
+    _cancel: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&r, None)?;
+    let mode: crate::virtual_file::IoMode = json_request(&mut r).await?;
+    crate::virtual_file::io_mode::set(mode);
+    json_response(StatusCode::OK, ())
+}
+
 /// Common functionality of all the HTTP API handlers.
 ///
 /// - Adds a tracing span to each request (by `request_span`)
@@ -2150,6 +2363,10 @@ pub fn make_router(
             "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/preserve_initdb_archive",
             |r| api_handler(r, timeline_preserve_initdb_handler),
         )
+        .post(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/detach_ancestor",
+            |r| api_handler(r, timeline_detach_ancestor_handler),
+        )
         .get("/v1/tenant/:tenant_shard_id/timeline/:timeline_id", |r| {
             api_handler(r, timeline_detail_handler)
         })
@@ -2181,9 +2398,25 @@ pub fn make_router(
             "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/download_remote_layers",
             |r| api_handler(r, timeline_download_remote_layers_handler_get),
         )
+        .post(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/import",
+            |r| api_handler(r, timeline_import_handler_post),
+        )
+        .get(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/import",
+            |r| api_handler(r, timeline_import_handler_get),
+        )
         .delete("/v1/tenant/:tenant_shard_id/timeline/:timeline_id", |r| {
             api_handler(r, timeline_delete_handler)
         })
+        .get(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/relation_sizes",
+            |r| api_handler(r, timeline_relation_sizes_handler),
+        )
+        .get(
+            "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/key_range_stats",
+            |r| api_handler(r, timeline_key_range_stats_handler),
+        )
         .get(
             "/v1/tenant/:tenant_shard_id/timeline/:timeline_id/layer",
             |r| api_handler(r, layer_map_info_handler),
@@ -2224,5 +2457,6 @@ pub fn make_router(
             |r| api_handler(r, timeline_collect_keyspace),
         )
         .put("/v1/io_engine", |r| api_handler(r, put_io_engine_handler))
+        .put("/v1/io_mode", |r| api_handler(r, put_io_mode_handler))
         .any(handler_404))
 }
@@ -587,6 +587,16 @@ async fn import_file(
             .put_twophase_file(xid, Bytes::copy_from_slice(&bytes[..]), ctx)
             .await?;
         debug!("imported twophase file");
+    } else if file_path.starts_with("pg_replslot") || file_path.starts_with("pg_logical") {
+        // Logical replication state. Keep it in the aux files keyspace, so that
+        // basebackups of this timeline include it, like they would if it had
+        // been written by the compute.
+        let path = file_path
+            .to_str()
+            .with_context(|| format!("non-UTF8 aux file path {}", file_path.display()))?;
+        let bytes = read_all_bytes(reader).await?;
+        modification.put_file(path, &bytes, ctx).await?;
+        debug!("imported aux file {}", path);
     } else if file_path.starts_with("pg_wal") {
         debug!("found wal file in base section. ignore it");
     } else if file_path.starts_with("zenith.signal") {
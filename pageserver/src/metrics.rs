@@ -466,6 +466,14 @@ pub(crate) static REMOTE_ONDEMAND_DOWNLOADED_BYTES: Lazy<IntCounter> = Lazy::new
     .unwrap()
 });
 
+pub(crate) static LAYER_CHECKSUM_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_layer_checksum_failures_total",
+        "Number of layer file reads that failed blob checksum verification",
+    )
+    .expect("failed to define a metric")
+});
+
 static CURRENT_LOGICAL_SIZE: Lazy<UIntGaugeVec> = Lazy::new(|| {
     register_uint_gauge_vec!(
         "pageserver_current_logical_size",
@@ -642,6 +650,15 @@ pub(crate) static TENANT_SYNTHETIC_SIZE_METRIC: Lazy<UIntGaugeVec> = Lazy::new(|
     .expect("Failed to register pageserver_tenant_synthetic_cached_size_bytes metric")
 });
 
+pub(crate) static TENANT_RESIDENT_SIZE_QUOTA: Lazy<UIntGaugeVec> = Lazy::new(|| {
+    register_uint_gauge_vec!(
+        "pageserver_tenant_resident_size_quota_bytes",
+        "Resident size quota of each tenant shard that has one, see pageserver_resident_physical_size for the usage",
+        &["tenant_id", "shard_id"]
+    )
+    .expect("failed to define a metric")
+});
+
 // Metrics for cloud upload. These metrics reflect data uploaded to cloud storage,
 // or in testing they estimate how much we would upload if we did.
 static NUM_PERSISTENT_FILES_CREATED: Lazy<IntCounterVec> = Lazy::new(|| {
@@ -681,6 +698,40 @@ static EVICTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
     .expect("failed to define a metric")
 });
 
+/// Why an image layer was or was not created for a key partition, see
+/// `Timeline::time_for_new_image_layer`.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    IntoStaticStr,
+    strum_macros::EnumCount,
+    strum_macros::EnumIter,
+    strum_macros::FromRepr,
+)]
+#[strum(serialize_all = "snake_case")]
+pub(crate) enum ImageLayerCreationDecision {
+    /// GC needs an image layer to remove the layers below it.
+    WantedByGc,
+    /// The partition has reached the image creation threshold.
+    Threshold,
+    /// The partition has reached the lowered threshold for read-hot partitions.
+    ReadHot,
+    /// The partition has reached the image creation threshold, but is not read.
+    SkippedCold,
+    /// The partition has too few deltas.
+    NotNeeded,
+}
+
+static IMAGE_LAYER_CREATION_DECISIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_image_layer_creation_decisions_total",
+        "Number of times compaction decided whether to create an image layer for a key partition, by the reason for the decision",
+        &["tenant_id", "shard_id", "timeline_id", "decision"]
+    )
+    .expect("failed to define a metric")
+});
+
 static EVICTIONS_WITH_LOW_RESIDENCE_DURATION: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
         "pageserver_evictions_with_low_residence_duration",
@@ -998,6 +1049,53 @@ pub(crate) mod virtual_file_descriptor_cache {
     // ```
 }
 
+pub(crate) mod virtual_file_io_scheduler {
+    use super::*;
+    use crate::virtual_file::io_scheduler::IoClass;
+
+    pub(crate) struct Metrics {
+        pub(crate) requests: [IntCounter; IoClass::COUNT],
+        pub(crate) bytes: [IntCounter; IoClass::COUNT],
+        pub(crate) queued: [IntGauge; IoClass::COUNT],
+        pub(crate) wait_time: [Histogram; IoClass::COUNT],
+    }
+
+    pub(crate) static METRICS: Lazy<Metrics> = Lazy::new(|| {
+        let requests = register_int_counter_vec!(
+            "pageserver_io_scheduler_requests_total",
+            "Number of VirtualFile reads and writes, by I/O class",
+            &["class"],
+        )
+        .unwrap();
+        let bytes = register_int_counter_vec!(
+            "pageserver_io_scheduler_bytes_total",
+            "Bytes requested by VirtualFile reads and writes, by I/O class",
+            &["class"],
+        )
+        .unwrap();
+        let queued = register_int_gauge_vec!(
+            "pageserver_io_scheduler_queued_requests",
+            "Number of VirtualFile reads and writes waiting for the I/O budget, by I/O class",
+            &["class"],
+        )
+        .unwrap();
+        let wait_time = register_histogram_vec!(
+            "pageserver_io_scheduler_wait_seconds",
+            "Time that VirtualFile reads and writes waited for the I/O budget, by I/O class",
+            &["class"],
+            STORAGE_IO_TIME_BUCKETS.into(),
+        )
+        .unwrap();
+        let class = |i| -> &'static str { IoClass::from_repr(i).unwrap().into() };
+        Metrics {
+            requests: std::array::from_fn(|i| requests.with_label_values(&[class(i)])),
+            bytes: std::array::from_fn(|i| bytes.with_label_values(&[class(i)])),
+            queued: std::array::from_fn(|i| queued.with_label_values(&[class(i)])),
+            wait_time: std::array::from_fn(|i| wait_time.with_label_values(&[class(i)])),
+        }
+    });
+}
+
 #[cfg(not(test))]
 pub(crate) mod virtual_file_io_engine {
     use super::*;
@@ -1010,6 +1108,15 @@ pub(crate) mod virtual_file_io_engine {
         )
         .unwrap()
     });
+
+    pub(crate) static MODE: Lazy<UIntGaugeVec> = Lazy::new(|| {
+        register_uint_gauge_vec!(
+            "pageserver_virtual_file_io_mode",
+            "The configured io mode for reads of layer files",
+            &["mode"],
+        )
+        .unwrap()
+    });
 }
 
 #[derive(Debug)]
@@ -1053,6 +1160,7 @@ pub enum SmgrQueryType {
     GetPageAtLsn,
     GetDbSize,
     GetSlruSegment,
+    GetPageBatch,
 }
 
 #[derive(Debug)]
@@ -1169,12 +1277,13 @@ mod smgr_query_time_tests {
     #[test]
     fn op_label_name() {
         use super::SmgrQueryType::*;
-        let expect: [(super::SmgrQueryType, &'static str); 5] = [
+        let expect: [(super::SmgrQueryType, &'static str); 6] = [
             (GetRelExists, "get_rel_exists"),
             (GetRelSize, "get_rel_size"),
             (GetPageAtLsn, "get_page_at_lsn"),
             (GetDbSize, "get_db_size"),
             (GetSlruSegment, "get_slru_segment"),
+            (GetPageBatch, "get_page_batch"),
         ];
         for (op, expect) in expect {
             let actual: &'static str = op.into();
@@ -1255,6 +1364,42 @@ impl DurationResultObserver for BasebackupQueryTime {
     }
 }
 
+static BASEBACKUP_CACHE_READ: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "pageserver_basebackup_cache_read_total",
+        "Number of basebackup requests looked up in the basebackup cache, by result",
+        &["result"]
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) struct BasebackupCacheMetrics {
+    pub(crate) hits: IntCounter,
+    pub(crate) misses: IntCounter,
+    pub(crate) entries: UIntGauge,
+    pub(crate) size: UIntGauge,
+}
+
+pub(crate) static BASEBACKUP_CACHE: Lazy<BasebackupCacheMetrics> =
+    Lazy::new(|| BasebackupCacheMetrics {
+        hits: BASEBACKUP_CACHE_READ
+            .get_metric_with_label_values(&["hit"])
+            .unwrap(),
+        misses: BASEBACKUP_CACHE_READ
+            .get_metric_with_label_values(&["miss"])
+            .unwrap(),
+        entries: register_uint_gauge!(
+            "pageserver_basebackup_cache_entries",
+            "Number of basebackup tarballs in the basebackup cache"
+        )
+        .expect("failed to define a metric"),
+        size: register_uint_gauge!(
+            "pageserver_basebackup_cache_size_bytes",
+            "Total size of the complete basebackup tarballs in the basebackup cache"
+        )
+        .expect("failed to define a metric"),
+    });
+
 pub(crate) static LIVE_CONNECTIONS_COUNT: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
         "pageserver_live_connections",
@@ -1660,6 +1805,30 @@ pub(crate) static WAL_REDO_RECORD_COUNTER: Lazy<IntCounter> = Lazy::new(|| {
     .unwrap()
 });
 
+pub(crate) static WAL_REDO_NATIVE_RECORD_COUNTER: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "pageserver_wal_redo_native_records_total",
+        "Number of Postgres WAL records replayed without the WAL redo process"
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static WAL_REDO_POOL_PROCESSES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pageserver_wal_redo_pool_processes",
+        "Number of WAL redo processes in the per-tenant process pools"
+    )
+    .expect("failed to define a metric")
+});
+
+pub(crate) static WAL_REDO_QUEUED_REQUESTS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pageserver_wal_redo_queued_requests",
+        "Number of WAL redo requests waiting for a busy WAL redo process"
+    )
+    .expect("failed to define a metric")
+});
+
 #[rustfmt::skip]
 pub(crate) static WAL_REDO_PROCESS_LAUNCH_DURATION_HISTOGRAM: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
@@ -1823,6 +1992,7 @@ pub(crate) struct TimelineMetrics {
     pub persistent_bytes_written: IntCounter,
     pub evictions: IntCounter,
     pub evictions_with_low_residence_duration: std::sync::RwLock<EvictionsWithLowResidenceDuration>,
+    image_layer_creation_decisions: [IntCounter; ImageLayerCreationDecision::COUNT],
 }
 
 impl TimelineMetrics {
@@ -1913,6 +2083,17 @@ impl TimelineMetrics {
             .unwrap();
         let evictions_with_low_residence_duration = evictions_with_low_residence_duration_builder
             .build(&tenant_id, &shard_id, &timeline_id);
+        let image_layer_creation_decisions = std::array::from_fn(|i| {
+            let decision = ImageLayerCreationDecision::from_repr(i).unwrap();
+            IMAGE_LAYER_CREATION_DECISIONS
+                .get_metric_with_label_values(&[
+                    &tenant_id,
+                    &shard_id,
+                    &timeline_id,
+                    decision.into(),
+                ])
+                .unwrap()
+        });
 
         TimelineMetrics {
             tenant_id,
@@ -1935,9 +2116,17 @@ impl TimelineMetrics {
             evictions_with_low_residence_duration: std::sync::RwLock::new(
                 evictions_with_low_residence_duration,
             ),
+            image_layer_creation_decisions,
         }
     }
 
+    pub(crate) fn record_image_layer_creation_decision(
+        &self,
+        decision: ImageLayerCreationDecision,
+    ) {
+        self.image_layer_creation_decisions[decision as usize].inc();
+    }
+
     pub(crate) fn record_new_file_metrics(&self, sz: u64) {
         self.resident_physical_size_add(sz);
         self.num_persistent_files_created.inc_by(1);
@@ -1978,6 +2167,14 @@ impl Drop for TimelineMetrics {
             NUM_PERSISTENT_FILES_CREATED.remove_label_values(&[tenant_id, &shard_id, timeline_id]);
         let _ = PERSISTENT_BYTES_WRITTEN.remove_label_values(&[tenant_id, &shard_id, timeline_id]);
         let _ = EVICTIONS.remove_label_values(&[tenant_id, &shard_id, timeline_id]);
+        for decision in ImageLayerCreationDecision::iter() {
+            let _ = IMAGE_LAYER_CREATION_DECISIONS.remove_label_values(&[
+                tenant_id,
+                shard_id,
+                timeline_id,
+                decision.into(),
+            ]);
+        }
 
         self.evictions_with_low_residence_duration
             .write()
@@ -2026,6 +2223,11 @@ pub(crate) fn remove_tenant_metrics(tenant_shard_id: &TenantShardId) {
         let _ = TENANT_SYNTHETIC_SIZE_METRIC.remove_label_values(&[&tid]);
     }
 
+    let _ = TENANT_RESIDENT_SIZE_QUOTA.remove_label_values(&[
+        &tenant_shard_id.tenant_id.to_string(),
+        &tenant_shard_id.shard_slug().to_string(),
+    ]);
+
     // we leave the BROKEN_TENANTS_SET entry if any
 }
 
@@ -2502,30 +2704,30 @@ pub(crate) mod tenant_throttling {
 
     use crate::tenant::{self, throttle::Metric};
 
-    pub(crate) struct TimelineGet {
-        wait_time: IntCounter,
-        count: IntCounter,
-    }
-
-    pub(crate) static TIMELINE_GET: Lazy<TimelineGet> = Lazy::new(|| {
-        static WAIT_USECS: Lazy<metrics::IntCounterVec> = Lazy::new(|| {
-            register_int_counter_vec!(
+    static WAIT_USECS: Lazy<metrics::IntCounterVec> = Lazy::new(|| {
+        register_int_counter_vec!(
             "pageserver_tenant_throttling_wait_usecs_sum_global",
             "Sum of microseconds that tenants spent waiting for a tenant throttle of a given kind.",
             &["kind"]
         )
-            .unwrap()
-        });
+        .unwrap()
+    });
 
-        static WAIT_COUNT: Lazy<metrics::IntCounterVec> = Lazy::new(|| {
-            register_int_counter_vec!(
-                "pageserver_tenant_throttling_count_global",
-                "Count of tenant throttlings, by kind of throttle.",
-                &["kind"]
-            )
-            .unwrap()
-        });
+    static WAIT_COUNT: Lazy<metrics::IntCounterVec> = Lazy::new(|| {
+        register_int_counter_vec!(
+            "pageserver_tenant_throttling_count_global",
+            "Count of tenant throttlings, by kind of throttle.",
+            &["kind"]
+        )
+        .unwrap()
+    });
+
+    pub(crate) struct TimelineGet {
+        wait_time: IntCounter,
+        count: IntCounter,
+    }
 
+    pub(crate) static TIMELINE_GET: Lazy<TimelineGet> = Lazy::new(|| {
         let kind = "timeline_get";
         TimelineGet {
             wait_time: WAIT_USECS.with_label_values(&[kind]),
@@ -2535,15 +2737,42 @@ pub(crate) mod tenant_throttling {
 
     impl Metric for &'static TimelineGet {
         #[inline(always)]
-        fn observe_throttling(
-            &self,
-            tenant::throttle::Observation { wait_time }: &tenant::throttle::Observation,
-        ) {
-            let val = u64::try_from(wait_time.as_micros()).unwrap();
-            self.wait_time.inc_by(val);
-            self.count.inc();
+        fn observe_throttling(&self, observation: &tenant::throttle::Observation) {
+            observe(&self.wait_time, &self.count, observation);
         }
     }
+
+    /// Throttling of the bytes written by WAL ingest, layer flushes and compaction.
+    pub(crate) struct Write {
+        wait_time: IntCounter,
+        count: IntCounter,
+    }
+
+    pub(crate) static WRITE: Lazy<Write> = Lazy::new(|| {
+        let kind = "write";
+        Write {
+            wait_time: WAIT_USECS.with_label_values(&[kind]),
+            count: WAIT_COUNT.with_label_values(&[kind]),
+        }
+    });
+
+    impl Metric for &'static Write {
+        #[inline(always)]
+        fn observe_throttling(&self, observation: &tenant::throttle::Observation) {
+            observe(&self.wait_time, &self.count, observation);
+        }
+    }
+
+    #[inline(always)]
+    fn observe(
+        wait_time_usecs: &IntCounter,
+        count: &IntCounter,
+        tenant::throttle::Observation { wait_time }: &tenant::throttle::Observation,
+    ) {
+        let val = u64::try_from(wait_time.as_micros()).unwrap();
+        wait_time_usecs.inc_by(val);
+        count.inc();
+    }
 }
 
 pub fn preinitialize_metrics() {
@@ -2608,4 +2837,6 @@ pub fn preinitialize_metrics() {
     // Custom
     Lazy::force(&RECONSTRUCT_TIME);
     Lazy::force(&tenant_throttling::TIMELINE_GET);
+    Lazy::force(&tenant_throttling::WRITE);
+    Lazy::force(&virtual_file_io_scheduler::METRICS);
 }
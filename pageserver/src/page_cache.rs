@@ -90,6 +90,7 @@ use crate::{
     context::RequestContext,
     metrics::{page_cache_eviction_metrics, PageCacheSizeMetrics},
     repository::Key,
+    virtual_file::aligned_buffer::AlignedBuffer,
 };
 
 static PAGE_CACHE: OnceCell<PageCache> = OnceCell::new();
@@ -940,10 +941,9 @@ impl PageCache {
     fn new(num_pages: usize) -> Self {
         assert!(num_pages > 0, "page cache size must be > 0");
 
-        // We could use Vec::leak here, but that potentially also leaks
-        // uninitialized reserved capacity. With into_boxed_slice and Box::leak
-        // this is avoided.
-        let page_buffer = Box::leak(vec![0u8; num_pages * PAGE_SZ].into_boxed_slice());
+        // The buffers are aligned, so that layer files opened with O_DIRECT can
+        // be read into them directly, see `virtual_file::io_mode`.
+        let page_buffer = AlignedBuffer::zeroed(num_pages * PAGE_SZ).leak();
 
         let size_metrics = &crate::metrics::PAGE_CACHE_SIZE;
         size_metrics.max_bytes.set_page_sz(num_pages);
@@ -7,10 +7,14 @@
 //     *status* -- show actual info about this pageserver,
 //     *pagestream* -- enter mode where smgr and pageserver talk with their
 //  custom protocol.
+//     *pagestream_v2* -- same as *pagestream*, but also accepts batched
+//  GetPage requests.
+//     *basebackup* -- send a tarball to bootstrap a compute node, optionally
+//  compressed and/or resuming an interrupted transfer.
 //
 
 use anyhow::Context;
-use async_compression::tokio::write::GzipEncoder;
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
 use bytes::Buf;
 use bytes::Bytes;
 use futures::stream::FuturesUnordered;
@@ -21,9 +25,10 @@ use pageserver_api::models::TenantState;
 use pageserver_api::models::{
     PagestreamBeMessage, PagestreamDbSizeRequest, PagestreamDbSizeResponse,
     PagestreamErrorResponse, PagestreamExistsRequest, PagestreamExistsResponse,
-    PagestreamFeMessage, PagestreamGetPageRequest, PagestreamGetPageResponse,
-    PagestreamGetSlruSegmentRequest, PagestreamGetSlruSegmentResponse, PagestreamNblocksRequest,
-    PagestreamNblocksResponse,
+    PagestreamFeMessage, PagestreamGetPageBatchRequest, PagestreamGetPageBatchResponse,
+    PagestreamGetPageRequest, PagestreamGetPageResponse, PagestreamGetSlruSegmentRequest,
+    PagestreamGetSlruSegmentResponse, PagestreamNblocksRequest, PagestreamNblocksResponse,
+    PagestreamProtocolVersion,
 };
 use pageserver_api::shard::ShardIndex;
 use pageserver_api::shard::ShardNumber;
@@ -57,6 +62,7 @@ use utils::{
 
 use crate::auth::check_permission;
 use crate::basebackup;
+use crate::basebackup_cache::{self, BasebackupCache, BasebackupCacheKey, Lookup, TarballWriter};
 use crate::config::PageServerConf;
 use crate::context::{DownloadBehavior, RequestContext};
 use crate::import_datadir::import_wal_from_tar;
@@ -77,7 +83,7 @@ use crate::tenant::PageReconstructError;
 use crate::tenant::Timeline;
 use crate::trace::Tracer;
 use pageserver_api::key::rel_block_to_key;
-use pageserver_api::reltag::SlruKind;
+use pageserver_api::reltag::{BlockNumber, RelTag, SlruKind};
 use postgres_ffi::pg_constants::DEFAULTTABLESPACE_OID;
 use postgres_ffi::BLCKSZ;
 
@@ -319,6 +325,31 @@ struct PageServerHandler {
     shard_timelines: HashMap<ShardIndex, HandlerTimeline>,
 }
 
+/// Compression of a basebackup tarball.
+///
+/// The client lists the algorithms it can decompress in order of preference,
+/// e.g. `--compression=zstd,gzip`, and we use the first one that we know. The
+/// client recognizes the chosen algorithm by the magic number at the start of
+/// the stream.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Hash, strum_macros::EnumString, strum_macros::Display,
+)]
+#[strum(serialize_all = "snake_case")]
+pub(crate) enum BasebackupCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl BasebackupCompression {
+    fn negotiate(accepted: &str) -> Self {
+        accepted
+            .split(',')
+            .find_map(|name| name.parse().ok())
+            .unwrap_or(BasebackupCompression::None)
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 enum PageStreamError {
     /// We encountered an error that should prompt the client to reconnect:
@@ -552,6 +583,7 @@ impl PageServerHandler {
         pgb: &mut PostgresBackend<IO>,
         tenant_id: TenantId,
         timeline_id: TimelineId,
+        protocol_version: PagestreamProtocolVersion,
         ctx: RequestContext,
     ) -> Result<(), QueryError>
     where
@@ -614,7 +646,8 @@ impl PageServerHandler {
                 t.trace(&copy_data_bytes)
             }
 
-            let neon_fe_msg = PagestreamFeMessage::parse(&mut copy_data_bytes.reader())?;
+            let neon_fe_msg =
+                PagestreamFeMessage::parse(&mut copy_data_bytes.reader(), protocol_version)?;
 
             // TODO: We could create a new per-request context here, with unique ID.
             // Currently we use the same per-timeline context for all requests
@@ -666,6 +699,15 @@ impl PageServerHandler {
                         span,
                     )
                 }
+                PagestreamFeMessage::GetPageBatch(req) => {
+                    let span = tracing::info_span!("handle_get_page_batch_request", npages = %req.pages.len(), req_lsn = %req.lsn);
+                    (
+                        self.handle_get_page_batch_request(tenant_id, timeline_id, &req, &ctx)
+                            .instrument(span.clone())
+                            .await,
+                        span,
+                    )
+                }
             };
 
             match response {
@@ -994,7 +1036,8 @@ impl PageServerHandler {
     /// looks up such a Timeline synchronously and without touching any global state.
     fn get_cached_timeline_for_page(
         &mut self,
-        req: &PagestreamGetPageRequest,
+        rel: RelTag,
+        blkno: BlockNumber,
     ) -> Result<&Arc<Timeline>, Key> {
         let key = if let Some((first_idx, first_timeline)) = self.shard_timelines.iter().next() {
             // Fastest path: single sharded case
@@ -1002,7 +1045,7 @@ impl PageServerHandler {
                 return Ok(&first_timeline.timeline);
             }
 
-            let key = rel_block_to_key(req.rel, req.blkno);
+            let key = rel_block_to_key(rel, blkno);
             let shard_num = first_timeline
                 .timeline
                 .get_shard_identity()
@@ -1026,7 +1069,7 @@ impl PageServerHandler {
 
             key
         } else {
-            rel_block_to_key(req.rel, req.blkno)
+            rel_block_to_key(rel, blkno)
         };
 
         Err(key)
@@ -1114,7 +1157,7 @@ impl PageServerHandler {
         req: &PagestreamGetPageRequest,
         ctx: &RequestContext,
     ) -> Result<PagestreamBeMessage, PageStreamError> {
-        let timeline = match self.get_cached_timeline_for_page(req) {
+        let timeline = match self.get_cached_timeline_for_page(req.rel, req.blkno) {
             Ok(tl) => tl,
             Err(key) => {
                 match self
@@ -1122,20 +1165,7 @@ impl PageServerHandler {
                     .await
                 {
                     Ok(t) => t,
-                    Err(GetActiveTimelineError::Tenant(GetActiveTenantError::NotFound(_))) => {
-                        // We already know this tenant exists in general, because we resolved it at
-                        // start of connection.  Getting a NotFound here indicates that the shard containing
-                        // the requested page is not present on this node: the client's knowledge of shard->pageserver
-                        // mapping is out of date.
-                        //
-                        // Closing the connection by returning ``::Reconnect` has the side effect of rate-limiting above message, via
-                        // client's reconnect backoff, as well as hopefully prompting the client to load its updated configuration
-                        // and talk to a different pageserver.
-                        return Err(PageStreamError::Reconnect(
-                            "getpage@lsn request routed to wrong shard".into(),
-                        ));
-                    }
-                    Err(e) => return Err(e.into()),
+                    Err(e) => return Err(Self::page_timeline_error(e)),
                 }
             }
         };
@@ -1161,6 +1191,121 @@ impl PageServerHandler {
         }))
     }
 
+    /// Maps an error from [`Self::load_timeline_for_page`] to the error returned to the client.
+    fn page_timeline_error(e: GetActiveTimelineError) -> PageStreamError {
+        match e {
+            GetActiveTimelineError::Tenant(GetActiveTenantError::NotFound(_)) => {
+                // We already know this tenant exists in general, because we resolved it at
+                // start of connection.  Getting a NotFound here indicates that the shard containing
+                // the requested page is not present on this node: the client's knowledge of shard->pageserver
+                // mapping is out of date.
+                //
+                // Closing the connection by returning ``::Reconnect` has the side effect of rate-limiting above message, via
+                // client's reconnect backoff, as well as hopefully prompting the client to load its updated configuration
+                // and talk to a different pageserver.
+                PageStreamError::Reconnect("getpage@lsn request routed to wrong shard".into())
+            }
+            e => e.into(),
+        }
+    }
+
+    /// Serve a batch of pages. The pages are grouped by the shard that holds them, and
+    /// each group is read with one vectored read per shard (see
+    /// [`Timeline::get_rel_page_at_lsn_batched`]).
+    ///
+    /// Failing to read a page is reported for that page only. Errors that prevent
+    /// serving the batch at all, like shutdown or a page routed to the wrong shard,
+    /// fail the whole request.
+    #[instrument(skip_all)]
+    async fn handle_get_page_batch_request(
+        &mut self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        req: &PagestreamGetPageBatchRequest,
+        ctx: &RequestContext,
+    ) -> Result<PagestreamBeMessage, PageStreamError> {
+        let mut shards: Vec<(Arc<Timeline>, Vec<usize>)> = Vec::new();
+        for (idx, (rel, blkno)) in req.pages.iter().enumerate() {
+            let timeline = match self.get_cached_timeline_for_page(*rel, *blkno) {
+                Ok(tl) => Arc::clone(tl),
+                Err(key) => Arc::clone(
+                    self.load_timeline_for_page(tenant_id, timeline_id, key)
+                        .await
+                        .map_err(Self::page_timeline_error)?,
+                ),
+            };
+
+            match shards.iter_mut().find(|(tl, _)| Arc::ptr_eq(tl, &timeline)) {
+                Some((_, indices)) => indices.push(idx),
+                None => shards.push((timeline, vec![idx])),
+            }
+        }
+
+        let mut pages = req.pages.iter().map(|_| None).collect::<Vec<_>>();
+        for (timeline, indices) in shards {
+            let shard_pages = indices
+                .iter()
+                .map(|idx| req.pages[*idx])
+                .collect::<Vec<_>>();
+            let span =
+                tracing::info_span!("shard", shard_id = %timeline.tenant_shard_id.shard_slug());
+            let results = Self::get_page_batch_from_shard(&timeline, req, &shard_pages, ctx)
+                .instrument(span.clone())
+                .await?;
+
+            for (idx, result) in indices.into_iter().zip(results) {
+                pages[idx] = Some(result.map_err(|e| {
+                    let (rel, blkno) = req.pages[idx];
+                    let full = utils::error::report_compact_sources(&e);
+                    span.in_scope(|| {
+                        error!("error reading page {rel} blk {blkno} of batch: {full:#}")
+                    });
+                    PagestreamErrorResponse {
+                        message: e.to_string(),
+                    }
+                }));
+            }
+        }
+
+        Ok(PagestreamBeMessage::GetPageBatch(
+            PagestreamGetPageBatchResponse {
+                pages: pages
+                    .into_iter()
+                    .map(|page| page.expect("every page of the batch belongs to a shard"))
+                    .collect(),
+            },
+        ))
+    }
+
+    async fn get_page_batch_from_shard(
+        timeline: &Timeline,
+        req: &PagestreamGetPageBatchRequest,
+        pages: &[(RelTag, BlockNumber)],
+        ctx: &RequestContext,
+    ) -> Result<Vec<Result<Bytes, PageReconstructError>>, PageStreamError> {
+        let _timer = timeline
+            .query_metrics
+            .start_timer(metrics::SmgrQueryType::GetPageBatch);
+
+        let latest_gc_cutoff_lsn = timeline.get_latest_gc_cutoff_lsn();
+        let lsn =
+            Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn, ctx)
+                .await?;
+
+        let results = timeline
+            .get_rel_page_at_lsn_batched(pages, lsn, req.latest, ctx)
+            .await?;
+
+        if results
+            .iter()
+            .any(|r| matches!(r, Err(e) if e.is_stopping()))
+        {
+            return Err(PageStreamError::Shutdown);
+        }
+
+        Ok(results)
+    }
+
     #[instrument(skip_all, fields(shard_id))]
     async fn handle_get_slru_segment_request(
         &mut self,
@@ -1190,7 +1335,10 @@ impl PageServerHandler {
     }
 
     #[allow(clippy::too_many_arguments)]
-    #[instrument(skip_all, fields(shard_id, ?lsn, ?prev_lsn, %full_backup))]
+    #[instrument(
+        skip_all,
+        fields(shard_id, ?lsn, ?prev_lsn, %full_backup, ?incremental_from, %compression, %skip_entries)
+    )]
     async fn handle_basebackup_request<IO>(
         &mut self,
         pgb: &mut PostgresBackend<IO>,
@@ -1199,7 +1347,9 @@ impl PageServerHandler {
         lsn: Option<Lsn>,
         prev_lsn: Option<Lsn>,
         full_backup: bool,
-        gzip: bool,
+        incremental_from: Option<Lsn>,
+        compression: BasebackupCompression,
+        skip_entries: usize,
         ctx: RequestContext,
     ) -> Result<(), QueryError>
     where
@@ -1220,6 +1370,11 @@ impl PageServerHandler {
                 .check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)
                 .context("invalid basebackup lsn")?;
         }
+        if let Some(from_lsn) = incremental_from {
+            timeline
+                .check_lsn_is_in_scope(from_lsn, &latest_gc_cutoff_lsn)
+                .context("invalid incremental backup start lsn")?;
+        }
 
         let lsn_awaited_after = started.elapsed();
 
@@ -1227,49 +1382,38 @@ impl PageServerHandler {
         pgb.write_message_noflush(&BeMessage::CopyOutResponse)?;
         self.flush_cancellable(pgb, &timeline.cancel).await?;
 
-        // Send a tarball of the latest layer on the timeline. Compress if not
-        // fullbackup. TODO Compress in that case too (tests need to be updated)
-        if full_backup {
-            let mut writer = pgb.copyout_writer();
-            basebackup::send_basebackup_tarball(
-                &mut writer,
-                &timeline,
-                lsn,
-                prev_lsn,
-                full_backup,
-                &ctx,
-            )
-            .await?;
-        } else {
-            let mut writer = pgb.copyout_writer();
-            if gzip {
-                let mut encoder = GzipEncoder::with_quality(
-                    writer,
-                    // NOTE using fast compression because it's on the critical path
-                    //      for compute startup. For an empty database, we get
-                    //      <100KB with this method. The Level::Best compression method
-                    //      gives us <20KB, but maybe we should add basebackup caching
-                    //      on compute shutdown first.
-                    async_compression::Level::Fastest,
-                );
-                basebackup::send_basebackup_tarball(
-                    &mut encoder,
+        // Send a tarball of the latest layer on the timeline.
+        //
+        // NOTE using fast compression because it's on the critical path
+        //      for compute startup. For an empty database, we get
+        //      <100KB with this method. The Level::Best compression method
+        //      gives us <20KB, but maybe we should add basebackup caching
+        //      on compute shutdown first.
+        let mut writer = pgb.copyout_writer();
+        match basebackup_cache::get() {
+            // Only plain basebackups from the start are worth caching: resumed
+            // ones and full backups are rare.
+            Some(cache) if !full_backup && skip_entries == 0 => {
+                send_cached_basebackup_tarball(
+                    cache,
+                    &mut writer,
                     &timeline,
                     lsn,
-                    prev_lsn,
-                    full_backup,
+                    compression,
                     &ctx,
                 )
                 .await?;
-                // shutdown the encoder to ensure the gzip footer is written
-                encoder.shutdown().await?;
-            } else {
-                basebackup::send_basebackup_tarball(
+            }
+            _ => {
+                send_basebackup_tarball(
                     &mut writer,
                     &timeline,
                     lsn,
                     prev_lsn,
                     full_backup,
+                    incremental_from,
+                    compression,
+                    skip_entries,
                     &ctx,
                 )
                 .await?;
@@ -1331,6 +1475,157 @@ impl PageServerHandler {
     }
 }
 
+/// Send a basebackup tarball, compressed with `compression`.
+#[allow(clippy::too_many_arguments)]
+async fn send_basebackup_tarball<W>(
+    writer: &mut W,
+    timeline: &Timeline,
+    lsn: Option<Lsn>,
+    prev_lsn: Option<Lsn>,
+    full_backup: bool,
+    incremental_from: Option<Lsn>,
+    compression: BasebackupCompression,
+    skip_entries: usize,
+    ctx: &RequestContext,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Send + Sync + Unpin,
+{
+    match compression {
+        BasebackupCompression::None => {
+            basebackup::send_basebackup_tarball(
+                writer,
+                timeline,
+                lsn,
+                prev_lsn,
+                full_backup,
+                incremental_from,
+                skip_entries,
+                ctx,
+            )
+            .await?;
+        }
+        BasebackupCompression::Gzip => {
+            let mut encoder = GzipEncoder::with_quality(writer, async_compression::Level::Fastest);
+            basebackup::send_basebackup_tarball(
+                &mut encoder,
+                timeline,
+                lsn,
+                prev_lsn,
+                full_backup,
+                incremental_from,
+                skip_entries,
+                ctx,
+            )
+            .await?;
+            // shutdown the encoder to ensure the gzip footer is written
+            encoder.shutdown().await?;
+        }
+        BasebackupCompression::Zstd => {
+            let mut encoder = ZstdEncoder::with_quality(writer, async_compression::Level::Fastest);
+            basebackup::send_basebackup_tarball(
+                &mut encoder,
+                timeline,
+                lsn,
+                prev_lsn,
+                full_backup,
+                incremental_from,
+                skip_entries,
+                ctx,
+            )
+            .await?;
+            // shutdown the encoder to ensure the end of the zstd frame is written
+            encoder.shutdown().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Send a basebackup tarball from the basebackup cache. On a miss, the tarball
+/// is generated into the cache while it is being sent, and kept there if the
+/// timeline didn't advance in the meantime.
+async fn send_cached_basebackup_tarball<W>(
+    cache: &'static BasebackupCache,
+    writer: &mut W,
+    timeline: &Arc<Timeline>,
+    lsn: Option<Lsn>,
+    compression: BasebackupCompression,
+    ctx: &RequestContext,
+) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Send + Sync + Unpin,
+{
+    let key = BasebackupCacheKey {
+        tenant_shard_id: timeline.tenant_shard_id,
+        timeline_id: timeline.timeline_id,
+        lsn,
+        compression,
+    };
+    let last_record_lsn = timeline.get_last_record_lsn();
+
+    match cache.get_or_start(&key, last_record_lsn).await? {
+        Lookup::Hit(tarball) => tarball.copy_to(writer).await,
+        Lookup::Miss(tarball_writer, tarball) => {
+            // Generate the tarball in a task of its own, so that the requests
+            // that joined still get it if this one goes away. If generating
+            // fails, the tarball writer is dropped, which fails the copies.
+            let timeline = Arc::clone(timeline);
+            let ctx = ctx.detached_child(TaskKind::BasebackupCacheFill, DownloadBehavior::Download);
+            task_mgr::spawn(
+                &tokio::runtime::Handle::current(),
+                TaskKind::BasebackupCacheFill,
+                Some(timeline.tenant_shard_id),
+                Some(timeline.timeline_id),
+                "generate cached basebackup",
+                false,
+                async move {
+                    tokio::select! {
+                        generated = generate_cached_basebackup_tarball(
+                            tarball_writer,
+                            &timeline,
+                            lsn,
+                            compression,
+                            last_record_lsn,
+                            &ctx,
+                        ) => generated,
+                        _ = task_mgr::shutdown_watcher() => Ok(()),
+                    }
+                }
+                .in_current_span(),
+            );
+            tarball.copy_to(writer).await
+        }
+    }
+}
+
+/// Generate a basebackup tarball into the basebackup cache, see
+/// [`send_cached_basebackup_tarball`].
+async fn generate_cached_basebackup_tarball(
+    tarball_writer: TarballWriter<'_>,
+    timeline: &Timeline,
+    lsn: Option<Lsn>,
+    compression: BasebackupCompression,
+    last_record_lsn: Lsn,
+    ctx: &RequestContext,
+) -> anyhow::Result<()> {
+    let mut buffered = tokio::io::BufWriter::new(tarball_writer);
+    send_basebackup_tarball(
+        &mut buffered,
+        timeline,
+        lsn,
+        None,
+        false,
+        None,
+        compression,
+        0,
+        ctx,
+    )
+    .await?;
+    buffered.flush().await?;
+    let keep = timeline.get_last_record_lsn() == last_record_lsn;
+    buffered.into_inner().finish(keep).await
+}
+
 #[async_trait::async_trait]
 impl<IO> postgres_backend::Handler<IO> for PageServerHandler
 where
@@ -1386,12 +1681,18 @@ where
 
         let ctx = self.connection_ctx.attached_child();
         debug!("process query {query_string:?}");
-        if query_string.starts_with("pagestream ") {
-            let (_, params_raw) = query_string.split_at("pagestream ".len());
+        if query_string.starts_with("pagestream ") || query_string.starts_with("pagestream_v2 ") {
+            let (command, params_raw) = query_string
+                .split_once(' ')
+                .expect("query string starts with the command");
+            let protocol_version = match command {
+                "pagestream_v2" => PagestreamProtocolVersion::V2,
+                _ => PagestreamProtocolVersion::V1,
+            };
             let params = params_raw.split(' ').collect::<Vec<_>>();
             if params.len() != 2 {
                 return Err(QueryError::Other(anyhow::anyhow!(
-                    "invalid param number for pagestream command"
+                    "invalid param number for {command} command"
                 )));
             }
             let tenant_id = TenantId::from_str(params[0])
@@ -1405,7 +1706,7 @@ where
 
             self.check_permission(Some(tenant_id))?;
 
-            self.handle_pagerequests(pgb, tenant_id, timeline_id, ctx)
+            self.handle_pagerequests(pgb, tenant_id, timeline_id, protocol_version, ctx)
                 .await?;
         } else if query_string.starts_with("basebackup ") {
             let (_, params_raw) = query_string.split_at("basebackup ".len());
@@ -1428,27 +1729,42 @@ where
 
             self.check_permission(Some(tenant_id))?;
 
-            let lsn = if params.len() >= 3 {
-                Some(
-                    Lsn::from_str(params[2])
-                        .with_context(|| format!("Failed to parse Lsn from {}", params[2]))?,
-                )
-            } else {
-                None
+            let mut flags_start = 2;
+            let lsn = match params.get(2) {
+                Some(param) if !param.starts_with("--") => {
+                    flags_start = 3;
+                    Some(
+                        Lsn::from_str(param)
+                            .with_context(|| format!("Failed to parse Lsn from {param}"))?,
+                    )
+                }
+                _ => None,
             };
 
-            let gzip = if params.len() >= 4 {
-                if params[3] == "--gzip" {
-                    true
+            let mut compression = BasebackupCompression::None;
+            let mut skip_entries = 0;
+            for (i, param) in params.iter().enumerate().skip(flags_start) {
+                if *param == "--gzip" {
+                    compression = BasebackupCompression::Gzip;
+                } else if let Some(accepted) = param.strip_prefix("--compression=") {
+                    compression = BasebackupCompression::negotiate(accepted);
+                } else if let Some(entries) = param.strip_prefix("--resume-from=") {
+                    skip_entries = entries.parse().with_context(|| {
+                        format!("Failed to parse number of tar entries from {entries}")
+                    })?;
                 } else {
                     return Err(QueryError::Other(anyhow::anyhow!(
-                        "Parameter in position 3 unknown {}",
-                        params[3],
+                        "Parameter in position {i} unknown {param}",
                     )));
                 }
-            } else {
-                false
-            };
+            }
+            if skip_entries > 0 && lsn.is_none() {
+                // Without an LSN, the tarball would be generated at a different
+                // point than the one the client got the first entries from.
+                return Err(QueryError::Other(anyhow::anyhow!(
+                    "resuming a basebackup requires an LSN"
+                )));
+            }
 
             ::metrics::metric_vec_duration::observe_async_block_duration_by_result(
                 &*metrics::BASEBACKUP_QUERY_TIME,
@@ -1460,7 +1776,9 @@ where
                         lsn,
                         None,
                         false,
-                        gzip,
+                        None,
+                        compression,
+                        skip_entries,
                         ctx,
                     )
                     .await?;
@@ -1518,7 +1836,7 @@ where
         // same as basebackup, but result includes relational data as well
         else if query_string.starts_with("fullbackup ") {
             let (_, params_raw) = query_string.split_at("fullbackup ".len());
-            let params = params_raw.split_whitespace().collect::<Vec<_>>();
+            let mut params = params_raw.split_whitespace().collect::<Vec<_>>();
 
             if params.len() < 2 {
                 return Err(QueryError::Other(anyhow::anyhow!(
@@ -1535,6 +1853,21 @@ where
                 .record("tenant_id", field::display(tenant_id))
                 .record("timeline_id", field::display(timeline_id));
 
+            // An incremental backup includes only what changed after the given
+            // LSN, usually the LSN of the previous backup.
+            let mut incremental_from = None;
+            if let Some(pos) = params
+                .iter()
+                .position(|param| param.starts_with("--incremental-from="))
+            {
+                let param = params.remove(pos);
+                let (_, from_lsn) = param.split_at("--incremental-from=".len());
+                incremental_from = Some(
+                    Lsn::from_str(from_lsn)
+                        .with_context(|| format!("Failed to parse Lsn from {from_lsn}"))?,
+                );
+            }
+
             // The caller is responsible for providing correct lsn and prev_lsn.
             let lsn = if params.len() > 2 {
                 Some(
@@ -1563,7 +1896,9 @@ where
                 lsn,
                 prev_lsn,
                 true,
-                false,
+                incremental_from,
+                BasebackupCompression::None,
+                0,
                 ctx,
             )
             .await?;
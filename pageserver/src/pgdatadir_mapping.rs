@@ -26,7 +26,7 @@ use postgres_ffi::relfile_utils::{FSM_FORKNUM, VISIBILITYMAP_FORKNUM};
 use postgres_ffi::BLCKSZ;
 use postgres_ffi::{Oid, TimestampTz, TransactionId};
 use serde::{Deserialize, Serialize};
-use std::collections::{hash_map, HashMap, HashSet};
+use std::collections::{hash_map, BTreeMap, HashMap, HashSet};
 use std::ops::ControlFlow;
 use std::ops::Range;
 use strum::IntoEnumIterator;
@@ -197,6 +197,89 @@ impl Timeline {
         version.get(self, key, ctx).await
     }
 
+    /// Look up many relation pages at the same LSN.
+    ///
+    /// Works like [`Self::get_rel_page_at_lsn`] for every page, but reads the pages that
+    /// are within their relation's size with vectored gets. Returns one result per
+    /// requested page, in request order. The outer error is for failures that affect
+    /// the whole batch, like shutdown.
+    pub(crate) async fn get_rel_page_at_lsn_batched(
+        &self,
+        pages: &[(RelTag, BlockNumber)],
+        lsn: Lsn,
+        latest: bool,
+        ctx: &RequestContext,
+    ) -> Result<Vec<Result<Bytes, PageReconstructError>>, PageReconstructError> {
+        let mut results: Vec<Option<Result<Bytes, PageReconstructError>>> =
+            pages.iter().map(|_| None).collect();
+
+        // Several entries of the batch may ask for the same page.
+        let mut key_to_pages: BTreeMap<Key, Vec<usize>> = BTreeMap::new();
+        for (idx, (tag, blknum)) in pages.iter().enumerate() {
+            if tag.relnode == 0 {
+                results[idx] = Some(Err(PageReconstructError::Other(
+                    RelationError::InvalidRelnode.into(),
+                )));
+                continue;
+            }
+
+            let nblocks = match self
+                .get_rel_size(*tag, Version::Lsn(lsn), latest, ctx)
+                .await
+            {
+                Ok(nblocks) => nblocks,
+                Err(PageReconstructError::Cancelled) => {
+                    return Err(PageReconstructError::Cancelled)
+                }
+                Err(e) => {
+                    results[idx] = Some(Err(e));
+                    continue;
+                }
+            };
+            if *blknum >= nblocks {
+                debug!(
+                    "read beyond EOF at {} blk {} at {}, size is {}: returning all-zeros page",
+                    tag, blknum, lsn, nblocks
+                );
+                results[idx] = Some(Ok(ZERO_PAGE.clone()));
+                continue;
+            }
+
+            key_to_pages
+                .entry(rel_block_to_key(*tag, *blknum))
+                .or_default()
+                .push(idx);
+        }
+
+        let keys = key_to_pages.keys().copied().collect::<Vec<_>>();
+        for chunk in keys.chunks(Timeline::MAX_GET_VECTORED_KEYS as usize) {
+            let mut keyspace = KeySpaceAccum::new();
+            for key in chunk {
+                keyspace.add_key(*key);
+            }
+            let keyspace = keyspace.to_keyspace();
+
+            let values = self.get_vectored(&keyspace.ranges, lsn, ctx).await?;
+            for (key, value) in values {
+                let Some((last, rest)) = key_to_pages[&key].split_last() else {
+                    continue;
+                };
+                for idx in rest {
+                    results[*idx] = Some(match &value {
+                        Ok(page) => Ok(page.clone()),
+                        Err(e) => Err(PageReconstructError::from(anyhow::anyhow!("{e:#}"))),
+                    });
+                }
+                results[*last] = Some(value);
+            }
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|result| result.expect("every page of the batch has a result"))
+            .collect())
+    }
+
     // Get size of a database in blocks
     pub(crate) async fn get_db_size(
         &self,
@@ -728,6 +811,35 @@ impl Timeline {
         Ok(total_size * BLCKSZ as u64)
     }
 
+    /// Get the size in bytes of each relation at the given LSN, all forks
+    /// included, grouped by tablespace and database oid.
+    ///
+    /// Like [`Self::get_current_logical_size_non_incremental`], this reads the
+    /// size of every relation, which can be slow.
+    pub(crate) async fn list_relation_sizes(
+        &self,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<BTreeMap<(Oid, Oid), HashMap<Oid, u64>>, CalculateLogicalSizeError> {
+        let mut sizes = BTreeMap::new();
+        for (spcnode, dbnode) in self.list_dbdirs(lsn, ctx).await?.into_keys() {
+            let db_sizes: &mut HashMap<Oid, u64> = sizes.entry((spcnode, dbnode)).or_default();
+            for rel in self
+                .list_rels(spcnode, dbnode, Version::Lsn(lsn), ctx)
+                .await?
+            {
+                if self.cancel.is_cancelled() {
+                    return Err(CalculateLogicalSizeError::Cancelled);
+                }
+                let nblocks = self
+                    .get_rel_size(rel, Version::Lsn(lsn), false, ctx)
+                    .await?;
+                *db_sizes.entry(rel.relnode).or_default() += nblocks as u64 * BLCKSZ as u64;
+            }
+        }
+        Ok(sizes)
+    }
+
     ///
     /// Get a KeySpace that covers all the Keys that are in use at the given LSN.
     /// Anything that's not listed maybe removed from the underlying storage (from
@@ -1432,10 +1544,11 @@ impl<'a> DatadirModification<'a> {
                 Err(
                     e @ (PageReconstructError::AncestorStopping(_)
                     | PageReconstructError::Cancelled
-                    | PageReconstructError::AncestorLsnTimeout(_)),
+                    | PageReconstructError::AncestorLsnTimeout(_)
+                    | PageReconstructError::Corrupt(_)),
                 ) => {
-                    // Important that we do not interpret a shutdown error as "not found" and thereby
-                    // reset the map.
+                    // Important that we do not interpret a shutdown error, or a corrupt layer
+                    // hiding the existing map, as "not found" and thereby reset the map.
                     return Err(e.into());
                 }
                 // FIXME: PageReconstructError doesn't have an explicit variant for key-not-found, so
@@ -2,8 +2,10 @@ use crate::walrecord::NeonWalRecord;
 use anyhow::Result;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::ops::AddAssign;
 use std::time::Duration;
+use utils::id::TimelineId;
 
 pub use pageserver_api::key::{Key, KEY_SIZE};
 
@@ -108,6 +110,11 @@ pub struct GcResult {
     pub layers_needed_by_branches: u64,
     pub layers_not_updated: u64,
     pub layers_removed: u64, // # of layer files removed because they have been made obsolete by newer ondisk files.
+    pub layers_created_at_branch_points: u64,
+
+    /// Bytes freed on each timeline: the size of the removed layer files, minus the
+    /// size of the image layers created at branch points.
+    pub reclaimed_bytes: HashMap<TimelineId, u64>,
 
     #[serde(serialize_with = "serialize_duration_as_millis")]
     pub elapsed: Duration,
@@ -137,6 +144,10 @@ impl AddAssign for GcResult {
         self.layers_needed_by_branches += other.layers_needed_by_branches;
         self.layers_not_updated += other.layers_not_updated;
         self.layers_removed += other.layers_removed;
+        self.layers_created_at_branch_points += other.layers_created_at_branch_points;
+        for (timeline_id, bytes) in &other.reclaimed_bytes {
+            *self.reclaimed_bytes.entry(*timeline_id).or_default() += bytes;
+        }
 
         self.elapsed += other.elapsed;
 
@@ -266,6 +266,9 @@ pub enum TaskKind {
 
     OndemandLogicalSizeCalculation,
 
+    // Per-database and per-relation logical size calculation
+    RelationSizesCalculation,
+
     // Task that flushes frozen in-memory layers to disk
     LayerFlushTask,
 
@@ -289,12 +292,18 @@ pub enum TaskKind {
 
     // task that drives downloading layers
     DownloadAllRemoteLayers,
+    // task that imports a timeline from a basebackup in remote storage
+    ImportTimeline,
     // Task that calculates synthetis size for all active tenants
     CalculateSyntheticSize,
 
     // A request that comes in via the pageserver HTTP API.
     MgmtRequest,
 
+    /// Generates a basebackup tarball into the cache, see [`crate::basebackup_cache`].
+    /// Compute startup waits for it, like for a [`PageRequestHandler`](Self::PageRequestHandler).
+    BasebackupCacheFill,
+
     DebugTool,
 
     #[cfg(test)]
@@ -19,6 +19,7 @@ use futures::stream::FuturesUnordered;
 use futures::FutureExt;
 use futures::StreamExt;
 use pageserver_api::models;
+use pageserver_api::models::TimelineImportTaskInfo;
 use pageserver_api::models::TimelineState;
 use pageserver_api::models::WalRedoManagerStatus;
 use pageserver_api::shard::ShardIdentity;
@@ -69,8 +70,10 @@ use crate::import_datadir;
 use crate::is_uninit_mark;
 use crate::metrics::TENANT;
 use crate::metrics::{
-    remove_tenant_metrics, BROKEN_TENANTS_SET, TENANT_STATE_METRIC, TENANT_SYNTHETIC_SIZE_METRIC,
+    remove_tenant_metrics, BROKEN_TENANTS_SET, TENANT_RESIDENT_SIZE_QUOTA, TENANT_STATE_METRIC,
+    TENANT_SYNTHETIC_SIZE_METRIC,
 };
+use crate::pgdatadir_mapping::LsnForTimestamp;
 use crate::repository::GcResult;
 use crate::task_mgr;
 use crate::task_mgr::TaskKind;
@@ -107,6 +110,7 @@ use crate::walredo::PostgresRedoManager;
 use crate::TEMP_FILE_SUFFIX;
 use once_cell::sync::Lazy;
 pub use pageserver_api::models::TenantState;
+use postgres_ffi::TimestampTz;
 use tokio::sync::Semaphore;
 
 static INIT_DB_SEMAPHORE: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(8));
@@ -146,6 +150,7 @@ macro_rules! pausable_failpoint {
 
 pub mod blob_io;
 pub mod block_io;
+pub mod vectored_blob_io;
 
 pub mod disk_btree;
 pub(crate) mod ephemeral_file;
@@ -153,6 +158,7 @@ pub mod layer_map;
 
 pub mod metadata;
 mod par_fsync;
+pub(crate) mod remote_import;
 pub mod remote_timeline_client;
 pub mod storage_layer;
 
@@ -272,6 +278,10 @@ pub struct Tenant {
     /// **Lock order**: if acquring both, acquire`timelines` before `timelines_creating`
     timelines_creating: std::sync::Mutex<HashSet<TimelineId>>,
 
+    /// Progress of the imports of timelines from remote storage since the pageserver
+    /// started, see [`remote_import`].
+    timeline_imports: std::sync::Mutex<HashMap<TimelineId, TimelineImportTaskInfo>>,
+
     // This mutex prevents creation of new timelines during GC.
     // Adding yet another mutex (in addition to `timelines`) is needed because holding
     // `timelines` mutex during all GC iteration
@@ -312,6 +322,11 @@ pub struct Tenant {
     /// All [`Tenant::timelines`] of a given [`Tenant`] instance share the same [`throttle::Throttle`] instance.
     pub(crate) timeline_get_throttle:
         Arc<throttle::Throttle<&'static crate::metrics::tenant_throttling::TimelineGet>>,
+
+    /// Throttle for the bytes written to disk by WAL ingest, layer flushes and compaction,
+    /// shared by all [`Tenant::timelines`] like [`Tenant::timeline_get_throttle`].
+    pub(crate) write_throttle:
+        Arc<throttle::Throttle<&'static crate::metrics::tenant_throttling::Write>>,
 }
 
 impl std::fmt::Debug for Tenant {
@@ -998,6 +1013,7 @@ impl Tenant {
                     remote_client: Some(remote_client),
                     deletion_queue_client: self.deletion_queue_client.clone(),
                     timeline_get_throttle: self.timeline_get_throttle.clone(),
+                    write_throttle: self.write_throttle.clone(),
                 },
                 ctx,
             )
@@ -1413,6 +1429,7 @@ impl Tenant {
         new_timeline_id: TimelineId,
         ancestor_timeline_id: Option<TimelineId>,
         mut ancestor_start_lsn: Option<Lsn>,
+        ancestor_start_timestamp: Option<TimestampTz>,
         pg_version: u32,
         load_existing_initdb: Option<TimelineId>,
         broker_client: storage_broker::BrokerClientChannel,
@@ -1526,6 +1543,7 @@ impl Tenant {
                     &ancestor_timeline,
                     new_timeline_id,
                     ancestor_start_lsn,
+                    ancestor_start_timestamp,
                     uninit_mark,
                     ctx,
                 )
@@ -2302,6 +2320,13 @@ impl Tenant {
             .or(self.conf.default_tenant_conf.min_resident_size_override)
     }
 
+    pub fn get_resident_size_quota(&self) -> Option<u64> {
+        let tenant_conf = self.tenant_conf.read().unwrap().tenant_conf.clone();
+        tenant_conf
+            .resident_size_quota
+            .or(self.conf.default_tenant_conf.resident_size_quota)
+    }
+
     pub fn get_heatmap_period(&self) -> Option<Duration> {
         let tenant_conf = self.tenant_conf.read().unwrap().tenant_conf.clone();
         let heatmap_period = tenant_conf
@@ -2348,12 +2373,40 @@ impl Tenant {
             .unwrap_or(psconf.default_tenant_conf.timeline_get_throttle.clone())
     }
 
+    fn get_write_throttle_config(
+        psconf: &'static PageServerConf,
+        overrides: &TenantConfOpt,
+    ) -> throttle::Config {
+        overrides
+            .write_throttle
+            .clone()
+            .unwrap_or(psconf.default_tenant_conf.write_throttle.clone())
+    }
+
     pub(crate) fn tenant_conf_updated(&self) {
-        let conf = {
+        let (timeline_get_conf, write_conf) = {
             let guard = self.tenant_conf.read().unwrap();
-            Self::get_timeline_get_throttle_config(self.conf, &guard.tenant_conf)
+            (
+                Self::get_timeline_get_throttle_config(self.conf, &guard.tenant_conf),
+                Self::get_write_throttle_config(self.conf, &guard.tenant_conf),
+            )
         };
-        self.timeline_get_throttle.reconfigure(conf)
+        self.timeline_get_throttle.reconfigure(timeline_get_conf);
+        self.write_throttle.reconfigure(write_conf);
+        self.update_resident_size_quota_metric();
+    }
+
+    fn update_resident_size_quota_metric(&self) {
+        let tenant_id = self.tenant_shard_id.tenant_id.to_string();
+        let shard_id = self.tenant_shard_id.shard_slug().to_string();
+        match self.get_resident_size_quota() {
+            Some(quota) => TENANT_RESIDENT_SIZE_QUOTA
+                .with_label_values(&[&tenant_id, &shard_id])
+                .set(quota),
+            None => {
+                let _ = TENANT_RESIDENT_SIZE_QUOTA.remove_label_values(&[&tenant_id, &shard_id]);
+            }
+        }
     }
 
     /// Helper function to create a new Timeline struct.
@@ -2474,7 +2527,7 @@ impl Tenant {
             }
         });
 
-        Tenant {
+        let tenant = Tenant {
             tenant_shard_id,
             shard_identity,
             generation: attached_conf.location.generation,
@@ -2484,6 +2537,7 @@ impl Tenant {
             constructed_at: Instant::now(),
             timelines: Mutex::new(HashMap::new()),
             timelines_creating: Mutex::new(HashSet::new()),
+            timeline_imports: Mutex::new(HashMap::new()),
             gc_cs: tokio::sync::Mutex::new(()),
             walredo_mgr,
             remote_storage,
@@ -2500,8 +2554,14 @@ impl Tenant {
                 Tenant::get_timeline_get_throttle_config(conf, &attached_conf.tenant_conf),
                 &crate::metrics::tenant_throttling::TIMELINE_GET,
             )),
+            write_throttle: Arc::new(throttle::Throttle::new(
+                Tenant::get_write_throttle_config(conf, &attached_conf.tenant_conf),
+                &crate::metrics::tenant_throttling::WRITE,
+            )),
             tenant_conf: Arc::new(RwLock::new(attached_conf)),
-        }
+        };
+        tenant.update_resident_size_quota_metric();
+        tenant
     }
 
     /// Locate and load config
@@ -2752,7 +2812,7 @@ impl Tenant {
                 // made.
                 break;
             }
-            let result = timeline.gc().await?;
+            let result = timeline.gc(ctx).await?;
             totals += result;
         }
 
@@ -2886,7 +2946,7 @@ impl Tenant {
     ) -> Result<Arc<Timeline>, CreateTimelineError> {
         let uninit_mark = self.create_timeline_uninit_mark(dst_id).unwrap();
         let tl = self
-            .branch_timeline_impl(src_timeline, dst_id, start_lsn, uninit_mark, ctx)
+            .branch_timeline_impl(src_timeline, dst_id, start_lsn, None, uninit_mark, ctx)
             .await?;
         tl.set_state(TimelineState::Active);
         Ok(tl)
@@ -2900,11 +2960,19 @@ impl Tenant {
         src_timeline: &Arc<Timeline>,
         dst_id: TimelineId,
         start_lsn: Option<Lsn>,
+        start_timestamp: Option<TimestampTz>,
         timeline_uninit_mark: TimelineUninitMark<'_>,
         ctx: &RequestContext,
     ) -> Result<Arc<Timeline>, CreateTimelineError> {
-        self.branch_timeline_impl(src_timeline, dst_id, start_lsn, timeline_uninit_mark, ctx)
-            .await
+        self.branch_timeline_impl(
+            src_timeline,
+            dst_id,
+            start_lsn,
+            start_timestamp,
+            timeline_uninit_mark,
+            ctx,
+        )
+        .await
     }
 
     async fn branch_timeline_impl(
@@ -2912,8 +2980,9 @@ impl Tenant {
         src_timeline: &Arc<Timeline>,
         dst_id: TimelineId,
         start_lsn: Option<Lsn>,
+        start_timestamp: Option<TimestampTz>,
         timeline_uninit_mark: TimelineUninitMark<'_>,
-        _ctx: &RequestContext,
+        ctx: &RequestContext,
     ) -> Result<Arc<Timeline>, CreateTimelineError> {
         let src_id = src_timeline.timeline_id;
 
@@ -2922,12 +2991,27 @@ impl Tenant {
         // valid while we are creating the branch.
         let _gc_cs = self.gc_cs.lock().await;
 
-        // If no start LSN is specified, we branch the new timeline from the source timeline's last record LSN
-        let start_lsn = start_lsn.unwrap_or_else(|| {
-            let lsn = src_timeline.get_last_record_lsn();
-            info!("branching timeline {dst_id} from timeline {src_id} at last record LSN: {lsn}");
-            lsn
-        });
+        // If no start LSN is specified, we branch the new timeline at the given timestamp, or
+        // else from the source timeline's last record LSN. The timestamp is resolved while
+        // holding the GC lock, so that the LSN it resolves to cannot be garbage collected
+        // before the branch exists.
+        let start_lsn = match (start_lsn, start_timestamp) {
+            (Some(lsn), _) => lsn,
+            (None, Some(timestamp)) => {
+                let lsn = self
+                    .find_branch_lsn_for_timestamp(src_timeline, timestamp, ctx)
+                    .await?;
+                info!("branching timeline {dst_id} from timeline {src_id} at LSN {lsn} for timestamp {timestamp}");
+                lsn
+            }
+            (None, None) => {
+                let lsn = src_timeline.get_last_record_lsn();
+                info!(
+                    "branching timeline {dst_id} from timeline {src_id} at last record LSN: {lsn}"
+                );
+                lsn
+            }
+        };
 
         // Ensure that `start_lsn` is valid, i.e. the LSN is within the PITR
         // horizon on the source timeline
@@ -3018,6 +3102,40 @@ impl Tenant {
         Ok(new_timeline)
     }
 
+    /// Resolve the branch point for a branch at `timestamp`, the same way the
+    /// `get_lsn_by_timestamp` API does. Only timestamps that fall within the retained history
+    /// of `src_timeline` can be branched at.
+    async fn find_branch_lsn_for_timestamp(
+        &self,
+        src_timeline: &Arc<Timeline>,
+        timestamp: TimestampTz,
+        ctx: &RequestContext,
+    ) -> Result<Lsn, CreateTimelineError> {
+        let found = src_timeline
+            .find_lsn_for_timestamp(timestamp, &self.cancel, ctx)
+            .await
+            .map_err(|e| {
+                if e.is_stopping() {
+                    CreateTimelineError::ShuttingDown
+                } else {
+                    CreateTimelineError::Other(
+                        anyhow::Error::new(e).context("find branch LSN for timestamp"),
+                    )
+                }
+            })?;
+
+        match found {
+            LsnForTimestamp::Present(lsn) | LsnForTimestamp::Future(lsn) => Ok(lsn),
+            LsnForTimestamp::Past(lsn) => Err(CreateTimelineError::AncestorLsn(anyhow::anyhow!(
+                "invalid branch start timestamp: no commits retained before it, earliest branch point is {lsn}"
+            ))),
+            LsnForTimestamp::NoData(_) => Err(CreateTimelineError::AncestorLsn(anyhow::anyhow!(
+                "invalid branch start timestamp: no commits found in timeline {}",
+                src_timeline.timeline_id
+            ))),
+        }
+    }
+
     /// For unit tests, make this visible so that other modules can directly create timelines
     #[cfg(test)]
     #[tracing::instrument(skip_all, fields(tenant_id=%self.tenant_shard_id.tenant_id, shard_id=%self.tenant_shard_id.shard_slug(), %timeline_id))]
@@ -3257,6 +3375,7 @@ impl Tenant {
             remote_client,
             deletion_queue_client: self.deletion_queue_client.clone(),
             timeline_get_throttle: self.timeline_get_throttle.clone(),
+            write_throttle: self.write_throttle.clone(),
         }
     }
 
@@ -3688,6 +3807,13 @@ pub(crate) mod harness {
                 heatmap_period: Some(tenant_conf.heatmap_period),
                 lazy_slru_download: Some(tenant_conf.lazy_slru_download),
                 timeline_get_throttle: Some(tenant_conf.timeline_get_throttle),
+                layer_compression: Some(tenant_conf.layer_compression),
+                compaction_algorithm: Some(tenant_conf.compaction_algorithm),
+                gc_branch_images: Some(tenant_conf.gc_branch_images),
+                resident_size_quota: tenant_conf.resident_size_quota,
+                write_throttle: Some(tenant_conf.write_throttle),
+                image_creation_read_heat: Some(tenant_conf.image_creation_read_heat),
+                layer_checksums: Some(tenant_conf.layer_checksums),
             }
         }
     }
@@ -3870,13 +3996,14 @@ pub(crate) mod harness {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::keyspace::KeySpaceAccum;
+    use crate::keyspace::{KeySpace, KeySpaceAccum};
     use crate::repository::{Key, Value};
     use crate::tenant::harness::*;
     use crate::DEFAULT_PG_VERSION;
-    use bytes::BytesMut;
+    use bytes::{Bytes, BytesMut};
     use hex_literal::hex;
     use once_cell::sync::Lazy;
+    use postgres_ffi::BLCKSZ;
     use rand::{thread_rng, Rng};
     use tokio_util::sync::CancellationToken;
 
@@ -4312,6 +4439,102 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_gc_branch_images() -> anyhow::Result<()> {
+        use pageserver_api::key::rel_block_to_key;
+        use pageserver_api::reltag::RelTag;
+
+        const NBLOCKS: u32 = 100;
+        const REL: RelTag = RelTag {
+            spcnode: 1663,
+            dbnode: 208101,
+            relnode: 1000,
+            forknum: 0,
+        };
+        fn page(blknum: u32, lsn: Lsn) -> Bytes {
+            let mut buf = BytesMut::from(format!("{blknum} at {lsn}").as_bytes());
+            buf.resize(BLCKSZ as usize, 0);
+            buf.freeze()
+        }
+        // Rewrite all pages of the relation, and flush them into a new layer
+        async fn write_pages(
+            tline: &Timeline,
+            lsn: Lsn,
+            ctx: &RequestContext,
+        ) -> anyhow::Result<()> {
+            let mut m = tline.begin_modification(lsn);
+            if lsn == Lsn(0x20) {
+                m.put_rel_creation(REL, NBLOCKS, ctx).await?;
+            }
+            for blknum in 0..NBLOCKS {
+                m.put_rel_page_image(REL, blknum, page(blknum, lsn))?;
+            }
+            m.commit(ctx).await?;
+            tline.freeze_and_flush().await?;
+            Ok(())
+        }
+
+        let mut harness = TenantHarness::create("test_gc_branch_images")?;
+        harness.tenant_conf.gc_branch_images = true;
+        let (tenant, ctx) = harness.load().await;
+        let tline = tenant
+            .create_test_timeline(TIMELINE_ID, Lsn(0x10), DEFAULT_PG_VERSION, &ctx)
+            .await?;
+
+        for lsn in [0x20, 0x30, 0x40, 0x50] {
+            write_pages(&tline, Lsn(lsn), &ctx).await?;
+        }
+        // Branch between the layers ending at 0x41 and the one starting there.
+        let branch_lsn = Lsn(0x41);
+        tenant
+            .branch_timeline_test(&tline, NEW_TIMELINE_ID, Some(branch_lsn), &ctx)
+            .await?;
+        let newtline = tenant
+            .get_timeline(NEW_TIMELINE_ID, true)
+            .expect("Should have a local timeline");
+        for lsn in [0x60, 0x70, 0x80, 0x90, 0xa0] {
+            write_pages(&tline, Lsn(lsn), &ctx).await?;
+        }
+
+        let result = tenant
+            .gc_iteration(
+                Some(TIMELINE_ID),
+                0x10,
+                Duration::ZERO,
+                &CancellationToken::new(),
+                &ctx,
+            )
+            .await?;
+        assert!(result.layers_created_at_branch_points > 0);
+        assert!(result.layers_removed > 0);
+        assert!(result.reclaimed_bytes[&TIMELINE_ID] > 0);
+
+        // Everything below the branch point was replaced by the images at it
+        let guard = tline.layers.read().await;
+        for layer in guard.layer_map().iter_historic_layers() {
+            assert!(
+                layer.get_lsn_range().end > branch_lsn,
+                "{} should have been garbage collected",
+                layer.filename()
+            );
+        }
+        drop(guard);
+
+        for blknum in 0..NBLOCKS {
+            let key = rel_block_to_key(REL, blknum);
+            assert_eq!(
+                newtline.get(key, branch_lsn, &ctx).await?,
+                page(blknum, Lsn(0x40))
+            );
+            assert_eq!(
+                tline.get(key, Lsn(0xa0), &ctx).await?,
+                page(blknum, Lsn(0xa0))
+            );
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn timeline_load() -> anyhow::Result<()> {
         const TEST_NAME: &str = "timeline_load";
@@ -4568,7 +4791,7 @@ mod tests {
             tline
                 .compact(&CancellationToken::new(), EnumSet::empty(), &ctx)
                 .await?;
-            tline.gc().await?;
+            tline.gc(&ctx).await?;
         }
 
         Ok(())
@@ -4656,7 +4879,119 @@ mod tests {
             tline
                 .compact(&CancellationToken::new(), EnumSet::empty(), &ctx)
                 .await?;
-            tline.gc().await?;
+            tline.gc(&ctx).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check that a vectored get of `keyspace` returns the same as a `get` of every key.
+    async fn validate_vectored_get(
+        tline: &Timeline,
+        keyspace: &KeySpace,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        for part in keyspace
+            .partition(Timeline::MAX_GET_VECTORED_KEYS * BLCKSZ as u64)
+            .parts
+        {
+            let vectored = tline.get_vectored(&part.ranges, lsn, ctx).await?;
+
+            let mut expected_keys = 0;
+            for range in &part.ranges {
+                let mut key = range.start;
+                while key != range.end {
+                    let expected = tline.get(key, lsn, ctx).await?;
+                    let actual = vectored
+                        .get(&key)
+                        .unwrap_or_else(|| panic!("key {key} missing from vectored get"))
+                        .as_ref()
+                        .map_err(|e| anyhow::anyhow!("vectored get of {key} failed: {e}"))?;
+                    assert_eq!(actual, &expected, "key {key} at {lsn}");
+
+                    expected_keys += 1;
+                    key = key.next();
+                }
+            }
+            assert_eq!(vectored.len(), expected_keys);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_vectored() -> anyhow::Result<()> {
+        let harness = TenantHarness::create("test_get_vectored")?;
+        let (tenant, ctx) = harness.load().await;
+        let mut tline = tenant
+            .create_test_timeline(TIMELINE_ID, Lsn(0x10), DEFAULT_PG_VERSION, &ctx)
+            .await?;
+
+        const NUM_KEYS: usize = 300;
+
+        let mut test_key = Key::from_hex("010000000033333333444444445500000000").unwrap();
+
+        let mut keyspace = KeySpaceAccum::new();
+        let mut lsn = Lsn(0x10);
+        for blknum in 0..NUM_KEYS {
+            lsn = Lsn(lsn.0 + 0x10);
+            test_key.field6 = blknum as u32;
+            let writer = tline.writer().await;
+            writer
+                .put(
+                    test_key,
+                    lsn,
+                    &Value::Image(test_img(&format!("{} at {}", blknum, lsn))),
+                    &ctx,
+                )
+                .await?;
+            writer.finish_write(lsn);
+            drop(writer);
+
+            keyspace.add_key(test_key);
+        }
+        let keyspace = keyspace.to_keyspace();
+
+        // Spread the updates over in-memory, delta and image layers, and over
+        // a few branches, so that the vectored read has to stitch them together.
+        for round in 0..6 {
+            if round % 2 == 1 {
+                let new_tline_id = TimelineId::generate();
+                tenant
+                    .branch_timeline_test(&tline, new_tline_id, Some(lsn), &ctx)
+                    .await?;
+                tline = tenant
+                    .get_timeline(new_tline_id, true)
+                    .expect("Should have the branched timeline");
+            }
+
+            for _ in 0..NUM_KEYS / 3 {
+                lsn = Lsn(lsn.0 + 0x10);
+                let blknum = thread_rng().gen_range(0..NUM_KEYS);
+                test_key.field6 = blknum as u32;
+                let writer = tline.writer().await;
+                writer
+                    .put(
+                        test_key,
+                        lsn,
+                        &Value::Image(test_img(&format!("{} at {}", blknum, lsn))),
+                        &ctx,
+                    )
+                    .await?;
+                writer.finish_write(lsn);
+                drop(writer);
+            }
+
+            validate_vectored_get(&tline, &keyspace, lsn, &ctx).await?;
+
+            tline.freeze_and_flush().await?;
+            validate_vectored_get(&tline, &keyspace, lsn, &ctx).await?;
+
+            tline
+                .compact(&CancellationToken::new(), EnumSet::empty(), &ctx)
+                .await?;
+            validate_vectored_get(&tline, &keyspace, lsn, &ctx).await?;
         }
 
         Ok(())
@@ -4754,7 +5089,7 @@ mod tests {
             tline
                 .compact(&CancellationToken::new(), EnumSet::empty(), &ctx)
                 .await?;
-            tline.gc().await?;
+            tline.gc(&ctx).await?;
         }
 
         Ok(())
@@ -11,7 +11,23 @@
 //! len <  128: 0XXXXXXX
 //! len >= 128: 1XXXXXXX XXXXXXXX XXXXXXXX XXXXXXXX
 //!
+//! Files written with blob compression enabled (see [`BlobCompression`])
+//! use the three bits after the high bit of a 4-byte header to describe
+//! how the payload is compressed, which limits the length to 28 bits.
+//! Short blobs are never compressed.
+//!
+//! len >= 128: 1CCCXXXX XXXXXXXX XXXXXXXX XXXXXXXX
+//!
+//! Files written with blob checksums (see [`BlobChecksum`]) follow the
+//! payload of each blob with a 4-byte big-endian CRC32C of the header and
+//! the payload as stored. Readers that don't know about checksums, like
+//! older versions, never look at those bytes: blobs are only ever located
+//! through their start offset.
+//!
 use bytes::{BufMut, BytesMut};
+use pageserver_api::models::CompressionAlgorithm;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
 use tokio_epoll_uring::{BoundedBuf, Slice};
 
 use crate::context::RequestContext;
@@ -21,6 +37,97 @@ use crate::virtual_file::VirtualFile;
 use std::cmp::min;
 use std::io::{Error, ErrorKind};
 
+/// Header byte of an uncompressed blob with a 4-byte length header
+const BYTE_UNCOMPRESSED: u8 = 0x80;
+/// Header byte of a zstd-compressed blob
+const BYTE_ZSTD: u8 = BYTE_UNCOMPRESSED | 0x10;
+/// The bits of the first header byte that carry compression information
+const LEN_COMPRESSION_BIT_MASK: u8 = 0xf0;
+
+/// Largest blob we can write into a file that may contain compressed blobs.
+const MAX_SUPPORTED_LEN_COMPRESSED: usize = 0x0fff_ffff;
+/// Largest blob we can write into a file without compression bits.
+const MAX_SUPPORTED_LEN: usize = 0x7fff_ffff;
+
+/// Compression that the blobs of a layer file may use, as recorded in the
+/// layer's summary. Layers written before compression support have no value
+/// there, and their length headers must be read without compression bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlobCompression {
+    Zstd,
+}
+
+impl BlobCompression {
+    /// The value to put in a layer summary for blobs written with `algorithm`.
+    pub fn for_algorithm(algorithm: CompressionAlgorithm) -> Option<Self> {
+        match algorithm {
+            CompressionAlgorithm::Disabled => None,
+            CompressionAlgorithm::Zstd { .. } => Some(BlobCompression::Zstd),
+        }
+    }
+}
+
+/// Checksum that follows every blob of a layer file, as recorded in the
+/// layer's summary. Layers written before checksum support have no value
+/// there, and their blobs are read without verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlobChecksum {
+    Crc32c,
+}
+
+/// Size of the checksum that follows a blob in files with [`BlobChecksum`].
+pub(crate) const CHECKSUM_LEN: usize = 4;
+
+/// The stored checksum of a blob does not match its contents.
+///
+/// Returned wrapped in an [`std::io::Error`] of kind [`ErrorKind::InvalidData`],
+/// use [`is_checksum_error`] to recognize it.
+#[derive(Debug, thiserror::Error)]
+#[error("checksum mismatch in blob at offset {offset}: stored {stored:#010x}, computed {computed:#010x}")]
+pub(crate) struct BlobChecksumError {
+    offset: u64,
+    stored: u32,
+    computed: u32,
+}
+
+/// Checksum of a blob with the given header and payload, as stored in the file.
+fn blob_checksum(header: &[u8], payload: &[u8]) -> u32 {
+    crc32c::crc32c_append(crc32c::crc32c(header), payload)
+}
+
+/// Verify the checksum stored after the blob at `offset`.
+pub(crate) fn verify_checksum(
+    offset: u64,
+    header: &[u8],
+    payload: &[u8],
+    stored: [u8; CHECKSUM_LEN],
+) -> Result<(), Error> {
+    let stored = u32::from_be_bytes(stored);
+    let computed = blob_checksum(header, payload);
+    if stored != computed {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            BlobChecksumError {
+                offset,
+                stored,
+                computed,
+            },
+        ));
+    }
+    Ok(())
+}
+
+/// Returns true if `err` was caused by a blob failing checksum verification.
+pub(crate) fn is_checksum_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause.is::<BlobChecksumError>()
+            || cause
+                .downcast_ref::<Error>()
+                .and_then(|e| e.get_ref())
+                .is_some_and(|inner| inner.is::<BlobChecksumError>())
+    })
+}
+
 impl<'a> BlockCursor<'a> {
     /// Read a blob into a new buffer.
     pub async fn read_blob(
@@ -47,9 +154,14 @@ impl<'a> BlockCursor<'a> {
 
         // peek at the first byte, to determine if it's a 1- or 4-byte length
         let first_len_byte = buf[off];
+        let mut compression_bits = BYTE_UNCOMPRESSED;
+        // the header as stored, for checksum verification
+        let mut header = [first_len_byte, 0, 0, 0];
+        let header_len;
         let len: usize = if first_len_byte < 0x80 {
             // 1-byte length header
             off += 1;
+            header_len = 1;
             first_len_byte as usize
         } else {
             // 4-byte length header
@@ -66,12 +178,31 @@ impl<'a> BlockCursor<'a> {
                 len_buf.copy_from_slice(&buf[off..off + 4]);
                 off += 4;
             }
-            len_buf[0] &= 0x7f;
+            header = len_buf;
+            header_len = 4;
+            if self.read_compressed {
+                compression_bits = len_buf[0] & LEN_COMPRESSION_BIT_MASK;
+                len_buf[0] &= !LEN_COMPRESSION_BIT_MASK;
+            } else {
+                len_buf[0] &= 0x7f;
+            }
             u32::from_be_bytes(len_buf) as usize
         };
 
-        dstbuf.clear();
-        dstbuf.reserve(len);
+        let mut compressed_buf = Vec::new();
+        let payload_buf = match compression_bits {
+            BYTE_UNCOMPRESSED => &mut *dstbuf,
+            BYTE_ZSTD => &mut compressed_buf,
+            bits => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown compression bits {bits:#04x} in blob header"),
+                ))
+            }
+        };
+
+        payload_buf.clear();
+        payload_buf.reserve(len);
 
         // Read the payload
         let mut remain = len;
@@ -85,14 +216,105 @@ impl<'a> BlockCursor<'a> {
                 page_remain = PAGE_SZ;
             }
             let this_blk_len = min(remain, page_remain);
-            dstbuf.extend_from_slice(&buf[off..off + this_blk_len]);
+            payload_buf.extend_from_slice(&buf[off..off + this_blk_len]);
             remain -= this_blk_len;
             off += this_blk_len;
         }
+
+        if self.read_checksums {
+            // The checksum may be split across two pages, like the header
+            let mut stored = [0u8; CHECKSUM_LEN];
+            let mut copied = 0;
+            while copied < CHECKSUM_LEN {
+                if off == PAGE_SZ {
+                    blknum += 1;
+                    buf = self.read_blk(blknum, ctx).await?;
+                    off = 0;
+                }
+                let this_blk_len = min(CHECKSUM_LEN - copied, PAGE_SZ - off);
+                stored[copied..copied + this_blk_len]
+                    .copy_from_slice(&buf[off..off + this_blk_len]);
+                copied += this_blk_len;
+                off += this_blk_len;
+            }
+            verify_checksum(offset, &header[..header_len], payload_buf, stored)?;
+        }
+
+        if compression_bits == BYTE_ZSTD {
+            // don't hold on to the page cache slot while decompressing
+            drop(buf);
+            dstbuf.clear();
+            *dstbuf = zstd_decompress(&compressed_buf, std::mem::take(dstbuf)).await?;
+        }
         Ok(())
     }
 }
 
+/// Length header of a blob, see the module documentation for the format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlobHeader {
+    /// Size of the header itself, 1 or 4 bytes
+    pub(crate) header_len: usize,
+    /// Size of the payload that follows the header, as stored
+    pub(crate) payload_len: usize,
+    /// Compression of the payload
+    pub(crate) compression: Option<BlobCompression>,
+}
+
+impl BlobHeader {
+    /// Parse the header at the start of `buf`. A 4-byte header must be fully
+    /// contained in `buf`. Compression bits are only interpreted if
+    /// `read_compressed` is set, see [`BlobCompression`].
+    pub(crate) fn parse(buf: &[u8], read_compressed: bool) -> Result<Self, Error> {
+        let first_len_byte = *buf
+            .first()
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "blob header out of bounds"))?;
+        if first_len_byte < 0x80 {
+            return Ok(BlobHeader {
+                header_len: 1,
+                payload_len: first_len_byte as usize,
+                compression: None,
+            });
+        }
+
+        let mut len_buf: [u8; 4] = buf
+            .get(..4)
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "blob header out of bounds"))?;
+        let compression = if read_compressed {
+            let bits = len_buf[0] & LEN_COMPRESSION_BIT_MASK;
+            len_buf[0] &= !LEN_COMPRESSION_BIT_MASK;
+            match bits {
+                BYTE_UNCOMPRESSED => None,
+                BYTE_ZSTD => Some(BlobCompression::Zstd),
+                bits => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("unknown compression bits {bits:#04x} in blob header"),
+                    ))
+                }
+            }
+        } else {
+            len_buf[0] &= 0x7f;
+            None
+        };
+
+        Ok(BlobHeader {
+            header_len: 4,
+            payload_len: u32::from_be_bytes(len_buf) as usize,
+            compression,
+        })
+    }
+}
+
+/// Decompress a zstd-compressed blob payload, appending to `dst`.
+pub(crate) async fn zstd_decompress(src: &[u8], dst: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let mut decoder = async_compression::tokio::write::ZstdDecoder::new(dst);
+    decoder.write_all(src).await?;
+    decoder.shutdown().await?;
+    Ok(decoder.into_inner())
+}
+
 /// A wrapper of `VirtualFile` that allows users to write blobs.
 ///
 /// If a `BlobWriter` is dropped, the internal buffer will be
@@ -105,6 +327,8 @@ pub struct BlobWriter<const BUFFERED: bool> {
     buf: Vec<u8>,
     /// We do tiny writes for the length headers; they need to be in an owned buffer;
     io_buf: Option<BytesMut>,
+    /// Whether to follow each blob with a checksum, see [`BlobChecksum`]
+    checksums: bool,
 }
 
 impl<const BUFFERED: bool> BlobWriter<BUFFERED> {
@@ -114,9 +338,17 @@ impl<const BUFFERED: bool> BlobWriter<BUFFERED> {
             offset: start_offset,
             buf: Vec::with_capacity(Self::CAPACITY),
             io_buf: Some(BytesMut::new()),
+            checksums: false,
         }
     }
 
+    /// Follow each blob with a checksum. The file's summary must then record
+    /// [`BlobChecksum::Crc32c`], so that readers know to skip and verify them.
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
     pub fn size(&self) -> u64 {
         self.offset
     }
@@ -211,12 +443,30 @@ impl<const BUFFERED: bool> BlobWriter<BUFFERED> {
     /// Write a blob of data. Returns the offset that it was written to,
     /// which can be used to retrieve the data later.
     pub async fn write_blob<B: BoundedBuf>(&mut self, srcbuf: B) -> (B::Buf, Result<u64, Error>) {
+        self.write_blob_maybe_compressed(srcbuf, CompressionAlgorithm::Disabled)
+            .await
+    }
+
+    /// Write a blob of data, compressed with `algorithm` if that makes it
+    /// smaller. Returns the offset that it was written to, which can be used
+    /// to retrieve the data later.
+    ///
+    /// Blobs written with compression enabled can only be read back by a
+    /// [`BlockCursor`] that reads compressed blobs, so the file's summary
+    /// must record [`BlobCompression::for_algorithm`].
+    pub async fn write_blob_maybe_compressed<B: BoundedBuf>(
+        &mut self,
+        srcbuf: B,
+        algorithm: CompressionAlgorithm,
+    ) -> (B::Buf, Result<u64, Error>) {
         let offset = self.offset;
 
+        let srcbuf = srcbuf.slice_full();
         let len = srcbuf.bytes_init();
 
         let mut io_buf = self.io_buf.take().expect("we always put it back below");
         io_buf.clear();
+        let mut compressed_buf = None;
         let (io_buf, hdr_res) = async {
             if len < 128 {
                 // Short blob. Write a 1-byte length header
@@ -224,7 +474,12 @@ impl<const BUFFERED: bool> BlobWriter<BUFFERED> {
                 self.write_all(io_buf).await
             } else {
                 // Write a 4-byte length header
-                if len > 0x7fff_ffff {
+                let max_len = if algorithm.is_enabled() {
+                    MAX_SUPPORTED_LEN_COMPRESSED
+                } else {
+                    MAX_SUPPORTED_LEN
+                };
+                if len > max_len {
                     return (
                         io_buf,
                         Err(Error::new(
@@ -233,23 +488,75 @@ impl<const BUFFERED: bool> BlobWriter<BUFFERED> {
                         )),
                     );
                 }
-                let mut len_buf = (len as u32).to_be_bytes();
-                len_buf[0] |= 0x80;
+                let (header_bits, len_written) = match algorithm {
+                    CompressionAlgorithm::Zstd { level } => {
+                        let compressed = match zstd_compress(&srcbuf[..], level).await {
+                            Ok(compressed) => compressed,
+                            Err(e) => return (io_buf, Err(e)),
+                        };
+                        if compressed.len() < len {
+                            let compressed_len = compressed.len();
+                            compressed_buf = Some(compressed);
+                            (BYTE_ZSTD, compressed_len)
+                        } else {
+                            (BYTE_UNCOMPRESSED, len)
+                        }
+                    }
+                    CompressionAlgorithm::Disabled => (BYTE_UNCOMPRESSED, len),
+                };
+                let mut len_buf = (len_written as u32).to_be_bytes();
+                len_buf[0] |= header_bits;
                 io_buf.extend_from_slice(&len_buf[..]);
                 self.write_all(io_buf).await
             }
         }
         .await;
+        let checksum = self.checksums.then(|| {
+            let payload = compressed_buf.as_deref().unwrap_or(&srcbuf[..]);
+            blob_checksum(&io_buf, payload)
+        });
         self.io_buf = Some(io_buf);
         match hdr_res {
             Ok(_) => (),
-            Err(e) => return (Slice::into_inner(srcbuf.slice(..)), Err(e)),
+            Err(e) => return (Slice::into_inner(srcbuf), Err(e)),
         }
-        let (srcbuf, res) = self.write_all(srcbuf).await;
-        (srcbuf, res.map(|_| offset))
+        let (srcbuf, res) = if let Some(compressed_buf) = compressed_buf {
+            let (_buf, res) = self.write_all(compressed_buf).await;
+            (Slice::into_inner(srcbuf), res)
+        } else {
+            self.write_all(srcbuf).await
+        };
+        if let Err(e) = res {
+            return (srcbuf, Err(e));
+        }
+        if let Some(checksum) = checksum {
+            let mut io_buf = self.io_buf.take().expect("we always put it back below");
+            io_buf.clear();
+            io_buf.put_u32(checksum);
+            let (io_buf, res) = self.write_all(io_buf).await;
+            self.io_buf = Some(io_buf);
+            if let Err(e) = res {
+                return (srcbuf, Err(e));
+            }
+        }
+        (srcbuf, Ok(offset))
     }
 }
 
+async fn zstd_compress(src: &[u8], level: Option<i8>) -> Result<Vec<u8>, Error> {
+    use async_compression::tokio::write::ZstdEncoder;
+
+    let mut encoder = match level {
+        Some(level) => {
+            ZstdEncoder::with_quality(Vec::new(), async_compression::Level::Precise(level.into()))
+        }
+        None => ZstdEncoder::new(Vec::new()),
+    };
+    encoder.write_all(src).await?;
+    encoder.shutdown().await?;
+    Ok(encoder.into_inner())
+}
+
 impl BlobWriter<true> {
     /// Access the underlying `VirtualFile`.
     ///
@@ -283,6 +590,28 @@ mod tests {
     use rand::{Rng, SeedableRng};
 
     async fn round_trip_test<const BUFFERED: bool>(blobs: &[Vec<u8>]) -> Result<(), Error> {
+        round_trip_test_compressed::<BUFFERED>(blobs, CompressionAlgorithm::Disabled).await
+    }
+
+    async fn round_trip_test_compressed<const BUFFERED: bool>(
+        blobs: &[Vec<u8>],
+        algorithm: CompressionAlgorithm,
+    ) -> Result<(), Error> {
+        round_trip_test_impl::<BUFFERED>(blobs, algorithm, false).await
+    }
+
+    async fn round_trip_test_checksummed<const BUFFERED: bool>(
+        blobs: &[Vec<u8>],
+        algorithm: CompressionAlgorithm,
+    ) -> Result<(), Error> {
+        round_trip_test_impl::<BUFFERED>(blobs, algorithm, true).await
+    }
+
+    async fn round_trip_test_impl<const BUFFERED: bool>(
+        blobs: &[Vec<u8>],
+        algorithm: CompressionAlgorithm,
+        checksums: bool,
+    ) -> Result<(), Error> {
         let temp_dir = camino_tempfile::tempdir()?;
         let pathbuf = temp_dir.path().join("file");
         let ctx = RequestContext::new(TaskKind::UnitTest, DownloadBehavior::Error);
@@ -291,9 +620,11 @@ mod tests {
         let mut offsets = Vec::new();
         {
             let file = VirtualFile::create(pathbuf.as_path()).await?;
-            let mut wtr = BlobWriter::<BUFFERED>::new(file, 0);
+            let mut wtr = BlobWriter::<BUFFERED>::new(file, 0).with_checksums(checksums);
             for blob in blobs.iter() {
-                let (_, res) = wtr.write_blob(blob.clone()).await;
+                let (_, res) = wtr
+                    .write_blob_maybe_compressed(blob.clone(), algorithm)
+                    .await;
                 let offs = res?;
                 offsets.push(offs);
             }
@@ -307,7 +638,8 @@ mod tests {
 
         let file = VirtualFile::open(pathbuf.as_path()).await?;
         let rdr = BlockReaderRef::VirtualFile(&file);
-        let rdr = BlockCursor::new(rdr);
+        let rdr = BlockCursor::new_with_compression(rdr, algorithm.is_enabled())
+            .with_checksums(checksums);
         for (idx, (blob, offset)) in blobs.iter().zip(offsets.iter()).enumerate() {
             let blob_read = rdr.read_blob(*offset, &ctx).await?;
             assert_eq!(
@@ -318,6 +650,8 @@ mod tests {
         Ok(())
     }
 
+    const ZSTD: CompressionAlgorithm = CompressionAlgorithm::Zstd { level: Some(1) };
+
     fn random_array(len: usize) -> Vec<u8> {
         let mut rng = rand::thread_rng();
         (0..len).map(|_| rng.gen()).collect::<_>()
@@ -353,6 +687,8 @@ mod tests {
         ];
         round_trip_test::<false>(blobs).await?;
         round_trip_test::<true>(blobs).await?;
+        round_trip_test_compressed::<false>(blobs, ZSTD).await?;
+        round_trip_test_compressed::<true>(blobs, ZSTD).await?;
         Ok(())
     }
 
@@ -363,6 +699,31 @@ mod tests {
             .collect::<Vec<_>>();
         round_trip_test::<false>(&blobs).await?;
         round_trip_test::<true>(&blobs).await?;
+        round_trip_test_compressed::<false>(&blobs, ZSTD).await?;
+        round_trip_test_compressed::<true>(&blobs, ZSTD).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compressible_arrays() -> Result<(), Error> {
+        let blobs = (0..PAGE_SZ / 64)
+            .map(|v| vec![v as u8; v * 64])
+            .collect::<Vec<_>>();
+        round_trip_test_compressed::<false>(&blobs, ZSTD).await?;
+        round_trip_test_compressed::<true>(&blobs, ZSTD).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compression_shrinks_blob() -> Result<(), Error> {
+        let temp_dir = camino_tempfile::tempdir()?;
+        let file = VirtualFile::create(temp_dir.path().join("file").as_path()).await?;
+        let mut wtr = BlobWriter::<true>::new(file, 0);
+        let (_, res) = wtr
+            .write_blob_maybe_compressed(vec![0xAB; PAGE_SZ], ZSTD)
+            .await;
+        assert_eq!(res?, 0);
+        assert!(wtr.size() < PAGE_SZ as u64 / 2, "size: {}", wtr.size());
         Ok(())
     }
 
@@ -393,6 +754,67 @@ mod tests {
         ];
         round_trip_test::<false>(blobs).await?;
         round_trip_test::<true>(blobs).await?;
+        round_trip_test_checksummed::<false>(blobs, CompressionAlgorithm::Disabled).await?;
+        round_trip_test_checksummed::<true>(blobs, CompressionAlgorithm::Disabled).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checksummed_arrays() -> Result<(), Error> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let blobs = (0..256)
+            .map(|_| {
+                let sz: u16 = rng.gen();
+                if rng.gen() {
+                    vec![sz as u8; sz.into()]
+                } else {
+                    random_array((sz & 255).into())
+                }
+            })
+            .collect::<Vec<_>>();
+        for algorithm in [CompressionAlgorithm::Disabled, ZSTD] {
+            round_trip_test_checksummed::<false>(&blobs, algorithm).await?;
+            round_trip_test_checksummed::<true>(&blobs, algorithm).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checksum_mismatch() -> Result<(), Error> {
+        let temp_dir = camino_tempfile::tempdir()?;
+        let pathbuf = temp_dir.path().join("file");
+        let ctx = RequestContext::new(TaskKind::UnitTest, DownloadBehavior::Error);
+
+        let blobs = [b"Hello, World!".to_vec(), random_array(3 * PAGE_SZ)];
+        let mut offsets = Vec::new();
+        {
+            let file = VirtualFile::create(pathbuf.as_path()).await?;
+            let mut wtr = BlobWriter::<true>::new(file, 0).with_checksums(true);
+            for blob in blobs.iter() {
+                let (_, res) = wtr.write_blob(blob.clone()).await;
+                offsets.push(res?);
+            }
+            let (_, res) = wtr.write_blob(vec![0; PAGE_SZ]).await;
+            res?;
+            wtr.flush_buffer().await?;
+        }
+
+        // Flip a bit in the payload of the second blob
+        let mut contents = std::fs::read(&pathbuf)?;
+        contents[offsets[1] as usize + 4 + PAGE_SZ] ^= 1;
+        std::fs::write(&pathbuf, contents)?;
+
+        let file = VirtualFile::open(pathbuf.as_path()).await?;
+        let rdr = BlockCursor::new(BlockReaderRef::VirtualFile(&file)).with_checksums(true);
+        assert_eq!(rdr.read_blob(offsets[0], &ctx).await?, blobs[0]);
+        let err = rdr.read_blob(offsets[1], &ctx).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = anyhow::Error::new(err).context("read blob");
+        assert!(is_checksum_error(&err), "{err:#}");
+
+        // Readers that don't know about the checksums don't notice
+        let rdr = BlockCursor::new(BlockReaderRef::VirtualFile(&file));
+        assert_eq!(rdr.read_blob(offsets[1], &ctx).await?.len(), blobs[1].len());
         Ok(())
     }
 }
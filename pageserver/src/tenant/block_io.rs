@@ -5,7 +5,7 @@
 use super::ephemeral_file::EphemeralFile;
 use super::storage_layer::delta_layer::{Adapter, DeltaLayerInner};
 use crate::context::RequestContext;
-use crate::page_cache::{self, PageReadGuard, PageWriteGuard, ReadBufResult, PAGE_SZ};
+use crate::page_cache::{self, PageReadGuard, PAGE_SZ};
 use crate::virtual_file::VirtualFile;
 use bytes::Bytes;
 use std::ops::Deref;
@@ -102,7 +102,7 @@ impl<'a> BlockReaderRef<'a> {
             #[cfg(test)]
             TestDisk(r) => r.read_blk(blknum),
             #[cfg(test)]
-            VirtualFile(r) => r.read_blk(blknum).await,
+            VirtualFile(r) => r.read_blk(blknum, ctx).await,
         }
     }
 }
@@ -127,19 +127,39 @@ impl<'a> BlockReaderRef<'a> {
 /// ```
 ///
 pub struct BlockCursor<'a> {
+    pub(super) read_compressed: bool,
+    pub(super) read_checksums: bool,
     reader: BlockReaderRef<'a>,
 }
 
 impl<'a> BlockCursor<'a> {
     pub(crate) fn new(reader: BlockReaderRef<'a>) -> Self {
-        BlockCursor { reader }
+        Self::new_with_compression(reader, false)
+    }
+    /// Create a cursor whose blob reads interpret the compression bits of
+    /// the length header. Only valid for files written with compression
+    /// support, see [`crate::tenant::blob_io`].
+    pub(crate) fn new_with_compression(reader: BlockReaderRef<'a>, read_compressed: bool) -> Self {
+        BlockCursor {
+            read_compressed,
+            read_checksums: false,
+            reader,
+        }
     }
     // Needed by cli
-    pub fn new_fileblockreader(reader: &'a FileBlockReader) -> Self {
+    pub fn new_fileblockreader(reader: &'a FileBlockReader, read_compressed: bool) -> Self {
         BlockCursor {
+            read_compressed,
+            read_checksums: false,
             reader: BlockReaderRef::FileBlockReader(reader),
         }
     }
+    /// Verify the checksum that follows every blob. Only valid for files
+    /// written with blob checksums, see [`crate::tenant::blob_io`].
+    pub fn with_checksums(mut self, read_checksums: bool) -> Self {
+        self.read_checksums = read_checksums;
+        self
+    }
 
     /// Read a block.
     ///
@@ -174,44 +194,25 @@ impl FileBlockReader {
         FileBlockReader { file_id, file }
     }
 
-    /// Read a page from the underlying file into given buffer.
-    async fn fill_buffer(
-        &self,
-        buf: PageWriteGuard<'static>,
-        blkno: u32,
-    ) -> Result<PageWriteGuard<'static>, std::io::Error> {
-        assert!(buf.len() == PAGE_SZ);
-        self.file
-            .read_exact_at_page(buf, blkno as u64 * PAGE_SZ as u64)
-            .await
-    }
     /// Read a block.
     ///
     /// Returns a "lease" object that can be used to
     /// access to the contents of the page. (For the page cache, the
     /// lease object represents a lock on the buffer.)
+    ///
+    /// Page cache buffers and block offsets are aligned for direct I/O, so with
+    /// [`crate::virtual_file::IoMode::Direct`] the page is read into the buffer
+    /// without a copy.
     pub async fn read_blk(
         &self,
         blknum: u32,
         ctx: &RequestContext,
     ) -> Result<BlockLease, std::io::Error> {
-        let cache = page_cache::get();
-        match cache
-            .read_immutable_buf(self.file_id, blknum, ctx)
-            .await
-            .map_err(|e| {
-                std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Failed to read immutable buf: {e:#}"),
-                )
-            })? {
-            ReadBufResult::Found(guard) => Ok(guard.into()),
-            ReadBufResult::NotFound(write_guard) => {
-                // Read the page from disk into the buffer
-                let write_guard = self.fill_buffer(write_guard, blknum).await?;
-                Ok(write_guard.mark_valid().into())
-            }
-        }
+        let guard = self
+            .file
+            .read_page_cached(self.file_id, blknum, ctx)
+            .await?;
+        Ok(guard.into())
     }
 }
 
@@ -9,8 +9,8 @@
 //! may lead to a data loss.
 //!
 use anyhow::bail;
-use pageserver_api::models::EvictionPolicy;
 use pageserver_api::models::{self, ThrottleConfig};
+use pageserver_api::models::{CompactionAlgorithm, CompressionAlgorithm, EvictionPolicy};
 use pageserver_api::shard::{ShardCount, ShardIdentity, ShardNumber, ShardStripeSize};
 use serde::de::IntoDeserializer;
 use serde::{Deserialize, Serialize};
@@ -350,6 +350,34 @@ pub struct TenantConf {
     pub lazy_slru_download: bool,
 
     pub timeline_get_throttle: pageserver_api::models::ThrottleConfig,
+
+    /// Compression of values in newly written image and delta layers.
+    pub layer_compression: CompressionAlgorithm,
+
+    /// Algorithm used to compact delta layers.
+    pub compaction_algorithm: CompactionAlgorithm,
+
+    /// Let GC create image layers at the branch points of child timelines, so
+    /// that the history below them can be collected.
+    pub gc_branch_images: bool,
+
+    /// If set, the disk usage based eviction task evicts the least recently used
+    /// layers of the tenant whenever its resident layers take more bytes than this.
+    pub resident_size_quota: Option<u64>,
+
+    /// Throttle for the bytes written to disk by WAL ingest, layer flushes and
+    /// compaction, depending on its `task_kinds`. Separate from
+    /// `timeline_get_throttle`: its amounts are bytes, not keys.
+    pub write_throttle: pageserver_api::models::ThrottleConfig,
+
+    /// Adjust `image_creation_threshold` to the observed reads of each partition:
+    /// lower it for partitions whose reads are expensive to reconstruct, and raise
+    /// it for partitions that are not read at all.
+    pub image_creation_read_heat: bool,
+
+    /// Write a checksum with every value of newly written image and delta layers,
+    /// verified when the values are read.
+    pub layer_checksums: bool,
 }
 
 /// Same as TenantConf, but this struct preserves the information about
@@ -442,6 +470,34 @@ pub struct TenantConfOpt {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeline_get_throttle: Option<pageserver_api::models::ThrottleConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub layer_compression: Option<CompressionAlgorithm>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub compaction_algorithm: Option<CompactionAlgorithm>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub gc_branch_images: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub resident_size_quota: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub write_throttle: Option<pageserver_api::models::ThrottleConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub image_creation_read_heat: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub layer_checksums: Option<bool>,
 }
 
 impl TenantConfOpt {
@@ -494,6 +550,24 @@ impl TenantConfOpt {
                 .timeline_get_throttle
                 .clone()
                 .unwrap_or(global_conf.timeline_get_throttle),
+            layer_compression: self
+                .layer_compression
+                .unwrap_or(global_conf.layer_compression),
+            compaction_algorithm: self
+                .compaction_algorithm
+                .unwrap_or(global_conf.compaction_algorithm),
+            gc_branch_images: self
+                .gc_branch_images
+                .unwrap_or(global_conf.gc_branch_images),
+            resident_size_quota: self.resident_size_quota.or(global_conf.resident_size_quota),
+            write_throttle: self
+                .write_throttle
+                .clone()
+                .unwrap_or(global_conf.write_throttle),
+            image_creation_read_heat: self
+                .image_creation_read_heat
+                .unwrap_or(global_conf.image_creation_read_heat),
+            layer_checksums: self.layer_checksums.unwrap_or(global_conf.layer_checksums),
         }
     }
 }
@@ -534,6 +608,13 @@ impl Default for TenantConf {
             heatmap_period: Duration::ZERO,
             lazy_slru_download: false,
             timeline_get_throttle: crate::tenant::throttle::Config::disabled(),
+            layer_compression: CompressionAlgorithm::Disabled,
+            compaction_algorithm: CompactionAlgorithm::Legacy,
+            gc_branch_images: false,
+            resident_size_quota: None,
+            write_throttle: crate::tenant::throttle::Config::disabled(),
+            image_creation_read_heat: true,
+            layer_checksums: false,
         }
     }
 }
@@ -607,6 +688,13 @@ impl From<TenantConfOpt> for models::TenantConfig {
             heatmap_period: value.heatmap_period.map(humantime),
             lazy_slru_download: value.lazy_slru_download,
             timeline_get_throttle: value.timeline_get_throttle.map(ThrottleConfig::from),
+            layer_compression: value.layer_compression,
+            compaction_algorithm: value.compaction_algorithm,
+            gc_branch_images: value.gc_branch_images,
+            resident_size_quota: value.resident_size_quota,
+            write_throttle: value.write_throttle,
+            image_creation_read_heat: value.image_creation_read_heat,
+            layer_checksums: value.layer_checksums,
         }
     }
 }
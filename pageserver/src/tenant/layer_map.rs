@@ -153,11 +153,27 @@ pub struct SearchResult {
     pub lsn_floor: Lsn,
 }
 
-pub struct OrderedSearchResult(SearchResult);
+pub struct OrderedSearchResult(pub SearchResult);
+
+impl OrderedSearchResult {
+    /// Results are ordered by LSN floor, and then by layer. Distinct layers
+    /// must not compare equal, since results are used as map keys.
+    fn sort_key(&self) -> (Lsn, Key, Key, Lsn, Lsn, bool) {
+        let SearchResult { layer, lsn_floor } = &self.0;
+        (
+            *lsn_floor,
+            layer.key_range.start,
+            layer.key_range.end,
+            layer.lsn_range.start,
+            layer.lsn_range.end,
+            layer.is_delta,
+        )
+    }
+}
 
 impl Ord for OrderedSearchResult {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.0.lsn_floor.cmp(&other.0.lsn_floor)
+        self.sort_key().cmp(&other.sort_key())
     }
 }
 
@@ -169,7 +185,7 @@ impl PartialOrd for OrderedSearchResult {
 
 impl PartialEq for OrderedSearchResult {
     fn eq(&self, other: &Self) -> bool {
-        self.0.lsn_floor == other.0.lsn_floor
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -870,6 +886,7 @@ impl LayerMap {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pageserver_api::keyspace::KeySpace;
 
     #[derive(Clone)]
     struct LayerDesc {
@@ -988,4 +1005,49 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn ranged_search_distinct_layers_with_same_lsn_floor() {
+        let layers = vec![
+            LayerDesc {
+                key_range: Key::from_i128(0)..Key::from_i128(10),
+                lsn_range: Lsn(10)..Lsn(11),
+                is_delta: false,
+            },
+            LayerDesc {
+                key_range: Key::from_i128(10)..Key::from_i128(20),
+                lsn_range: Lsn(10)..Lsn(11),
+                is_delta: false,
+            },
+        ];
+
+        let layer_map = create_layer_map(layers);
+        let range = Key::from_i128(5)..Key::from_i128(15);
+        let result = layer_map.range_search(range, Lsn(100)).unwrap();
+
+        let found: Vec<_> = result
+            .found
+            .into_iter()
+            .map(|(search_result, accum)| {
+                (search_result.0.layer.key_range.clone(), accum.to_keyspace())
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    Key::from_i128(0)..Key::from_i128(10),
+                    KeySpace {
+                        ranges: vec![Key::from_i128(5)..Key::from_i128(10)]
+                    }
+                ),
+                (
+                    Key::from_i128(10)..Key::from_i128(20),
+                    KeySpace {
+                        ranges: vec![Key::from_i128(10)..Key::from_i128(15)]
+                    }
+                ),
+            ]
+        );
+    }
 }
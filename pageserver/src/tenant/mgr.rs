@@ -1903,6 +1903,9 @@ pub(crate) async fn detach_tenant(
         deletion_queue_client,
     )
     .await?;
+    if let Some(cache) = crate::basebackup_cache::get() {
+        cache.remove_tenant(tenant_shard_id).await;
+    }
     // Although we are cleaning up the tenant, this task is not meant to be bound by the lifetime of the tenant in memory.
     // After a tenant is detached, there are no more task_mgr tasks for that tenant_id.
     let task_tenant_id = None;
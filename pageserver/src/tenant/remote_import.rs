@@ -0,0 +1,355 @@
+//! Importing a new timeline from a basebackup in remote storage.
+//!
+//! This is the remote storage counterpart of the `import basebackup` and `import wal`
+//! page service commands. Instead of streaming the tarballs through a libpq connection,
+//! the client puts them in the pageserver's remote storage, and the pageserver downloads
+//! and imports them in a background task. The progress of the task is kept in
+//! [`Tenant::timeline_imports`], for the client to poll.
+//!
+//! The basebackups must be under [`import_prefix`], so that a tenant can't import the
+//! layers or the imports of another tenant.
+//!
+//! The tarballs are downloaded in byte ranges of [`DOWNLOAD_CHUNK_SIZE`], each one a
+//! separate request: a single request for the basebackup of a large database would run
+//! into the remote storage timeout.
+
+use anyhow::Context;
+use camino::{Utf8Component, Utf8Path};
+use futures::StreamExt;
+use pageserver_api::models::{
+    TimelineImportRequest, TimelineImportTaskInfo, TimelineImportTaskState,
+};
+use remote_storage::{DownloadError, GenericRemoteStorage, RemotePath};
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::*;
+use utils::id::{TenantId, TimelineId};
+
+use super::Tenant;
+use crate::context::{DownloadBehavior, RequestContext};
+use crate::import_datadir;
+use crate::task_mgr::{self, TaskKind};
+
+const DOWNLOAD_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Buffer between the download of a tarball and its import.
+const IMPORT_BUFFER_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("importing a timeline requires remote storage")]
+    NoRemoteStorage,
+
+    #[error("importing a timeline into a sharded tenant is not supported")]
+    Sharded,
+
+    #[error("invalid remote path: {0:#}")]
+    InvalidPath(anyhow::Error),
+
+    #[error("timeline already exists")]
+    AlreadyExists,
+
+    #[error("timeline is already being imported")]
+    AlreadyInProgress,
+}
+
+impl Tenant {
+    /// Start importing a new timeline from a basebackup in remote storage, see the
+    /// module documentation.
+    pub(crate) fn spawn_import_timeline_from_remote(
+        self: &Arc<Self>,
+        timeline_id: TimelineId,
+        request: TimelineImportRequest,
+        broker_client: storage_broker::BrokerClientChannel,
+    ) -> Result<TimelineImportTaskInfo, Error> {
+        let remote_storage = self.remote_storage.clone().ok_or(Error::NoRemoteStorage)?;
+        if !self.tenant_shard_id.is_unsharded() {
+            return Err(Error::Sharded);
+        }
+        let remote_path = parse_remote_path(self.tenant_shard_id.tenant_id, &request.remote_path)
+            .map_err(Error::InvalidPath)?;
+
+        let mut imports = self.timeline_imports.lock().unwrap();
+        if let Some(info) = imports.get(&timeline_id) {
+            if info.state == TimelineImportTaskState::Running {
+                return Err(Error::AlreadyInProgress);
+            }
+        }
+        if self.get_timeline(timeline_id, false).is_ok() {
+            return Err(Error::AlreadyExists);
+        }
+
+        let tenant = Arc::clone(self);
+        let task_id = task_mgr::spawn(
+            task_mgr::BACKGROUND_RUNTIME.handle(),
+            TaskKind::ImportTimeline,
+            Some(self.tenant_shard_id),
+            Some(timeline_id),
+            "import timeline from remote storage",
+            false,
+            async move {
+                let ctx = RequestContext::new(TaskKind::ImportTimeline, DownloadBehavior::Download);
+                let res = tenant
+                    .import_timeline_from_remote(
+                        timeline_id,
+                        &remote_storage,
+                        &remote_path,
+                        &request,
+                        broker_client,
+                        &ctx,
+                    )
+                    .await;
+
+                let mut imports = tenant.timeline_imports.lock().unwrap();
+                let info = imports
+                    .get_mut(&timeline_id)
+                    .expect("inserted before the task can run, and never removed");
+                match res {
+                    Ok(()) => {
+                        info!("import complete");
+                        info.state = TimelineImportTaskState::Completed;
+                    }
+                    Err(e) => {
+                        error!("import failed: {e:#}");
+                        info.state = TimelineImportTaskState::Failed;
+                        info.error = Some(format!("{e:#}"));
+                    }
+                }
+                Ok(())
+            }
+            .instrument(info_span!(parent: None, "import_timeline_from_remote", tenant_id = %self.tenant_shard_id.tenant_id, shard_id = %self.tenant_shard_id.shard_slug(), %timeline_id)),
+        );
+
+        let info = TimelineImportTaskInfo {
+            task_id: format!("{task_id}"),
+            state: TimelineImportTaskState::Running,
+            downloaded_bytes: 0,
+            error: None,
+        };
+        imports.insert(timeline_id, info.clone());
+        Ok(info)
+    }
+
+    pub(crate) fn get_timeline_import_task_info(
+        &self,
+        timeline_id: TimelineId,
+    ) -> Option<TimelineImportTaskInfo> {
+        self.timeline_imports
+            .lock()
+            .unwrap()
+            .get(&timeline_id)
+            .cloned()
+    }
+
+    async fn import_timeline_from_remote(
+        &self,
+        timeline_id: TimelineId,
+        remote_storage: &GenericRemoteStorage,
+        remote_path: &RemotePath,
+        request: &TimelineImportRequest,
+        broker_client: storage_broker::BrokerClientChannel,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let _gate = self
+            .gate
+            .enter()
+            .map_err(|_| anyhow::anyhow!("tenant is shutting down"))?;
+
+        // Either a single tarball, or the output directory of pg_basebackup
+        let (base_path, wal_path) = if remote_path.extension() == Some("tar") {
+            (remote_path.clone(), None)
+        } else {
+            let wal_path = remote_path.join(Utf8Path::new("pg_wal.tar"));
+            let wal_path = self
+                .remote_object_exists(remote_storage, &wal_path)
+                .await?
+                .then_some(wal_path);
+            (remote_path.join(Utf8Path::new("base.tar")), wal_path)
+        };
+        if wal_path.is_none() && request.end_lsn != request.base_lsn {
+            anyhow::bail!(
+                "no WAL to import from {} to {}",
+                request.base_lsn,
+                request.end_lsn
+            );
+        }
+
+        info!("importing basebackup from {base_path}");
+        let uninit = self
+            .create_empty_timeline(timeline_id, request.base_lsn, request.pg_version, ctx)
+            .await?;
+        let (writer, mut reader) = tokio::io::duplex(IMPORT_BUFFER_SIZE);
+        let (imported, downloaded) = tokio::join!(
+            async move {
+                uninit
+                    .import_basebackup_from_tar(&mut reader, request.base_lsn, broker_client, ctx)
+                    .await
+            },
+            self.download_tarball(remote_storage, &base_path, timeline_id, writer),
+        );
+        let timeline = import_result(imported, downloaded)?;
+
+        let Some(wal_path) = wal_path else {
+            return Ok(());
+        };
+
+        // TODO like the `import wal` command, this doesn't prevent a compute from
+        // connecting to the timeline before all the WAL is imported.
+        info!("importing WAL from {wal_path}");
+        let (writer, mut reader) = tokio::io::duplex(IMPORT_BUFFER_SIZE);
+        let timeline_ref = &timeline;
+        let (imported, downloaded) = tokio::join!(
+            async move {
+                import_datadir::import_wal_from_tar(
+                    timeline_ref,
+                    &mut reader,
+                    request.base_lsn,
+                    request.end_lsn,
+                    ctx,
+                )
+                .await
+            },
+            self.download_tarball(remote_storage, &wal_path, timeline_id, writer),
+        );
+        import_result(imported, downloaded)?;
+
+        let last_record_lsn = timeline.get_last_record_lsn();
+        anyhow::ensure!(
+            last_record_lsn >= request.end_lsn,
+            "imported WAL ends at {last_record_lsn}, before {}",
+            request.end_lsn
+        );
+
+        // Flush data to disk, then upload to s3, like the `import wal` command.
+        timeline.freeze_and_flush().await?;
+        Ok(())
+    }
+
+    async fn remote_object_exists(
+        &self,
+        remote_storage: &GenericRemoteStorage,
+        path: &RemotePath,
+    ) -> anyhow::Result<bool> {
+        // Only the first byte: tarballs are never empty, and the response isn't read.
+        match remote_storage
+            .download_byte_range(path, 0, Some(1), &self.cancel)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(DownloadError::NotFound) => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("probe {path}")),
+        }
+    }
+
+    /// Download a tarball from remote storage into `writer`, adding the downloaded bytes
+    /// to the progress of the import.
+    async fn download_tarball(
+        &self,
+        remote_storage: &GenericRemoteStorage,
+        path: &RemotePath,
+        timeline_id: TimelineId,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> anyhow::Result<()> {
+        let mut offset = 0;
+        loop {
+            // Except for the first one, ranges start on the last byte that we already
+            // have: we don't know the size of the object, and a range starting at its
+            // end would be invalid.
+            let start = u64::saturating_sub(offset, 1);
+            let end = start + DOWNLOAD_CHUNK_SIZE;
+            let download = remote_storage
+                .download_byte_range(path, start, Some(end), &self.cancel)
+                .await
+                .with_context(|| format!("download {path}"))?;
+
+            let mut stream = download.download_stream;
+            let mut received = 0;
+            while let Some(bytes) = stream.next().await {
+                let bytes = bytes.with_context(|| format!("download {path}"))?;
+                let skip = offset.saturating_sub(start + received) as usize;
+                let skip = skip.min(bytes.len());
+                received += bytes.len() as u64;
+
+                writer.write_all(&bytes[skip..]).await?;
+                offset += (bytes.len() - skip) as u64;
+                if let Some(info) = self.timeline_imports.lock().unwrap().get_mut(&timeline_id) {
+                    info.downloaded_bytes += (bytes.len() - skip) as u64;
+                }
+            }
+            if start + received < end {
+                break;
+            }
+        }
+        writer.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// The remote storage prefix that the basebackups of a tenant are imported from.
+fn import_prefix(tenant_id: TenantId) -> RemotePath {
+    RemotePath::from_string(&format!("imports/{tenant_id}")).expect("a relative path")
+}
+
+fn parse_remote_path(tenant_id: TenantId, remote_path: &str) -> anyhow::Result<RemotePath> {
+    let path = RemotePath::from_string(remote_path)?;
+    let prefix = import_prefix(tenant_id);
+    let relative = path
+        .strip_prefix(&prefix)
+        .map_err(|_| anyhow::anyhow!("{path} is not under {prefix}"))?;
+    anyhow::ensure!(
+        relative.components().next().is_some()
+            && relative
+                .components()
+                .all(|c| matches!(c, Utf8Component::Normal(_))),
+        "{path} must be a path below {prefix}, without `..`"
+    );
+    Ok(path)
+}
+
+/// Combine the results of importing a tarball and of downloading it. When either side
+/// fails, the other one fails too, because its end of the pipe is gone.
+fn import_result<T>(
+    imported: anyhow::Result<T>,
+    downloaded: anyhow::Result<()>,
+) -> anyhow::Result<T> {
+    match (imported, downloaded) {
+        (Ok(res), Ok(())) => Ok(res),
+        (Ok(res), Err(e)) => {
+            // The importers stop reading at the end of the tar archive, or for WAL, at
+            // the end LSN.
+            debug!("download stopped after the import completed: {e:#}");
+            Ok(res)
+        }
+        (Err(e), Ok(())) => Err(e),
+        (Err(import_err), Err(download_err)) => Err(anyhow::anyhow!(
+            "{import_err:#} (download: {download_err:#})"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_path_must_be_under_import_prefix() {
+        let tenant_id = TenantId::generate();
+        let other_tenant_id = TenantId::generate();
+
+        for ok in ["vanilla", "vanilla/base.tar", "a/b/backup.tar"] {
+            let path = format!("imports/{tenant_id}/{ok}");
+            assert!(parse_remote_path(tenant_id, &path).is_ok(), "{path}");
+        }
+        for bad in [
+            format!("imports/{tenant_id}"),
+            format!("imports/{tenant_id}/"),
+            format!("imports/{tenant_id}/../{other_tenant_id}/vanilla"),
+            format!("imports/{other_tenant_id}/vanilla"),
+            format!("tenants/{tenant_id}/timelines"),
+            format!("/imports/{tenant_id}/vanilla"),
+            "vanilla".to_string(),
+        ] {
+            assert!(parse_remote_path(tenant_id, &bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -1307,6 +1307,8 @@ impl RemoteTimelineClient {
     ///
     async fn perform_upload_task(self: &Arc<Self>, task: Arc<UploadTask>) {
         let cancel = shutdown_token();
+        // Hash of an uploaded layer, to be recorded in the index
+        let mut uploaded_sha256 = None;
         // Loop to retry until it completes.
         loop {
             // If we're requested to shut down, close up shop and exit.
@@ -1346,6 +1348,7 @@ impl RemoteTimelineClient {
                         Arc::clone(&self.metrics),
                     )
                     .await
+                    .map(|sha256| uploaded_sha256 = sha256)
                 }
                 UploadOp::UploadMetadata(ref index_part, _lsn) => {
                     let mention_having_future_layers = if cfg!(feature = "testing") {
@@ -1472,8 +1475,16 @@ impl RemoteTimelineClient {
             upload_queue.inprogress_tasks.remove(&task.task_id);
 
             let lsn_update = match task.op {
-                UploadOp::UploadLayer(_, _) => {
+                UploadOp::UploadLayer(ref layer, ref metadata) => {
                     upload_queue.num_inprogress_layer_uploads -= 1;
+                    if let Some(sha256) = uploaded_sha256 {
+                        layer.as_ref().set_uploaded_sha256(sha256);
+                        upload_queue.record_uploaded_sha256(
+                            &layer.layer_desc().filename(),
+                            metadata,
+                            sha256,
+                        );
+                    }
                     None
                 }
                 UploadOp::UploadMetadata(_, lsn) => {
@@ -8,6 +8,7 @@ use std::future::Future;
 
 use anyhow::{anyhow, Context};
 use camino::{Utf8Path, Utf8PathBuf};
+use futures::TryStreamExt;
 use pageserver_api::shard::TenantShardId;
 use tokio::fs::{self, File, OpenOptions};
 use tokio::io::{AsyncSeekExt, AsyncWriteExt};
@@ -27,7 +28,7 @@ use remote_storage::{DownloadError, GenericRemoteStorage, ListingMode};
 use utils::crashsafe::path_with_suffix_extension;
 use utils::id::TimelineId;
 
-use super::index::{IndexPart, LayerFileMetadata};
+use super::index::{IndexPart, LayerFileHasher, LayerFileMetadata};
 use super::{
     parse_remote_index_path, remote_index_path, remote_initdb_archive_path,
     remote_initdb_preserved_archive_path, FAILED_DOWNLOAD_WARN_THRESHOLD, FAILED_REMOTE_OP_RETRIES,
@@ -35,8 +36,8 @@ use super::{
 };
 
 ///
-/// If 'metadata' is given, we will validate that the downloaded file's size matches that
-/// in the metadata. (In the future, we might do more cross-checks, like CRC validation)
+/// We will validate that the downloaded file's size matches that in the metadata, and its
+/// contents the hash, if the metadata has one.
 ///
 /// Returns the size of the downloaded file.
 pub async fn download_layer_file<'a>(
@@ -94,7 +95,11 @@ pub async fn download_layer_file<'a>(
             let mut destination_file =
                 tokio::io::BufWriter::with_capacity(super::BUFFER_SIZE, destination_file);
 
-            let mut reader = tokio_util::io::StreamReader::new(download.download_stream);
+            let mut hasher = LayerFileHasher::default();
+            let download_stream = download
+                .download_stream
+                .inspect_ok(|chunk| hasher.update(chunk));
+            let mut reader = tokio_util::io::StreamReader::new(download_stream);
 
             let bytes_amount = tokio::io::copy_buf(&mut reader, &mut destination_file)
                 .await
@@ -102,6 +107,18 @@ pub async fn download_layer_file<'a>(
                     "download layer at remote path '{remote_path:?}' into file {temp_file_path:?}"
                 ))
                 .map_err(DownloadError::Other);
+            drop(reader);
+
+            // A mismatch may be a transfer error, so check it here to retry the download
+            let bytes_amount = bytes_amount.and_then(|bytes_amount| {
+                let actual = hasher.finish();
+                match layer_metadata.sha256 {
+                    Some(expected) if expected != actual => Err(DownloadError::Other(anyhow!(
+                        "According to layer file metadata should have downloaded contents with sha256 {expected} but downloaded {actual} into file {temp_file_path:?}",
+                    ))),
+                    _ => Ok(bytes_amount),
+                }
+            });
 
             match bytes_amount {
                 Ok(bytes_amount) => {
@@ -3,8 +3,10 @@
 use anyhow::{bail, Context};
 use camino::Utf8Path;
 use fail::fail_point;
+use futures::TryStreamExt;
 use pageserver_api::shard::TenantShardId;
 use std::io::{ErrorKind, SeekFrom};
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 use tokio::fs::{self, File};
 use tokio::io::AsyncSeekExt;
@@ -22,7 +24,7 @@ use crate::{
 use remote_storage::{GenericRemoteStorage, TimeTravelError};
 use utils::id::{TenantId, TimelineId};
 
-use super::index::LayerFileMetadata;
+use super::index::{LayerFileHash, LayerFileHasher, LayerFileMetadata};
 
 use tracing::info;
 
@@ -63,6 +65,9 @@ pub(crate) async fn upload_index_part<'a>(
 /// Attempts to upload given layer files.
 /// No extra checks for overlapping files is made and any files that are already present remotely will be overwritten, if submitted during the upload.
 ///
+/// Returns the hash of the uploaded contents, computed while uploading, or `None` if the file
+/// no longer exists.
+///
 /// On an error, bumps the retries count and reschedules the entire task.
 pub(super) async fn upload_timeline_layer<'a>(
     conf: &'static PageServerConf,
@@ -71,7 +76,7 @@ pub(super) async fn upload_timeline_layer<'a>(
     known_metadata: &'a LayerFileMetadata,
     generation: Generation,
     cancel: &CancellationToken,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Option<LayerFileHash>> {
     fail_point!("before-upload-layer", |_| {
         bail!("failpoint before-upload-layer")
     });
@@ -91,7 +96,7 @@ pub(super) async fn upload_timeline_layer<'a>(
             //
             // This is tested against `test_compaction_delete_before_upload`
             info!(path = %source_path, "File to upload doesn't exist. Likely the file has been deleted and an upload is not required any more.");
-            return Ok(());
+            return Ok(None);
         }
         Err(e) => {
             Err(e).with_context(|| format!("open a source file for layer {source_path:?}"))?
@@ -112,12 +117,21 @@ pub(super) async fn upload_timeline_layer<'a>(
     let fs_size = usize::try_from(fs_size)
         .with_context(|| format!("convert {source_path:?} size {fs_size} usize"))?;
 
-    let reader = tokio_util::io::ReaderStream::with_capacity(source_file, super::BUFFER_SIZE);
+    // Hash the contents as they are streamed out, the stream has to be 'static
+    let hasher = Arc::new(Mutex::new(LayerFileHasher::default()));
+    let reader = tokio_util::io::ReaderStream::with_capacity(source_file, super::BUFFER_SIZE)
+        .inspect_ok({
+            let hasher = Arc::clone(&hasher);
+            move |chunk| hasher.lock().unwrap().update(chunk)
+        });
 
     storage
         .upload(reader, fs_size, &storage_path, None, cancel)
         .await
-        .with_context(|| format!("upload layer from local path '{source_path}'"))
+        .with_context(|| format!("upload layer from local path '{source_path}'"))?;
+
+    let hasher = hasher.lock().unwrap().clone();
+    Ok(Some(hasher.finish()))
 }
 
 /// Uploads the given `initdb` data to the remote storage.
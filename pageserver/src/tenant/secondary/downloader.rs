@@ -168,6 +168,7 @@ impl SecondaryDetail {
                         }),
                         last_activity_ts: ods.access_time,
                         relative_last_activity: finite_f32::FiniteF32::ZERO,
+                        eviction_cost: finite_f32::FiniteF32::ZERO,
                     }
                 }));
 
@@ -718,7 +719,10 @@ impl<'a> TenantDownloader<'a> {
                 use std::collections::hash_map::Entry;
                 match timeline_detail.on_disk_layers.entry(t.name.clone()) {
                     Entry::Occupied(mut v) => {
-                        v.get_mut().access_time = t.access_time;
+                        let on_disk = v.get_mut();
+                        on_disk.access_time = t.access_time;
+                        // pick up the hash of layers uploaded since we downloaded them
+                        on_disk.metadata = LayerFileMetadata::from(&t.metadata);
                     }
                     Entry::Vacant(e) => {
                         e.insert(OnDiskState::new(
@@ -8,17 +8,21 @@ pub(crate) mod layer;
 mod layer_desc;
 
 use crate::context::{AccessStatsBehavior, RequestContext};
+use crate::metrics::MATERIALIZED_PAGE_CACHE_HIT;
+use crate::repository::{Key, Value};
 use crate::task_mgr::TaskKind;
 use crate::walrecord::NeonWalRecord;
 use bytes::Bytes;
 use enum_map::EnumMap;
 use enumset::EnumSet;
 use once_cell::sync::Lazy;
+use pageserver_api::keyspace::{KeySpace, KeySpaceRandomAccum};
 use pageserver_api::models::{
     LayerAccessKind, LayerResidenceEvent, LayerResidenceEventReason, LayerResidenceStatus,
 };
+use std::collections::HashMap;
 use std::ops::Range;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::warn;
 use utils::history_buffer::HistoryBufferWithDropCounter;
@@ -26,6 +30,8 @@ use utils::rate_limit::RateLimit;
 
 use utils::{id::TimelineId, lsn::Lsn};
 
+use super::timeline::PageReconstructError;
+
 pub use delta_layer::{DeltaLayer, DeltaLayerWriter, ValueRef};
 pub use filename::{DeltaFileName, ImageFileName, LayerFileName};
 pub use image_layer::{ImageLayer, ImageLayerWriter};
@@ -82,6 +88,317 @@ pub enum ValueReconstructResult {
     Missing,
 }
 
+/// Whether a key of a vectored read has all the data it needs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum ValueReconstructSituation {
+    /// An image or a will_init record has been found, or all the records newer than
+    /// a cached page image; older versions are not needed.
+    Complete,
+    /// More records are needed from older layers.
+    #[default]
+    Continue,
+}
+
+/// Reconstruct data of a single key, collected by a vectored read.
+#[derive(Debug, Default)]
+pub(crate) struct VectoredValueReconstructState {
+    pub(crate) records: Vec<(Lsn, NeonWalRecord)>,
+    pub(crate) img: Option<(Lsn, Bytes)>,
+
+    situation: ValueReconstructSituation,
+    /// Persistent layers that were searched for this key
+    layers_visited: u32,
+}
+
+impl VectoredValueReconstructState {
+    pub(crate) fn situation(&self) -> ValueReconstructSituation {
+        self.situation
+    }
+
+    pub(crate) fn layers_visited(&self) -> u32 {
+        self.layers_visited
+    }
+}
+
+impl From<VectoredValueReconstructState> for ValueReconstructState {
+    fn from(state: VectoredValueReconstructState) -> Self {
+        // Records were collected newest first, which is what walredo expects
+        ValueReconstructState {
+            records: state.records,
+            img: state.img,
+        }
+    }
+}
+
+/// Bag of data accumulated during a vectored get, the counterpart of
+/// [`ValueReconstructState`] for many keys at once.
+///
+/// Layers are visited from newest to oldest, and each of them adds the values
+/// it holds for the keys it was asked about via [`Self::update_key`]. Keys for
+/// which all the required data was found are reported by
+/// [`Self::consume_done_keys`], so that older layers are not asked about them.
+pub(crate) struct ValuesReconstructState {
+    pub(crate) keys: HashMap<Key, Result<VectoredValueReconstructState, PageReconstructError>>,
+
+    keys_done: KeySpaceRandomAccum,
+    layers_visited: u32,
+}
+
+impl ValuesReconstructState {
+    pub(crate) fn new() -> Self {
+        Self {
+            keys: HashMap::new(),
+            keys_done: KeySpaceRandomAccum::new(),
+            layers_visited: 0,
+        }
+    }
+
+    /// Start the reconstruction of `key` from a page image that was materialized
+    /// at `lsn` earlier: only the values newer than that are still needed.
+    pub(crate) fn set_cached_image(&mut self, key: Key, lsn: Lsn, img: Bytes) {
+        self.keys.insert(
+            key,
+            Ok(VectoredValueReconstructState {
+                records: Vec::new(),
+                img: Some((lsn, img)),
+                situation: ValueReconstructSituation::Continue,
+                layers_visited: 0,
+            }),
+        );
+    }
+
+    /// Called once all the values of the keys in `keyspace` at or above `cont_lsn`
+    /// have been collected. Keys that have a cached image at least that new are
+    /// complete.
+    pub(crate) fn on_lsn_advanced(&mut self, keyspace: &KeySpace, cont_lsn: Lsn) {
+        for range in keyspace.ranges.iter() {
+            let mut key = range.start;
+            while key != range.end {
+                if let Some(Ok(state)) = self.keys.get_mut(&key) {
+                    if state.situation == ValueReconstructSituation::Continue
+                        && matches!(&state.img, Some((cached_lsn, _)) if *cached_lsn + 1 >= cont_lsn)
+                    {
+                        state.situation = ValueReconstructSituation::Complete;
+                        self.keys_done.add_key(key);
+                        MATERIALIZED_PAGE_CACHE_HIT.inc();
+                    }
+                }
+                key = key.next();
+            }
+        }
+    }
+
+    /// Associate a key with the error which it encountered and mark it as done
+    pub(crate) fn on_key_error(&mut self, key: Key, err: PageReconstructError) {
+        self.keys.insert(key, Err(err));
+        self.keys_done.add_key(key);
+    }
+
+    /// Called after a persistent layer was searched for the keys in `keyspace`.
+    pub(crate) fn on_layer_visited(&mut self, keyspace: &KeySpace) {
+        self.layers_visited += 1;
+
+        for range in keyspace.ranges.iter() {
+            let mut key = range.start;
+            while key != range.end {
+                let state = self
+                    .keys
+                    .entry(key)
+                    .or_insert(Ok(VectoredValueReconstructState::default()));
+                if let Ok(state) = state {
+                    state.layers_visited += 1;
+                }
+                key = key.next();
+            }
+        }
+    }
+
+    pub(crate) fn get_layers_visited(&self) -> u32 {
+        self.layers_visited
+    }
+
+    /// Update the state collected for a given key.
+    ///
+    /// Values of a key must be passed in descending LSN order. Values passed
+    /// after the key became complete are ignored. Returns the situation of the
+    /// key after the update.
+    pub(crate) fn update_key(
+        &mut self,
+        key: &Key,
+        lsn: Lsn,
+        value: Value,
+    ) -> ValueReconstructSituation {
+        let state = self
+            .keys
+            .entry(*key)
+            .or_insert(Ok(VectoredValueReconstructState::default()));
+
+        let state = match state {
+            Ok(state) => state,
+            // The key already failed; it is done from the point of view of the traversal
+            Err(_) => return ValueReconstructSituation::Complete,
+        };
+
+        if state.situation == ValueReconstructSituation::Complete {
+            return ValueReconstructSituation::Complete;
+        }
+
+        // An image on an incomplete key can only come from the page cache, see
+        // [`Self::set_cached_image`]. We have everything since then already.
+        if matches!(&state.img, Some((cached_lsn, _)) if lsn <= *cached_lsn) {
+            state.situation = ValueReconstructSituation::Complete;
+            self.keys_done.add_key(*key);
+            MATERIALIZED_PAGE_CACHE_HIT.inc();
+            return ValueReconstructSituation::Complete;
+        }
+
+        let key_done = match value {
+            Value::Image(img) => {
+                state.img = Some((lsn, img));
+                true
+            }
+            Value::WalRecord(rec) => {
+                let will_init = rec.will_init();
+                state.records.push((lsn, rec));
+                will_init
+            }
+        };
+
+        if key_done {
+            state.situation = ValueReconstructSituation::Complete;
+            self.keys_done.add_key(*key);
+        }
+
+        state.situation
+    }
+
+    /// Returns the key space describing the keys that have
+    /// been marked as completed since the last call to this function.
+    pub(crate) fn consume_done_keys(&mut self) -> KeySpace {
+        std::mem::take(&mut self.keys_done).to_keyspace()
+    }
+}
+
+impl Default for ValuesReconstructState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Identifies a layer (and the LSN ceiling it is read up to) within a [`LayerFringe`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum LayerId {
+    Persistent(PersistentLayerKey),
+    InMemory(Lsn),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct LayerReadId {
+    layer: LayerId,
+    lsn_range: Range<Lsn>,
+}
+
+/// Layer wrapper for the read path. Note that it is valid
+/// to use these layers only after a [`LayerFringe`] has
+/// been built.
+#[derive(Clone)]
+pub(crate) enum ReadableLayer {
+    PersistentLayer(Layer),
+    InMemoryLayer(Arc<InMemoryLayer>),
+}
+
+impl ReadableLayer {
+    fn id(&self) -> LayerId {
+        match self {
+            ReadableLayer::PersistentLayer(layer) => LayerId::Persistent(layer.layer_desc().key()),
+            ReadableLayer::InMemoryLayer(layer) => LayerId::InMemory(layer.get_lsn_range().start),
+        }
+    }
+
+    /// Collect the values of all keys in `keyspace` within `lsn_range` into
+    /// `reconstruct_state`.
+    pub(crate) async fn get_values_reconstruct_data(
+        &self,
+        keyspace: KeySpace,
+        lsn_range: Range<Lsn>,
+        reconstruct_state: &mut ValuesReconstructState,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        match self {
+            ReadableLayer::PersistentLayer(layer) => {
+                layer
+                    .get_values_reconstruct_data(keyspace, lsn_range, reconstruct_state, ctx)
+                    .await
+            }
+            ReadableLayer::InMemoryLayer(layer) => {
+                layer
+                    .get_values_reconstruct_data(keyspace, lsn_range, reconstruct_state, ctx)
+                    .await
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ReadableLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadableLayer::PersistentLayer(layer) => write!(f, "{layer}"),
+            ReadableLayer::InMemoryLayer(layer) => write!(f, "{layer}"),
+        }
+    }
+}
+
+/// The set of layers that a vectored read still has to visit, together with
+/// the keys that need to be read from each of them.
+///
+/// Layers are handed out newest first. A layer that is reached by several key
+/// ranges within the same LSN range is read only once, for all of them.
+#[derive(Default)]
+pub(crate) struct LayerFringe {
+    layers: HashMap<LayerReadId, (ReadableLayer, KeySpaceRandomAccum)>,
+}
+
+impl LayerFringe {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove the layer with the highest LSN ceiling from the fringe and
+    /// return it together with the keys to read from it and the LSN range
+    /// to read them in.
+    pub(crate) fn next_layer(&mut self) -> Option<(ReadableLayer, KeySpace, Range<Lsn>)> {
+        let next = self
+            .layers
+            .keys()
+            .max_by_key(|read_id| (read_id.lsn_range.end, read_id.lsn_range.start))?
+            .clone();
+
+        let (layer, keyspace) = self.layers.remove(&next).expect("key was just found");
+        Some((layer, keyspace.to_keyspace(), next.lsn_range))
+    }
+
+    /// Plan to read `keyspace` from `layer` within `lsn_range`.
+    pub(crate) fn update(
+        &mut self,
+        layer: ReadableLayer,
+        keyspace: KeySpace,
+        lsn_range: Range<Lsn>,
+    ) {
+        let read_id = LayerReadId {
+            layer: layer.id(),
+            lsn_range,
+        };
+
+        let (_, accum) = self
+            .layers
+            .entry(read_id)
+            .or_insert_with(|| (layer, KeySpaceRandomAccum::new()));
+        for range in keyspace.ranges {
+            accum.add_range(range);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct LayerAccessStats(Mutex<LayerAccessStatsLocked>);
 
@@ -263,6 +580,21 @@ impl LayerAccessStats {
         self.latest_activity().unwrap_or_else(SystemTime::now)
     }
 
+    /// Estimate how often the layer is accessed, in accesses per second, from the recent
+    /// accesses it remembers. A layer that was not accessed since it became resident counts
+    /// as accessed once, when it did.
+    pub(crate) fn access_frequency(&self, now: SystemTime) -> f32 {
+        let locked = self.0.lock().unwrap();
+        let inner = &locked.for_eviction_policy;
+        match inner.last_accesses.oldest_ordered().next() {
+            Some(oldest) => access_frequency(inner.last_accesses.len(), oldest.when, now),
+            None => match inner.last_residence_changes.recent() {
+                Some(e) => access_frequency(1, e.timestamp, now),
+                None => 0.0,
+            },
+        }
+    }
+
     /// Get the latest access timestamp, falling back to latest residence event.
     ///
     /// This function can only return `None` if there has not yet been a call to the
@@ -300,6 +632,16 @@ impl LayerAccessStats {
     }
 }
 
+/// Accesses per second, for `accesses` since `since`. Periods shorter than a second
+/// count as a second.
+pub(crate) fn access_frequency(accesses: usize, since: SystemTime, now: SystemTime) -> f32 {
+    let period = now
+        .duration_since(since)
+        .unwrap_or_default()
+        .max(Duration::from_secs(1));
+    accesses as f32 / period.as_secs_f32()
+}
+
 /// Get a layer descriptor from a layer.
 pub trait AsLayerDesc {
     /// Get the layer descriptor.
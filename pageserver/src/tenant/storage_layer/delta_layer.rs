@@ -31,17 +31,23 @@ use crate::config::PageServerConf;
 use crate::context::{PageContentKind, RequestContext, RequestContextBuilder};
 use crate::page_cache::PAGE_SZ;
 use crate::repository::{Key, Value, KEY_SIZE};
-use crate::tenant::blob_io::BlobWriter;
-use crate::tenant::block_io::{BlockBuf, BlockCursor, BlockLease, BlockReader, FileBlockReader};
+use crate::tenant::blob_io::{BlobChecksum, BlobCompression, BlobWriter};
+use crate::tenant::block_io::{BlockBuf, BlockCursor, BlockLease, BlockReaderRef, FileBlockReader};
 use crate::tenant::disk_btree::{DiskBtreeBuilder, DiskBtreeReader, VisitDirection};
-use crate::tenant::storage_layer::{Layer, ValueReconstructResult, ValueReconstructState};
+use crate::tenant::storage_layer::{
+    Layer, ValueReconstructResult, ValueReconstructState, ValuesReconstructState,
+};
+use crate::tenant::vectored_blob_io::{
+    BlobFlag, VectoredBlobReader, VectoredRead, VectoredReadPlanner, MAX_VECTORED_READ_BYTES,
+};
 use crate::tenant::Timeline;
 use crate::virtual_file::{self, VirtualFile};
 use crate::{walrecord, TEMP_FILE_SUFFIX};
 use crate::{DELTA_FILE_MAGIC, STORAGE_FORMAT_VERSION};
 use anyhow::{bail, ensure, Context, Result};
 use camino::{Utf8Path, Utf8PathBuf};
-use pageserver_api::models::LayerAccessKind;
+use pageserver_api::keyspace::KeySpace;
+use pageserver_api::models::{CompressionAlgorithm, LayerAccessKind};
 use pageserver_api::shard::TenantShardId;
 use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
@@ -82,6 +88,12 @@ pub struct Summary {
     pub index_start_blk: u32,
     /// Block within the 'index', where the B-tree root page is stored
     pub index_root_blk: u32,
+    /// Compression that the values may use. `None` for layers written before
+    /// compression was supported.
+    pub compression: Option<BlobCompression>,
+    /// Checksum that follows each value. `None` for layers written before
+    /// blob checksums were supported.
+    pub checksum: Option<BlobChecksum>,
 }
 
 impl From<&DeltaLayer> for Summary {
@@ -113,6 +125,8 @@ impl Summary {
 
             index_start_blk: 0,
             index_root_blk: 0,
+            compression: None,
+            checksum: None,
         }
     }
 }
@@ -207,6 +221,8 @@ pub struct DeltaLayerInner {
     // values copied from summary
     index_start_blk: u32,
     index_root_blk: u32,
+    compression: Option<BlobCompression>,
+    checksum: Option<BlobChecksum>,
 
     /// Reader object for reading blocks from the file.
     file: FileBlockReader,
@@ -217,6 +233,8 @@ impl std::fmt::Debug for DeltaLayerInner {
         f.debug_struct("DeltaLayerInner")
             .field("index_start_blk", &self.index_start_blk)
             .field("index_root_blk", &self.index_root_blk)
+            .field("compression", &self.compression)
+            .field("checksum", &self.checksum)
             .finish()
     }
 }
@@ -363,6 +381,8 @@ struct DeltaLayerWriterInner {
 
     key_start: Key,
     lsn_range: Range<Lsn>,
+    compression: CompressionAlgorithm,
+    checksums: bool,
 
     tree: DiskBtreeBuilder<BlockBuf, DELTA_KEY_SIZE>,
 
@@ -379,6 +399,8 @@ impl DeltaLayerWriterInner {
         tenant_shard_id: TenantShardId,
         key_start: Key,
         lsn_range: Range<Lsn>,
+        compression: CompressionAlgorithm,
+        checksums: bool,
     ) -> anyhow::Result<Self> {
         // Create the file initially with a temporary filename. We don't know
         // the end key yet, so we cannot form the final filename yet. We will
@@ -392,7 +414,7 @@ impl DeltaLayerWriterInner {
         let mut file = VirtualFile::create(&path).await?;
         // make room for the header block
         file.seek(SeekFrom::Start(PAGE_SZ as u64)).await?;
-        let blob_writer = BlobWriter::new(file, PAGE_SZ as u64);
+        let blob_writer = BlobWriter::new(file, PAGE_SZ as u64).with_checksums(checksums);
 
         // Initialize the b-tree index builder
         let block_buf = BlockBuf::new();
@@ -405,6 +427,8 @@ impl DeltaLayerWriterInner {
             tenant_shard_id,
             key_start,
             lsn_range,
+            compression,
+            checksums,
             tree: tree_builder,
             blob_writer,
         })
@@ -430,7 +454,10 @@ impl DeltaLayerWriterInner {
         will_init: bool,
     ) -> (Vec<u8>, anyhow::Result<()>) {
         assert!(self.lsn_range.start <= lsn);
-        let (val, res) = self.blob_writer.write_blob(val).await;
+        let (val, res) = self
+            .blob_writer
+            .write_blob_maybe_compressed(val, self.compression)
+            .await;
         let off = match res {
             Ok(off) => off,
             Err(e) => return (val, Err(anyhow::anyhow!(e))),
@@ -475,6 +502,8 @@ impl DeltaLayerWriterInner {
             lsn_range: self.lsn_range.clone(),
             index_start_blk,
             index_root_blk,
+            compression: BlobCompression::for_algorithm(self.compression),
+            checksum: self.checksums.then_some(BlobChecksum::Crc32c),
         };
 
         let mut buf = Vec::with_capacity(PAGE_SZ);
@@ -559,6 +588,8 @@ impl DeltaLayerWriter {
         tenant_shard_id: TenantShardId,
         key_start: Key,
         lsn_range: Range<Lsn>,
+        compression: CompressionAlgorithm,
+        checksums: bool,
     ) -> anyhow::Result<Self> {
         Ok(Self {
             inner: Some(
@@ -568,6 +599,8 @@ impl DeltaLayerWriter {
                     tenant_shard_id,
                     key_start,
                     lsn_range,
+                    compression,
+                    checksums,
                 )
                 .await?,
             ),
@@ -694,7 +727,7 @@ impl DeltaLayerInner {
         summary: Option<Summary>,
         ctx: &RequestContext,
     ) -> Result<Result<Self, anyhow::Error>, anyhow::Error> {
-        let file = match VirtualFile::open(path).await {
+        let file = match VirtualFile::open_layer(path).await {
             Ok(file) => file,
             Err(e) => return Ok(Err(anyhow::Error::new(e).context("open layer file"))),
         };
@@ -713,6 +746,8 @@ impl DeltaLayerInner {
             // production code path
             expected_summary.index_start_blk = actual_summary.index_start_blk;
             expected_summary.index_root_blk = actual_summary.index_root_blk;
+            expected_summary.compression = actual_summary.compression;
+            expected_summary.checksum = actual_summary.checksum;
             if actual_summary != expected_summary {
                 bail!(
                     "in-file summary does not match expected summary. actual = {:?} expected = {:?}",
@@ -726,6 +761,8 @@ impl DeltaLayerInner {
             file,
             index_start_blk: actual_summary.index_start_blk,
             index_root_blk: actual_summary.index_root_blk,
+            compression: actual_summary.compression,
+            checksum: actual_summary.checksum,
         }))
     }
 
@@ -776,7 +813,7 @@ impl DeltaLayerInner {
             .build();
 
         // Ok, 'offsets' now contains the offsets of all the entries we need to read
-        let cursor = file.block_cursor();
+        let cursor = self.block_cursor();
         let mut buf = Vec::new();
         for (entry_lsn, pos) in offsets {
             cursor
@@ -818,6 +855,150 @@ impl DeltaLayerInner {
         }
     }
 
+    /// Collect the values of all keys in `keyspace` within `lsn_range`, newest
+    /// first, into `reconstruct_state`.
+    ///
+    /// The index is walked once per key range, and the values are read with
+    /// as few disk reads as possible, bypassing the page cache.
+    pub(super) async fn get_values_reconstruct_data(
+        &self,
+        keyspace: KeySpace,
+        lsn_range: Range<Lsn>,
+        reconstruct_state: &mut ValuesReconstructState,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let reads = self.plan_reads(keyspace, lsn_range, ctx).await?;
+
+        self.do_reads_and_update_state(reads, reconstruct_state, ctx)
+            .await
+    }
+
+    async fn plan_reads(
+        &self,
+        keyspace: KeySpace,
+        lsn_range: Range<Lsn>,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Vec<VectoredRead>> {
+        let mut planner = VectoredReadPlanner::new(MAX_VECTORED_READ_BYTES);
+
+        let tree_reader = DiskBtreeReader::<_, DELTA_KEY_SIZE>::new(
+            self.index_start_blk,
+            self.index_root_blk,
+            &self.file,
+        );
+        let ctx = &RequestContextBuilder::extend(ctx)
+            .page_content_kind(PageContentKind::DeltaLayerBtreeNode)
+            .build();
+
+        // Values are stored right before the index, in key and LSN order, so
+        // the value of the last entry ends where the index begins.
+        let values_end = self.index_start_blk as u64 * PAGE_SZ as u64;
+
+        for range in keyspace.ranges.iter() {
+            let mut range_end_handled = false;
+
+            let start_key = DeltaKey::from_key_lsn(&range.start, lsn_range.start);
+            tree_reader
+                .visit(
+                    &start_key.0,
+                    VisitDirection::Forwards,
+                    |raw_key, value| {
+                        let key = Key::from_slice(&raw_key[..KEY_SIZE]);
+                        let lsn = DeltaKey::extract_lsn_from_buf(raw_key);
+                        let blob_ref = BlobRef(value);
+
+                        if key >= range.end {
+                            planner.handle_range_end(blob_ref.pos());
+                            range_end_handled = true;
+                            return false;
+                        }
+
+                        let flag = if lsn_range.contains(&lsn) {
+                            if blob_ref.will_init() {
+                                BlobFlag::ReplaceAll
+                            } else {
+                                BlobFlag::None
+                            }
+                        } else {
+                            BlobFlag::Ignore
+                        };
+
+                        planner.handle(key, lsn, blob_ref.pos(), flag);
+                        true
+                    },
+                    ctx,
+                )
+                .await?;
+
+            if !range_end_handled {
+                planner.handle_range_end(values_end);
+            }
+        }
+
+        Ok(planner.finish())
+    }
+
+    async fn do_reads_and_update_state(
+        &self,
+        reads: Vec<VectoredRead>,
+        reconstruct_state: &mut ValuesReconstructState,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let vectored_blob_reader =
+            VectoredBlobReader::new(&self.file, self.compression.is_some())
+                .with_checksums(self.checksum.is_some());
+
+        // Values of a key must be passed to the reconstruct state newest first,
+        // so go through the reads, and the blobs within each, in reverse.
+        //
+        // Read and verify everything before touching the reconstruct state, so that
+        // a failed visit of this layer can be retried from scratch.
+        let mut values = Vec::new();
+        for read in reads.into_iter().rev() {
+            let bufs = vectored_blob_reader
+                .read_blobs(&read, ctx)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to read blobs {}..{} from virtual file {}",
+                        read.start, read.end, self.file.file.path
+                    )
+                })?;
+
+            for blob in bufs.blobs.iter().rev() {
+                let payload = bufs.payload(blob).await.with_context(|| {
+                    format!(
+                        "Failed to decompress blob from virtual file {}",
+                        self.file.file.path
+                    )
+                })?;
+                let value = Value::des(&payload).with_context(|| {
+                    format!(
+                        "Failed to deserialize file blob from virtual file {}",
+                        self.file.file.path
+                    )
+                })?;
+
+                values.push((blob.meta, value));
+            }
+        }
+
+        for (meta, value) in values {
+            reconstruct_state.update_key(&meta.key, meta.lsn, value);
+        }
+
+        Ok(())
+    }
+
+    /// A cursor for reading the values of this layer.
+    fn block_cursor(&self) -> BlockCursor<'_> {
+        BlockCursor::new_with_compression(
+            BlockReaderRef::FileBlockReader(&self.file),
+            self.compression.is_some(),
+        )
+        .with_checksums(self.checksum.is_some())
+    }
+
     pub(super) async fn load_keys<'a>(
         &'a self,
         ctx: &RequestContext,
@@ -840,9 +1021,11 @@ impl DeltaLayerInner {
                     let delta_key = DeltaKey::from_slice(key);
                     let val_ref = ValueRef {
                         blob_ref: BlobRef(value),
-                        reader: BlockCursor::new(crate::tenant::block_io::BlockReaderRef::Adapter(
-                            Adapter(self),
-                        )),
+                        reader: BlockCursor::new_with_compression(
+                            BlockReaderRef::Adapter(Adapter(self)),
+                            self.compression.is_some(),
+                        )
+                        .with_checksums(self.checksum.is_some()),
                     };
                     let pos = BlobRef(value).pos();
                     if let Some(last) = all_keys.last_mut() {
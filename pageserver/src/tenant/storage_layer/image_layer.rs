@@ -26,21 +26,26 @@
 use crate::config::PageServerConf;
 use crate::context::{PageContentKind, RequestContext, RequestContextBuilder};
 use crate::page_cache::PAGE_SZ;
-use crate::repository::{Key, KEY_SIZE};
-use crate::tenant::blob_io::BlobWriter;
-use crate::tenant::block_io::{BlockBuf, BlockReader, FileBlockReader};
+use crate::repository::{Key, Value, KEY_SIZE};
+use crate::tenant::blob_io::{BlobChecksum, BlobCompression, BlobWriter};
+use crate::tenant::block_io::{BlockBuf, BlockCursor, BlockReaderRef, FileBlockReader};
 use crate::tenant::disk_btree::{DiskBtreeBuilder, DiskBtreeReader, VisitDirection};
 use crate::tenant::storage_layer::{
-    LayerAccessStats, ValueReconstructResult, ValueReconstructState,
+    LayerAccessStats, ValueReconstructResult, ValueReconstructState, ValuesReconstructState,
+};
+use crate::tenant::timeline::PageReconstructError;
+use crate::tenant::vectored_blob_io::{
+    BlobFlag, VectoredBlobReader, VectoredRead, VectoredReadPlanner, MAX_VECTORED_READ_BYTES,
 };
 use crate::tenant::Timeline;
 use crate::virtual_file::{self, VirtualFile};
 use crate::{IMAGE_FILE_MAGIC, STORAGE_FORMAT_VERSION, TEMP_FILE_SUFFIX};
-use anyhow::{bail, ensure, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use bytes::Bytes;
 use camino::{Utf8Path, Utf8PathBuf};
 use hex;
-use pageserver_api::models::LayerAccessKind;
+use pageserver_api::keyspace::{KeySpace, KeySpaceAccum};
+use pageserver_api::models::{CompressionAlgorithm, LayerAccessKind};
 use pageserver_api::shard::TenantShardId;
 use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
@@ -83,6 +88,12 @@ pub struct Summary {
     /// Block within the 'index', where the B-tree root page is stored
     pub index_root_blk: u32,
     // the 'values' part starts after the summary header, on block 1.
+    /// Compression that the values may use. `None` for layers written before
+    /// compression was supported.
+    pub compression: Option<BlobCompression>,
+    /// Checksum that follows each value. `None` for layers written before
+    /// blob checksums were supported.
+    pub checksum: Option<BlobChecksum>,
 }
 
 impl From<&ImageLayer> for Summary {
@@ -113,6 +124,8 @@ impl Summary {
 
             index_start_blk: 0,
             index_root_blk: 0,
+            compression: None,
+            checksum: None,
         }
     }
 }
@@ -147,6 +160,8 @@ pub struct ImageLayerInner {
     // values copied from summary
     index_start_blk: u32,
     index_root_blk: u32,
+    compression: Option<BlobCompression>,
+    checksum: Option<BlobChecksum>,
 
     lsn: Lsn,
 
@@ -159,6 +174,8 @@ impl std::fmt::Debug for ImageLayerInner {
         f.debug_struct("ImageLayerInner")
             .field("index_start_blk", &self.index_start_blk)
             .field("index_root_blk", &self.index_root_blk)
+            .field("compression", &self.compression)
+            .field("checksum", &self.checksum)
             .finish()
     }
 }
@@ -361,7 +378,7 @@ impl ImageLayerInner {
         summary: Option<Summary>,
         ctx: &RequestContext,
     ) -> Result<Result<Self, anyhow::Error>, anyhow::Error> {
-        let file = match VirtualFile::open(path).await {
+        let file = match VirtualFile::open_layer(path).await {
             Ok(file) => file,
             Err(e) => return Ok(Err(anyhow::Error::new(e).context("open layer file"))),
         };
@@ -382,6 +399,8 @@ impl ImageLayerInner {
             // production code path
             expected_summary.index_start_blk = actual_summary.index_start_blk;
             expected_summary.index_root_blk = actual_summary.index_root_blk;
+            expected_summary.compression = actual_summary.compression;
+            expected_summary.checksum = actual_summary.checksum;
 
             if actual_summary != expected_summary {
                 bail!(
@@ -395,6 +414,8 @@ impl ImageLayerInner {
         Ok(Ok(ImageLayerInner {
             index_start_blk: actual_summary.index_start_blk,
             index_root_blk: actual_summary.index_root_blk,
+            compression: actual_summary.compression,
+            checksum: actual_summary.checksum,
             lsn,
             file,
         }))
@@ -420,7 +441,7 @@ impl ImageLayerInner {
             )
             .await?
         {
-            let blob = file
+            let blob = self
                 .block_cursor()
                 .read_blob(
                     offset,
@@ -438,6 +459,147 @@ impl ImageLayerInner {
             Ok(ValueReconstructResult::Missing)
         }
     }
+
+    /// Collect the images of all keys in `keyspace` into `reconstruct_state`.
+    ///
+    /// An image layer holds every key of its key range that exists at its LSN,
+    /// so keys that are not found here are reported as errors rather than
+    /// looked up in older layers.
+    pub(super) async fn get_values_reconstruct_data(
+        &self,
+        keyspace: KeySpace,
+        reconstruct_state: &mut ValuesReconstructState,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let (reads, found) = self.plan_reads(&keyspace, ctx).await?;
+
+        self.do_reads_and_update_state(reads, reconstruct_state, ctx)
+            .await?;
+
+        let mut missing = keyspace;
+        missing.remove_overlapping_with(&found);
+        for range in missing.ranges {
+            let mut key = range.start;
+            while key < range.end {
+                reconstruct_state.on_key_error(
+                    key,
+                    PageReconstructError::from(anyhow!(
+                        "could not find data for key {} in image layer at LSN {}",
+                        key,
+                        self.lsn
+                    )),
+                );
+                key = key.next();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Plan the reads for all keys of `keyspace` present in this layer.
+    /// Returns the reads together with the keys that were found.
+    async fn plan_reads(
+        &self,
+        keyspace: &KeySpace,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<(Vec<VectoredRead>, KeySpace)> {
+        let mut planner = VectoredReadPlanner::new(MAX_VECTORED_READ_BYTES);
+        let mut found = KeySpaceAccum::new();
+
+        let tree_reader = DiskBtreeReader::<_, KEY_SIZE>::new(
+            self.index_start_blk,
+            self.index_root_blk,
+            &self.file,
+        );
+        let ctx = &RequestContextBuilder::extend(ctx)
+            .page_content_kind(PageContentKind::ImageLayerBtreeNode)
+            .build();
+
+        // The value of the last key ends where the index begins
+        let values_end = self.index_start_blk as u64 * PAGE_SZ as u64;
+
+        for range in keyspace.ranges.iter() {
+            let mut range_end_handled = false;
+
+            let mut search_key: [u8; KEY_SIZE] = [0u8; KEY_SIZE];
+            range.start.write_to_byte_slice(&mut search_key);
+
+            tree_reader
+                .visit(
+                    &search_key,
+                    VisitDirection::Forwards,
+                    |raw_key, offset| {
+                        let key = Key::from_slice(raw_key);
+                        assert!(key >= range.start);
+
+                        if key >= range.end {
+                            planner.handle_range_end(offset);
+                            range_end_handled = true;
+                            return false;
+                        }
+
+                        planner.handle(key, self.lsn, offset, BlobFlag::None);
+                        found.add_key(key);
+                        true
+                    },
+                    ctx,
+                )
+                .await?;
+
+            if !range_end_handled {
+                planner.handle_range_end(values_end);
+            }
+        }
+
+        Ok((planner.finish(), found.to_keyspace()))
+    }
+
+    async fn do_reads_and_update_state(
+        &self,
+        reads: Vec<VectoredRead>,
+        reconstruct_state: &mut ValuesReconstructState,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let vectored_blob_reader =
+            VectoredBlobReader::new(&self.file, self.compression.is_some())
+                .with_checksums(self.checksum.is_some());
+        // Read and verify everything before touching the reconstruct state, so that
+        // a failed visit of this layer can be retried from scratch.
+        let mut values = Vec::new();
+        for read in reads.into_iter() {
+            let bufs = vectored_blob_reader
+                .read_blobs(&read, ctx)
+                .await
+                .with_context(|| {
+                    format!(
+                        "failed to read blobs {}..{} from image layer",
+                        read.start, read.end
+                    )
+                })?;
+
+            for blob in bufs.blobs.iter() {
+                let payload = bufs.payload(blob).await.with_context(|| {
+                    format!("failed to decompress value of key {}", blob.meta.key)
+                })?;
+                values.push((blob.meta, payload));
+            }
+        }
+
+        for (meta, payload) in values {
+            reconstruct_state.update_key(&meta.key, meta.lsn, Value::Image(Bytes::from(payload)));
+        }
+
+        Ok(())
+    }
+
+    /// A cursor for reading the values of this layer.
+    fn block_cursor(&self) -> BlockCursor<'_> {
+        BlockCursor::new_with_compression(
+            BlockReaderRef::FileBlockReader(&self.file),
+            self.compression.is_some(),
+        )
+        .with_checksums(self.checksum.is_some())
+    }
 }
 
 /// A builder object for constructing a new image layer.
@@ -458,6 +620,8 @@ struct ImageLayerWriterInner {
     tenant_shard_id: TenantShardId,
     key_range: Range<Key>,
     lsn: Lsn,
+    compression: CompressionAlgorithm,
+    checksums: bool,
 
     blob_writer: BlobWriter<false>,
     tree: DiskBtreeBuilder<BlockBuf, KEY_SIZE>,
@@ -473,6 +637,8 @@ impl ImageLayerWriterInner {
         tenant_shard_id: TenantShardId,
         key_range: &Range<Key>,
         lsn: Lsn,
+        compression: CompressionAlgorithm,
+        checksums: bool,
     ) -> anyhow::Result<Self> {
         // Create the file initially with a temporary filename.
         // We'll atomically rename it to the final name when we're done.
@@ -497,7 +663,7 @@ impl ImageLayerWriterInner {
         };
         // make room for the header block
         file.seek(SeekFrom::Start(PAGE_SZ as u64)).await?;
-        let blob_writer = BlobWriter::new(file, PAGE_SZ as u64);
+        let blob_writer = BlobWriter::new(file, PAGE_SZ as u64).with_checksums(checksums);
 
         // Initialize the b-tree index builder
         let block_buf = BlockBuf::new();
@@ -510,6 +676,8 @@ impl ImageLayerWriterInner {
             tenant_shard_id,
             key_range: key_range.clone(),
             lsn,
+            compression,
+            checksums,
             tree: tree_builder,
             blob_writer,
         };
@@ -524,7 +692,10 @@ impl ImageLayerWriterInner {
     ///
     async fn put_image(&mut self, key: Key, img: Bytes) -> anyhow::Result<()> {
         ensure!(self.key_range.contains(&key));
-        let (_img, res) = self.blob_writer.write_blob(img).await;
+        let (_img, res) = self
+            .blob_writer
+            .write_blob_maybe_compressed(img, self.compression)
+            .await;
         // TODO: re-use the buffer for `img` further upstack
         let off = res?;
 
@@ -563,6 +734,8 @@ impl ImageLayerWriterInner {
             lsn: self.lsn,
             index_start_blk,
             index_root_blk,
+            compression: BlobCompression::for_algorithm(self.compression),
+            checksum: self.checksums.then_some(BlobChecksum::Crc32c),
         };
 
         let mut buf = Vec::with_capacity(PAGE_SZ);
@@ -637,11 +810,21 @@ impl ImageLayerWriter {
         tenant_shard_id: TenantShardId,
         key_range: &Range<Key>,
         lsn: Lsn,
+        compression: CompressionAlgorithm,
+        checksums: bool,
     ) -> anyhow::Result<ImageLayerWriter> {
         Ok(Self {
             inner: Some(
-                ImageLayerWriterInner::new(conf, timeline_id, tenant_shard_id, key_range, lsn)
-                    .await?,
+                ImageLayerWriterInner::new(
+                    conf,
+                    timeline_id,
+                    tenant_shard_id,
+                    key_range,
+                    lsn,
+                    compression,
+                    checksums,
+                )
+                .await?,
             ),
         })
     }
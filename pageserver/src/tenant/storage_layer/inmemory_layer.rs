@@ -9,10 +9,14 @@ use crate::context::{PageContentKind, RequestContext, RequestContextBuilder};
 use crate::repository::{Key, Value};
 use crate::tenant::block_io::BlockReader;
 use crate::tenant::ephemeral_file::EphemeralFile;
-use crate::tenant::storage_layer::{ValueReconstructResult, ValueReconstructState};
+use crate::tenant::storage_layer::{
+    ValueReconstructResult, ValueReconstructSituation, ValueReconstructState,
+    ValuesReconstructState,
+};
 use crate::tenant::Timeline;
 use crate::walrecord;
 use anyhow::{ensure, Result};
+use pageserver_api::keyspace::KeySpace;
 use pageserver_api::models::InMemoryLayerInfo;
 use pageserver_api::shard::TenantShardId;
 use std::collections::HashMap;
@@ -100,6 +104,22 @@ impl InMemoryLayer {
         self.start_lsn..self.end_lsn_or_max()
     }
 
+    /// Returns the keys that have a value at an LSN within `lsn_range` in this layer.
+    pub(crate) async fn keys_modified_in(&self, lsn_range: &Range<Lsn>) -> Vec<Key> {
+        let inner = self.inner.read().await;
+        inner
+            .index
+            .iter()
+            .filter(|(_, vec_map)| {
+                vec_map
+                    .as_slice()
+                    .iter()
+                    .any(|(lsn, _)| lsn_range.contains(lsn))
+            })
+            .map(|(key, _)| *key)
+            .collect()
+    }
+
     /// debugging function to print out the contents of the layer
     ///
     /// this is likely completly unused
@@ -202,6 +222,47 @@ impl InMemoryLayer {
             Ok(ValueReconstructResult::Complete)
         }
     }
+
+    /// Look up the values of all keys in `keyspace` within `lsn_range`, and
+    /// add them to `reconstruct_state`, newest first.
+    pub(crate) async fn get_values_reconstruct_data(
+        &self,
+        keyspace: KeySpace,
+        lsn_range: Range<Lsn>,
+        reconstruct_state: &mut ValuesReconstructState,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        ensure!(lsn_range.start >= self.start_lsn);
+
+        let ctx = RequestContextBuilder::extend(ctx)
+            .page_content_kind(PageContentKind::InMemoryLayer)
+            .build();
+
+        let inner = self.inner.read().await;
+        let reader = inner.file.block_cursor();
+
+        for range in keyspace.ranges.iter() {
+            let mut key = range.start;
+            while key < range.end {
+                if let Some(vec_map) = inner.index.get(&key) {
+                    let slice = vec_map.slice_range(lsn_range.clone());
+                    for (entry_lsn, pos) in slice.iter().rev() {
+                        let buf = reader.read_blob(*pos, &ctx).await?;
+                        let value = Value::des(&buf)?;
+
+                        let situation = reconstruct_state.update_key(&key, *entry_lsn, value);
+                        if situation == ValueReconstructSituation::Complete {
+                            break;
+                        }
+                    }
+                }
+
+                key = key.next();
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for InMemoryLayer {
@@ -246,32 +307,39 @@ impl InMemoryLayer {
 
     /// Common subroutine of the public put_wal_record() and put_page_image() functions.
     /// Adds the page version to the in-memory tree
+    ///
+    /// Returns the number of bytes written to the ephemeral file.
     pub(crate) async fn put_value(
         &self,
         key: Key,
         lsn: Lsn,
         val: &Value,
         ctx: &RequestContext,
-    ) -> Result<()> {
+    ) -> Result<u64> {
         let mut inner = self.inner.write().await;
         self.assert_writable();
-        self.put_value_locked(&mut inner, key, lsn, val, ctx).await
+        let len_before = inner.file.len();
+        self.put_value_locked(&mut inner, key, lsn, val, ctx)
+            .await?;
+        Ok(inner.file.len() - len_before)
     }
 
+    /// Like [`Self::put_value`], for a batch of values.
     pub(crate) async fn put_values(
         &self,
         values: &HashMap<Key, Vec<(Lsn, Value)>>,
         ctx: &RequestContext,
-    ) -> Result<()> {
+    ) -> Result<u64> {
         let mut inner = self.inner.write().await;
         self.assert_writable();
+        let len_before = inner.file.len();
         for (key, vals) in values {
             for (lsn, val) in vals {
                 self.put_value_locked(&mut inner, *key, *lsn, val, ctx)
                     .await?;
             }
         }
-        Ok(())
+        Ok(inner.file.len() - len_before)
     }
 
     async fn put_value_locked(
@@ -359,6 +427,8 @@ impl InMemoryLayer {
             self.tenant_shard_id,
             Key::MIN,
             self.start_lsn..end_lsn,
+            timeline.get_layer_compression(),
+            timeline.get_layer_checksums(),
         )
         .await?;
 
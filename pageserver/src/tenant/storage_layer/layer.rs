@@ -1,13 +1,14 @@
 use anyhow::Context;
 use camino::{Utf8Path, Utf8PathBuf};
+use pageserver_api::keyspace::KeySpace;
 use pageserver_api::models::{
     HistoricLayerInfo, LayerAccessKind, LayerResidenceEventReason, LayerResidenceStatus,
 };
 use pageserver_api::shard::ShardIndex;
 use std::ops::Range;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::{Arc, Weak};
-use std::time::SystemTime;
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::{Duration, SystemTime};
 use tracing::Instrument;
 use utils::lsn::Lsn;
 use utils::sync::heavier_once_cell;
@@ -16,13 +17,15 @@ use crate::config::PageServerConf;
 use crate::context::RequestContext;
 use crate::repository::Key;
 use crate::span::debug_assert_current_span_has_tenant_and_timeline_id;
+use crate::tenant::blob_io;
+use crate::tenant::remote_timeline_client::index::LayerFileHash;
 use crate::tenant::{remote_timeline_client::LayerFileMetadata, Timeline};
 
 use super::delta_layer::{self, DeltaEntry};
 use super::image_layer;
 use super::{
     AsLayerDesc, LayerAccessStats, LayerAccessStatsReset, LayerFileName, PersistentLayerDesc,
-    ValueReconstructResult, ValueReconstructState,
+    ValueReconstructResult, ValueReconstructState, ValuesReconstructState,
 };
 
 use utils::generation::Generation;
@@ -99,6 +102,7 @@ impl Layer {
             None,
             metadata.generation,
             metadata.shard,
+            metadata.sha256,
         )));
 
         debug_assert!(owner.0.needs_download_blocking().unwrap().is_some());
@@ -140,6 +144,7 @@ impl Layer {
                 Some(inner),
                 metadata.generation,
                 metadata.shard,
+                metadata.sha256,
             )
         }));
 
@@ -184,6 +189,7 @@ impl Layer {
                 Some(inner),
                 timeline.generation,
                 timeline.get_shard_index(),
+                None,
             )
         }));
 
@@ -229,6 +235,9 @@ impl Layer {
     /// It is up to the caller to collect more data from the previous layer and
     /// perform WAL redo, if necessary.
     ///
+    /// If the local file fails checksum verification, it is evicted and the read is retried
+    /// once with a freshly downloaded copy.
+    ///
     /// # Cancellation-Safety
     ///
     /// This method is cancellation-safe.
@@ -241,11 +250,6 @@ impl Layer {
     ) -> anyhow::Result<ValueReconstructResult> {
         use anyhow::ensure;
 
-        let layer = self.0.get_or_maybe_download(true, Some(ctx)).await?;
-        self.0
-            .access_stats
-            .record_access(LayerAccessKind::GetValueReconstructData, ctx);
-
         if self.layer_desc().is_delta {
             ensure!(lsn_range.start >= self.layer_desc().lsn_range.start);
             ensure!(self.layer_desc().key_range.contains(&key));
@@ -255,11 +259,83 @@ impl Layer {
             ensure!(lsn_range.end >= self.layer_desc().image_layer_lsn());
         }
 
-        layer
-            .get_value_reconstruct_data(key, lsn_range, reconstruct_data, &self.0, ctx)
-            .instrument(tracing::debug_span!("get_value_reconstruct_data", layer=%self))
-            .await
-            .with_context(|| format!("get_value_reconstruct_data for layer {self}"))
+        // a failed read may have left some of the values of this layer behind
+        let records_before = reconstruct_data.records.len();
+        let img_before = reconstruct_data.img.clone();
+
+        let mut retried = false;
+        loop {
+            let layer = self.0.get_or_maybe_download(true, Some(ctx)).await?;
+            self.0
+                .access_stats
+                .record_access(LayerAccessKind::GetValueReconstructData, ctx);
+
+            let res = layer
+                .get_value_reconstruct_data(key, lsn_range.clone(), reconstruct_data, &self.0, ctx)
+                .instrument(tracing::debug_span!("get_value_reconstruct_data", layer=%self))
+                .await
+                .with_context(|| format!("get_value_reconstruct_data for layer {self}"));
+
+            match res {
+                Err(e) if !retried && blob_io::is_checksum_error(&e) => {
+                    drop(layer);
+                    if !self.0.evict_corrupt(&e).await {
+                        return Err(e);
+                    }
+                    retried = true;
+                    reconstruct_data.records.truncate(records_before);
+                    reconstruct_data.img = img_before.clone();
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Collect the data needed to reconstruct all keys of `keyspace` within
+    /// `lsn_range` from this layer, downloading it if needed.
+    ///
+    /// # Cancellation-Safety
+    ///
+    /// This method is cancellation-safe.
+    pub(crate) async fn get_values_reconstruct_data(
+        &self,
+        keyspace: KeySpace,
+        lsn_range: Range<Lsn>,
+        reconstruct_data: &mut ValuesReconstructState,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        let mut retried = false;
+        loop {
+            let layer = self.0.get_or_maybe_download(true, Some(ctx)).await?;
+            self.0
+                .access_stats
+                .record_access(LayerAccessKind::GetValueReconstructData, ctx);
+
+            // Layers only update `reconstruct_data` once all of their reads have been
+            // verified, so a failed attempt can simply be repeated.
+            let res = layer
+                .get_values_reconstruct_data(
+                    keyspace.clone(),
+                    lsn_range.clone(),
+                    reconstruct_data,
+                    &self.0,
+                    ctx,
+                )
+                .instrument(tracing::debug_span!("get_values_reconstruct_data", layer=%self))
+                .await
+                .with_context(|| format!("get_values_reconstruct_data for layer {self}"));
+
+            match res {
+                Err(e) if !retried && blob_io::is_checksum_error(&e) => {
+                    drop(layer);
+                    if !self.0.evict_corrupt(&e).await {
+                        return Err(e);
+                    }
+                    retried = true;
+                }
+                res => return res,
+            }
+        }
     }
 
     /// Download the layer if evicted.
@@ -316,6 +392,12 @@ impl Layer {
         self.0.metadata()
     }
 
+    /// Record the hash of the file, computed while uploading it.
+    pub(crate) fn set_uploaded_sha256(&self, sha256: LayerFileHash) {
+        // the contents never change, so neither does the hash
+        let _ = self.0.sha256.set(sha256);
+    }
+
     /// Traditional debug dumping facility
     #[allow(unused)]
     pub(crate) async fn dump(&self, verbose: bool, ctx: &RequestContext) -> anyhow::Result<()> {
@@ -457,6 +539,12 @@ struct LayerInner {
     /// a shard split since the layer was originally written.
     shard: ShardIndex,
 
+    /// Hash of the file contents, see [`LayerFileMetadata`].
+    ///
+    /// For loaded layers this comes from the index, for created layers it is set once the
+    /// upload completes.
+    sha256: OnceLock<LayerFileHash>,
+
     last_evicted_at: std::sync::Mutex<Option<std::time::Instant>>,
 }
 
@@ -560,6 +648,7 @@ impl LayerInner {
         downloaded: Option<Arc<DownloadedLayer>>,
         generation: Generation,
         shard: ShardIndex,
+        sha256: Option<LayerFileHash>,
     ) -> Self {
         let path = conf
             .timeline_path(&timeline.tenant_shard_id, &timeline.timeline_id)
@@ -588,6 +677,7 @@ impl LayerInner {
             consecutive_failures: AtomicUsize::new(0),
             generation,
             shard,
+            sha256: sha256.map(OnceLock::from).unwrap_or_default(),
             last_evicted_at: std::sync::Mutex::default(),
         }
     }
@@ -602,6 +692,39 @@ impl LayerInner {
         }
     }
 
+    /// Evict the local file after a read failed checksum verification, so that the next access
+    /// downloads it again. Returns false if the file could not be evicted, in which case there
+    /// is no point in retrying the read.
+    ///
+    /// The caller must not hold on to a [`DownloadedLayer`] of this layer.
+    async fn evict_corrupt(&self, err: &anyhow::Error) -> bool {
+        /// Eviction waits for concurrent readers to finish with the file.
+        const CORRUPT_LAYER_EVICTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+        crate::metrics::LAYER_CHECKSUM_FAILURES.inc();
+
+        if !self.have_remote_client {
+            tracing::error!(
+                "local layer file {self} is corrupt, cannot download it again: {err:#}"
+            );
+            return false;
+        }
+
+        tracing::error!(
+            "local layer file {self} is corrupt, evicting it to download it again: {err:#}"
+        );
+
+        match tokio::time::timeout(CORRUPT_LAYER_EVICTION_TIMEOUT, self.evict_and_wait()).await {
+            Ok(Ok(()) | Err(EvictionError::NotFound)) => true,
+            // someone else downloaded it again already
+            Ok(Err(EvictionError::Downloaded)) => true,
+            Err(_) => {
+                tracing::warn!("timed out evicting corrupt layer file {self}");
+                false
+            }
+        }
+    }
+
     /// Cancellation safe, however dropping the future and calling this method again might result
     /// in a new attempt to evict OR join the previously started attempt.
     pub(crate) async fn evict_and_wait(&self) -> Result<(), EvictionError> {
@@ -1155,6 +1278,7 @@ impl LayerInner {
 
     fn metadata(&self) -> LayerFileMetadata {
         LayerFileMetadata::new(self.desc.file_size, self.generation, self.shard)
+            .with_sha256(self.sha256.get().copied())
     }
 }
 
@@ -1337,6 +1461,28 @@ impl DownloadedLayer {
         }
     }
 
+    async fn get_values_reconstruct_data(
+        &self,
+        keyspace: KeySpace,
+        lsn_range: Range<Lsn>,
+        reconstruct_data: &mut ValuesReconstructState,
+        owner: &Arc<LayerInner>,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<()> {
+        use LayerKind::*;
+
+        match self.get(owner, ctx).await? {
+            Delta(d) => {
+                d.get_values_reconstruct_data(keyspace, lsn_range, reconstruct_data, ctx)
+                    .await
+            }
+            Image(i) => {
+                i.get_values_reconstruct_data(keyspace, reconstruct_data, ctx)
+                    .await
+            }
+        }
+    }
+
     async fn dump(&self, owner: &Arc<LayerInner>, ctx: &RequestContext) -> anyhow::Result<()> {
         use LayerKind::*;
         match self.get(owner, ctx).await? {
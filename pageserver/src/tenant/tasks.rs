@@ -9,7 +9,7 @@ use crate::context::{DownloadBehavior, RequestContext};
 use crate::metrics::TENANT_TASK_EVENTS;
 use crate::task_mgr;
 use crate::task_mgr::{TaskKind, BACKGROUND_RUNTIME};
-use crate::tenant::throttle::Stats;
+use crate::tenant::throttle::{Metric, Stats, Throttle};
 use crate::tenant::timeline::CompactionError;
 use crate::tenant::{Tenant, TenantState};
 use tokio_util::sync::CancellationToken;
@@ -208,23 +208,14 @@ async fn compaction_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
 
             // TODO: move this (and walredo quiesce) to a separate task that isn't affected by the back-off,
             // so we get some upper bound guarantee on when walredo quiesce / this throttling reporting here happens.
+            let now = Instant::now();
+            let prev = std::mem::replace(&mut last_throttle_flag_reset_at, now);
+            let delta = now - prev;
             info_span!(parent: None, "timeline_get_throttle", tenant_id=%tenant.tenant_shard_id, shard_id=%tenant.tenant_shard_id.shard_slug()).in_scope(|| {
-                let now = Instant::now();
-                let prev = std::mem::replace(&mut last_throttle_flag_reset_at, now);
-                let Stats { count_accounted, count_throttled, sum_throttled_usecs } = tenant.timeline_get_throttle.reset_stats();
-                if count_throttled == 0 {
-                    return;
-                }
-                let allowed_rps = tenant.timeline_get_throttle.steady_rps();
-                let delta = now - prev;
-                warn!(
-                    n_seconds=%format_args!("{:.3}",
-                    delta.as_secs_f64()),
-                    count_accounted,
-                    count_throttled,
-                    sum_throttled_usecs,
-                    allowed_rps=%format_args!("{allowed_rps:.0}"),
-                    "shard was throttled in the last n_seconds")
+                warn_if_throttled(&tenant.timeline_get_throttle, delta)
+            });
+            info_span!(parent: None, "write_throttle", tenant_id=%tenant.tenant_shard_id, shard_id=%tenant.tenant_shard_id.shard_slug()).in_scope(|| {
+                warn_if_throttled(&tenant.write_throttle, delta)
             });
 
             // Sleep
@@ -240,6 +231,29 @@ async fn compaction_loop(tenant: Arc<Tenant>, cancel: CancellationToken) {
     TENANT_TASK_EVENTS.with_label_values(&["stop"]).inc();
 }
 
+/// Log the [`Stats`] of `throttle` since the last call, `delta` ago, if it throttled anything.
+fn warn_if_throttled<M: Metric>(throttle: &Throttle<M>, delta: Duration) {
+    let Stats {
+        count_accounted,
+        sum_accounted_amount,
+        count_throttled,
+        sum_throttled_usecs,
+    } = throttle.reset_stats();
+    if count_throttled == 0 {
+        return;
+    }
+    let allowed_rps = throttle.steady_rps();
+    warn!(
+        n_seconds=%format_args!("{:.3}",
+        delta.as_secs_f64()),
+        count_accounted,
+        sum_accounted_amount,
+        count_throttled,
+        sum_throttled_usecs,
+        allowed_rps=%format_args!("{allowed_rps:.0}"),
+        "shard was throttled in the last n_seconds")
+}
+
 fn log_compaction_error(
     e: &CompactionError,
     error_run_count: u32,
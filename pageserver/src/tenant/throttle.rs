@@ -20,11 +20,15 @@ use crate::{context::RequestContext, task_mgr::TaskKind};
 /// To share a throttle among multiple entities, wrap it in an [`Arc`].
 ///
 /// The intial use case for this is tenant-wide throttling of getpage@lsn requests.
+/// It is also used to throttle the bytes that a tenant writes to disk, see
+/// [`crate::tenant::Tenant::write_throttle`].
 pub struct Throttle<M: Metric> {
     inner: ArcSwap<Inner>,
     metric: M,
     /// will be turned into [`Stats::count_accounted`]
     count_accounted: AtomicU64,
+    /// will be turned into [`Stats::sum_accounted_amount`]
+    sum_accounted_amount: AtomicU64,
     /// will be turned into [`Stats::count_throttled`]
     count_throttled: AtomicU64,
     /// will be turned into [`Stats::sum_throttled_usecs`]
@@ -50,6 +54,8 @@ pub trait Metric {
 pub struct Stats {
     // Number of requests that were subject to throttling, i.e., requests of the configured [`Config::task_kinds`].
     pub count_accounted: u64,
+    // Sum of the amounts that the `accounted` requests acquired, e.g., keys or bytes.
+    pub sum_accounted_amount: u64,
     // Subset of the `accounted` requests that were actually throttled.
     // Note that the numbers are stored as two independent atomics, so, there might be a slight drift.
     pub count_throttled: u64,
@@ -66,6 +72,7 @@ where
             inner: ArcSwap::new(Arc::new(Self::new_inner(config))),
             metric,
             count_accounted: AtomicU64::new(0),
+            sum_accounted_amount: AtomicU64::new(0),
             count_throttled: AtomicU64::new(0),
             sum_throttled_usecs: AtomicU64::new(0),
         }
@@ -116,10 +123,12 @@ where
     /// Useful for periodic reporting.
     pub fn reset_stats(&self) -> Stats {
         let count_accounted = self.count_accounted.swap(0, Ordering::Relaxed);
+        let sum_accounted_amount = self.sum_accounted_amount.swap(0, Ordering::Relaxed);
         let count_throttled = self.count_throttled.swap(0, Ordering::Relaxed);
         let sum_throttled_usecs = self.sum_throttled_usecs.swap(0, Ordering::Relaxed);
         Stats {
             count_accounted,
+            sum_accounted_amount,
             count_throttled,
             sum_throttled_usecs,
         }
@@ -130,14 +139,17 @@ where
         self.inner.load().config.steady_rps()
     }
 
-    pub async fn throttle(&self, ctx: &RequestContext, key_count: usize) {
+    /// Acquire `amount` from the rate limiter if the task kind of `ctx` is throttled.
+    ///
+    /// The unit of `amount` is up to the user, e.g., keys for getpage@lsn, bytes for writes.
+    pub async fn throttle(&self, ctx: &RequestContext, amount: usize) {
         let inner = self.inner.load_full(); // clones the `Inner` Arc
         if !inner.task_kinds.contains(ctx.task_kind()) {
             return;
         };
         let start = std::time::Instant::now();
         let mut did_throttle = false;
-        let acquire = inner.rate_limiter.acquire(key_count);
+        let acquire = inner.rate_limiter.acquire(amount);
         // turn off runtime-induced preemption (aka coop) so our `did_throttle` is accurate
         let acquire = tokio::task::unconstrained(acquire);
         let mut acquire = std::pin::pin!(acquire);
@@ -149,6 +161,8 @@ where
         })
         .await;
         self.count_accounted.fetch_add(1, Ordering::Relaxed);
+        self.sum_accounted_amount
+            .fetch_add(amount as u64, Ordering::Relaxed);
         if did_throttle {
             self.count_throttled.fetch_add(1, Ordering::Relaxed);
             let now = Instant::now();
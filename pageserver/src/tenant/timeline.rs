@@ -1,13 +1,18 @@
 pub mod delete;
+pub(crate) mod detach_ancestor;
 mod eviction_task;
 mod init;
+pub(crate) mod key_range_stats;
 pub mod layer_manager;
 pub(crate) mod logical_size;
+pub(crate) mod relation_sizes;
 pub mod span;
+mod tiered_compaction;
 pub mod uninit;
 mod walreceiver;
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
+use arc_swap::ArcSwapOption;
 use bytes::Bytes;
 use camino::{Utf8Path, Utf8PathBuf};
 use enumset::EnumSet;
@@ -18,8 +23,8 @@ use once_cell::sync::Lazy;
 use pageserver_api::{
     keyspace::{key_range_size, KeySpaceAccum},
     models::{
-        DownloadRemoteLayersTaskInfo, DownloadRemoteLayersTaskSpawnRequest, EvictionPolicy,
-        LayerMapInfo, TimelineState,
+        CompactionAlgorithm, CompressionAlgorithm, DownloadRemoteLayersTaskInfo,
+        DownloadRemoteLayersTaskSpawnRequest, EvictionPolicy, LayerMapInfo, TimelineState,
     },
     reltag::BlockNumber,
     shard::{ShardIdentity, TenantShardId},
@@ -42,7 +47,7 @@ use std::sync::{Arc, Mutex, RwLock, Weak};
 use std::time::{Duration, Instant, SystemTime};
 use std::{
     array,
-    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet},
     sync::atomic::AtomicU64,
 };
 use std::{
@@ -53,7 +58,8 @@ use std::{
 use crate::pgdatadir_mapping::DirectoryKind;
 use crate::tenant::timeline::logical_size::CurrentLogicalSize;
 use crate::tenant::{
-    layer_map::{LayerMap, SearchResult},
+    blob_io,
+    layer_map::{LayerMap, OrderedSearchResult, SearchResult},
     metadata::{save_metadata, TimelineMetadata},
     par_fsync,
 };
@@ -66,8 +72,9 @@ use crate::{
     disk_usage_eviction_task::finite_f32,
     tenant::storage_layer::{
         AsLayerDesc, DeltaLayerWriter, EvictionError, ImageLayerWriter, InMemoryLayer, Layer,
-        LayerAccessStatsReset, LayerFileName, ResidentLayer, ValueReconstructResult,
-        ValueReconstructState,
+        LayerAccessStatsReset, LayerFileName, LayerFringe, PersistentLayerDesc, ReadableLayer,
+        ResidentLayer, ValueReconstructResult, ValueReconstructSituation, ValueReconstructState,
+        ValuesReconstructState,
     },
 };
 use crate::{
@@ -78,7 +85,8 @@ use crate::{pgdatadir_mapping::LsnForTimestamp, tenant::tasks::BackgroundLoopKin
 use crate::config::PageServerConf;
 use crate::keyspace::{KeyPartitioning, KeySpace, KeySpaceRandomAccum};
 use crate::metrics::{
-    TimelineMetrics, MATERIALIZED_PAGE_CACHE_HIT, MATERIALIZED_PAGE_CACHE_HIT_DIRECT,
+    ImageLayerCreationDecision, TimelineMetrics, MATERIALIZED_PAGE_CACHE_HIT,
+    MATERIALIZED_PAGE_CACHE_HIT_DIRECT,
 };
 use crate::pgdatadir_mapping::CalculateLogicalSizeError;
 use crate::tenant::config::TenantConfOpt;
@@ -87,7 +95,7 @@ use pageserver_api::reltag::RelTag;
 use pageserver_api::shard::ShardIndex;
 
 use postgres_connection::PgConnectionConfig;
-use postgres_ffi::to_pg_timestamp;
+use postgres_ffi::{to_pg_timestamp, BLCKSZ};
 use utils::{
     completion,
     generation::Generation,
@@ -107,8 +115,10 @@ use crate::ZERO_PAGE;
 use self::delete::DeleteTimelineFlow;
 pub(super) use self::eviction_task::EvictionTaskTenantState;
 use self::eviction_task::EvictionTaskTimelineState;
+use self::key_range_stats::{KeyRangeStatsCollector, ReadHeat};
 use self::layer_manager::LayerManager;
 use self::logical_size::LogicalSize;
+use self::relation_sizes::RelationSizesCache;
 use self::walreceiver::{WalReceiver, WalReceiverConf};
 
 use super::config::TenantConf;
@@ -167,6 +177,15 @@ pub struct TimelineResources {
     pub timeline_get_throttle: Arc<
         crate::tenant::throttle::Throttle<&'static crate::metrics::tenant_throttling::TimelineGet>,
     >,
+    pub write_throttle:
+        Arc<crate::tenant::throttle::Throttle<&'static crate::metrics::tenant_throttling::Write>>,
+}
+
+/// The timeline that a timeline was branched from, see [`Timeline::get_ancestor_timeline`].
+struct Ancestor {
+    timeline: Arc<Timeline>,
+    /// The LSN of the branch point
+    lsn: Lsn,
 }
 
 pub struct Timeline {
@@ -257,9 +276,9 @@ pub struct Timeline {
     disk_consistent_lsn: AtomicLsn,
 
     // Parent timeline that this timeline was branched from, and the LSN
-    // of the branch point.
-    ancestor_timeline: Option<Arc<Timeline>>,
-    ancestor_lsn: Lsn,
+    // of the branch point. Cleared when the timeline is detached from its
+    // ancestor, see [`detach_ancestor`].
+    ancestor: ArcSwapOption<Ancestor>,
 
     pub(super) metrics: TimelineMetrics,
 
@@ -311,6 +330,12 @@ pub struct Timeline {
     /// Current logical size of the "datadir", at the last LSN.
     current_logical_size: LogicalSize,
 
+    /// Logical size of each database and relation at the last LSN, see [`relation_sizes`].
+    relation_sizes: RelationSizesCache,
+
+    /// Sampled reads and writes per key range, see [`key_range_stats`].
+    pub(crate) key_range_stats: KeyRangeStatsCollector,
+
     /// Information about the last processed message by the WAL receiver,
     /// or None if WAL receiver has not received anything for this timeline
     /// yet.
@@ -363,6 +388,10 @@ pub struct Timeline {
     timeline_get_throttle: Arc<
         crate::tenant::throttle::Throttle<&'static crate::metrics::tenant_throttling::TimelineGet>,
     >,
+
+    /// Cloned from [`super::Tenant::write_throttle`] on construction.
+    write_throttle:
+        Arc<crate::tenant::throttle::Throttle<&'static crate::metrics::tenant_throttling::Write>>,
 }
 
 pub struct WalReceiverInfo {
@@ -404,7 +433,7 @@ pub struct GcInfo {
 #[derive(thiserror::Error, Debug)]
 pub(crate) enum PageReconstructError {
     #[error(transparent)]
-    Other(#[from] anyhow::Error),
+    Other(anyhow::Error),
 
     #[error("Ancestor LSN wait error: {0}")]
     AncestorLsnTimeout(#[from] WaitLsnError),
@@ -419,6 +448,20 @@ pub(crate) enum PageReconstructError {
     /// An error happened replaying WAL records
     #[error(transparent)]
     WalRedo(anyhow::Error),
+
+    /// A layer file failed checksum verification, even after downloading it again
+    #[error(transparent)]
+    Corrupt(anyhow::Error),
+}
+
+impl From<anyhow::Error> for PageReconstructError {
+    fn from(e: anyhow::Error) -> Self {
+        if blob_io::is_checksum_error(&e) {
+            PageReconstructError::Corrupt(e)
+        } else {
+            PageReconstructError::Other(e)
+        }
+    }
 }
 
 impl PageReconstructError {
@@ -430,6 +473,7 @@ impl PageReconstructError {
             AncestorLsnTimeout(_) => false,
             Cancelled | AncestorStopping(_) => true,
             WalRedo(_) => false,
+            Corrupt(_) => false,
         }
     }
 }
@@ -472,6 +516,12 @@ pub(crate) enum GetVectoredError {
 
     #[error("Requested at invalid LSN: {0}")]
     InvalidLsn(Lsn),
+
+    #[error(transparent)]
+    GetReadyAncestorError(GetReadyAncestorError),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -558,6 +608,17 @@ impl From<PageReconstructError> for CreateImageLayersError {
     }
 }
 
+impl From<GetReadyAncestorError> for GetVectoredError {
+    fn from(e: GetReadyAncestorError) -> Self {
+        match e {
+            GetReadyAncestorError::Cancelled | GetReadyAncestorError::AncestorStopping(_) => {
+                GetVectoredError::Cancelled
+            }
+            _ => GetVectoredError::GetReadyAncestorError(e),
+        }
+    }
+}
+
 impl From<GetVectoredError> for CreateImageLayersError {
     fn from(e: GetVectoredError) -> Self {
         match e {
@@ -567,6 +628,16 @@ impl From<GetVectoredError> for CreateImageLayersError {
     }
 }
 
+impl From<GetVectoredError> for PageReconstructError {
+    fn from(e: GetVectoredError) -> Self {
+        match e {
+            GetVectoredError::Cancelled => PageReconstructError::Cancelled,
+            GetVectoredError::Other(other) => PageReconstructError::from(other),
+            _ => PageReconstructError::Other(anyhow::Error::new(e)),
+        }
+    }
+}
+
 impl From<GetReadyAncestorError> for PageReconstructError {
     fn from(e: GetReadyAncestorError) -> Self {
         use GetReadyAncestorError::*;
@@ -581,16 +652,21 @@ impl From<GetReadyAncestorError> for PageReconstructError {
 
 /// Public interface functions
 impl Timeline {
-    /// Get the LSN where this branch was created
+    /// Get the LSN where this branch was created, or `Lsn(0)` if it has no ancestor
     pub(crate) fn get_ancestor_lsn(&self) -> Lsn {
-        self.ancestor_lsn
+        self.ancestor
+            .load()
+            .as_ref()
+            .map(|ancestor| ancestor.lsn)
+            .unwrap_or(Lsn(0))
     }
 
     /// Get the ancestor's timeline id
     pub(crate) fn get_ancestor_timeline_id(&self) -> Option<TimelineId> {
-        self.ancestor_timeline
+        self.ancestor
+            .load()
             .as_ref()
-            .map(|ancestor| ancestor.timeline_id)
+            .map(|ancestor| ancestor.timeline.timeline_id)
     }
 
     /// Lock and get timeline's GC cutoff
@@ -669,6 +745,8 @@ impl Timeline {
             .get_reconstruct_data(key, lsn, &mut reconstruct_state, ctx)
             .await?;
         timer.stop_and_record();
+        self.key_range_stats
+            .record_read(key, path.len(), reconstruct_state.records.len());
 
         let start = Instant::now();
         let res = self.reconstruct_value(key, lsn, reconstruct_state).await;
@@ -700,12 +778,20 @@ impl Timeline {
         res
     }
 
-    pub(crate) const MAX_GET_VECTORED_KEYS: u64 = 32;
+    pub(crate) const MAX_GET_VECTORED_KEYS: u64 = 128;
 
     /// Look up multiple page versions at a given LSN
     ///
-    /// This naive implementation will be replaced with a more efficient one
-    /// which actually vectorizes the read path.
+    /// The layer map is walked once for all the requested keys rather than
+    /// once per key: each layer is visited at most once per LSN range, and
+    /// returns the data of all the keys it holds in one pass, reading its
+    /// index once per key range and coalescing the value reads (see
+    /// [`Self::get_vectored_reconstruct_data`]).
+    ///
+    /// Like with [`Self::get`], failing to reconstruct a key is reported
+    /// per key in the returned map. That includes errors reading a layer,
+    /// which only fail the keys that were read from it. Cancellation and
+    /// failing to reach the ancestor timeline fail the whole request.
     pub(crate) async fn get_vectored(
         &self,
         key_ranges: &[Range<Key>],
@@ -733,12 +819,67 @@ impl Timeline {
             .map(|t| t.start_timer());
 
         let mut values = BTreeMap::new();
+        let mut reconstruct_state = ValuesReconstructState::new();
+
+        // Pages that were materialized at exactly this LSN before can be
+        // served from the page cache without visiting any layers. Like in
+        // [`Self::get`], an older materialized version still saves us from
+        // collecting and replaying the WAL below it.
+        let mut keyspace = KeySpaceRandomAccum::new();
         for range in key_ranges {
             let mut key = range.start;
             while key != range.end {
                 assert!(!self.shard_identity.is_key_disposable(&key));
 
-                let block = self.get(key, lsn, ctx).await;
+                match self.lookup_cached_page(&key, lsn, ctx).await {
+                    Some((cached_lsn, img)) if cached_lsn == lsn => {
+                        MATERIALIZED_PAGE_CACHE_HIT_DIRECT.inc();
+                        values.insert(key, Ok(img));
+                    }
+                    Some((cached_lsn, img)) => {
+                        reconstruct_state.set_cached_image(key, cached_lsn, img);
+                        keyspace.add_key(key);
+                    }
+                    None => keyspace.add_key(key),
+                }
+                key = key.next();
+            }
+        }
+        let keyspace = keyspace.to_keyspace();
+
+        let timer = crate::metrics::GET_RECONSTRUCT_DATA_TIME.start_timer();
+        self.get_vectored_reconstruct_data(keyspace.clone(), lsn, &mut reconstruct_state, ctx)
+            .await?;
+        timer.stop_and_record();
+
+        // Image layer creation reads every key of the partition: counting those reads
+        // would keep the partitions from ever looking cold.
+        let record_reads = ctx.task_kind() != TaskKind::Compaction;
+        let mut reads = Vec::new();
+
+        for range in keyspace.ranges {
+            let mut key = range.start;
+            while key != range.end {
+                let block = match reconstruct_state.keys.remove(&key) {
+                    Some(Ok(state)) if state.situation() == ValueReconstructSituation::Complete => {
+                        if record_reads {
+                            reads.push((key, state.layers_visited() as usize, state.records.len()));
+                        }
+                        let start = Instant::now();
+                        let res = self.reconstruct_value(key, lsn, state.into()).await;
+                        crate::metrics::RECONSTRUCT_TIME
+                            .for_result(&res)
+                            .observe(start.elapsed().as_secs_f64());
+                        res
+                    }
+                    Some(Err(err)) => Err(err),
+                    Some(Ok(_)) | None => Err(PageReconstructError::from(anyhow!(
+                        "could not find data for key {} (shard {:?}) at LSN {}",
+                        key,
+                        self.shard_identity.get_shard_number(&key),
+                        lsn
+                    ))),
+                };
 
                 if matches!(
                     block,
@@ -751,6 +892,7 @@ impl Timeline {
                 key = key.next();
             }
         }
+        self.key_range_stats.record_reads(reads.into_iter());
 
         Ok(values)
     }
@@ -1009,7 +1151,14 @@ impl Timeline {
 
                 // 2. Compact
                 let timer = self.metrics.compact_time_histo.start_timer();
-                self.compact_level0(target_file_size, ctx).await?;
+                match self.get_compaction_algorithm() {
+                    CompactionAlgorithm::Legacy => {
+                        self.compact_level0(target_file_size, ctx).await?
+                    }
+                    CompactionAlgorithm::Tiered => {
+                        self.compact_tiered(target_file_size, ctx).await?
+                    }
+                }
                 timer.stop_and_record();
 
                 // 3. Create new image layers for partitions that have been modified
@@ -1304,6 +1453,67 @@ impl Timeline {
         }
     }
 
+    /// Collect the keys that were modified after `from_lsn`, up to and including
+    /// `lsn`, from the delta and in-memory layers covering that range.
+    ///
+    /// The WAL of the range must not have been garbage collected yet, and it must
+    /// all be on this timeline: `from_lsn` can't be below the branch point.
+    pub(crate) async fn collect_modified_keys(
+        &self,
+        from_lsn: Lsn,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<BTreeSet<Key>> {
+        ensure!(
+            from_lsn <= lsn,
+            "start LSN {from_lsn} is after end LSN {lsn}"
+        );
+        let ancestor_lsn = self.get_ancestor_lsn();
+        ensure!(
+            from_lsn >= ancestor_lsn,
+            "start LSN {from_lsn} is before the branch point {ancestor_lsn}",
+        );
+        // Hold the GC cutoff while reading the layers, so that GC doesn't remove them
+        let latest_gc_cutoff_lsn = self.get_latest_gc_cutoff_lsn();
+        self.check_lsn_is_in_scope(from_lsn, &latest_gc_cutoff_lsn)?;
+
+        let lsn_range = Lsn(from_lsn.0 + 1)..Lsn(lsn.0 + 1);
+        let overlaps = |r: &Range<Lsn>| r.start < lsn_range.end && lsn_range.start < r.end;
+
+        let (in_memory_layers, delta_layers) = {
+            let guard = self.layers.read().await;
+            let layer_map = guard.layer_map();
+            let in_memory_layers = layer_map
+                .open_layer
+                .iter()
+                .chain(layer_map.frozen_layers.iter())
+                .filter(|layer| overlaps(&layer.get_lsn_range()))
+                .cloned()
+                .collect::<Vec<_>>();
+            let delta_layers = layer_map
+                .iter_historic_layers()
+                .filter(|desc| desc.is_delta() && overlaps(&desc.get_lsn_range()))
+                .map(|desc| guard.get_from_desc(&desc))
+                .collect::<Vec<_>>();
+            (in_memory_layers, delta_layers)
+        };
+
+        let mut keys = BTreeSet::new();
+        for layer in in_memory_layers {
+            keys.extend(layer.keys_modified_in(&lsn_range).await);
+        }
+        for layer in delta_layers {
+            let resident = layer.download_and_keep_resident().await?;
+            for entry in resident.load_keys(ctx).await? {
+                if lsn_range.contains(&entry.lsn) {
+                    keys.insert(entry.key);
+                }
+            }
+        }
+        drop(latest_gc_cutoff_lsn);
+        Ok(keys)
+    }
+
     #[instrument(skip_all, fields(tenant_id = %self.tenant_shard_id.tenant_id, shard_id = %self.tenant_shard_id.shard_slug(), timeline_id = %self.timeline_id))]
     pub(crate) async fn download_layer(
         &self,
@@ -1346,6 +1556,10 @@ impl Timeline {
 /// Number of times we will compute partition within a checkpoint distance.
 const REPARTITION_FREQ_IN_CHECKPOINT_DISTANCE: u64 = 10;
 
+/// Partitions that are not read get image layers only once they have this many
+/// times the image creation threshold of deltas.
+const COLD_IMAGE_CREATION_THRESHOLD_FACTOR: usize = 4;
+
 // Private functions
 impl Timeline {
     pub(crate) fn get_lazy_slru_download(&self) -> bool {
@@ -1355,6 +1569,20 @@ impl Timeline {
             .unwrap_or(self.conf.default_tenant_conf.lazy_slru_download)
     }
 
+    pub(crate) fn get_layer_compression(&self) -> CompressionAlgorithm {
+        let tenant_conf = self.tenant_conf.read().unwrap().tenant_conf.clone();
+        tenant_conf
+            .layer_compression
+            .unwrap_or(self.conf.default_tenant_conf.layer_compression)
+    }
+
+    pub(crate) fn get_layer_checksums(&self) -> bool {
+        let tenant_conf = self.tenant_conf.read().unwrap().tenant_conf.clone();
+        tenant_conf
+            .layer_checksums
+            .unwrap_or(self.conf.default_tenant_conf.layer_checksums)
+    }
+
     fn get_checkpoint_distance(&self) -> u64 {
         let tenant_conf = self.tenant_conf.read().unwrap().tenant_conf.clone();
         tenant_conf
@@ -1383,6 +1611,13 @@ impl Timeline {
             .unwrap_or(self.conf.default_tenant_conf.compaction_threshold)
     }
 
+    fn get_compaction_algorithm(&self) -> CompactionAlgorithm {
+        let tenant_conf = self.tenant_conf.read().unwrap().tenant_conf.clone();
+        tenant_conf
+            .compaction_algorithm
+            .unwrap_or(self.conf.default_tenant_conf.compaction_algorithm)
+    }
+
     fn get_image_creation_threshold(&self) -> usize {
         let tenant_conf = self.tenant_conf.read().unwrap().tenant_conf.clone();
         tenant_conf
@@ -1413,6 +1648,20 @@ impl Timeline {
             .unwrap_or(self.conf.default_tenant_conf.gc_feedback)
     }
 
+    fn get_gc_branch_images(&self) -> bool {
+        let tenant_conf = &self.tenant_conf.read().unwrap().tenant_conf.clone();
+        tenant_conf
+            .gc_branch_images
+            .unwrap_or(self.conf.default_tenant_conf.gc_branch_images)
+    }
+
+    fn get_image_creation_read_heat(&self) -> bool {
+        let tenant_conf = &self.tenant_conf.read().unwrap().tenant_conf.clone();
+        tenant_conf
+            .image_creation_read_heat
+            .unwrap_or(self.conf.default_tenant_conf.image_creation_read_heat)
+    }
+
     pub(super) fn tenant_conf_updated(&self) {
         // NB: Most tenant conf options are read by background loops, so,
         // changes will automatically be picked up.
@@ -1505,8 +1754,12 @@ impl Timeline {
 
                 loaded_at: (disk_consistent_lsn, SystemTime::now()),
 
-                ancestor_timeline: ancestor,
-                ancestor_lsn: metadata.ancestor_lsn(),
+                ancestor: ArcSwapOption::from(ancestor.map(|timeline| {
+                    Arc::new(Ancestor {
+                        timeline,
+                        lsn: metadata.ancestor_lsn(),
+                    })
+                })),
 
                 metrics: TimelineMetrics::new(
                     &tenant_shard_id,
@@ -1549,6 +1802,8 @@ impl Timeline {
                     // initial logical size is 0.
                     LogicalSize::empty_initial()
                 },
+                relation_sizes: RelationSizesCache::default(),
+                key_range_stats: KeyRangeStatsCollector::default(),
                 partitioning: Mutex::new((KeyPartitioning::new(), Lsn(0))),
                 repartition_threshold: 0,
 
@@ -1571,6 +1826,7 @@ impl Timeline {
                 gc_lock: tokio::sync::Mutex::default(),
 
                 timeline_get_throttle: resources.timeline_get_throttle,
+                write_throttle: resources.write_throttle,
             };
             result.repartition_threshold =
                 result.get_checkpoint_distance() / REPARTITION_FREQ_IN_CHECKPOINT_DISTANCE;
@@ -2449,7 +2705,7 @@ impl Timeline {
                             key,
                             Lsn(cont_lsn.0 - 1),
                             request_lsn,
-                            timeline.ancestor_lsn
+                            timeline.get_ancestor_lsn()
                         ), traversal_path));
                     }
                     prev_lsn = cont_lsn;
@@ -2473,10 +2729,11 @@ impl Timeline {
             }
 
             // Recurse into ancestor if needed
-            if is_inherited_key(key) && Lsn(cont_lsn.0 - 1) <= timeline.ancestor_lsn {
+            let ancestor_lsn = timeline.get_ancestor_lsn();
+            if is_inherited_key(key) && Lsn(cont_lsn.0 - 1) <= ancestor_lsn {
                 trace!(
                     "going into ancestor {}, cont_lsn is {}",
-                    timeline.ancestor_lsn,
+                    ancestor_lsn,
                     cont_lsn
                 );
 
@@ -2577,10 +2834,10 @@ impl Timeline {
                     }),
                 ));
                 continue 'outer;
-            } else if timeline.ancestor_timeline.is_some() {
+            } else if let Some(ancestor) = timeline.ancestor.load().as_ref() {
                 // Nothing on this timeline. Traverse to parent
                 result = ValueReconstructResult::Continue;
-                cont_lsn = Lsn(timeline.ancestor_lsn.0 + 1);
+                cont_lsn = Lsn(ancestor.lsn.0 + 1);
                 continue 'outer;
             } else {
                 // Nothing found
@@ -2590,6 +2847,197 @@ impl Timeline {
         }
     }
 
+    /// Collect the data needed to reconstruct all keys of `keyspace` at
+    /// `request_lsn` into `reconstruct_state`, following the ancestor chain
+    /// for the keys that are not complete on this timeline.
+    ///
+    /// Keys for which no complete set of data was found are left incomplete
+    /// (or absent) in `reconstruct_state`; it is up to the caller to report them.
+    ///
+    /// # Cancel-Safety
+    ///
+    /// This method is cancellation-safe.
+    async fn get_vectored_reconstruct_data(
+        &self,
+        mut keyspace: KeySpace,
+        request_lsn: Lsn,
+        reconstruct_state: &mut ValuesReconstructState,
+        ctx: &RequestContext,
+    ) -> Result<(), GetVectoredError> {
+        let mut timeline_owned: Arc<Timeline>;
+        let mut timeline = self;
+
+        let mut cont_lsn = Lsn(request_lsn.0 + 1);
+
+        loop {
+            if self.cancel.is_cancelled() {
+                return Err(GetVectoredError::Cancelled);
+            }
+
+            let completed = Self::get_vectored_reconstruct_data_timeline(
+                timeline,
+                keyspace.clone(),
+                cont_lsn,
+                reconstruct_state,
+                &self.cancel,
+                ctx,
+            )
+            .await?;
+            keyspace.remove_overlapping_with(&completed);
+
+            let Some(ancestor_lsn) = timeline.ancestor.load().as_ref().map(|a| a.lsn) else {
+                break;
+            };
+
+            // Only inherited keys continue on the ancestor timeline
+            let mut inherited = KeySpaceRandomAccum::new();
+            for range in keyspace.ranges {
+                let mut key = range.start;
+                while key != range.end {
+                    if is_inherited_key(key) {
+                        inherited.add_key(key);
+                    }
+                    key = key.next();
+                }
+            }
+            keyspace = inherited.to_keyspace();
+            if keyspace.ranges.is_empty() {
+                break;
+            }
+
+            trace!(
+                "going into ancestor {}, cont_lsn is {}",
+                ancestor_lsn,
+                cont_lsn
+            );
+            cont_lsn = min(cont_lsn, Lsn(ancestor_lsn.0 + 1));
+            reconstruct_state.on_lsn_advanced(&keyspace, cont_lsn);
+            timeline_owned = timeline.get_ready_ancestor_timeline(ctx).await?;
+            timeline = &*timeline_owned;
+        }
+
+        crate::metrics::READ_NUM_FS_LAYERS.observe(reconstruct_state.get_layers_visited() as f64);
+
+        Ok(())
+    }
+
+    /// Collect the data for `keyspace` below `cont_lsn` from the layers of
+    /// `timeline` only, ignoring its ancestors. Returns the keys that were
+    /// completed.
+    ///
+    /// The layers to read form a [`LayerFringe`], which is consumed newest
+    /// layer first. Whenever a layer has been read, the keys that are still
+    /// incomplete are looked up again in the layer map below that layer's
+    /// LSN floor, with one range search per key range. A layer reached by
+    /// several key ranges is read once for all of them.
+    ///
+    /// If reading a layer fails, the keys that were read from it are completed
+    /// with the error, and the other keys carry on.
+    async fn get_vectored_reconstruct_data_timeline(
+        timeline: &Timeline,
+        keyspace: KeySpace,
+        mut cont_lsn: Lsn,
+        reconstruct_state: &mut ValuesReconstructState,
+        cancel: &CancellationToken,
+        ctx: &RequestContext,
+    ) -> Result<KeySpace, GetVectoredError> {
+        let mut unmapped_keyspace = keyspace;
+        let mut fringe = LayerFringe::new();
+
+        let mut completed_keyspace = KeySpaceRandomAccum::new();
+
+        loop {
+            if cancel.is_cancelled() {
+                return Err(GetVectoredError::Cancelled);
+            }
+
+            let keys_done_last_step = reconstruct_state.consume_done_keys();
+            unmapped_keyspace.remove_overlapping_with(&keys_done_last_step);
+            for range in keys_done_last_step.ranges {
+                completed_keyspace.add_range(range);
+            }
+
+            if !unmapped_keyspace.ranges.is_empty() {
+                let guard = timeline.layers.read().await;
+                let layers = guard.layer_map();
+
+                // Check the open and frozen in-memory layers first, in order from newest
+                // to oldest.
+                let in_memory_layer = layers
+                    .open_layer
+                    .iter()
+                    .chain(layers.frozen_layers.iter().rev())
+                    .find(|layer| cont_lsn > layer.get_lsn_range().start);
+
+                match in_memory_layer {
+                    Some(layer) => {
+                        let lsn_range = layer.get_lsn_range().start..cont_lsn;
+                        fringe.update(
+                            ReadableLayer::InMemoryLayer(Arc::clone(layer)),
+                            unmapped_keyspace.clone(),
+                            lsn_range,
+                        );
+                    }
+                    None => {
+                        for range in unmapped_keyspace.ranges.iter() {
+                            let Some(results) = layers.range_search(range.clone(), cont_lsn) else {
+                                continue;
+                            };
+
+                            for (OrderedSearchResult(SearchResult { layer, lsn_floor }), keys) in
+                                results.found
+                            {
+                                fringe.update(
+                                    ReadableLayer::PersistentLayer(guard.get_from_desc(&layer)),
+                                    keys.to_keyspace(),
+                                    lsn_floor..cont_lsn,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+
+            let Some((layer, keyspace, lsn_range)) = fringe.next_layer() else {
+                break;
+            };
+
+            if let Err(err) = layer
+                .get_values_reconstruct_data(
+                    keyspace.clone(),
+                    lsn_range.clone(),
+                    reconstruct_state,
+                    ctx,
+                )
+                .await
+            {
+                let err = format!("{err:#}");
+                for range in keyspace.ranges.iter() {
+                    let mut key = range.start;
+                    while key != range.end {
+                        reconstruct_state.on_key_error(
+                            key,
+                            PageReconstructError::from(anyhow!("read layer: {err}")),
+                        );
+                        key = key.next();
+                    }
+                }
+                unmapped_keyspace = KeySpace::default();
+                continue;
+            }
+            if matches!(layer, ReadableLayer::PersistentLayer(_)) {
+                // metrics: in-memory layers do not count as fs access
+                reconstruct_state.on_layer_visited(&keyspace);
+            }
+
+            cont_lsn = lsn_range.start;
+            reconstruct_state.on_lsn_advanced(&keyspace, cont_lsn);
+            unmapped_keyspace = keyspace;
+        }
+
+        Ok(completed_keyspace.to_keyspace())
+    }
+
     /// # Cancel-safety
     ///
     /// This method is cancellation-safe.
@@ -2659,7 +3107,7 @@ impl Timeline {
             }
         }
         ancestor
-            .wait_lsn(self.ancestor_lsn, ctx)
+            .wait_lsn(self.get_ancestor_lsn(), ctx)
             .await
             .map_err(|e| match e {
                 e @ WaitLsnError::Timeout(_) => GetReadyAncestorError::AncestorLsnTimeout(e),
@@ -2671,14 +3119,14 @@ impl Timeline {
     }
 
     fn get_ancestor_timeline(&self) -> anyhow::Result<Arc<Timeline>> {
-        let ancestor = self.ancestor_timeline.as_ref().with_context(|| {
+        let ancestor = self.ancestor.load_full().with_context(|| {
             format!(
                 "Ancestor is missing. Timeline id: {} Ancestor id {:?}",
                 self.timeline_id,
                 self.get_ancestor_timeline_id(),
             )
         })?;
-        Ok(Arc::clone(ancestor))
+        Ok(Arc::clone(&ancestor.timeline))
     }
 
     pub(crate) fn get_shard_identity(&self) -> &ShardIdentity {
@@ -2711,7 +3159,9 @@ impl Timeline {
     ) -> anyhow::Result<()> {
         //info!("PUT: key {} at {}", key, lsn);
         let layer = self.get_layer_for_write(lsn).await?;
-        layer.put_value(key, lsn, val, ctx).await?;
+        let written = layer.put_value(key, lsn, val, ctx).await?;
+        self.key_range_stats.record_writes(std::iter::once(key));
+        self.write_throttle.throttle(ctx, written as usize).await;
         Ok(())
     }
 
@@ -2721,13 +3171,20 @@ impl Timeline {
         ctx: &RequestContext,
     ) -> anyhow::Result<()> {
         // Pick the first LSN in the batch to get the layer to write to.
+        let mut written = 0;
         for lsns in values.values() {
             if let Some((lsn, _)) = lsns.first() {
                 let layer = self.get_layer_for_write(*lsn).await?;
-                layer.put_values(values, ctx).await?;
+                written = layer.put_values(values, ctx).await?;
                 break;
             }
         }
+        self.key_range_stats.record_writes(
+            values
+                .iter()
+                .flat_map(|(key, lsns)| std::iter::repeat(*key).take(lsns.len())),
+        );
+        self.write_throttle.throttle(ctx, written as usize).await;
         Ok(())
     }
 
@@ -2944,6 +3401,7 @@ impl Timeline {
                 // `create_delta_layer` will not modify the layer map.
                 // We will remove frozen layer and add delta layer in one atomic operation later.
                 let layer = self.create_delta_layer(&frozen_layer, ctx).await?;
+                self.throttle_layer_write(&layer, ctx).await;
                 (
                     // FIXME: even though we have a single image and single delta layer assumption
                     // we push them to vec
@@ -3036,16 +3494,16 @@ impl Timeline {
             None
         };
 
-        let ancestor_timeline_id = self
-            .ancestor_timeline
-            .as_ref()
-            .map(|ancestor| ancestor.timeline_id);
+        let (ancestor_timeline_id, ancestor_lsn) = match self.ancestor.load().as_ref() {
+            Some(ancestor) => (Some(ancestor.timeline.timeline_id), ancestor.lsn),
+            None => (None, Lsn(0)),
+        };
 
         let metadata = TimelineMetadata::new(
             disk_consistent_lsn,
             ondisk_prev_record_lsn,
             ancestor_timeline_id,
-            self.ancestor_lsn,
+            ancestor_lsn,
             *self.latest_gc_cutoff_lsn.read(),
             self.initdb_lsn,
             self.pg_version,
@@ -3100,6 +3558,16 @@ impl Timeline {
         Ok(())
     }
 
+    /// Account a newly written layer file to the tenant's write throttle.
+    ///
+    /// Layers are accounted after they have been written: if the tenant writes
+    /// faster than allowed, the task waits before writing its next layer.
+    async fn throttle_layer_write(&self, layer: &ResidentLayer, ctx: &RequestContext) {
+        self.write_throttle
+            .throttle(ctx, layer.layer_desc().file_size as usize)
+            .await;
+    }
+
     // Write out the given frozen in-memory layer as a new L0 delta file. This L0 file will not be tracked
     // in layer map immediately. The caller is responsible to put it into the layer map.
     async fn create_delta_layer(
@@ -3192,12 +3660,45 @@ impl Timeline {
 
     // Is it time to create a new image layer for the given partition?
     async fn time_for_new_image_layer(&self, partition: &KeySpace, lsn: Lsn) -> bool {
+        let decision = self.image_layer_creation_decision(partition, lsn).await;
+        self.metrics.record_image_layer_creation_decision(decision);
+        match decision {
+            ImageLayerCreationDecision::WantedByGc
+            | ImageLayerCreationDecision::Threshold
+            | ImageLayerCreationDecision::ReadHot => true,
+            ImageLayerCreationDecision::SkippedCold | ImageLayerCreationDecision::NotNeeded => {
+                false
+            }
+        }
+    }
+
+    async fn image_layer_creation_decision(
+        &self,
+        partition: &KeySpace,
+        lsn: Lsn,
+    ) -> ImageLayerCreationDecision {
         let threshold = self.get_image_creation_threshold();
 
+        // Adjust the threshold to the observed cost of reading the partition. Reads of a
+        // read-hot partition reconstruct pages from long delta chains over and over
+        // again, so cut the chains short. A partition that is not read at all only
+        // needs images to keep the layer map from growing deep.
+        let partition_range =
+            partition.ranges.first().unwrap().start..partition.ranges.last().unwrap().end;
+        let read_heat = if self.get_image_creation_read_heat() {
+            self.key_range_stats.read_heat(&partition_range)
+        } else {
+            ReadHeat::Normal
+        };
+        let effective_threshold = match read_heat {
+            ReadHeat::Hot => (threshold / 2).max(1),
+            ReadHeat::Normal => threshold,
+            ReadHeat::Cold => threshold.saturating_mul(COLD_IMAGE_CREATION_THRESHOLD_FACTOR),
+        };
+
         let guard = self.layers.read().await;
         let layers = guard.layer_map();
 
-        let mut max_deltas = 0;
         {
             let wanted_image_layers = self.wanted_image_layers.lock().unwrap();
             if let Some((cutoff_lsn, wanted)) = &*wanted_image_layers {
@@ -3217,12 +3718,16 @@ impl Timeline {
                             "Force generation of layer {}-{} wanted by GC, cutoff={}, lsn={})",
                             img_range.start, img_range.end, cutoff_lsn, lsn
                         );
-                        return true;
+                        return ImageLayerCreationDecision::WantedByGc;
                     }
                 }
             }
         }
 
+        // Count up to the larger of the two thresholds, to tell whether the
+        // adjustment made the difference.
+        let limit = threshold.max(effective_threshold);
+        let mut max_deltas = 0;
         for part_range in &partition.ranges {
             let image_coverage = layers.image_coverage(part_range, lsn);
             for (img_range, last_img) in image_coverage {
@@ -3244,26 +3749,39 @@ impl Timeline {
                 // are some delta layers *later* than current 'lsn', if more WAL was processed and flushed
                 // after we read last_record_lsn, which is passed here in the 'lsn' argument.
                 if img_lsn < lsn {
-                    let num_deltas =
-                        layers.count_deltas(&img_range, &(img_lsn..lsn), Some(threshold));
+                    let num_deltas = layers.count_deltas(&img_range, &(img_lsn..lsn), Some(limit));
 
-                    max_deltas = max_deltas.max(num_deltas);
-                    if num_deltas >= threshold {
+                    if num_deltas >= effective_threshold {
                         debug!(
                             "key range {}-{}, has {} deltas on this timeline in LSN range {}..{}",
                             img_range.start, img_range.end, num_deltas, img_lsn, lsn
                         );
-                        return true;
                     }
+                    max_deltas = max_deltas.max(num_deltas);
                 }
             }
         }
 
+        let decision = if max_deltas >= effective_threshold {
+            if max_deltas < threshold {
+                ImageLayerCreationDecision::ReadHot
+            } else {
+                ImageLayerCreationDecision::Threshold
+            }
+        } else if max_deltas >= threshold {
+            ImageLayerCreationDecision::SkippedCold
+        } else {
+            ImageLayerCreationDecision::NotNeeded
+        };
         debug!(
             max_deltas,
-            "none of the partitioned ranges had >= {threshold} deltas"
+            threshold,
+            effective_threshold,
+            ?read_heat,
+            ?decision,
+            "image layer creation decision"
         );
-        false
+        decision
     }
 
     #[tracing::instrument(skip_all, fields(%lsn, %force))]
@@ -3301,6 +3819,8 @@ impl Timeline {
                 self.tenant_shard_id,
                 &img_range,
                 lsn,
+                self.get_layer_compression(),
+                self.get_layer_checksums(),
             )
             .await?;
 
@@ -3310,80 +3830,16 @@ impl Timeline {
                 )))
             });
 
-            let mut wrote_keys = false;
-
-            let mut key_request_accum = KeySpaceAccum::new();
-            for range in &partition.ranges {
-                let mut key = range.start;
-                while key < range.end {
-                    // Decide whether to retain this key: usually we do, but sharded tenants may
-                    // need to drop keys that don't belong to them.  If we retain the key, add it
-                    // to `key_request_accum` for later issuing a vectored get
-                    if self.shard_identity.is_key_disposable(&key) {
-                        debug!(
-                            "Dropping key {} during compaction (it belongs on shard {:?})",
-                            key,
-                            self.shard_identity.get_shard_number(&key)
-                        );
-                    } else {
-                        key_request_accum.add_key(key);
-                    }
-
-                    let last_key_in_range = key.next() == range.end;
-                    key = key.next();
-
-                    // Maybe flush `key_rest_accum`
-                    if key_request_accum.size() >= Timeline::MAX_GET_VECTORED_KEYS
-                        || last_key_in_range
-                    {
-                        let results = self
-                            .get_vectored(&key_request_accum.consume_keyspace().ranges, lsn, ctx)
-                            .await?;
-
-                        for (img_key, img) in results {
-                            let img = match img {
-                                Ok(img) => img,
-                                Err(err) => {
-                                    // If we fail to reconstruct a VM or FSM page, we can zero the
-                                    // page without losing any actual user data. That seems better
-                                    // than failing repeatedly and getting stuck.
-                                    //
-                                    // We had a bug at one point, where we truncated the FSM and VM
-                                    // in the pageserver, but the Postgres didn't know about that
-                                    // and continued to generate incremental WAL records for pages
-                                    // that didn't exist in the pageserver. Trying to replay those
-                                    // WAL records failed to find the previous image of the page.
-                                    // This special case allows us to recover from that situation.
-                                    // See https://github.com/neondatabase/neon/issues/2601.
-                                    //
-                                    // Unfortunately we cannot do this for the main fork, or for
-                                    // any metadata keys, keys, as that would lead to actual data
-                                    // loss.
-                                    if is_rel_fsm_block_key(img_key) || is_rel_vm_block_key(img_key)
-                                    {
-                                        warn!("could not reconstruct FSM or VM key {img_key}, filling with zeros: {err:?}");
-                                        ZERO_PAGE.clone()
-                                    } else {
-                                        return Err(CreateImageLayersError::PageReconstructError(
-                                            err,
-                                        ));
-                                    }
-                                }
-                            };
-
-                            // Write all the keys we just read into our new image layer.
-                            image_layer_writer.put_image(img_key, img).await?;
-                            wrote_keys = true;
-                        }
-                    }
-                }
-            }
+            let wrote_keys = self
+                .put_partition_images(&mut image_layer_writer, partition, lsn, ctx)
+                .await?;
 
             if wrote_keys {
                 // Normal path: we have written some data into the new image layer for this
                 // partition, so flush it to disk.
                 start = img_range.end;
                 let image_layer = image_layer_writer.finish(self).await?;
+                self.throttle_layer_write(&image_layer, ctx).await;
                 image_layers.push(image_layer);
             } else {
                 // Special case: the image layer may be empty if this is a sharded tenant and the
@@ -3400,10 +3856,99 @@ impl Timeline {
         // That's OK, because the next GC iteration will put it back in.
         *self.wanted_image_layers.lock().unwrap() = None;
 
-        // Sync the new layer to disk before adding it to the layer map, to make sure
-        // we don't garbage collect something based on the new layer, before it has
-        // reached the disk.
-        //
+        self.finish_image_layers(&image_layers).await?;
+        timer.stop_and_record();
+
+        Ok(image_layers)
+    }
+
+    /// Read the images of all keys of `partition` at `lsn`, and write them to
+    /// `image_layer_writer`. Returns false if there were no keys to write, which
+    /// happens if none of the keys belong to this shard.
+    async fn put_partition_images(
+        &self,
+        image_layer_writer: &mut ImageLayerWriter,
+        partition: &KeySpace,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<bool, CreateImageLayersError> {
+        let mut wrote_keys = false;
+
+        let mut key_request_accum = KeySpaceAccum::new();
+        for range in &partition.ranges {
+            let mut key = range.start;
+            while key < range.end {
+                // Decide whether to retain this key: usually we do, but sharded tenants may
+                // need to drop keys that don't belong to them.  If we retain the key, add it
+                // to `key_request_accum` for later issuing a vectored get
+                if self.shard_identity.is_key_disposable(&key) {
+                    debug!(
+                        "Dropping key {} during compaction (it belongs on shard {:?})",
+                        key,
+                        self.shard_identity.get_shard_number(&key)
+                    );
+                } else {
+                    key_request_accum.add_key(key);
+                }
+
+                let last_key_in_range = key.next() == range.end;
+                key = key.next();
+
+                // Maybe flush `key_rest_accum`
+                if key_request_accum.size() >= Timeline::MAX_GET_VECTORED_KEYS || last_key_in_range
+                {
+                    let results = self
+                        .get_vectored(&key_request_accum.consume_keyspace().ranges, lsn, ctx)
+                        .await?;
+
+                    for (img_key, img) in results {
+                        let img = match img {
+                            Ok(img) => img,
+                            Err(err) => {
+                                // If we fail to reconstruct a VM or FSM page, we can zero the
+                                // page without losing any actual user data. That seems better
+                                // than failing repeatedly and getting stuck.
+                                //
+                                // We had a bug at one point, where we truncated the FSM and VM
+                                // in the pageserver, but the Postgres didn't know about that
+                                // and continued to generate incremental WAL records for pages
+                                // that didn't exist in the pageserver. Trying to replay those
+                                // WAL records failed to find the previous image of the page.
+                                // This special case allows us to recover from that situation.
+                                // See https://github.com/neondatabase/neon/issues/2601.
+                                //
+                                // Unfortunately we cannot do this for the main fork, or for
+                                // any metadata keys, keys, as that would lead to actual data
+                                // loss.
+                                if is_rel_fsm_block_key(img_key) || is_rel_vm_block_key(img_key) {
+                                    warn!("could not reconstruct FSM or VM key {img_key}, filling with zeros: {err:?}");
+                                    ZERO_PAGE.clone()
+                                } else {
+                                    return Err(CreateImageLayersError::PageReconstructError(err));
+                                }
+                            }
+                        };
+
+                        // Write all the keys we just read into our new image layer.
+                        image_layer_writer.put_image(img_key, img).await?;
+                        wrote_keys = true;
+                    }
+                }
+            }
+        }
+
+        Ok(wrote_keys)
+    }
+
+    /// Sync new image layers to disk and add them to the layer map.
+    ///
+    /// The layers must be durable before they are added to the layer map, to make
+    /// sure we don't garbage collect something based on a new layer, before it has
+    /// reached the disk.
+    async fn finish_image_layers(
+        &self,
+        image_layers: &[ResidentLayer],
+    ) -> Result<(), CreateImageLayersError> {
         // We must also fsync the timeline dir to ensure the directory entries for
         // new layer files are durable
         //
@@ -3430,11 +3975,10 @@ impl Timeline {
 
         // FIXME: we could add the images to be uploaded *before* returning from here, but right
         // now they are being scheduled outside of write lock
-        guard.track_new_image_layers(&image_layers, &self.metrics);
+        guard.track_new_image_layers(image_layers, &self.metrics);
         drop_wlock(guard);
-        timer.stop_and_record();
 
-        Ok(image_layers)
+        Ok(())
     }
 
     /// Wait until the background initial logical size calculation is complete, or
@@ -3912,13 +4456,13 @@ impl Timeline {
                         || contains_hole
                     {
                         // ... if so, flush previous layer and prepare to write new one
-                        new_layers.push(
-                            writer
-                                .take()
-                                .unwrap()
-                                .finish(prev_key.unwrap().next(), self)
-                                .await?,
-                        );
+                        let new_layer = writer
+                            .take()
+                            .unwrap()
+                            .finish(prev_key.unwrap().next(), self)
+                            .await?;
+                        self.throttle_layer_write(&new_layer, ctx).await;
+                        new_layers.push(new_layer);
                         writer = None;
 
                         if contains_hole {
@@ -3946,6 +4490,8 @@ impl Timeline {
                             debug!("Create new layer {}..{}", lsn_range.start, lsn_range.end);
                             lsn_range.clone()
                         },
+                        self.get_layer_compression(),
+                        self.get_layer_checksums(),
                     )
                     .await?,
                 );
@@ -3974,7 +4520,9 @@ impl Timeline {
             prev_key = Some(key);
         }
         if let Some(writer) = writer {
-            new_layers.push(writer.finish(prev_key.unwrap().next(), self).await?);
+            let new_layer = writer.finish(prev_key.unwrap().next(), self).await?;
+            self.throttle_layer_write(&new_layer, ctx).await;
+            new_layers.push(new_layer);
         }
 
         // Sync layers
@@ -4225,7 +4773,7 @@ impl Timeline {
     /// Currently, we don't make any attempt at removing unneeded page versions
     /// within a layer file. We can only remove the whole file if it's fully
     /// obsolete.
-    pub(super) async fn gc(&self) -> anyhow::Result<GcResult> {
+    pub(super) async fn gc(self: &Arc<Self>, ctx: &RequestContext) -> anyhow::Result<GcResult> {
         // this is most likely the background tasks, but it might be the spawned task from
         // immediate_gc
         let cancel = crate::task_mgr::shutdown_token();
@@ -4255,7 +4803,7 @@ impl Timeline {
         let new_gc_cutoff = Lsn::min(horizon_cutoff, pitr_cutoff);
 
         let res = self
-            .gc_timeline(horizon_cutoff, pitr_cutoff, retain_lsns, new_gc_cutoff)
+            .gc_timeline(horizon_cutoff, pitr_cutoff, retain_lsns, new_gc_cutoff, ctx)
             .instrument(
                 info_span!("gc_timeline", timeline_id = %self.timeline_id, cutoff = %new_gc_cutoff),
             )
@@ -4268,11 +4816,12 @@ impl Timeline {
     }
 
     async fn gc_timeline(
-        &self,
+        self: &Arc<Self>,
         horizon_cutoff: Lsn,
         pitr_cutoff: Lsn,
         retain_lsns: Vec<Lsn>,
         new_gc_cutoff: Lsn,
+        ctx: &RequestContext,
     ) -> anyhow::Result<GcResult> {
         let now = SystemTime::now();
         let mut result: GcResult = GcResult::default();
@@ -4307,6 +4856,24 @@ impl Timeline {
 
         debug!("retain_lsns: {:?}", retain_lsns);
 
+        let mut created_bytes = 0;
+        let gc_branch_images = self.get_gc_branch_images();
+        if gc_branch_images {
+            let image_layers = self
+                .create_branch_image_layers(&retain_lsns, new_gc_cutoff, ctx)
+                .await?;
+            if let Some(remote_client) = &self.remote_client {
+                for layer in &image_layers {
+                    remote_client.schedule_layer_file_upload(layer.clone())?;
+                }
+            }
+            result.layers_created_at_branch_points = image_layers.len() as u64;
+            created_bytes = image_layers
+                .iter()
+                .map(|l| l.layer_desc().file_size)
+                .sum::<u64>();
+        }
+
         let mut layers_to_remove = Vec::new();
         let mut wanted_image_layers = KeySpaceRandomAccum::default();
 
@@ -4347,15 +4914,17 @@ impl Timeline {
             }
 
             // 3. Is it needed by a child branch?
-            // NOTE With that we would keep data that
-            // might be referenced by child branches forever.
-            // We can track this in child timeline GC and delete parent layers when
-            // they are no longer needed. This might be complicated with long inheritance chains.
+            //
+            // A child branch reads the layer at its branch point. With
+            // `gc_branch_images`, that's unless newer image layers at or below the
+            // branch point cover the layer's key range, such as the ones created above.
             //
             // TODO Vec is not a great choice for `retain_lsns`
             for retain_lsn in &retain_lsns {
                 // start_lsn is inclusive
-                if &l.get_lsn_range().start <= retain_lsn {
+                if &l.get_lsn_range().start <= retain_lsn
+                    && !(gc_branch_images && Self::is_shadowed_at(layers, &l, *retain_lsn))
+                {
                     debug!(
                         "keeping {} because it's still might be referenced by child branch forked at {} is_dropped: xx is_incremental: {}",
                         l.filename(),
@@ -4416,6 +4985,7 @@ impl Timeline {
             .unwrap()
             .replace((new_gc_cutoff, wanted_image_layers.to_keyspace()));
 
+        let mut removed_bytes = 0;
         if !layers_to_remove.is_empty() {
             // Persist the new GC cutoff value in the metadata file, before
             // we actually remove anything.
@@ -4436,6 +5006,7 @@ impl Timeline {
                 .collect::<Vec<Layer>>();
 
             result.layers_removed = gc_layers.len() as u64;
+            removed_bytes = layers_to_remove.iter().map(|l| l.file_size()).sum::<u64>();
 
             if let Some(remote_client) = self.remote_client.as_ref() {
                 remote_client.schedule_gc_update(&gc_layers)?;
@@ -4449,15 +5020,142 @@ impl Timeline {
             }
         }
 
+        result.reclaimed_bytes.insert(
+            self.timeline_id,
+            removed_bytes.saturating_sub(created_bytes),
+        );
+
         info!(
-            "GC completed removing {} layers, cutoff {}",
-            result.layers_removed, new_gc_cutoff
+            "GC completed removing {} layers of {} bytes, cutoff {}",
+            result.layers_removed, removed_bytes, new_gc_cutoff
         );
 
         result.elapsed = now.elapsed()?;
         Ok(result)
     }
 
+    /// Does a read at `lsn` never reach layer `l`, because newer image layers cover
+    /// its whole key range at or below `lsn`?
+    fn is_shadowed_at(layers: &LayerMap, l: &PersistentLayerDesc, lsn: Lsn) -> bool {
+        let lsn_range = l.get_lsn_range();
+        lsn_range.end <= lsn
+            && layers.image_layer_exists(&l.get_key_range(), &(lsn_range.end..lsn + 1))
+    }
+
+    /// Create image layers at the branch points below `gc_cutoff`, covering the key
+    /// ranges of the layers that are only retained because a child timeline reads
+    /// them at that branch point. The layers can then be garbage collected, both
+    /// for the child and for this timeline.
+    ///
+    /// Images are only created at a branch point if they are smaller than the layers
+    /// they make obsolete, assuming that each key takes a page.
+    async fn create_branch_image_layers(
+        self: &Arc<Self>,
+        retain_lsns: &[Lsn],
+        gc_cutoff: Lsn,
+        ctx: &RequestContext,
+    ) -> anyhow::Result<Vec<ResidentLayer>> {
+        // Key ranges and total size of the layers pinned by each branch point
+        let mut pinned: BTreeMap<Lsn, (KeySpaceRandomAccum, u64)> = BTreeMap::new();
+        {
+            let guard = self.layers.read().await;
+            let layers = guard.layer_map();
+            for l in layers.iter_historic_layers() {
+                let lsn_range = l.get_lsn_range();
+                // The oldest branch point that reads the layer. An image layer there
+                // shadows the layer for later branch points too.
+                let Some(branch_lsn) = retain_lsns
+                    .iter()
+                    .copied()
+                    .filter(|lsn| {
+                        lsn_range.start <= *lsn && !Self::is_shadowed_at(layers, &l, *lsn)
+                    })
+                    .min()
+                else {
+                    continue;
+                };
+                // Layers newer than the cutoff are retained anyway, and a layer that
+                // contains the branch point cannot be replaced by an image at it.
+                if branch_lsn >= gc_cutoff || lsn_range.end > branch_lsn {
+                    continue;
+                }
+                let (key_ranges, size) = pinned.entry(branch_lsn).or_default();
+                key_ranges.add_range(l.get_key_range());
+                *size += l.file_size();
+            }
+        }
+
+        let target_file_size = self.get_compaction_target_size();
+        let mut image_layers = Vec::new();
+        for (branch_lsn, (key_ranges, pinned_size)) in pinned {
+            let keyspace = self.collect_keyspace(branch_lsn, ctx).await?;
+
+            // The keys that exist at the branch point, for each range to cover
+            let mut parts = Vec::new();
+            let mut image_size = 0;
+            for range in key_ranges.to_keyspace().ranges {
+                let keys = KeySpace {
+                    ranges: keyspace
+                        .ranges
+                        .iter()
+                        .filter(|r| r.start < range.end && range.start < r.end)
+                        .map(|r| max(r.start, range.start)..min(r.end, range.end))
+                        .collect(),
+                };
+                image_size += keys.total_size() as u64 * BLCKSZ as u64;
+                parts.push((range, keys));
+            }
+            if image_size >= pinned_size {
+                debug!(
+                    "not creating images at branch point {branch_lsn}: {image_size} bytes of images would only replace {pinned_size} bytes of layers"
+                );
+                continue;
+            }
+
+            info!(
+                "creating images at branch point {branch_lsn} to replace {pinned_size} bytes of layers"
+            );
+            for (range, keys) in parts {
+                // Like in create_image_layers, avoid holes between the image layers,
+                // and cover the whole range.
+                let mut start = range.start;
+                let partitioning = keys.partition(target_file_size);
+                let num_parts = partitioning.parts.len();
+                for (i, part) in partitioning.parts.iter().enumerate() {
+                    let end = if i + 1 == num_parts {
+                        range.end
+                    } else {
+                        part.ranges.last().unwrap().end
+                    };
+                    let img_range = start..end;
+                    let mut image_layer_writer = ImageLayerWriter::new(
+                        self.conf,
+                        self.timeline_id,
+                        self.tenant_shard_id,
+                        &img_range,
+                        branch_lsn,
+                        self.get_layer_compression(),
+                        self.get_layer_checksums(),
+                    )
+                    .await?;
+                    if self
+                        .put_partition_images(&mut image_layer_writer, part, branch_lsn, ctx)
+                        .await?
+                    {
+                        start = end;
+                        let image_layer = image_layer_writer.finish(self).await?;
+                        self.throttle_layer_write(&image_layer, ctx).await;
+                        image_layers.push(image_layer);
+                    }
+                }
+            }
+        }
+
+        self.finish_image_layers(&image_layers).await?;
+
+        Ok(image_layers)
+    }
+
     /// Reconstruct a value, using the given base image and WAL records in 'data'.
     async fn reconstruct_value(
         &self,
@@ -4730,6 +5428,7 @@ impl Timeline {
                     layer: layer.into(),
                     last_activity_ts,
                     relative_last_activity: finite_f32::FiniteF32::ZERO,
+                    eviction_cost: finite_f32::FiniteF32::ZERO,
                 }
             })
             .collect()
@@ -4870,11 +5569,18 @@ fn rename_to_backup(path: &Utf8Path) -> anyhow::Result<()> {
 
 #[cfg(test)]
 mod tests {
+    use bytes::Bytes;
     use utils::{id::TimelineId, lsn::Lsn};
 
+    use crate::keyspace::KeySpace;
+    use crate::repository::{Key, Value};
     use crate::tenant::{
-        harness::TenantHarness, storage_layer::Layer, timeline::EvictionError, Timeline,
+        harness::TenantHarness,
+        storage_layer::{Layer, ValueReconstructSituation, ValuesReconstructState},
+        timeline::EvictionError,
+        Timeline,
     };
+    use crate::walrecord::NeonWalRecord;
 
     #[tokio::test]
     async fn two_layer_eviction_attempts_at_the_same_time() {
@@ -4916,6 +5622,75 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn vectored_read_stops_at_cached_image() {
+        let harness = TenantHarness::create("vectored_read_stops_at_cached_image").unwrap();
+
+        let ctx = any_context();
+        let tenant = harness.do_try_load(&ctx).await.unwrap();
+        let timeline = tenant
+            .create_test_timeline(TimelineId::generate(), Lsn(0x10), 14, &ctx)
+            .await
+            .unwrap();
+
+        let key = Key::from_hex("010000000033333333444444445500000000").unwrap();
+        let img = Bytes::from_static(b"image");
+        let rec = NeonWalRecord::Postgres {
+            will_init: false,
+            rec: Bytes::from_static(b"record"),
+        };
+
+        let writer = timeline.writer().await;
+        writer
+            .put(key, Lsn(0x20), &Value::Image(img.clone()), &ctx)
+            .await
+            .unwrap();
+        for lsn in [0x30, 0x40, 0x50] {
+            writer
+                .put(key, Lsn(lsn), &Value::WalRecord(rec.clone()), &ctx)
+                .await
+                .unwrap();
+        }
+        writer.finish_write(Lsn(0x50));
+        drop(writer);
+
+        let keyspace = KeySpace {
+            ranges: vec![key..key.next()],
+        };
+
+        for flushed in [false, true] {
+            if flushed {
+                timeline.freeze_and_flush().await.unwrap();
+            }
+
+            let mut state = ValuesReconstructState::new();
+            timeline
+                .get_vectored_reconstruct_data(keyspace.clone(), Lsn(0x50), &mut state, &ctx)
+                .await
+                .unwrap();
+            let state = state.keys.remove(&key).unwrap().unwrap();
+            assert_eq!(state.situation(), ValueReconstructSituation::Complete);
+            assert_eq!(state.img.unwrap().0, Lsn(0x20));
+            assert_eq!(state.records.len(), 3);
+
+            // With a page image materialized at 0x30, only the newer records are needed
+            let mut state = ValuesReconstructState::new();
+            state.set_cached_image(key, Lsn(0x30), Bytes::from_static(b"cached"));
+            timeline
+                .get_vectored_reconstruct_data(keyspace.clone(), Lsn(0x50), &mut state, &ctx)
+                .await
+                .unwrap();
+            let state = state.keys.remove(&key).unwrap().unwrap();
+            assert_eq!(state.situation(), ValueReconstructSituation::Complete);
+            assert_eq!(
+                state.img.unwrap(),
+                (Lsn(0x30), Bytes::from_static(b"cached"))
+            );
+            let lsns: Vec<Lsn> = state.records.iter().map(|(lsn, _)| *lsn).collect();
+            assert_eq!(lsns, vec![Lsn(0x50), Lsn(0x40)]);
+        }
+    }
+
     fn any_context() -> crate::context::RequestContext {
         use crate::context::*;
         use crate::task_mgr::*;
@@ -10,6 +10,7 @@ use tracing::{debug, error, info, instrument, warn, Instrument};
 use utils::{crashsafe, fs_ext, id::TimelineId};
 
 use crate::{
+    basebackup_cache,
     config::PageServerConf,
     deletion_queue::DeletionQueueClient,
     task_mgr::{self, TaskKind},
@@ -315,12 +316,39 @@ async fn remove_timeline_from_tenant(
         panic!("Timeline grew children while we removed layer files");
     }
 
-    timelines
+    let timeline = timelines
         .remove(&timeline_id)
         .expect("timeline that we were deleting was concurrently removed from 'timelines' map");
 
+    // Stop retaining the branch point in the ancestor right away, rather than
+    // waiting for the next GC iteration to recompute it, unless a sibling was
+    // branched at the same LSN.
+    if let Some(ancestor_id) = timeline.get_ancestor_timeline_id() {
+        let ancestor_lsn = timeline.get_ancestor_lsn();
+        let still_needed = timelines.values().any(|t| {
+            t.get_ancestor_timeline_id() == Some(ancestor_id)
+                && t.get_ancestor_lsn() == ancestor_lsn
+        });
+        if !still_needed {
+            if let Some(ancestor) = timelines.get(&ancestor_id) {
+                ancestor
+                    .gc_info
+                    .write()
+                    .unwrap()
+                    .retain_lsns
+                    .retain(|lsn| *lsn != ancestor_lsn);
+            }
+        }
+    }
+
     drop(timelines);
 
+    if let Some(cache) = basebackup_cache::get() {
+        cache
+            .remove_timeline(tenant.tenant_shard_id, timeline_id)
+            .await;
+    }
+
     Ok(())
 }
 
@@ -420,6 +448,7 @@ impl DeleteTimelineFlow {
                     remote_client,
                     deletion_queue_client,
                     timeline_get_throttle: tenant.timeline_get_throttle.clone(),
+                    write_throttle: tenant.write_throttle.clone(),
                 },
                 // Important. We dont pass ancestor above because it can be missing.
                 // Thus we need to skip the validation here.
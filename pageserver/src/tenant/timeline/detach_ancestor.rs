@@ -0,0 +1,179 @@
+//! Detaching a branch from its ancestor.
+//!
+//! A branch reads everything older than its branch point from the ancestor timeline, which
+//! is why the ancestor cannot be deleted while it has children. Detaching materializes the
+//! whole keyspace of the branch at the branch point as image layers of the branch itself,
+//! after which the branch no longer needs anything from the ancestor.
+//!
+//! The detach itself is clearing `Timeline::ancestor`: from then on, reads stop at the
+//! branch, and the `TimelineMetadata` written by the following uploads, including the
+//! `IndexPart` uploaded together with the image layers, has no ancestor. If the upload
+//! fails, the ancestor is restored, and a retry reuses the image layers created by the
+//! failed attempt.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use pageserver_api::keyspace::KeyPartitioning;
+use tracing::{info, warn};
+use utils::lsn::Lsn;
+
+use super::{CreateImageLayersError, Timeline};
+use crate::context::RequestContext;
+use crate::pgdatadir_mapping::CollectKeySpaceError;
+use crate::repository::Key;
+use crate::tenant::remote_timeline_client::RemoteTimelineClient;
+use crate::tenant::storage_layer::ResidentLayer;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum Error {
+    #[error("timeline has no ancestor")]
+    NoAncestor,
+
+    #[error("detaching from ancestor requires remote storage")]
+    NoRemoteStorage,
+
+    #[error("shutting down, please retry later")]
+    ShuttingDown,
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl From<CollectKeySpaceError> for Error {
+    fn from(value: CollectKeySpaceError) -> Self {
+        match value {
+            CollectKeySpaceError::Cancelled => Error::ShuttingDown,
+            e => Error::Other(anyhow::Error::new(e).context("collect keyspace at branch point")),
+        }
+    }
+}
+
+impl From<CreateImageLayersError> for Error {
+    fn from(value: CreateImageLayersError) -> Self {
+        match value {
+            CreateImageLayersError::Cancelled => Error::ShuttingDown,
+            e => Error::Other(anyhow::Error::new(e).context("create images at branch point")),
+        }
+    }
+}
+
+impl Timeline {
+    /// Make this timeline independent of its ancestor, see the module documentation.
+    ///
+    /// Returns once the new image layers and the detached index part have been uploaded.
+    pub(crate) async fn detach_from_ancestor(
+        self: &Arc<Self>,
+        ctx: &RequestContext,
+    ) -> Result<(), Error> {
+        let Some(remote_client) = self.remote_client.as_ref() else {
+            return Err(Error::NoRemoteStorage);
+        };
+
+        // Keep compaction from creating image layers of its own while we are at it. This
+        // also serializes concurrent detach requests.
+        let _compaction_guard = self.compaction_lock.lock().await;
+
+        let Some(ancestor) = self.ancestor.load_full() else {
+            return Err(Error::NoAncestor);
+        };
+        let ancestor_lsn = ancestor.lsn;
+
+        if self.cancel.is_cancelled() {
+            return Err(Error::ShuttingDown);
+        }
+
+        info!(
+            ancestor_timeline_id = %ancestor.timeline.timeline_id,
+            %ancestor_lsn,
+            "detaching from ancestor"
+        );
+
+        // Reads at the branch point go through to the ancestor, so this sees the whole
+        // keyspace the branch inherited.
+        let keyspace = self.collect_keyspace(ancestor_lsn, ctx).await?;
+        let partitioning = keyspace.partition(self.get_compaction_target_size());
+
+        let image_layers = if self
+            .has_images_at_branch_point(&partitioning, ancestor_lsn)
+            .await
+        {
+            // An earlier attempt created them and scheduled their upload already
+            info!("reusing image layers at branch point");
+            Vec::new()
+        } else {
+            let image_layers = self
+                .create_image_layers(&partitioning, ancestor_lsn, true, ctx)
+                .await?;
+            info!(
+                count = image_layers.len(),
+                "created image layers at branch point"
+            );
+            image_layers
+        };
+
+        // The image layers are in the layer map now, so reads below the branch point
+        // no longer need the ancestor.
+        self.ancestor.store(None);
+
+        if let Err(e) = self.upload_detached(remote_client, image_layers).await {
+            // Until the detached index is known to be uploaded, the ancestor can't be
+            // deleted: keep using it, and make sure the next index upload says so.
+            self.ancestor.store(Some(ancestor));
+            if let Err(e) = self
+                .update_metadata_file(self.disk_consistent_lsn.load(), Vec::new())
+                .await
+            {
+                warn!("failed to schedule index upload with the ancestor restored: {e:#}");
+            }
+            return Err(e.into());
+        }
+
+        info!("detached from ancestor");
+
+        Ok(())
+    }
+
+    /// Do image layers at the branch point cover the whole `partitioning` already?
+    async fn has_images_at_branch_point(
+        &self,
+        partitioning: &KeyPartitioning,
+        ancestor_lsn: Lsn,
+    ) -> bool {
+        let Some(end) = partitioning
+            .parts
+            .last()
+            .and_then(|part| part.ranges.last())
+            .map(|range| range.end)
+        else {
+            return false;
+        };
+        let guard = self.layers.read().await;
+        guard
+            .layer_map()
+            .image_layer_exists(&(Key::MIN..end), &(ancestor_lsn..ancestor_lsn + 1))
+    }
+
+    /// Upload the image layers and the detached index part, and wait for them to
+    /// complete.
+    async fn upload_detached(
+        &self,
+        remote_client: &RemoteTimelineClient,
+        image_layers: Vec<ResidentLayer>,
+    ) -> anyhow::Result<()> {
+        self.update_metadata_file(self.disk_consistent_lsn.load(), image_layers)
+            .await
+            .context("schedule image layer and detached index upload")?;
+
+        fail::fail_point!("timeline-detach-ancestor-before-upload-completion", |_| {
+            Err(anyhow::anyhow!(
+                "failpoint: timeline-detach-ancestor-before-upload-completion"
+            ))
+        });
+
+        remote_client
+            .wait_completion()
+            .await
+            .context("wait for uploads")
+    }
+}
@@ -0,0 +1,328 @@
+//! Sampled read and write counters per key range of a timeline.
+//!
+//! The key space is cut into ranges of [`RANGE_BLOCKS`] consecutive keys,
+//! i.e. 8 MiB of a relation. One in [`SAMPLE_RATIO`] writes from WAL ingest and
+//! reads through `Timeline::get` and `Timeline::get_vectored`, other than image
+//! layer creation's, is counted, towards the range of its key. For the reads,
+//! the cost of reconstructing the page is counted as well: the number of layers
+//! visited and of WAL records applied for that key. The counters are halved
+//! every [`DECAY_PERIOD`], so that they reflect recent activity.
+//!
+//! Ranges that are read a lot and are expensive to reconstruct get image layers
+//! earlier, and ranges that are not read at all later, see [`ReadHeat`] and
+//! `Timeline::time_for_new_image_layer`.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use pageserver_api::key::Key;
+use pageserver_api::models::KeyRangeStats;
+use rand::Rng;
+
+/// Number of keys in a range: the low bits of the last key field are ignored.
+const RANGE_BLOCKS: u32 = 1024;
+
+/// One in this many reads and writes is counted.
+const SAMPLE_RATIO: u32 = 16;
+
+const DECAY_PERIOD: Duration = Duration::from_secs(600);
+
+/// A key range is read-hot if it has at least this many estimated reads...
+const READ_HOT_MIN_READS: u64 = 1000;
+/// ...and its reads apply at least this many WAL records on average...
+const READ_HOT_RECORDS_PER_READ: u64 = 16;
+/// ...or visit at least this many layers on average.
+const READ_HOT_LAYERS_PER_READ: u64 = 4;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Counters {
+    pub(crate) reads: u64,
+    pub(crate) writes: u64,
+    /// Layers visited by the reads, in total
+    pub(crate) layers_visited: u64,
+    /// WAL records applied by the reads, in total
+    pub(crate) records_applied: u64,
+}
+
+/// How much reads of a key range would benefit from a new image layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReadHeat {
+    /// Not read since the statistics were started, at least one
+    /// [`DECAY_PERIOD`] ago.
+    Cold,
+    Normal,
+    /// Read a lot, and the reads are expensive to reconstruct.
+    Hot,
+}
+
+pub(crate) struct KeyRangeStatsCollector {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// Sampled counts, by the first key of the range
+    ranges: BTreeMap<Key, Counters>,
+    last_decay: Instant,
+    /// When the statistics were started
+    started: Instant,
+}
+
+impl Default for KeyRangeStatsCollector {
+    fn default() -> Self {
+        let now = Instant::now();
+        KeyRangeStatsCollector {
+            inner: Mutex::new(Inner {
+                ranges: BTreeMap::new(),
+                last_decay: now,
+                started: now,
+            }),
+        }
+    }
+}
+
+fn range_start(key: Key) -> Key {
+    Key {
+        field6: key.field6 & !(RANGE_BLOCKS - 1),
+        ..key
+    }
+}
+
+impl KeyRangeStatsCollector {
+    /// Record a read of `key`, which visited `layers_visited` layers and
+    /// applied `records_applied` WAL records to reconstruct the page.
+    pub(crate) fn record_read(&self, key: Key, layers_visited: usize, records_applied: usize) {
+        self.record_reads(std::iter::once((key, layers_visited, records_applied)));
+    }
+
+    /// Record a batch of reads, like [`Self::record_read`], taking the lock once
+    /// for all the sampled ones.
+    pub(crate) fn record_reads(&self, reads: impl Iterator<Item = (Key, usize, usize)>) {
+        let mut rng = rand::thread_rng();
+        let sampled: Vec<_> = reads.filter(|_| rng.gen_ratio(1, SAMPLE_RATIO)).collect();
+        if sampled.is_empty() {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.decay(Instant::now());
+        for (key, layers_visited, records_applied) in sampled {
+            let counters = inner.ranges.entry(range_start(key)).or_default();
+            counters.reads += 1;
+            counters.layers_visited += layers_visited as u64;
+            counters.records_applied += records_applied as u64;
+        }
+    }
+
+    /// Record a batch of writes, taking the lock once for all the sampled ones.
+    pub(crate) fn record_writes(&self, keys: impl Iterator<Item = Key>) {
+        let mut rng = rand::thread_rng();
+        let sampled: Vec<Key> = keys.filter(|_| rng.gen_ratio(1, SAMPLE_RATIO)).collect();
+        if sampled.is_empty() {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.decay(Instant::now());
+        for key in sampled {
+            inner.ranges.entry(range_start(key)).or_default().writes += 1;
+        }
+    }
+
+    /// Whether image layers for `key_range` pay off earlier or later than for
+    /// other ranges, going by the recent reads of it.
+    pub(crate) fn read_heat(&self, key_range: &Range<Key>) -> ReadHeat {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        inner.decay(now);
+        inner.read_heat(key_range, now)
+    }
+
+    /// Estimated reads and writes of each range that had any, in key order.
+    pub(crate) fn snapshot(&self) -> Vec<KeyRangeStats> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.decay(Instant::now());
+        inner
+            .ranges
+            .iter()
+            .map(|(start, counters)| KeyRangeStats {
+                key_start: start.to_string(),
+                key_end: start.add(RANGE_BLOCKS).to_string(),
+                reads: counters.reads * SAMPLE_RATIO as u64,
+                writes: counters.writes * SAMPLE_RATIO as u64,
+                layers_visited: counters.layers_visited * SAMPLE_RATIO as u64,
+                records_applied: counters.records_applied * SAMPLE_RATIO as u64,
+            })
+            .collect()
+    }
+}
+
+impl Inner {
+    /// Estimated counts in `key_range`.
+    fn get(&self, key_range: &Range<Key>) -> Counters {
+        let mut total = Counters::default();
+        if key_range.start >= key_range.end {
+            return total;
+        }
+        for counters in self
+            .ranges
+            .range(range_start(key_range.start)..key_range.end)
+            .map(|(_, counters)| counters)
+        {
+            total.reads += counters.reads * SAMPLE_RATIO as u64;
+            total.writes += counters.writes * SAMPLE_RATIO as u64;
+            total.layers_visited += counters.layers_visited * SAMPLE_RATIO as u64;
+            total.records_applied += counters.records_applied * SAMPLE_RATIO as u64;
+        }
+        total
+    }
+
+    fn read_heat(&self, key_range: &Range<Key>, now: Instant) -> ReadHeat {
+        let counters = self.get(key_range);
+        if counters.reads == 0 {
+            if now.duration_since(self.started) >= DECAY_PERIOD {
+                ReadHeat::Cold
+            } else {
+                ReadHeat::Normal
+            }
+        } else if counters.reads >= READ_HOT_MIN_READS
+            && (counters.records_applied >= counters.reads * READ_HOT_RECORDS_PER_READ
+                || counters.layers_visited >= counters.reads * READ_HOT_LAYERS_PER_READ)
+        {
+            ReadHeat::Hot
+        } else {
+            ReadHeat::Normal
+        }
+    }
+
+    fn decay(&mut self, now: Instant) {
+        while now.duration_since(self.last_decay) >= DECAY_PERIOD {
+            self.ranges.retain(|_, counters| {
+                counters.reads /= 2;
+                counters.writes /= 2;
+                counters.layers_visited /= 2;
+                counters.records_applied /= 2;
+                counters.reads > 0 || counters.writes > 0
+            });
+            self.last_decay += DECAY_PERIOD;
+            if self.ranges.is_empty() {
+                self.last_decay = now;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pageserver_api::key::rel_block_to_key;
+    use pageserver_api::reltag::RelTag;
+
+    #[test]
+    fn ranges_and_decay() {
+        let rel = RelTag {
+            spcnode: 1663,
+            dbnode: 5,
+            relnode: 16384,
+            forknum: 0,
+        };
+        let stats = KeyRangeStatsCollector::default();
+        {
+            let mut inner = stats.inner.lock().unwrap();
+            for blknum in [0, 1, 1023, 1024, 5000] {
+                inner
+                    .ranges
+                    .entry(range_start(rel_block_to_key(rel, blknum)))
+                    .or_default()
+                    .reads += 4;
+            }
+        }
+
+        assert_eq!(stats.snapshot().len(), 3);
+
+        let range = |start, end| rel_block_to_key(rel, start)..rel_block_to_key(rel, end);
+        let mut inner = stats.inner.lock().unwrap();
+        // Whole ranges are counted, even if the key range only overlaps them
+        assert_eq!(inner.get(&range(0, 1)).reads, 12 * SAMPLE_RATIO as u64);
+        assert_eq!(
+            inner.get(&range(1000, 1100)).reads,
+            16 * SAMPLE_RATIO as u64
+        );
+        assert_eq!(inner.get(&range(2048, 4096)).reads, 0);
+
+        let start = inner.last_decay;
+        inner.decay(start + DECAY_PERIOD);
+        assert_eq!(
+            inner.ranges.values().map(|c| c.reads).sum::<u64>(),
+            6 + 2 + 2
+        );
+        inner.decay(start + DECAY_PERIOD * 5);
+        assert!(inner.ranges.is_empty());
+    }
+
+    #[test]
+    fn read_heat() {
+        let rel = RelTag {
+            spcnode: 1663,
+            dbnode: 5,
+            relnode: 16384,
+            forknum: 0,
+        };
+        let range = |start, end| rel_block_to_key(rel, start)..rel_block_to_key(rel, end);
+        let stats = KeyRangeStatsCollector::default();
+        let mut inner = stats.inner.lock().unwrap();
+        let mut set = |blknum, counters| {
+            inner
+                .ranges
+                .insert(range_start(rel_block_to_key(rel, blknum)), counters);
+        };
+        set(
+            0,
+            Counters {
+                reads: 100,
+                writes: 0,
+                layers_visited: 200,
+                records_applied: 100 * READ_HOT_RECORDS_PER_READ,
+            },
+        );
+        set(
+            1024,
+            Counters {
+                reads: 100,
+                writes: 0,
+                layers_visited: 100 * READ_HOT_LAYERS_PER_READ,
+                records_applied: 0,
+            },
+        );
+        set(
+            2048,
+            Counters {
+                reads: 100,
+                writes: 0,
+                layers_visited: 100,
+                records_applied: 100,
+            },
+        );
+        set(
+            3072,
+            Counters {
+                reads: 1,
+                writes: 0,
+                layers_visited: 100,
+                records_applied: 1000,
+            },
+        );
+
+        let now = inner.started;
+        assert_eq!(inner.read_heat(&range(0, 1024), now), ReadHeat::Hot);
+        assert_eq!(inner.read_heat(&range(1024, 2048), now), ReadHeat::Hot);
+        // Cheap reads, or too few of them
+        assert_eq!(inner.read_heat(&range(2048, 3072), now), ReadHeat::Normal);
+        assert_eq!(inner.read_heat(&range(3072, 4096), now), ReadHeat::Normal);
+        // Ranges without reads are only cold once they have been observed long enough
+        assert_eq!(inner.read_heat(&range(4096, 8192), now), ReadHeat::Normal);
+        let later = now + DECAY_PERIOD;
+        assert_eq!(inner.read_heat(&range(4096, 8192), later), ReadHeat::Cold);
+    }
+}
@@ -0,0 +1,142 @@
+//! Logical size of each database and relation of a timeline.
+//!
+//! Calculating them requires reading the size of every relation, like the
+//! non-incremental logical size, so the sizes at the end of the timeline are
+//! cached. When they are requested after [`REFRESH_INTERVAL`], and the timeline
+//! has advanced since, the cached sizes are returned and recalculated in the
+//! background.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use pageserver_api::models::{DatabaseSize, RelationSize, TimelineRelationSizes};
+use tracing::{info_span, warn, Instrument};
+use utils::lsn::Lsn;
+
+use super::Timeline;
+use crate::context::{DownloadBehavior, RequestContext};
+use crate::pgdatadir_mapping::CalculateLogicalSizeError;
+use crate::task_mgr::{self, TaskKind, BACKGROUND_RUNTIME};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+pub(super) struct RelationSizesCache {
+    inner: Mutex<RelationSizesCacheInner>,
+}
+
+#[derive(Default)]
+struct RelationSizesCacheInner {
+    /// Sizes at the end of the timeline, and when they were calculated.
+    latest: Option<(Arc<TimelineRelationSizes>, Instant)>,
+    refresh_in_progress: bool,
+}
+
+impl RelationSizesCache {
+    fn set_latest(&self, sizes: Arc<TimelineRelationSizes>) {
+        let mut inner = self.inner.lock().unwrap();
+        match &inner.latest {
+            Some((latest, _)) if latest.lsn > sizes.lsn => {}
+            _ => inner.latest = Some((sizes, Instant::now())),
+        }
+    }
+}
+
+impl Timeline {
+    /// Get the logical size of each database and relation at `lsn`, or at the
+    /// end of the timeline if `lsn` is `None`.
+    pub(crate) async fn get_relation_sizes(
+        self: &Arc<Self>,
+        lsn: Option<Lsn>,
+        ctx: &RequestContext,
+    ) -> Result<Arc<TimelineRelationSizes>, CalculateLogicalSizeError> {
+        let latest = self.relation_sizes.inner.lock().unwrap().latest.clone();
+        match (lsn, latest) {
+            (Some(lsn), Some((sizes, _))) if sizes.lsn == lsn => Ok(sizes),
+            (Some(lsn), _) => Ok(Arc::new(self.calculate_relation_sizes(lsn, ctx).await?)),
+            (None, Some((sizes, calculated_at))) => {
+                if sizes.lsn < self.get_last_record_lsn()
+                    && calculated_at.elapsed() >= REFRESH_INTERVAL
+                {
+                    self.spawn_relation_sizes_refresh(ctx);
+                }
+                Ok(sizes)
+            }
+            (None, None) => {
+                let lsn = self.get_last_record_lsn();
+                let sizes = Arc::new(self.calculate_relation_sizes(lsn, ctx).await?);
+                self.relation_sizes.set_latest(Arc::clone(&sizes));
+                Ok(sizes)
+            }
+        }
+    }
+
+    fn spawn_relation_sizes_refresh(self: &Arc<Self>, ctx: &RequestContext) {
+        {
+            let mut inner = self.relation_sizes.inner.lock().unwrap();
+            if inner.refresh_in_progress {
+                return;
+            }
+            inner.refresh_in_progress = true;
+        }
+
+        let self_clone = Arc::clone(self);
+        let ctx = ctx.detached_child(
+            TaskKind::RelationSizesCalculation,
+            DownloadBehavior::Download,
+        );
+        task_mgr::spawn(
+            BACKGROUND_RUNTIME.handle(),
+            TaskKind::RelationSizesCalculation,
+            Some(self.tenant_shard_id),
+            Some(self.timeline_id),
+            "relation sizes calculation",
+            false,
+            async move {
+                if let Ok(_guard) = self_clone.gate.enter() {
+                    let lsn = self_clone.get_last_record_lsn();
+                    match self_clone.calculate_relation_sizes(lsn, &ctx).await {
+                        Ok(sizes) => self_clone.relation_sizes.set_latest(Arc::new(sizes)),
+                        Err(CalculateLogicalSizeError::Cancelled) => {}
+                        Err(e) => warn!("failed to calculate relation sizes: {e:#}"),
+                    }
+                }
+                self_clone.relation_sizes.inner.lock().unwrap().refresh_in_progress = false;
+                Ok(())
+            }
+            .instrument(info_span!(parent: None, "relation_sizes_calculation", tenant_id=%self.tenant_shard_id.tenant_id, shard_id=%self.tenant_shard_id.shard_slug(), timeline_id=%self.timeline_id)),
+        );
+    }
+
+    async fn calculate_relation_sizes(
+        &self,
+        lsn: Lsn,
+        ctx: &RequestContext,
+    ) -> Result<TimelineRelationSizes, CalculateLogicalSizeError> {
+        let calculated_at = chrono::Utc::now();
+        let databases = self
+            .list_relation_sizes(lsn, ctx)
+            .await?
+            .into_iter()
+            .map(|((spcnode, dbnode), rel_sizes)| {
+                let mut relations = rel_sizes
+                    .into_iter()
+                    .map(|(relnode, size)| RelationSize { relnode, size })
+                    .collect::<Vec<_>>();
+                relations
+                    .sort_unstable_by(|a, b| b.size.cmp(&a.size).then(a.relnode.cmp(&b.relnode)));
+                DatabaseSize {
+                    spcnode,
+                    dbnode,
+                    size: relations.iter().map(|rel| rel.size).sum(),
+                    relations,
+                }
+            })
+            .collect();
+        Ok(TimelineRelationSizes {
+            lsn,
+            calculated_at,
+            databases,
+        })
+    }
+}
@@ -0,0 +1,608 @@
+//! Tiered compaction of delta layers, an alternative to [`Timeline::compact_level0`].
+//!
+//! Delta layers whose LSN ranges overlap form a *sorted run*: a set of layers that
+//! partition the key space for one LSN range. An L0 layer is a run of its own, and
+//! so is the set of L1 layers produced by one compaction. Instead of always merging
+//! all L0 layers into L1 layers, tiered compaction merges a number of LSN-adjacent
+//! runs of similar size into one bigger run. A byte of WAL is thus rewritten about
+//! once per size tier, i.e. a logarithmic number of times, rather than every time
+//! its key range is compacted again.
+//!
+//! Runs are picked by LSN only, and a merge always covers the whole key space of
+//! its input runs. To bound the work done at a time, the merge is then cut by key
+//! into *windows* at the key boundaries of its input layers, which are merged one
+//! after another. An L0 layer covers all keys, so a merge of L0 layers only is a
+//! single window; the runs written by earlier merges are cut by key, so merges of
+//! bigger runs get a window per few output layers.
+//!
+//! The planning part, [`TieredCompactionPlanner`], only looks at the LSN ranges,
+//! key ranges and sizes of layers, and is generic over the layer handle, so that
+//! it can be exercised by a simulation without a real timeline (see the tests
+//! below). The execution part lives in [`Timeline::compact_tiered`].
+
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use camino::Utf8PathBuf;
+use tracing::{debug, info, warn};
+use utils::lsn::Lsn;
+
+use super::{drop_wlock, CompactionError, Timeline};
+use crate::context::RequestContext;
+use crate::page_cache;
+use crate::repository::Key;
+use crate::tenant::layer_map::LayerMap;
+use crate::tenant::par_fsync;
+use crate::tenant::storage_layer::delta_layer::DeltaEntry;
+use crate::tenant::storage_layer::{AsLayerDesc, DeltaLayerWriter, Layer};
+
+/// A delta layer as seen by the planner.
+#[derive(Debug, Clone)]
+pub(crate) struct DeltaLayerSummary<L> {
+    pub(crate) layer: L,
+    pub(crate) key_range: Range<Key>,
+    pub(crate) lsn_range: Range<Lsn>,
+    pub(crate) file_size: u64,
+}
+
+/// Delta layers with overlapping LSN ranges. The runs of a timeline have
+/// disjoint LSN ranges.
+#[derive(Debug)]
+struct SortedRun<L> {
+    lsn_range: Range<Lsn>,
+    size: u64,
+    layers: Vec<DeltaLayerSummary<L>>,
+}
+
+/// The runs selected by the planner, to be merged into a new run covering `lsn_range`.
+#[derive(Debug)]
+pub(crate) struct CompactionJob<L> {
+    pub(crate) lsn_range: Range<Lsn>,
+    pub(crate) input: Vec<DeltaLayerSummary<L>>,
+    /// Consecutive key ranges covering the key space of `input`, merged one at a time.
+    pub(crate) key_windows: Vec<Range<Key>>,
+}
+
+impl<L> CompactionJob<L> {
+    pub(crate) fn input_size(&self) -> u64 {
+        self.input.iter().map(|l| l.file_size).sum()
+    }
+}
+
+pub(crate) struct TieredCompactionPlanner {
+    /// Merge at least this many runs at a time. This is the fan-out of the tiers.
+    pub(crate) min_merge_width: usize,
+    /// A run joins the newer runs of a merge if it is at most this many percent of
+    /// the size of the biggest of them, i.e. if it belongs to the same tier.
+    pub(crate) size_ratio_percent: u64,
+    /// Bytes of input layers after which a key window is closed, see [`key_windows`].
+    pub(crate) window_size: u64,
+}
+
+impl TieredCompactionPlanner {
+    pub(crate) const DEFAULT_SIZE_RATIO_PERCENT: u64 = 150;
+
+    /// A key window merges about `min_merge_width` layers of `target_file_size`.
+    pub(crate) fn new(min_merge_width: usize, target_file_size: u64) -> Self {
+        // Merging a single run would just rewrite it.
+        let min_merge_width = min_merge_width.max(2);
+        TieredCompactionPlanner {
+            min_merge_width,
+            size_ratio_percent: Self::DEFAULT_SIZE_RATIO_PERCENT,
+            window_size: target_file_size.saturating_mul(min_merge_width as u64),
+        }
+    }
+
+    /// Pick the runs to merge next, if any.
+    ///
+    /// Starting from the newest run, extend the merge to older runs as long as
+    /// each older run is not much bigger than the runs already selected, and is
+    /// adjacent to them in LSN. If that yields enough runs, merge them; otherwise
+    /// retry starting from the next older run. A big old run is therefore only
+    /// rewritten once enough runs of its size have accumulated on top of it.
+    pub(crate) fn plan<L>(&self, deltas: Vec<DeltaLayerSummary<L>>) -> Option<CompactionJob<L>> {
+        let mut runs = sorted_runs(deltas);
+        if runs.len() < self.min_merge_width {
+            return None;
+        }
+
+        // `runs` is sorted oldest first.
+        let mut selected = None;
+        for newest in (0..runs.len()).rev() {
+            let mut oldest = newest;
+            let mut max_size = runs[newest].size;
+            while oldest > 0 {
+                let candidate = &runs[oldest - 1];
+                if candidate.lsn_range.end != runs[oldest].lsn_range.start {
+                    // Runs are normally contiguous. Don't let the output claim to
+                    // cover an LSN range that none of the inputs covered.
+                    break;
+                }
+                if candidate.size.saturating_mul(100)
+                    > max_size.saturating_mul(self.size_ratio_percent)
+                {
+                    break;
+                }
+                max_size = u64::max(max_size, candidate.size);
+                oldest -= 1;
+            }
+
+            if newest - oldest + 1 >= self.min_merge_width {
+                selected = Some(oldest..newest + 1);
+                break;
+            }
+        }
+
+        let selected = selected?;
+        let lsn_range = runs[selected.start].lsn_range.start..runs[selected.end - 1].lsn_range.end;
+        let input = runs
+            .drain(selected)
+            .flat_map(|run| run.layers)
+            .collect::<Vec<_>>();
+        let key_windows = key_windows(&input, self.window_size);
+        Some(CompactionJob {
+            lsn_range,
+            input,
+            key_windows,
+        })
+    }
+}
+
+/// Cut the key space of `input` into consecutive windows, at the start keys of its
+/// layers, such that the layers starting in a window add up to at most `window_size`
+/// bytes, unless a window would otherwise start and end at the same key.
+///
+/// A layer is accounted to the window it starts in, even if it extends into the
+/// following ones.
+fn key_windows<L>(input: &[DeltaLayerSummary<L>], window_size: u64) -> Vec<Range<Key>> {
+    let mut starts = input
+        .iter()
+        .map(|l| (l.key_range.start, l.file_size))
+        .collect::<Vec<_>>();
+    starts.sort_by_key(|(key, _)| *key);
+    let Some(end) = input.iter().map(|l| l.key_range.end).max() else {
+        return Vec::new();
+    };
+
+    let mut windows = Vec::new();
+    let mut window_start = starts[0].0;
+    let mut size = 0;
+    for (key, file_size) in starts {
+        if size > 0 && size + file_size > window_size && key > window_start {
+            windows.push(window_start..key);
+            window_start = key;
+            size = 0;
+        }
+        size += file_size;
+    }
+    windows.push(window_start..end);
+    windows
+}
+
+/// Group the delta layers into sorted runs, oldest first.
+fn sorted_runs<L>(mut deltas: Vec<DeltaLayerSummary<L>>) -> Vec<SortedRun<L>> {
+    deltas.sort_by_key(|l| (l.lsn_range.start, l.lsn_range.end));
+
+    let mut runs: Vec<SortedRun<L>> = Vec::new();
+    for delta in deltas {
+        match runs.last_mut() {
+            Some(run) if delta.lsn_range.start < run.lsn_range.end => {
+                run.lsn_range.end = Lsn::max(run.lsn_range.end, delta.lsn_range.end);
+                run.size += delta.file_size;
+                run.layers.push(delta);
+            }
+            _ => runs.push(SortedRun {
+                lsn_range: delta.lsn_range.clone(),
+                size: delta.file_size,
+                layers: vec![delta],
+            }),
+        }
+    }
+    runs
+}
+
+impl Timeline {
+    /// Merge delta layers as planned by [`TieredCompactionPlanner`].
+    ///
+    /// The values of the input layers are written out in key, LSN order into new
+    /// delta layers covering the LSN range of the whole job. Output layers are cut
+    /// at key boundaries once they reach `target_file_size`, so a key with a lot of
+    /// history can make a layer bigger than that.
+    pub(super) async fn compact_tiered(
+        self: &Arc<Self>,
+        target_file_size: u64,
+        ctx: &RequestContext,
+    ) -> Result<(), CompactionError> {
+        let job = {
+            let guard = self.layers.read().await;
+            let deltas = guard
+                .layer_map()
+                .iter_historic_layers()
+                .filter(|desc| desc.is_delta())
+                .map(|desc| DeltaLayerSummary {
+                    layer: guard.get_from_desc(&desc),
+                    key_range: desc.get_key_range(),
+                    lsn_range: desc.get_lsn_range(),
+                    file_size: desc.file_size(),
+                })
+                .collect::<Vec<_>>();
+            TieredCompactionPlanner::new(self.get_compaction_threshold(), target_file_size)
+                .plan(deltas)
+        };
+        let Some(job) = job else {
+            debug!("no runs of delta layers to merge");
+            return Ok(());
+        };
+
+        info!(
+            "Starting tiered compaction in LSN range {}-{} for {} layers of {} bytes in {} key windows",
+            job.lsn_range.start,
+            job.lsn_range.end,
+            job.input.len(),
+            job.input_size(),
+            job.key_windows.len(),
+        );
+
+        let mut resident_inputs = Vec::with_capacity(job.input.len());
+        for summary in &job.input {
+            resident_inputs.push(summary.layer.download_and_keep_resident().await?);
+        }
+
+        // The output layers are cut by size only, so one writer carries on from
+        // one key window to the next.
+        let mut new_layers = Vec::new();
+        let mut writer: Option<DeltaLayerWriter> = None;
+        let mut prev_key: Option<Key> = None;
+        for window in job.key_windows.iter() {
+            let mut all_keys = Vec::new();
+            for l in resident_inputs.iter() {
+                let key_range = l.layer_desc().get_key_range();
+                if key_range.start >= window.end || key_range.end <= window.start {
+                    continue;
+                }
+                all_keys.extend(
+                    l.load_keys(ctx)
+                        .await?
+                        .into_iter()
+                        .filter(|entry| window.contains(&entry.key)),
+                );
+            }
+            all_keys.sort_by_key(|DeltaEntry { key, lsn, .. }| (*key, *lsn));
+
+            for DeltaEntry { key, lsn, val, .. } in all_keys.iter() {
+                let (key, lsn) = (*key, *lsn);
+                if self.shard_identity.is_key_disposable(&key) {
+                    debug!(
+                        "Dropping key {} during compaction (it belongs on shard {:?})",
+                        key,
+                        self.shard_identity.get_shard_number(&key)
+                    );
+                    continue;
+                }
+
+                let new_key = prev_key.map_or(true, |prev_key| prev_key != key);
+                if new_key
+                    && writer
+                        .as_ref()
+                        .is_some_and(|w| w.size() >= target_file_size)
+                {
+                    let new_layer = writer.take().unwrap().finish(key, self).await?;
+                    self.throttle_layer_write(&new_layer, ctx).await;
+                    new_layers.push(new_layer);
+                }
+
+                let value = val.load(ctx).await?;
+                if writer.is_none() {
+                    writer = Some(
+                        DeltaLayerWriter::new(
+                            self.conf,
+                            self.timeline_id,
+                            self.tenant_shard_id,
+                            key,
+                            job.lsn_range.clone(),
+                            self.get_layer_compression(),
+                            self.get_layer_checksums(),
+                        )
+                        .await?,
+                    );
+                }
+                writer.as_mut().unwrap().put_value(key, lsn, value).await?;
+                prev_key = Some(key);
+            }
+        }
+        if let Some(writer) = writer {
+            let new_layer = writer.finish(prev_key.unwrap().next(), self).await?;
+            self.throttle_layer_write(&new_layer, ctx).await;
+            new_layers.push(new_layer);
+        }
+
+        let warn_limit = target_file_size * 2 + page_cache::PAGE_SZ as u64 * 2;
+        for layer in new_layers.iter() {
+            if layer.layer_desc().file_size > warn_limit {
+                warn!(
+                    %layer,
+                    "created delta file of size {} larger than double of target of {target_file_size}", layer.layer_desc().file_size
+                );
+            }
+        }
+
+        if !new_layers.is_empty() {
+            let layer_paths: Vec<Utf8PathBuf> = new_layers
+                .iter()
+                .map(|l| l.local_path().to_owned())
+                .collect();
+            par_fsync::par_fsync_async(&layer_paths)
+                .await
+                .context("fsync all new layers")?;
+
+            let timeline_dir = self
+                .conf
+                .timeline_path(&self.tenant_shard_id, &self.timeline_id);
+            par_fsync::par_fsync_async(&[timeline_dir])
+                .await
+                .context("fsync of timeline dir")?;
+        }
+
+        info!(
+            "Finished tiered compaction: {} new layers of {} bytes",
+            new_layers.len(),
+            new_layers
+                .iter()
+                .map(|l| l.layer_desc().file_size)
+                .sum::<u64>()
+        );
+
+        let mut guard = self.layers.write().await;
+
+        let mut duplicated_layers = HashSet::new();
+        let mut insert_layers = Vec::with_capacity(new_layers.len());
+        for l in &new_layers {
+            if guard.contains(l.as_ref()) {
+                // A previous attempt crashed after writing its outputs, see the
+                // comments in `compact_level0_phase1`.
+                tracing::error!(layer=%l, "duplicated layer");
+                duplicated_layers.insert(l.layer_desc().key());
+            } else if LayerMap::is_l0(l.layer_desc()) {
+                return Err(CompactionError::Other(anyhow!("compaction generates a L0 layer file as output, which will cause infinite compaction.")));
+            } else {
+                insert_layers.push(l.clone());
+            }
+        }
+
+        let remove_layers = resident_inputs
+            .into_iter()
+            .map(|l| l.drop_eviction_guard())
+            .filter(|l| !duplicated_layers.contains(&l.layer_desc().key()))
+            .collect::<Vec<Layer>>();
+
+        // deletion will happen later, the layer file manager calls garbage_collect_on_drop
+        guard.finish_compact_l0(&remove_layers, &insert_layers, &self.metrics);
+
+        if let Some(remote_client) = self.remote_client.as_ref() {
+            remote_client.schedule_compaction_update(&remove_layers, &new_layers)?;
+        }
+
+        drop_wlock(guard);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    /// An L0 layer: it covers all keys.
+    fn delta(id: usize, lsn_range: Range<u64>, file_size: u64) -> DeltaLayerSummary<usize> {
+        keyed_delta(id, Key::MIN..Key::MAX, lsn_range, file_size)
+    }
+
+    fn keyed_delta(
+        id: usize,
+        key_range: Range<Key>,
+        lsn_range: Range<u64>,
+        file_size: u64,
+    ) -> DeltaLayerSummary<usize> {
+        DeltaLayerSummary {
+            layer: id,
+            key_range,
+            lsn_range: Lsn(lsn_range.start)..Lsn(lsn_range.end),
+            file_size,
+        }
+    }
+
+    fn key(i: i128) -> Key {
+        Key::from_i128(i)
+    }
+
+    /// The key windows are consecutive and cover the key space of the job's input.
+    fn assert_windows_cover_input<L>(job: &CompactionJob<L>) {
+        let start = job.input.iter().map(|l| l.key_range.start).min().unwrap();
+        let end = job.input.iter().map(|l| l.key_range.end).max().unwrap();
+        assert_eq!(job.key_windows.first().unwrap().start, start);
+        assert_eq!(job.key_windows.last().unwrap().end, end);
+        for pair in job.key_windows.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+            assert!(pair[0].start < pair[0].end);
+        }
+    }
+
+    #[test]
+    fn too_few_runs() {
+        let planner = TieredCompactionPlanner::new(4, 128 * MB);
+        let deltas = (0..3)
+            .map(|i| delta(i, i as u64 * 10..(i as u64 + 1) * 10, MB))
+            .collect::<Vec<_>>();
+        assert!(planner.plan(deltas).is_none());
+    }
+
+    #[test]
+    fn overlapping_layers_form_one_run() {
+        let planner = TieredCompactionPlanner::new(2, 128 * MB);
+        // Two layers of one run (e.g. a key with a lot of history split on LSN),
+        // followed by a gap in LSN, and two more runs.
+        let deltas = vec![
+            delta(0, 10..20, MB),
+            delta(1, 10..15, MB),
+            delta(2, 25..30, MB),
+            delta(3, 30..40, MB),
+        ];
+        let job = planner.plan(deltas).unwrap();
+        assert_eq!(job.lsn_range, Lsn(25)..Lsn(40));
+        let mut ids = job.input.iter().map(|l| l.layer).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec![2, 3]);
+
+        let deltas = vec![
+            delta(0, 10..20, MB),
+            delta(1, 15..20, MB),
+            delta(2, 20..30, 2 * MB),
+        ];
+        let job = planner.plan(deltas).unwrap();
+        assert_eq!(job.lsn_range, Lsn(10)..Lsn(30));
+        assert_eq!(job.input.len(), 3);
+    }
+
+    #[test]
+    fn big_old_run_is_left_alone() {
+        let planner = TieredCompactionPlanner::new(3, 128 * MB);
+        let mut deltas = vec![delta(0, 0..100, 100 * MB)];
+        deltas.extend((1..4).map(|i| delta(i, 99 + i as u64..100 + i as u64, MB)));
+        let job = planner.plan(deltas).unwrap();
+        assert_eq!(job.lsn_range, Lsn(100)..Lsn(103));
+        assert!(job.input.iter().all(|l| l.layer != 0));
+    }
+
+    #[test]
+    fn l0_merge_is_one_key_window() {
+        let planner = TieredCompactionPlanner::new(2, MB);
+        let deltas = (0..4)
+            .map(|i| delta(i, i as u64 * 10..(i as u64 + 1) * 10, 64 * MB))
+            .collect::<Vec<_>>();
+        let job = planner.plan(deltas).unwrap();
+        assert_eq!(job.key_windows, vec![Key::MIN..Key::MAX]);
+    }
+
+    #[test]
+    fn key_windows_follow_layer_boundaries() {
+        // window_size is 2 * 64 MB
+        let planner = TieredCompactionPlanner::new(2, 64 * MB);
+        // Two runs of four key partitioned layers each, the newer one cut at different keys
+        let mut deltas = (0..4)
+            .map(|i| {
+                keyed_delta(
+                    i,
+                    key(i as i128 * 100)..key((i as i128 + 1) * 100),
+                    0..10,
+                    32 * MB,
+                )
+            })
+            .collect::<Vec<_>>();
+        deltas.extend((0..4).map(|i| {
+            keyed_delta(
+                4 + i,
+                key(i as i128 * 100 + 50)..key((i as i128 + 1) * 100 + 50),
+                10..20,
+                32 * MB,
+            )
+        }));
+        let job = planner.plan(deltas).unwrap();
+        assert_eq!(job.input.len(), 8);
+        assert_windows_cover_input(&job);
+        assert_eq!(job.key_windows, vec![key(0)..key(200), key(200)..key(450)]);
+
+        // Layers starting at the same key are never split into separate windows
+        let deltas = (0..4)
+            .map(|i| {
+                keyed_delta(
+                    i,
+                    key(0)..key(100),
+                    i as u64 * 10..(i as u64 + 1) * 10,
+                    64 * MB,
+                )
+            })
+            .collect::<Vec<_>>();
+        let job = planner.plan(deltas).unwrap();
+        assert_eq!(job.key_windows, vec![key(0)..key(100)]);
+    }
+
+    /// Ingest a lot of equally sized L0 layers, compacting after each one like the
+    /// compaction loop would, and check the number of runs and the write amplification.
+    #[test]
+    fn simulate_ingest() {
+        const MIN_MERGE_WIDTH: usize = 4;
+        const TARGET_FILE_SIZE: u64 = 128 * MB;
+        const L0_SIZE: u64 = 256 * MB;
+        const NUM_L0: u64 = 1000;
+
+        let planner = TieredCompactionPlanner::new(MIN_MERGE_WIDTH, TARGET_FILE_SIZE);
+        let mut layers: Vec<DeltaLayerSummary<usize>> = Vec::new();
+        let mut next_id = 0;
+        let mut bytes_written = 0;
+        let mut max_windows = 0;
+
+        for i in 0..NUM_L0 {
+            layers.push(delta(next_id, i * 100..(i + 1) * 100, L0_SIZE));
+            next_id += 1;
+            bytes_written += L0_SIZE;
+
+            while let Some(job) = planner.plan(layers.clone()) {
+                assert_windows_cover_input(&job);
+                max_windows = usize::max(max_windows, job.key_windows.len());
+
+                let input_ids = job.input.iter().map(|l| l.layer).collect::<HashSet<_>>();
+                layers.retain(|l| !input_ids.contains(&l.layer));
+
+                // Pretend that nothing gets garbage collected: the output is as big
+                // as the input, cut into layers of the target size, and the keys are
+                // spread evenly over the first KEYS keys.
+                const KEYS: i128 = 1 << 20;
+                let output_layers = job.input_size().div_ceil(TARGET_FILE_SIZE);
+                let mut remaining = job.input_size();
+                for i in 0..output_layers as i128 {
+                    let file_size = u64::min(remaining, TARGET_FILE_SIZE);
+                    let key_range = if i == output_layers as i128 - 1 {
+                        key(i * KEYS / output_layers as i128)..Key::MAX
+                    } else {
+                        key(i * KEYS / output_layers as i128)
+                            ..key((i + 1) * KEYS / output_layers as i128)
+                    };
+                    layers.push(DeltaLayerSummary {
+                        layer: next_id,
+                        key_range,
+                        lsn_range: job.lsn_range.clone(),
+                        file_size,
+                    });
+                    next_id += 1;
+                    remaining -= file_size;
+                    bytes_written += file_size;
+                }
+            }
+        }
+
+        let runs = sorted_runs(layers);
+        let tiers = (NUM_L0 as f64).log(MIN_MERGE_WIDTH as f64).ceil() as usize;
+        assert!(
+            runs.len() < MIN_MERGE_WIDTH * (tiers + 1),
+            "{} runs left for {tiers} tiers",
+            runs.len(),
+        );
+        // Merges never leave gaps between the runs
+        for pair in runs.windows(2) {
+            assert_eq!(pair[0].lsn_range.end, pair[1].lsn_range.start);
+        }
+
+        // Merges of the bigger runs are cut into key windows
+        assert!(max_windows > 1);
+
+        let write_amplification = bytes_written as f64 / (NUM_L0 * L0_SIZE) as f64;
+        assert!(
+            write_amplification <= (tiers + 1) as f64,
+            "write amplification {write_amplification} for {tiers} tiers"
+        );
+    }
+}
@@ -2,7 +2,7 @@ use super::storage_layer::LayerFileName;
 use super::storage_layer::ResidentLayer;
 use crate::tenant::metadata::TimelineMetadata;
 use crate::tenant::remote_timeline_client::index::IndexPart;
-use crate::tenant::remote_timeline_client::index::LayerFileMetadata;
+use crate::tenant::remote_timeline_client::index::{LayerFileHash, LayerFileMetadata};
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 
@@ -112,6 +112,34 @@ impl UploadQueueInitialized {
     pub(super) fn get_last_remote_consistent_lsn_projected(&self) -> Option<Lsn> {
         self.projected_remote_consistent_lsn
     }
+
+    /// Record the hash of a layer file whose upload with `uploaded` metadata just completed,
+    /// so that it is included in the index parts uploaded from now on.
+    ///
+    /// Index uploads referencing the layer wait for its upload, so they are all still queued.
+    pub(super) fn record_uploaded_sha256(
+        &mut self,
+        name: &LayerFileName,
+        uploaded: &LayerFileMetadata,
+        sha256: LayerFileHash,
+    ) {
+        if let Some(metadata) = self.latest_files.get_mut(name) {
+            if metadata.generation == uploaded.generation && metadata.shard == uploaded.shard {
+                metadata.sha256 = Some(sha256);
+            }
+        }
+
+        for op in self.queued_operations.iter_mut() {
+            let UploadOp::UploadMetadata(index_part, _) = op else {
+                continue;
+            };
+            if let Some(metadata) = index_part.layer_metadata.get_mut(name) {
+                if metadata.generation == uploaded.generation && metadata.shard == uploaded.shard {
+                    metadata.sha256 = Some(sha256);
+                }
+            }
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -0,0 +1,575 @@
+//!
+//! Utilities for vectored reading of variable-sized "blobs".
+//!
+//! The "blob" api is an abstraction on top of the "block" api,
+//! with the main difference being that blobs do not have a fixed
+//! size (each blob is prefixed with 1 or 4 byte length field)
+//!
+//! The vectored apis provided in this module allow for planning
+//! and executing disk IO which covers multiple blobs.
+//!
+//! Reads are planned with [`VectoredReadPlanner`] which will coalesce
+//! adjacent blobs into a single disk IO request and executed by
+//! [`VectoredBlobReader`] which does all the required offset juggling
+//! and returns a buffer housing all the blobs and a list of offsets.
+//!
+//! Note that the vectored blob api does *not* go through the page cache, except
+//! for files opened for direct I/O, see [`VectoredBlobReader::read_blobs`].
+//!
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+
+use pageserver_api::key::Key;
+use utils::lsn::Lsn;
+use utils::vec_map::VecMap;
+
+use crate::context::RequestContext;
+use crate::page_cache::PAGE_SZ;
+use crate::tenant::blob_io::{self, BlobCompression, BlobHeader};
+use crate::tenant::block_io::FileBlockReader;
+
+/// Upper bound for the size of a single disk read issued by the vectored read
+/// path. A single blob larger than this is still read with one IO.
+pub(crate) const MAX_VECTORED_READ_BYTES: usize = 128 * 1024;
+
+/// Metadata bundled with the start and end offset of a blob.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlobMeta {
+    pub key: Key,
+    pub lsn: Lsn,
+}
+
+/// Blob offsets into [`VectoredBlobsBuf::buf`]
+#[derive(Debug)]
+pub struct VectoredBlob {
+    pub start: usize,
+    pub end: usize,
+    pub meta: BlobMeta,
+    compression: Option<BlobCompression>,
+}
+
+/// Return type of [`VectoredBlobReader::read_blobs`]
+pub struct VectoredBlobsBuf {
+    /// Buffer for all blobs in this read
+    pub buf: Vec<u8>,
+    /// Offsets into the buffer and metadata for all blobs in this read
+    pub blobs: Vec<VectoredBlob>,
+}
+
+impl VectoredBlobsBuf {
+    /// Returns the payload of `blob`, decompressing it if needed.
+    pub async fn payload(&self, blob: &VectoredBlob) -> Result<Vec<u8>, Error> {
+        let stored = &self.buf[blob.start..blob.end];
+        match blob.compression {
+            None => Ok(stored.to_vec()),
+            Some(BlobCompression::Zstd) => blob_io::zstd_decompress(stored, Vec::new()).await,
+        }
+    }
+}
+
+/// A single disk read covering one or more blobs.
+#[derive(Debug)]
+pub struct VectoredRead {
+    pub start: u64,
+    pub end: u64,
+    /// Starting offsets and metadata for each blob in this read
+    pub blobs_at: VecMap<u64, BlobMeta>,
+}
+
+impl VectoredRead {
+    pub fn size(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+}
+
+#[derive(Eq, PartialEq, Debug)]
+enum VectoredReadExtended {
+    Yes,
+    No,
+}
+
+struct VectoredReadBuilder {
+    start: u64,
+    end: u64,
+    blobs_at: VecMap<u64, BlobMeta>,
+    max_read_size: usize,
+}
+
+impl VectoredReadBuilder {
+    fn new(start_offset: u64, end_offset: u64, meta: BlobMeta, max_read_size: usize) -> Self {
+        let mut blobs_at = VecMap::default();
+        blobs_at
+            .append(start_offset, meta)
+            .expect("First insertion always succeeds");
+
+        Self {
+            start: start_offset,
+            end: end_offset,
+            blobs_at,
+            max_read_size,
+        }
+    }
+
+    /// Attempt to extend the current read with a new blob if the start
+    /// offset matches with the current end of the vectored read
+    /// and the resuting size is below the max read size
+    fn extend(&mut self, start: u64, end: u64, meta: BlobMeta) -> VectoredReadExtended {
+        let size = (end - self.start) as usize;
+        if self.end == start && size <= self.max_read_size {
+            self.end = end;
+            self.blobs_at
+                .append(start, meta)
+                .expect("Offsets are ordered within vectored reads");
+
+            return VectoredReadExtended::Yes;
+        }
+
+        VectoredReadExtended::No
+    }
+
+    fn build(self) -> VectoredRead {
+        VectoredRead {
+            start: self.start,
+            end: self.end,
+            blobs_at: self.blobs_at,
+        }
+    }
+}
+
+/// What to do with a blob handed to [`VectoredReadPlanner::handle`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlobFlag {
+    /// Read the blob
+    None,
+    /// Don't read the blob, it is only used to find where the previous blob ends
+    Ignore,
+    /// Read the blob, and drop all previously planned blobs of the same key:
+    /// it is a page image or a record that initializes the page
+    ReplaceAll,
+}
+
+/// Planner for vectored blob reads.
+///
+/// Blob offsets are received via [`VectoredReadPlanner::handle`]
+/// and coalesced into disk reads.
+///
+/// The implementation is very simple:
+/// * Collect all blob offsets in an ordered structure
+/// * Iterate over the collected blobs and coalesce them into reads at the end
+pub struct VectoredReadPlanner {
+    // Track all the blob offsets. Start offsets must be ordered.
+    blobs: BTreeMap<Key, Vec<(Lsn, u64, u64)>>,
+    // Arguments for previous blob passed into [`VectoredReadPlanner::handle`]
+    prev: Option<(Key, Lsn, u64, BlobFlag)>,
+
+    max_read_size: usize,
+}
+
+impl VectoredReadPlanner {
+    pub fn new(max_read_size: usize) -> Self {
+        Self {
+            blobs: BTreeMap::new(),
+            prev: None,
+            max_read_size,
+        }
+    }
+
+    /// Include a new blob in the read plan.
+    ///
+    /// This function is called from a B-Tree index visitor (see `DeltaLayerInner::plan_reads`
+    /// and `ImageLayerInner::plan_reads`). Said visitor wants to collect blob offsets for all
+    /// keys in a given keyspace. This function must be called for each key in the desired
+    /// keyspace (monotonically continuous). [`Self::handle_range_end`] must
+    /// be called after every range in the offset.
+    ///
+    /// In the event that keys are skipped, the behaviour is undefined and can lead to an
+    /// incorrect read plan. We can end up asserting, erroring in wal redo or returning
+    /// incorrect data to the user.
+    ///
+    /// The `flag` argument has two interesting values:
+    /// * [`BlobFlag::ReplaceAll`]: The blob for this key should replace all existing blobs.
+    /// This is used for WAL records that `will_init`.
+    /// * [`BlobFlag::Ignore`]: This blob should not be included in the read. This happens
+    /// if the blob is outside of the LSN range being read.
+    pub fn handle(&mut self, key: Key, lsn: Lsn, offset: u64, flag: BlobFlag) {
+        // Implementation note: internally lag behind by one blob such that
+        // we have a start and end offset when initialising [`VectoredRead`]
+        let (prev_key, prev_lsn, prev_offset, prev_flag) = match self.prev {
+            None => {
+                self.prev = Some((key, lsn, offset, flag));
+                return;
+            }
+            Some(prev) => prev,
+        };
+
+        self.add_blob(prev_key, prev_lsn, prev_offset, offset, prev_flag);
+
+        self.prev = Some((key, lsn, offset, flag));
+    }
+
+    /// Mark the end of a key range: the last blob handed to [`Self::handle`]
+    /// ends at `offset`.
+    pub fn handle_range_end(&mut self, offset: u64) {
+        if let Some((prev_key, prev_lsn, prev_offset, prev_flag)) = self.prev {
+            self.add_blob(prev_key, prev_lsn, prev_offset, offset, prev_flag);
+        }
+
+        self.prev = None;
+    }
+
+    fn add_blob(&mut self, key: Key, lsn: Lsn, start_offset: u64, end_offset: u64, flag: BlobFlag) {
+        match flag {
+            BlobFlag::None => {
+                let blobs_for_key = self.blobs.entry(key).or_default();
+                blobs_for_key.push((lsn, start_offset, end_offset));
+            }
+            BlobFlag::ReplaceAll => {
+                let blobs_for_key = self.blobs.entry(key).or_default();
+                blobs_for_key.clear();
+                blobs_for_key.push((lsn, start_offset, end_offset));
+            }
+            BlobFlag::Ignore => {}
+        }
+    }
+
+    pub fn finish(self) -> Vec<VectoredRead> {
+        let mut blobs = self
+            .blobs
+            .into_iter()
+            .flat_map(|(key, blobs_for_key)| {
+                blobs_for_key
+                    .into_iter()
+                    .map(move |(lsn, start, end)| (start, end, BlobMeta { key, lsn }))
+            })
+            .collect::<Vec<_>>();
+        blobs.sort_by_key(|(start, _, _)| *start);
+
+        let mut current_read: Option<VectoredReadBuilder> = None;
+        let mut reads = Vec::new();
+
+        for (start_offset, end_offset, meta) in blobs {
+            let extended = match &mut current_read {
+                Some(read) => read.extend(start_offset, end_offset, meta),
+                None => VectoredReadExtended::No,
+            };
+
+            if extended == VectoredReadExtended::No {
+                let next_read =
+                    VectoredReadBuilder::new(start_offset, end_offset, meta, self.max_read_size);
+
+                let prev_read = current_read.replace(next_read);
+
+                // `current_read` is None in the first iteration of the loop
+                if let Some(read) = prev_read {
+                    reads.push(read.build());
+                }
+            }
+        }
+
+        if let Some(read) = current_read {
+            reads.push(read.build());
+        }
+
+        reads
+    }
+}
+
+/// Disk reader for vectored blob spans
+pub struct VectoredBlobReader<'a> {
+    file: &'a FileBlockReader,
+    read_compressed: bool,
+    read_checksums: bool,
+}
+
+impl<'a> VectoredBlobReader<'a> {
+    /// `read_compressed` tells whether the blob headers of the file may carry
+    /// compression bits, see [`BlobCompression`].
+    pub fn new(file: &'a FileBlockReader, read_compressed: bool) -> Self {
+        Self {
+            file,
+            read_compressed,
+            read_checksums: false,
+        }
+    }
+
+    /// Verify the checksum that follows every blob, see [`blob_io::BlobChecksum`].
+    pub fn with_checksums(mut self, read_checksums: bool) -> Self {
+        self.read_checksums = read_checksums;
+        self
+    }
+
+    /// Read the requested blobs into the buffer.
+    ///
+    /// We have to deal with the fact that blobs are not fixed size.
+    /// Each blob is prefixed by a size header.
+    ///
+    /// The success return value is a struct which contains the buffer
+    /// filled from disk and a list of offsets at which each blob lies
+    /// in the buffer.
+    ///
+    /// The read goes straight to the file, and so relies on the kernel page cache,
+    /// unless the file was opened for direct I/O: then it goes through our page
+    /// cache, block by block, instead of reading from disk every time.
+    pub async fn read_blobs(
+        &self,
+        read: &VectoredRead,
+        ctx: &RequestContext,
+    ) -> Result<VectoredBlobsBuf, Error> {
+        assert!(read.size() > 0);
+
+        let buf = if self.file.file.is_direct() {
+            self.read_page_cached(read, ctx).await?
+        } else {
+            // `Vec::with_capacity` allocates exactly the requested capacity,
+            // which is what `read_exact_at` fills.
+            let buf = Vec::with_capacity(read.size());
+            self.file.file.read_exact_at(buf, read.start, ctx).await?
+        };
+
+        let mut blobs = Vec::with_capacity(read.blobs_at.as_slice().len());
+        for (offset, meta) in read.blobs_at.as_slice() {
+            let header_start = (offset - read.start) as usize;
+            let header = BlobHeader::parse(&buf[header_start..], self.read_compressed)?;
+
+            let start = header_start + header.header_len;
+            let end = start + header.payload_len;
+            if end > buf.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "blob at offset {offset} with length {} exceeds the read {}..{}",
+                        header.payload_len, read.start, read.end
+                    ),
+                ));
+            }
+
+            if self.read_checksums {
+                let stored = buf
+                    .get(end..end + blob_io::CHECKSUM_LEN)
+                    .and_then(|b| b.try_into().ok())
+                    .ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "checksum of blob at offset {offset} exceeds the read {}..{}",
+                                read.start, read.end
+                            ),
+                        )
+                    })?;
+                blob_io::verify_checksum(
+                    *offset,
+                    &buf[header_start..start],
+                    &buf[start..end],
+                    stored,
+                )?;
+            }
+
+            blobs.push(VectoredBlob {
+                start,
+                end,
+                meta: *meta,
+                compression: header.compression,
+            });
+        }
+
+        Ok(VectoredBlobsBuf { buf, blobs })
+    }
+
+    /// Copy the range of `read` out of the blocks that contain it.
+    async fn read_page_cached(
+        &self,
+        read: &VectoredRead,
+        ctx: &RequestContext,
+    ) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::with_capacity(read.size());
+        let first_blk = read.start / PAGE_SZ as u64;
+        let last_blk = (read.end - 1) / PAGE_SZ as u64;
+        for blknum in first_blk..=last_blk {
+            let block = self.file.read_blk(blknum as u32, ctx).await?;
+            let block_start = blknum * PAGE_SZ as u64;
+            let from = read.start.saturating_sub(block_start) as usize;
+            let to = u64::min(read.end - block_start, PAGE_SZ as u64) as usize;
+            buf.extend_from_slice(&block[from..to]);
+        }
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate_read(read: &VectoredRead, offset_range: &[(Key, Lsn, u64, BlobFlag)]) {
+        assert_eq!(read.start, offset_range.first().unwrap().2);
+
+        let expected_offsets_in_read: Vec<_> = offset_range.iter().map(|o| o.2).collect();
+
+        let offsets_in_read: Vec<_> = read
+            .blobs_at
+            .as_slice()
+            .iter()
+            .map(|(offset, _)| *offset)
+            .collect();
+
+        assert_eq!(expected_offsets_in_read, offsets_in_read);
+    }
+
+    #[test]
+    fn planner_max_read_size_test() {
+        let max_read_size = 128 * 1024;
+        let key = Key::MIN;
+        let lsn = Lsn(0);
+
+        let blob_descriptions = vec![
+            (key, lsn, 0, BlobFlag::None),
+            (key, lsn, 32 * 1024, BlobFlag::None),
+            (key, lsn, 96 * 1024, BlobFlag::None), // Last in read
+            (key, lsn, 128 * 1024, BlobFlag::None),
+            (key, lsn, 198 * 1024, BlobFlag::None),
+            (key, lsn, 268 * 1024, BlobFlag::None),
+            (key, lsn, 396 * 1024, BlobFlag::None),
+            (key, lsn, 652 * 1024, BlobFlag::None),
+        ];
+
+        let ranges = [
+            &blob_descriptions[0..3],
+            &blob_descriptions[3..4],
+            &blob_descriptions[4..5],
+            &blob_descriptions[5..6],
+            &blob_descriptions[6..7],
+            &blob_descriptions[7..],
+        ];
+
+        let mut planner = VectoredReadPlanner::new(max_read_size);
+        for (key, lsn, offset, flag) in blob_descriptions.clone() {
+            planner.handle(key, lsn, offset, flag);
+        }
+
+        planner.handle_range_end(700 * 1024);
+
+        let reads = planner.finish();
+        assert_eq!(reads.len(), 6);
+
+        for (idx, read) in reads.iter().enumerate() {
+            validate_read(read, ranges[idx]);
+        }
+    }
+
+    #[test]
+    fn planner_replacement_test() {
+        let max_read_size = 128 * 1024;
+        let first_key = Key::MIN;
+        let second_key = first_key.next();
+        let lsn = Lsn(0);
+
+        let blob_descriptions = vec![
+            (first_key, lsn, 0, BlobFlag::None),    // First in read 1
+            (first_key, lsn, 1024, BlobFlag::None), // Last in read 1
+            (second_key, lsn, 2 * 1024, BlobFlag::ReplaceAll),
+            (second_key, lsn, 3 * 1024, BlobFlag::None),
+            (second_key, lsn, 4 * 1024, BlobFlag::ReplaceAll), // First in read 2
+            (second_key, lsn, 5 * 1024, BlobFlag::None),       // Last in read 2
+        ];
+
+        let ranges = [&blob_descriptions[0..2], &blob_descriptions[4..]];
+
+        let mut planner = VectoredReadPlanner::new(max_read_size);
+        for (key, lsn, offset, flag) in blob_descriptions.clone() {
+            planner.handle(key, lsn, offset, flag);
+        }
+
+        planner.handle_range_end(6 * 1024);
+
+        let reads = planner.finish();
+        assert_eq!(reads.len(), 2);
+
+        for (idx, read) in reads.iter().enumerate() {
+            validate_read(read, ranges[idx]);
+        }
+    }
+
+    #[test]
+    fn planner_ignored_blobs_split_reads() {
+        let max_read_size = 128 * 1024;
+        let key = Key::MIN;
+        let lsn = Lsn(0);
+
+        let blob_descriptions = vec![
+            (key, lsn, 0, BlobFlag::None),
+            (key, lsn, 1024, BlobFlag::Ignore),
+            (key, lsn, 2 * 1024, BlobFlag::None),
+            (key, lsn, 3 * 1024, BlobFlag::None),
+        ];
+
+        let ranges = [&blob_descriptions[0..1], &blob_descriptions[2..]];
+
+        let mut planner = VectoredReadPlanner::new(max_read_size);
+        for (key, lsn, offset, flag) in blob_descriptions.clone() {
+            planner.handle(key, lsn, offset, flag);
+        }
+
+        planner.handle_range_end(4 * 1024);
+
+        let reads = planner.finish();
+        assert_eq!(reads.len(), 2);
+
+        for (idx, read) in reads.iter().enumerate() {
+            validate_read(read, ranges[idx]);
+        }
+        assert_eq!(reads[0].end, 1024);
+        assert_eq!(reads[1].end, 4 * 1024);
+    }
+
+    /// Vectored reads of a direct I/O file are served from the page cache: once the
+    /// blocks are cached, changes to the file on disk are not seen.
+    #[tokio::test]
+    async fn direct_io_reads_are_cached() -> Result<(), Error> {
+        use crate::context::DownloadBehavior;
+        use crate::task_mgr::TaskKind;
+        use crate::virtual_file::VirtualFile;
+
+        let ctx = RequestContext::new(TaskKind::UnitTest, DownloadBehavior::Error);
+        let testdir = crate::config::PageServerConf::test_repo_dir("direct_io_reads_are_cached");
+        std::fs::create_dir_all(&testdir)?;
+        let path = testdir.join("blobs");
+
+        // Two blobs with 1-byte headers, the first one across a block boundary
+        let first = (PAGE_SZ - 10) as u64;
+        let second = first + 1 + 50;
+        let mut content = vec![0u8; 2 * PAGE_SZ];
+        content[first as usize] = 50;
+        content[first as usize + 1..second as usize].fill(1);
+        content[second as usize] = 20;
+        content[second as usize + 1..second as usize + 21].fill(2);
+        std::fs::write(&path, &content)?;
+
+        let file = FileBlockReader::new(VirtualFile::open(&path).await?.assume_direct());
+        let reader = VectoredBlobReader::new(&file, false);
+        let meta = BlobMeta {
+            key: Key::MIN,
+            lsn: Lsn(0),
+        };
+        let mut blobs_at = VecMap::default();
+        blobs_at.append(first, meta).unwrap();
+        blobs_at.append(second, meta).unwrap();
+        let read = VectoredRead {
+            start: first,
+            end: second + 21,
+            blobs_at,
+        };
+
+        let check = |bufs: VectoredBlobsBuf| async move {
+            assert_eq!(bufs.blobs.len(), 2);
+            assert_eq!(bufs.payload(&bufs.blobs[0]).await.unwrap(), vec![1; 50]);
+            assert_eq!(bufs.payload(&bufs.blobs[1]).await.unwrap(), vec![2; 20]);
+        };
+        check(reader.read_blobs(&read, &ctx).await?).await;
+
+        // Pin the blocks, so that other tests using the page cache can't evict them
+        let _pinned = [file.read_blk(0, &ctx).await?, file.read_blk(1, &ctx).await?];
+        std::fs::write(&path, vec![0u8; 2 * PAGE_SZ])?;
+        check(reader.read_blobs(&read, &ctx).await?).await;
+
+        Ok(())
+    }
+}
@@ -10,9 +10,10 @@
 //! This is similar to PostgreSQL's virtual file descriptor facility in
 //! src/backend/storage/file/fd.c
 //!
+use crate::context::RequestContext;
 use crate::metrics::{StorageIoOperation, STORAGE_IO_SIZE, STORAGE_IO_TIME_METRIC};
 
-use crate::page_cache::PageWriteGuard;
+use crate::page_cache::{self, PageReadGuard, PageWriteGuard, ReadBufResult, PAGE_SZ};
 use crate::tenant::TENANTS_SEGMENT_NAME;
 use camino::{Utf8Path, Utf8PathBuf};
 use once_cell::sync::OnceCell;
@@ -29,9 +30,16 @@ use tokio::time::Instant;
 use utils::fs_ext;
 
 pub use pageserver_api::models::virtual_file as api;
+pub(crate) mod aligned_buffer;
 pub(crate) mod io_engine;
+pub(crate) mod io_mode;
+pub(crate) mod io_scheduler;
 mod open_options;
+use aligned_buffer::{AlignedBuffer, DIO_ALIGN};
 pub(crate) use io_engine::IoEngineKind;
+pub(crate) use io_mode::IoMode;
+use io_scheduler::IoClass;
+pub use io_scheduler::IoSchedulerConfig;
 pub(crate) use open_options::*;
 
 ///
@@ -68,6 +76,10 @@ pub struct VirtualFile {
     pub path: Utf8PathBuf,
     open_options: OpenOptions,
 
+    /// Opened with `O_DIRECT`, see [`io_mode`]. Reads that are not aligned to
+    /// [`DIO_ALIGN`] go through a bounce buffer.
+    direct: bool,
+
     // These are strings becase we only use them for metrics, and those expect strings.
     // It makes no sense for us to constantly turn the `TimelineId` and `TenantId` into
     // strings.
@@ -328,6 +340,45 @@ impl VirtualFile {
         Self::open_with_options(path, OpenOptions::new().read(true)).await
     }
 
+    /// Open a layer file for reading. In [`IoMode::Direct`], the file is opened
+    /// with `O_DIRECT`, see [`io_mode`].
+    pub async fn open_layer(path: &Utf8Path) -> Result<VirtualFile, std::io::Error> {
+        match io_mode::get() {
+            IoMode::Buffered => Self::open(path).await,
+            #[cfg(target_os = "linux")]
+            IoMode::Direct => Self::open_direct(path).await,
+        }
+    }
+
+    /// Open a file in read-only mode, with `O_DIRECT`.
+    #[cfg(target_os = "linux")]
+    async fn open_direct(path: &Utf8Path) -> Result<VirtualFile, std::io::Error> {
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut vfile = Self::open_with_options(
+            path,
+            OpenOptions::new()
+                .read(true)
+                .custom_flags(nix::fcntl::OFlag::O_DIRECT.bits()),
+        )
+        .await?;
+        vfile.direct = true;
+        Ok(vfile)
+    }
+
+    /// Whether the file was opened with `O_DIRECT`, i.e. reads of it bypass the
+    /// kernel page cache.
+    pub(crate) fn is_direct(&self) -> bool {
+        self.direct
+    }
+
+    /// Treat a file opened without `O_DIRECT` as if it had been, to exercise the
+    /// direct I/O read paths on file systems that don't support it.
+    #[cfg(test)]
+    pub(crate) fn assume_direct(mut self) -> Self {
+        self.direct = true;
+        self
+    }
+
     /// Create a new file for writing. If the file exists, it will be truncated.
     /// Like File::create.
     pub async fn create(path: &Utf8Path) -> Result<VirtualFile, std::io::Error> {
@@ -391,6 +442,7 @@ impl VirtualFile {
             pos: 0,
             path: path.to_path_buf(),
             open_options: reopen_options,
+            direct: false,
             tenant_id,
             shard_id,
             timeline_id,
@@ -557,28 +609,130 @@ impl VirtualFile {
         Ok(self.pos)
     }
 
-    pub async fn read_exact_at<B>(&self, buf: B, offset: u64) -> Result<B, Error>
+    pub async fn read_exact_at<B>(
+        &self,
+        buf: B,
+        offset: u64,
+        ctx: &RequestContext,
+    ) -> Result<B, Error>
+    where
+        B: IoBufMut + Send,
+    {
+        io_scheduler::get()
+            .schedule(IoClass::of(ctx), buf.bytes_total())
+            .await;
+        self.read_exact_at_scheduled(buf, offset).await
+    }
+
+    /// [`Self::read_exact_at`], once the I/O budget was taken for the whole buffer,
+    /// however many reads it takes to fill it.
+    async fn read_exact_at_scheduled<B>(&self, buf: B, offset: u64) -> Result<B, Error>
     where
         B: IoBufMut + Send,
     {
+        if self.direct && !aligned_buffer::is_aligned(buf.stable_ptr(), offset, buf.bytes_total()) {
+            return self.read_exact_at_bounced(buf, offset).await;
+        }
         let (buf, res) =
             read_exact_at_impl(buf, offset, |buf, offset| self.read_at(buf, offset)).await;
         res.map(|()| buf)
     }
 
-    /// Like [`Self::read_exact_at`] but for [`PageWriteGuard`].
-    pub async fn read_exact_at_page(
+    /// [`Self::read_exact_at`] of a direct I/O file, for a buffer or offset that
+    /// is not aligned: reads the aligned range around it into an [`AlignedBuffer`],
+    /// and copies the requested part over.
+    async fn read_exact_at_bounced<B>(&self, mut buf: B, offset: u64) -> Result<B, Error>
+    where
+        B: IoBufMut + Send,
+    {
+        let len = buf.bytes_total();
+        let end = offset + len as u64;
+        let aligned_start = offset - offset % DIO_ALIGN as u64;
+        let aligned_end = end.next_multiple_of(DIO_ALIGN as u64);
+
+        let mut bounce = AlignedBuffer::zeroed((aligned_end - aligned_start) as usize).slice_full();
+        let mut read_up_to = aligned_start;
+        while bounce.bytes_total() != 0 {
+            let res;
+            (bounce, res) = self.read_at(bounce, read_up_to).await;
+            match res {
+                Ok(n) => {
+                    read_up_to += n as u64;
+                    // The aligned range may extend past the end of the file: a short
+                    // read means that we reached it.
+                    if n == 0 || n % DIO_ALIGN != 0 {
+                        break;
+                    }
+                    bounce = bounce.slice(n..);
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if read_up_to < end {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ));
+        }
+
+        let bounce = bounce.into_inner();
+        let skip = (offset - aligned_start) as usize;
+        let src = &bounce[skip..skip + len];
+        // SAFETY: `buf` has room for `len` bytes, which are all initialized by the copy
+        unsafe {
+            std::ptr::copy_nonoverlapping(src.as_ptr(), buf.stable_mut_ptr(), len);
+            buf.set_init(len);
+        }
+        Ok(buf)
+    }
+
+    /// Read block `blknum` through the page cache, in which the file is `file_id`.
+    ///
+    /// On a miss, the read doesn't wait for the I/O budget while holding the page
+    /// cache slot that it fills: slots are pinned until they are filled, and with
+    /// enough reads waiting for the budget, the page cache would run out of them.
+    pub(crate) async fn read_page_cached(
         &self,
-        page: PageWriteGuard<'static>,
-        offset: u64,
-    ) -> Result<PageWriteGuard<'static>, Error> {
-        let buf = PageWriteGuardBuf {
-            page,
-            init_up_to: 0,
-        };
-        let res = self.read_exact_at(buf, offset).await;
-        res.map(|PageWriteGuardBuf { page, .. }| page)
-            .map_err(|e| Error::new(ErrorKind::Other, e))
+        file_id: page_cache::FileId,
+        blknum: u32,
+        ctx: &RequestContext,
+    ) -> Result<PageReadGuard<'static>, Error> {
+        let cache = page_cache::get();
+        let class = IoClass::of(ctx);
+        let mut scheduled = false;
+        loop {
+            let write_guard = match cache
+                .read_immutable_buf(file_id, blknum, ctx)
+                .await
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::Other,
+                        // order path before error because error is anyhow::Error => might have many contexts
+                        format!("read immutable page #{blknum} of {}: {e:#}", self.path),
+                    )
+                })? {
+                ReadBufResult::Found(guard) => return Ok(guard),
+                ReadBufResult::NotFound(write_guard) => write_guard,
+            };
+            if !scheduled && !io_scheduler::get().try_schedule(class, PAGE_SZ) {
+                // Wait without the slot. Another read may fill it meanwhile.
+                drop(write_guard);
+                io_scheduler::get().schedule(class, PAGE_SZ).await;
+                scheduled = true;
+                continue;
+            }
+
+            let buf = PageWriteGuardBuf {
+                page: write_guard,
+                init_up_to: 0,
+            };
+            let PageWriteGuardBuf { page, .. } = self
+                .read_exact_at_scheduled(buf, blknum as u64 * PAGE_SZ as u64)
+                .await
+                .map_err(|e| Error::new(ErrorKind::Other, e))?;
+            return Ok(page.mark_valid());
+        }
     }
 
     // Copied from https://doc.rust-lang.org/1.72.0/src/std/os/unix/fs.rs.html#219-235
@@ -586,11 +740,15 @@ impl VirtualFile {
         &self,
         buf: B,
         mut offset: u64,
+        ctx: &RequestContext,
     ) -> (B::Buf, Result<(), Error>) {
         let buf_len = buf.bytes_init();
         if buf_len == 0 {
             return (Slice::into_inner(buf.slice_full()), Ok(()));
         }
+        io_scheduler::get()
+            .schedule(IoClass::of(ctx), buf_len)
+            .await;
         let mut buf = buf.slice(0..buf_len);
         while !buf.is_empty() {
             // TODO: push `buf` further down
@@ -616,6 +774,7 @@ impl VirtualFile {
     }
 
     /// Writes `buf.slice(0..buf.bytes_init())`.
+    /// The writes are scheduled as [`IoClass::Background`] I/O.
     /// Returns the IoBuf that is underlying the BoundedBuf `buf`.
     /// I.e., the returned value's `bytes_init()` method returns something different than the `bytes_init()` that was passed in.
     /// It's quite brittle and easy to mis-use, so, we return the size in the Ok() variant.
@@ -624,6 +783,9 @@ impl VirtualFile {
         if nbytes == 0 {
             return (Slice::into_inner(buf.slice_full()), Ok(0));
         }
+        io_scheduler::get()
+            .schedule(IoClass::Background, nbytes)
+            .await;
         let mut buf = buf.slice(0..nbytes);
         while !buf.is_empty() {
             // TODO: push `Slice` further down
@@ -654,6 +816,8 @@ impl VirtualFile {
         Ok(n)
     }
 
+    /// Doesn't take from the I/O budget: the callers do, once for all the reads
+    /// of a buffer, see [`io_scheduler`].
     pub(crate) async fn read_at<B>(&self, buf: B, offset: u64) -> (B, Result<usize, Error>)
     where
         B: tokio_epoll_uring::BoundedBufMut + Send,
@@ -679,6 +843,7 @@ impl VirtualFile {
         })
     }
 
+    /// Like [`Self::read_at`], doesn't take from the I/O budget.
     async fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize, Error> {
         let result = with_file!(self, StorageIoOperation::Write, |file_guard| {
             file_guard.with_std_file(|std_file| std_file.write_at(buf, offset))
@@ -931,19 +1096,22 @@ impl VirtualFile {
     pub(crate) async fn read_blk(
         &self,
         blknum: u32,
+        ctx: &RequestContext,
     ) -> Result<crate::tenant::block_io::BlockLease<'_>, std::io::Error> {
-        use crate::page_cache::PAGE_SZ;
         let buf = vec![0; PAGE_SZ];
         let buf = self
-            .read_exact_at(buf, blknum as u64 * (PAGE_SZ as u64))
+            .read_exact_at(buf, blknum as u64 * (PAGE_SZ as u64), ctx)
             .await?;
         Ok(crate::tenant::block_io::BlockLease::Vec(buf))
     }
 
-    async fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<(), Error> {
+    async fn read_to_end(&mut self, buf: &mut Vec<u8>, ctx: &RequestContext) -> Result<(), Error> {
         let mut tmp = vec![0; 128];
         loop {
             let res;
+            io_scheduler::get()
+                .schedule(IoClass::of(ctx), tmp.len())
+                .await;
             (tmp, res) = self.read_at(tmp, self.pos).await;
             match res {
                 Ok(0) => return Ok(()),
@@ -1030,6 +1198,20 @@ pub fn init(num_slots: usize, engine: IoEngineKind) {
     crate::metrics::virtual_file_descriptor_cache::SIZE_MAX.set(num_slots as u64);
 }
 
+/// Set the bandwidth and IOPS budgets of [`VirtualFile`] I/O, see [`io_scheduler`].
+///
+/// Must be called before any I/O, if at all: without it, there are no budgets.
+#[cfg(not(test))]
+pub fn init_io_scheduler(config: IoSchedulerConfig) {
+    io_scheduler::init(config);
+}
+
+/// Set whether layer files are read with `O_DIRECT`, see [`io_mode`].
+#[cfg(not(test))]
+pub fn init_io_mode(mode: IoMode) {
+    io_mode::init(mode);
+}
+
 const TEST_MAX_FILE_DESCRIPTORS: usize = 10;
 
 // Get a handle to the global slots array.
@@ -1052,6 +1234,8 @@ fn get_open_files() -> &'static OpenFiles {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::context::DownloadBehavior;
+    use crate::task_mgr::TaskKind;
     use rand::seq::SliceRandom;
     use rand::thread_rng;
     use rand::Rng;
@@ -1071,16 +1255,26 @@ mod tests {
     }
 
     impl MaybeVirtualFile {
-        async fn read_exact_at(&self, mut buf: Vec<u8>, offset: u64) -> Result<Vec<u8>, Error> {
+        async fn read_exact_at(
+            &self,
+            mut buf: Vec<u8>,
+            offset: u64,
+            ctx: &RequestContext,
+        ) -> Result<Vec<u8>, Error> {
             match self {
-                MaybeVirtualFile::VirtualFile(file) => file.read_exact_at(buf, offset).await,
+                MaybeVirtualFile::VirtualFile(file) => file.read_exact_at(buf, offset, ctx).await,
                 MaybeVirtualFile::File(file) => file.read_exact_at(&mut buf, offset).map(|()| buf),
             }
         }
-        async fn write_all_at<B: BoundedBuf>(&self, buf: B, offset: u64) -> Result<(), Error> {
+        async fn write_all_at<B: BoundedBuf>(
+            &self,
+            buf: B,
+            offset: u64,
+            ctx: &RequestContext,
+        ) -> Result<(), Error> {
             match self {
                 MaybeVirtualFile::VirtualFile(file) => {
-                    let (_buf, res) = file.write_all_at(buf, offset).await;
+                    let (_buf, res) = file.write_all_at(buf, offset, ctx).await;
                     res
                 }
                 MaybeVirtualFile::File(file) => {
@@ -1116,13 +1310,13 @@ mod tests {
 
         // Helper function to slurp contents of a file, starting at the current position,
         // into a string
-        async fn read_string(&mut self) -> Result<String, Error> {
+        async fn read_string(&mut self, ctx: &RequestContext) -> Result<String, Error> {
             use std::io::Read;
             let mut buf = String::new();
             match self {
                 MaybeVirtualFile::VirtualFile(file) => {
                     let mut buf = Vec::new();
-                    file.read_to_end(&mut buf).await?;
+                    file.read_to_end(&mut buf, ctx).await?;
                     return Ok(String::from_utf8(buf).unwrap());
                 }
                 MaybeVirtualFile::File(file) => {
@@ -1133,9 +1327,14 @@ mod tests {
         }
 
         // Helper function to slurp a portion of a file into a string
-        async fn read_string_at(&mut self, pos: u64, len: usize) -> Result<String, Error> {
+        async fn read_string_at(
+            &mut self,
+            pos: u64,
+            len: usize,
+            ctx: &RequestContext,
+        ) -> Result<String, Error> {
             let buf = vec![0; len];
-            let buf = self.read_exact_at(buf, pos).await?;
+            let buf = self.read_exact_at(buf, pos, ctx).await?;
             Ok(String::from_utf8(buf).unwrap())
         }
     }
@@ -1172,6 +1371,7 @@ mod tests {
         OF: Fn(Utf8PathBuf, OpenOptions) -> FT,
         FT: Future<Output = Result<MaybeVirtualFile, std::io::Error>>,
     {
+        let ctx = RequestContext::new(TaskKind::UnitTest, DownloadBehavior::Error);
         let testdir = crate::config::PageServerConf::test_repo_dir(testname);
         std::fs::create_dir_all(&testdir)?;
 
@@ -1188,7 +1388,7 @@ mod tests {
         file_a.write_all(b"foobar".to_vec()).await?;
 
         // cannot read from a file opened in write-only mode
-        let _ = file_a.read_string().await.unwrap_err();
+        let _ = file_a.read_string(&ctx).await.unwrap_err();
 
         // Close the file and re-open for reading
         let mut file_a = openfunc(path_a, OpenOptions::new().read(true).to_owned()).await?;
@@ -1197,24 +1397,24 @@ mod tests {
         let _ = file_a.write_all(b"bar".to_vec()).await.unwrap_err();
 
         // Try simple read
-        assert_eq!("foobar", file_a.read_string().await?);
+        assert_eq!("foobar", file_a.read_string(&ctx).await?);
 
         // It's positioned at the EOF now.
-        assert_eq!("", file_a.read_string().await?);
+        assert_eq!("", file_a.read_string(&ctx).await?);
 
         // Test seeks.
         assert_eq!(file_a.seek(SeekFrom::Start(1)).await?, 1);
-        assert_eq!("oobar", file_a.read_string().await?);
+        assert_eq!("oobar", file_a.read_string(&ctx).await?);
 
         assert_eq!(file_a.seek(SeekFrom::End(-2)).await?, 4);
-        assert_eq!("ar", file_a.read_string().await?);
+        assert_eq!("ar", file_a.read_string(&ctx).await?);
 
         assert_eq!(file_a.seek(SeekFrom::Start(1)).await?, 1);
         assert_eq!(file_a.seek(SeekFrom::Current(2)).await?, 3);
-        assert_eq!("bar", file_a.read_string().await?);
+        assert_eq!("bar", file_a.read_string(&ctx).await?);
 
         assert_eq!(file_a.seek(SeekFrom::Current(-5)).await?, 1);
-        assert_eq!("oobar", file_a.read_string().await?);
+        assert_eq!("oobar", file_a.read_string(&ctx).await?);
 
         // Test erroneous seeks to before byte 0
         file_a.seek(SeekFrom::End(-7)).await.unwrap_err();
@@ -1222,7 +1422,7 @@ mod tests {
         file_a.seek(SeekFrom::Current(-2)).await.unwrap_err();
 
         // the erroneous seek should have left the position unchanged
-        assert_eq!("oobar", file_a.read_string().await?);
+        assert_eq!("oobar", file_a.read_string(&ctx).await?);
 
         // Create another test file, and try FileExt functions on it.
         let path_b = testdir.join("file_b");
@@ -1236,10 +1436,10 @@ mod tests {
                 .to_owned(),
         )
         .await?;
-        file_b.write_all_at(b"BAR".to_vec(), 3).await?;
-        file_b.write_all_at(b"FOO".to_vec(), 0).await?;
+        file_b.write_all_at(b"BAR".to_vec(), 3, &ctx).await?;
+        file_b.write_all_at(b"FOO".to_vec(), 0, &ctx).await?;
 
-        assert_eq!(file_b.read_string_at(2, 3).await?, "OBA");
+        assert_eq!(file_b.read_string_at(2, 3, &ctx).await?, "OBA");
 
         // Open a lot of files, enough to cause some evictions. (Or to be precise,
         // open the same file many times. The effect is the same.)
@@ -1251,7 +1451,7 @@ mod tests {
         for _ in 0..100 {
             let mut vfile =
                 openfunc(path_b.clone(), OpenOptions::new().read(true).to_owned()).await?;
-            assert_eq!("FOOBAR", vfile.read_string().await?);
+            assert_eq!("FOOBAR", vfile.read_string(&ctx).await?);
             vfiles.push(vfile);
         }
 
@@ -1260,13 +1460,13 @@ mod tests {
 
         // The underlying file descriptor for 'file_a' should be closed now. Try to read
         // from it again. We left the file positioned at offset 1 above.
-        assert_eq!("oobar", file_a.read_string().await?);
+        assert_eq!("oobar", file_a.read_string(&ctx).await?);
 
         // Check that all the other FDs still work too. Use them in random order for
         // good measure.
         vfiles.as_mut_slice().shuffle(&mut thread_rng());
         for vfile in vfiles.iter_mut() {
-            assert_eq!("OOBAR", vfile.read_string_at(1, 5).await?);
+            assert_eq!("OOBAR", vfile.read_string_at(1, 5, &ctx).await?);
         }
 
         Ok(())
@@ -1311,11 +1511,12 @@ mod tests {
         for _threadno in 0..THREADS {
             let files = files.clone();
             let hdl = rt.spawn(async move {
+                let ctx = RequestContext::new(TaskKind::UnitTest, DownloadBehavior::Error);
                 let mut buf = vec![0u8; SIZE];
                 let mut rng = rand::rngs::OsRng;
                 for _ in 1..1000 {
                     let f = &files[rng.gen_range(0..files.len())];
-                    buf = f.read_exact_at(buf, 0).await.unwrap();
+                    buf = f.read_exact_at(buf, 0, &ctx).await.unwrap();
                     assert!(buf == SAMPLE);
                 }
             });
@@ -1329,8 +1530,87 @@ mod tests {
         Ok(())
     }
 
+    const DIRECT_IO_TEST_FILE_SIZE: usize = 2 * DIO_ALIGN + DIO_ALIGN / 2;
+
+    fn direct_io_test_file(name: &str) -> Result<(Utf8PathBuf, Vec<u8>), Error> {
+        let testdir = crate::config::PageServerConf::test_repo_dir(name);
+        std::fs::create_dir_all(&testdir)?;
+
+        let path = testdir.join("direct_io_test_file");
+        let content: Vec<u8> = (0..DIRECT_IO_TEST_FILE_SIZE)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&path, &content)?;
+        Ok((path, content))
+    }
+
+    /// Reads of a file with `direct` set, aligned ones and ones that go through a
+    /// bounce buffer, up to and past the end of the file.
+    async fn check_direct_io_reads(file: &VirtualFile, content: &[u8]) -> Result<(), Error> {
+        const SIZE: usize = DIRECT_IO_TEST_FILE_SIZE;
+        let ctx = RequestContext::new(TaskKind::UnitTest, DownloadBehavior::Error);
+
+        // Aligned, read into the buffer directly
+        let buf = file
+            .read_exact_at(AlignedBuffer::zeroed(DIO_ALIGN), DIO_ALIGN as u64, &ctx)
+            .await?;
+        assert_eq!(&buf[..], &content[DIO_ALIGN..2 * DIO_ALIGN]);
+
+        // Unaligned offset and length, across blocks
+        let buf = file.read_exact_at(vec![0; 5000], 100, &ctx).await?;
+        assert_eq!(buf, &content[100..5100]);
+
+        // Aligned offset, unaligned length
+        let buf = file.read_exact_at(vec![0; 100], 0, &ctx).await?;
+        assert_eq!(buf, &content[..100]);
+
+        // Up to the end of the file, which is not aligned: the read of the last
+        // block comes back short
+        let buf = file
+            .read_exact_at(vec![0; 1000], (SIZE - 1000) as u64, &ctx)
+            .await?;
+        assert_eq!(buf, &content[SIZE - 1000..]);
+
+        // Past the end of the file
+        let err = file
+            .read_exact_at(vec![0; 1000], (SIZE - 500) as u64, &ctx)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        // Starting past the end of the file, where the first read returns nothing
+        let err = file
+            .read_exact_at(vec![0; 100], (SIZE + DIO_ALIGN) as u64, &ctx)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        Ok(())
+    }
+
+    /// The bounce buffer path of direct I/O, on a file opened without `O_DIRECT`,
+    /// so that it runs on any file system.
+    #[tokio::test]
+    async fn test_bounced_reads() -> Result<(), Error> {
+        let (path, content) = direct_io_test_file("bounced_reads")?;
+        let file = VirtualFile::open(&path).await?.assume_direct();
+        check_direct_io_reads(&file, &content).await
+    }
+
+    /// Reads of a file opened with `O_DIRECT`. Not every file system supports it,
+    /// e.g. tmpfs doesn't, so this only runs on request.
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore = "needs a file system with O_DIRECT support"]
+    async fn test_direct_io_reads() -> Result<(), Error> {
+        let (path, content) = direct_io_test_file("direct_io_reads")?;
+        let file = VirtualFile::open_direct(&path).await?;
+        check_direct_io_reads(&file, &content).await
+    }
+
     #[tokio::test]
     async fn test_atomic_overwrite_basic() {
+        let ctx = RequestContext::new(TaskKind::UnitTest, DownloadBehavior::Error);
         let testdir = crate::config::PageServerConf::test_repo_dir("test_atomic_overwrite_basic");
         std::fs::create_dir_all(&testdir).unwrap();
 
@@ -1341,7 +1621,7 @@ mod tests {
             .await
             .unwrap();
         let mut file = MaybeVirtualFile::from(VirtualFile::open(&path).await.unwrap());
-        let post = file.read_string().await.unwrap();
+        let post = file.read_string(&ctx).await.unwrap();
         assert_eq!(post, "foo");
         assert!(!tmp_path.exists());
         drop(file);
@@ -1350,7 +1630,7 @@ mod tests {
             .await
             .unwrap();
         let mut file = MaybeVirtualFile::from(VirtualFile::open(&path).await.unwrap());
-        let post = file.read_string().await.unwrap();
+        let post = file.read_string(&ctx).await.unwrap();
         assert_eq!(post, "bar");
         assert!(!tmp_path.exists());
         drop(file);
@@ -1358,6 +1638,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_atomic_overwrite_preexisting_tmp() {
+        let ctx = RequestContext::new(TaskKind::UnitTest, DownloadBehavior::Error);
         let testdir =
             crate::config::PageServerConf::test_repo_dir("test_atomic_overwrite_preexisting_tmp");
         std::fs::create_dir_all(&testdir).unwrap();
@@ -1373,7 +1654,7 @@ mod tests {
             .unwrap();
 
         let mut file = MaybeVirtualFile::from(VirtualFile::open(&path).await.unwrap());
-        let post = file.read_string().await.unwrap();
+        let post = file.read_string(&ctx).await.unwrap();
         assert_eq!(post, "foo");
         assert!(!tmp_path.exists());
         drop(file);
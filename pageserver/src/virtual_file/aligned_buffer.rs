@@ -0,0 +1,131 @@
+//! Buffers for direct I/O, see [`super::io_mode`].
+//!
+//! With `O_DIRECT`, the memory address, the file offset and the length of every
+//! read must be multiples of the logical block size of the device. [`DIO_ALIGN`]
+//! is a multiple of all block sizes in use.
+
+use std::alloc::Layout;
+use std::ops::Deref;
+use std::ptr::NonNull;
+
+/// Alignment of the memory addresses, file offsets and lengths of direct I/O.
+pub(crate) const DIO_ALIGN: usize = 4096;
+
+pub(crate) fn is_aligned(ptr: *const u8, offset: u64, len: usize) -> bool {
+    ptr as usize % DIO_ALIGN == 0 && offset % DIO_ALIGN as u64 == 0 && len % DIO_ALIGN == 0
+}
+
+/// A zeroed heap allocation that starts at a multiple of [`DIO_ALIGN`].
+///
+/// Like a `Vec<u8>` with fixed capacity, it derefs to the part that was
+/// initialized by reads.
+pub(crate) struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    capacity: usize,
+    len: usize,
+}
+
+// SAFETY: the buffer owns its allocation, like a `Vec<u8>` does.
+unsafe impl Send for AlignedBuffer {}
+// SAFETY: see above
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    fn layout(capacity: usize) -> Layout {
+        // Allocations of size zero are not allowed
+        Layout::from_size_align(capacity.max(1), DIO_ALIGN).unwrap()
+    }
+
+    pub(crate) fn zeroed(capacity: usize) -> Self {
+        let layout = Self::layout(capacity);
+        // SAFETY: the layout has a non-zero size
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            std::alloc::handle_alloc_error(layout)
+        };
+        AlignedBuffer {
+            ptr,
+            capacity,
+            len: 0,
+        }
+    }
+
+    /// Leak the whole buffer, for allocations that live as long as the process,
+    /// like the page cache.
+    pub(crate) fn leak(self) -> &'static mut [u8] {
+        let this = std::mem::ManuallyDrop::new(self);
+        // SAFETY: the allocation is zeroed, hence initialized, and is never freed
+        unsafe { std::slice::from_raw_parts_mut(this.ptr.as_ptr(), this.capacity) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `zeroed` with the same layout
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr(), Self::layout(self.capacity)) }
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the first `len` bytes are initialized
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+// SAFETY: the allocation does not move when the buffer is moved.
+unsafe impl tokio_epoll_uring::IoBuf for AlignedBuffer {
+    fn stable_ptr(&self) -> *const u8 {
+        self.ptr.as_ptr()
+    }
+    fn bytes_init(&self) -> usize {
+        self.len
+    }
+    fn bytes_total(&self) -> usize {
+        self.capacity
+    }
+}
+
+// SAFETY: see above, plus: the buffer is owned, hence it's safe to hand out the
+// `stable_mut_ptr()`.
+unsafe impl tokio_epoll_uring::IoBufMut for AlignedBuffer {
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    unsafe fn set_init(&mut self, pos: usize) {
+        assert!(pos <= self.capacity);
+        if self.len < pos {
+            self.len = pos;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_epoll_uring::{BoundedBuf, BoundedBufMut};
+
+    #[test]
+    fn aligned_and_initialized_by_slices() {
+        let buf = AlignedBuffer::zeroed(3 * DIO_ALIGN);
+        assert!(is_aligned(buf.as_ptr(), 0, buf.bytes_total()));
+        assert!(buf.is_empty());
+
+        // Reads into slices initialize the buffer up to the end of the read
+        let mut slice = buf.slice_full();
+        slice.put_slice(&[1; DIO_ALIGN]);
+        let mut slice = slice.into_inner().slice(DIO_ALIGN..);
+        assert_eq!(slice.bytes_total(), 2 * DIO_ALIGN);
+        slice.put_slice(b"foo");
+        let buf = slice.into_inner();
+        assert_eq!(buf.len(), DIO_ALIGN + 3);
+        assert_eq!(&buf[DIO_ALIGN - 1..], b"\x01foo");
+
+        assert!(!is_aligned(buf[1..].as_ptr(), 0, DIO_ALIGN));
+        assert!(!is_aligned(buf.as_ptr(), 1, DIO_ALIGN));
+        assert!(!is_aligned(buf.as_ptr(), 0, DIO_ALIGN + 1));
+    }
+}
@@ -0,0 +1,36 @@
+//! Layer files are read either through the kernel page cache or with
+//! `O_DIRECT`, see [`IoMode`] and [`super::VirtualFile::open_layer`].
+//!
+//! In direct mode, layer file pages are only cached in [`crate::page_cache`],
+//! instead of in both the kernel page cache and ours, so vectored reads of
+//! layers go through our page cache as well. Reads into page cache
+//! buffers are aligned as is, other reads go through an
+//! [`super::aligned_buffer::AlignedBuffer`].
+//!
+//! The choice of mode is global. It can be changed at runtime, but only applies
+//! to layer files that are opened afterwards.
+//! Initialize using [`init`].
+
+pub(crate) use super::api::IoMode;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+static IO_MODE: AtomicU8 = AtomicU8::new(IoMode::Buffered as u8);
+
+pub(crate) fn set(mode: IoMode) {
+    IO_MODE.store(mode as u8, Ordering::Relaxed);
+    #[cfg(not(test))]
+    {
+        let metric = &crate::metrics::virtual_file_io_engine::MODE;
+        metric.reset();
+        metric.with_label_values(&[&format!("{mode}")]).set(1);
+    }
+}
+
+#[cfg(not(test))]
+pub(super) fn init(mode: IoMode) {
+    set(mode);
+}
+
+pub(super) fn get() -> IoMode {
+    IoMode::from_repr(IO_MODE.load(Ordering::Relaxed)).unwrap()
+}
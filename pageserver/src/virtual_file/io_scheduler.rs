@@ -0,0 +1,443 @@
+//! Pageserver-wide scheduling of [`super::VirtualFile`] I/O.
+//!
+//! Every read and write is classified into an [`IoClass`] by the task kind of
+//! its [`RequestContext`]: reads on behalf of compute, i.e. getpage requests,
+//! basebackups and WAL ingest, are [`IoClass::Foreground`], everything else,
+//! e.g. compaction, eviction and downloads, is [`IoClass::Background`].
+//!
+//! Without budgets in the [`IoSchedulerConfig`], requests are only counted. With
+//! a bandwidth or IOPS budget, requests wait until the budget allows them, and
+//! background requests also wait while any foreground request is waiting, so
+//! that the foreground gets the budget first. So that layer flushes and
+//! compaction still make progress under constant foreground load, a waiting
+//! background request gets the budget after at most [`MAX_FOREGROUND_STREAK`]
+//! foreground requests. A read or write of a buffer takes from the budget once,
+//! however many system calls it takes to complete.
+//!
+//! The choice of budgets is global.
+//! Initialize using [`init`], otherwise there are no budgets.
+
+use std::num::NonZeroU64;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use strum::EnumCount;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::context::RequestContext;
+use crate::metrics::virtual_file_io_scheduler::METRICS;
+use crate::task_mgr::TaskKind;
+
+/// Unused budget accumulates for at most this long, which bounds the bursts
+/// after idle periods.
+const MAX_BURST: Duration = Duration::from_millis(100);
+
+/// Foreground requests served in a row while background requests are waiting,
+/// after which a background request goes first.
+const MAX_FOREGROUND_STREAK: u32 = 10;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoSchedulerConfig {
+    /// Bytes per second that all [`super::VirtualFile`]s may read and write in total.
+    pub bandwidth: Option<NonZeroU64>,
+    /// Read and write operations per second, in total.
+    pub iops: Option<NonZeroU64>,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    strum_macros::IntoStaticStr,
+    strum_macros::EnumCount,
+    strum_macros::FromRepr,
+)]
+#[strum(serialize_all = "snake_case")]
+pub(crate) enum IoClass {
+    /// Served before any background I/O.
+    Foreground,
+    Background,
+}
+
+impl IoClass {
+    pub(crate) fn of(ctx: &RequestContext) -> IoClass {
+        match ctx.task_kind() {
+            TaskKind::PageRequestHandler
+            | TaskKind::WalReceiverConnectionHandler
+            | TaskKind::BasebackupCacheFill => IoClass::Foreground,
+            _ => IoClass::Background,
+        }
+    }
+}
+
+pub(crate) struct IoScheduler {
+    config: IoSchedulerConfig,
+    state: Mutex<State>,
+    /// Wakes the requests of an [`IoClass`] that wait for the other class, rather
+    /// than for the budget, see [`State::preempted`].
+    unblocked: [Notify; IoClass::COUNT],
+}
+
+struct State {
+    /// Operations left in the budget, negative if a request went over it.
+    ops: f64,
+    /// Bytes left in the budget, negative if a request went over it.
+    bytes: f64,
+    refilled_at: Instant,
+    /// Requests waiting for the budget, by [`IoClass`].
+    waiting: [usize; IoClass::COUNT],
+    /// Foreground requests served since the last background request, counted
+    /// while background requests are waiting.
+    foreground_streak: u32,
+}
+
+static IO_SCHEDULER: OnceCell<IoScheduler> = OnceCell::new();
+
+#[cfg(not(test))]
+pub(super) fn init(config: IoSchedulerConfig) {
+    if IO_SCHEDULER.set(IoScheduler::new(config)).is_err() {
+        panic!("io_scheduler::init called twice");
+    }
+}
+
+pub(super) fn get() -> &'static IoScheduler {
+    IO_SCHEDULER.get_or_init(|| IoScheduler::new(IoSchedulerConfig::default()))
+}
+
+impl IoScheduler {
+    fn new(config: IoSchedulerConfig) -> Self {
+        let mut state = State {
+            ops: 0.0,
+            bytes: 0.0,
+            refilled_at: Instant::now(),
+            waiting: [0; IoClass::COUNT],
+            foreground_streak: 0,
+        };
+        state.refill(&config, MAX_BURST);
+        IoScheduler {
+            config,
+            state: Mutex::new(state),
+            unblocked: [Notify::new(), Notify::new()],
+        }
+    }
+
+    /// Wait until a request of `bytes` of class `class` may be issued.
+    pub(super) async fn schedule(&self, class: IoClass, bytes: usize) {
+        METRICS.requests[class as usize].inc();
+        METRICS.bytes[class as usize].inc_by(bytes as u64);
+        if self.is_unlimited() {
+            return;
+        }
+
+        let mut waiting: Option<Waiting> = None;
+        loop {
+            // Listen before looking at the state, so that an unblocking in between
+            // isn't missed.
+            let unblocked = self.unblocked[class as usize].notified();
+            tokio::pin!(unblocked);
+            unblocked.as_mut().enable();
+
+            let (preempted, wait) = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                if state.try_take(&self.config, class, bytes, now) {
+                    let unblock = state.unblocks(class);
+                    drop(state);
+                    if let Some(other) = unblock {
+                        self.unblocked[other as usize].notify_waiters();
+                    }
+                    break;
+                }
+
+                if waiting.is_none() {
+                    state.waiting[class as usize] += 1;
+                    METRICS.queued[class as usize].inc();
+                    waiting = Some(Waiting {
+                        scheduler: self,
+                        class,
+                        started_at: now,
+                    });
+                }
+                (
+                    state.preempted(class),
+                    state.time_until_budget(&self.config),
+                )
+            };
+            if preempted {
+                unblocked.await;
+            } else {
+                tokio::time::sleep(wait).await;
+            }
+        }
+        if let Some(waiting) = waiting {
+            METRICS.wait_time[class as usize].observe(waiting.started_at.elapsed().as_secs_f64());
+        }
+    }
+
+    /// Like [`Self::schedule`], but returns `false` instead of waiting, for callers
+    /// that must not wait while holding a resource.
+    pub(super) fn try_schedule(&self, class: IoClass, bytes: usize) -> bool {
+        if !self.is_unlimited() {
+            let mut state = self.state.lock().unwrap();
+            if !state.try_take(&self.config, class, bytes, Instant::now()) {
+                return false;
+            }
+            let unblock = state.unblocks(class);
+            drop(state);
+            if let Some(other) = unblock {
+                self.unblocked[other as usize].notify_waiters();
+            }
+        }
+        METRICS.requests[class as usize].inc();
+        METRICS.bytes[class as usize].inc_by(bytes as u64);
+        true
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.config.bandwidth.is_none() && self.config.iops.is_none()
+    }
+}
+
+impl State {
+    /// Take a request from the budget, if the budget allows it now and it doesn't
+    /// have to give way to requests of the other class.
+    fn try_take(
+        &mut self,
+        config: &IoSchedulerConfig,
+        class: IoClass,
+        bytes: usize,
+        now: Instant,
+    ) -> bool {
+        let elapsed = now - self.refilled_at;
+        self.refilled_at = now;
+        self.refill(config, elapsed);
+
+        if self.preempted(class) || self.ops < 0.0 || self.bytes < 0.0 {
+            return false;
+        }
+        // Take the whole request from the budget, even if it goes negative: the
+        // following requests wait until it is paid off.
+        self.ops -= 1.0;
+        self.bytes -= bytes as f64;
+        match class {
+            IoClass::Foreground if self.waiting[IoClass::Background as usize] > 0 => {
+                self.foreground_streak += 1
+            }
+            IoClass::Foreground | IoClass::Background => self.foreground_streak = 0,
+        }
+        true
+    }
+
+    /// Whether requests of `class` have to give way to the other class: background
+    /// requests to waiting foreground requests, unless the foreground had its
+    /// streak, and foreground requests to waiting background requests if it had.
+    fn preempted(&self, class: IoClass) -> bool {
+        let background_due = self.waiting[IoClass::Background as usize] > 0
+            && self.foreground_streak >= MAX_FOREGROUND_STREAK;
+        match class {
+            IoClass::Foreground => background_due,
+            IoClass::Background => {
+                self.waiting[IoClass::Foreground as usize] > 0 && !background_due
+            }
+        }
+    }
+
+    /// After a request of `class` was served: the class whose waiting requests
+    /// may no longer be preempted, if any.
+    fn unblocks(&self, class: IoClass) -> Option<IoClass> {
+        match class {
+            IoClass::Foreground if self.foreground_streak == MAX_FOREGROUND_STREAK => {
+                Some(IoClass::Background)
+            }
+            IoClass::Foreground => None,
+            IoClass::Background => Some(IoClass::Foreground),
+        }
+    }
+
+    fn refill(&mut self, config: &IoSchedulerConfig, elapsed: Duration) {
+        let elapsed = elapsed.as_secs_f64();
+        let max_burst = MAX_BURST.as_secs_f64();
+        if let Some(iops) = config.iops {
+            let iops = iops.get() as f64;
+            self.ops = (self.ops + iops * elapsed).min(iops * max_burst);
+        } else {
+            self.ops = f64::INFINITY;
+        }
+        if let Some(bandwidth) = config.bandwidth {
+            let bandwidth = bandwidth.get() as f64;
+            self.bytes = (self.bytes + bandwidth * elapsed).min(bandwidth * max_burst);
+        } else {
+            self.bytes = f64::INFINITY;
+        }
+    }
+
+    /// How long until neither budget is negative anymore.
+    fn time_until_budget(&self, config: &IoSchedulerConfig) -> Duration {
+        let until = |left: f64, rate: Option<NonZeroU64>| match rate {
+            Some(rate) if left < 0.0 => Duration::from_secs_f64(-left / rate.get() as f64),
+            _ => Duration::ZERO,
+        };
+        until(self.ops, config.iops).max(until(self.bytes, config.bandwidth))
+    }
+}
+
+/// A request waiting for the budget. Counted in [`State::waiting`] until it
+/// gets the budget or is cancelled.
+struct Waiting<'a> {
+    scheduler: &'a IoScheduler,
+    class: IoClass,
+    started_at: Instant,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        state.waiting[self.class as usize] -= 1;
+        METRICS.queued[self.class as usize].dec();
+        if state.waiting[self.class as usize] == 0 {
+            // The last waiting request of a class no longer holds up the other one
+            if self.class == IoClass::Background {
+                state.foreground_streak = 0;
+            }
+            drop(state);
+            let other = match self.class {
+                IoClass::Foreground => IoClass::Background,
+                IoClass::Background => IoClass::Foreground,
+            };
+            self.scheduler.unblocked[other as usize].notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn budget() {
+        let config = IoSchedulerConfig {
+            bandwidth: NonZeroU64::new(1000),
+            iops: NonZeroU64::new(10),
+        };
+        let mut state = State {
+            ops: 0.0,
+            bytes: 0.0,
+            refilled_at: Instant::now(),
+            waiting: [0; IoClass::COUNT],
+            foreground_streak: 0,
+        };
+
+        // Unused budget only accumulates up to the burst
+        state.refill(&config, Duration::from_secs(10));
+        assert_eq!(state.ops, 1.0);
+        assert_eq!(state.bytes, 100.0);
+        assert_eq!(state.time_until_budget(&config), Duration::ZERO);
+
+        // A request bigger than the budget is paid off by the following ones
+        state.ops -= 1.0;
+        state.bytes -= 300.0;
+        let secs = |state: &State| state.time_until_budget(&config).as_secs_f64();
+        assert!((secs(&state) - 0.2).abs() < 1e-6);
+        state.refill(&config, Duration::from_millis(50));
+        assert!((secs(&state) - 0.15).abs() < 1e-6);
+
+        // Without a budget, requests never wait for it
+        let unlimited = IoSchedulerConfig::default();
+        state.refill(&unlimited, Duration::ZERO);
+        assert_eq!(state.time_until_budget(&unlimited), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn foreground_first() {
+        let scheduler = Arc::new(IoScheduler::new(IoSchedulerConfig {
+            bandwidth: None,
+            iops: NonZeroU64::new(100),
+        }));
+        let waiting = |class: IoClass| scheduler.state.lock().unwrap().waiting[class as usize];
+
+        // Use up the burst, so that the following requests queue up
+        while scheduler.try_schedule(IoClass::Background, 1) {}
+        assert!(!scheduler.try_schedule(IoClass::Foreground, 1));
+
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let spawn = |class| {
+            let scheduler = Arc::clone(&scheduler);
+            let served = Arc::clone(&served);
+            tokio::spawn(async move {
+                scheduler.schedule(class, 1).await;
+                served.lock().unwrap().push(class);
+            })
+        };
+
+        // Time doesn't advance while this task keeps running
+        let mut tasks: Vec<_> = (0..5).map(|_| spawn(IoClass::Background)).collect();
+        while waiting(IoClass::Background) < 5 {
+            tokio::task::yield_now().await;
+        }
+        tasks.push(spawn(IoClass::Foreground));
+        while waiting(IoClass::Foreground) < 1 {
+            tokio::task::yield_now().await;
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let served = served.lock().unwrap();
+        assert_eq!(served.len(), 6);
+        assert_eq!(served[0], IoClass::Foreground);
+        assert_eq!(waiting(IoClass::Background), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn background_share() {
+        let scheduler = Arc::new(IoScheduler::new(IoSchedulerConfig {
+            bandwidth: None,
+            iops: NonZeroU64::new(100),
+        }));
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let spawn = |class, count| {
+            let scheduler = Arc::clone(&scheduler);
+            let served = Arc::clone(&served);
+            tokio::spawn(async move {
+                for _ in 0..count {
+                    scheduler.schedule(class, 1).await;
+                    served.lock().unwrap().push(class);
+                }
+            })
+        };
+
+        // Keep several foreground requests waiting at all times, more than the
+        // budget allows, while a background task writes a few buffers
+        let foreground: Vec<_> = (0..4).map(|_| spawn(IoClass::Foreground, 100)).collect();
+        let background = spawn(IoClass::Background, 5);
+        background.await.unwrap();
+
+        // The background task finished while the foreground was still busy, and
+        // got its share of the budget on the way
+        let served = served.lock().unwrap().clone();
+        let foreground_served = served
+            .iter()
+            .filter(|class| **class == IoClass::Foreground)
+            .count();
+        assert!(foreground_served < 400, "{foreground_served}");
+        let background_at: Vec<_> = (0..served.len())
+            .filter(|i| served[*i] == IoClass::Background)
+            .collect();
+        for pair in background_at.windows(2) {
+            let foreground_between = pair[1] - pair[0] - 1;
+            assert!(
+                foreground_between <= MAX_FOREGROUND_STREAK as usize,
+                "{served:?}"
+            );
+        }
+        for task in foreground {
+            task.await.unwrap();
+        }
+    }
+}
@@ -24,9 +24,13 @@ mod process;
 /// Code to apply [`NeonWalRecord`]s.
 pub(crate) mod apply_neon;
 
+/// Code to apply common Postgres WAL records without the walredo process.
+mod native;
+
 use crate::config::PageServerConf;
 use crate::metrics::{
-    WAL_REDO_BYTES_HISTOGRAM, WAL_REDO_PROCESS_LAUNCH_DURATION_HISTOGRAM,
+    WAL_REDO_BYTES_HISTOGRAM, WAL_REDO_NATIVE_RECORD_COUNTER, WAL_REDO_POOL_PROCESSES,
+    WAL_REDO_PROCESS_LAUNCH_DURATION_HISTOGRAM, WAL_REDO_QUEUED_REQUESTS,
     WAL_REDO_RECORDS_HISTOGRAM, WAL_REDO_TIME,
 };
 use crate::repository::Key;
@@ -36,24 +40,143 @@ use bytes::{Bytes, BytesMut};
 use pageserver_api::key::key_to_rel_block;
 use pageserver_api::models::WalRedoManagerStatus;
 use pageserver_api::shard::TenantShardId;
-use std::sync::{Arc, RwLock};
+use postgres_ffi::BLCKSZ;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, RwLock};
 use std::time::Duration;
 use std::time::Instant;
 use tracing::*;
 use utils::lsn::Lsn;
 
+/// Which implementation replays a run of consecutive records
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BatchKind {
+    /// Neon's own record types, see [`apply_neon`]
+    Neon,
+    /// Postgres records that we can replay in-process, see [`native`]
+    Native,
+    /// Everything else goes to the wal-redo postgres process
+    Postgres,
+}
+
 ///
-/// This is the real implementation that uses a Postgres process to
-/// perform WAL replay. Only one thread can use the process at a time,
-/// that is controlled by the Mutex. In the future, we might want to
-/// launch a pool of processes to allow concurrent replay of multiple
-/// records.
+/// This is the real implementation that uses Postgres processes to
+/// perform WAL replay. Each process handles one request at a time, so
+/// we keep a pool of up to `wal_redo_process_pool_size` processes per
+/// tenant shard. A new process is launched when all the existing ones
+/// are busy; once the pool is full, requests queue up behind the least
+/// busy process.
 ///
 pub struct PostgresRedoManager {
     tenant_shard_id: TenantShardId,
     conf: &'static PageServerConf,
     last_redo_at: std::sync::Mutex<Option<Instant>>,
-    redo_process: RwLock<Option<Arc<process::WalRedoProcess>>>,
+    redo_processes: RwLock<Vec<Arc<PooledProcess>>>,
+    /// Number of processes being launched. They count towards the pool size, so
+    /// that concurrent callers don't launch more than `wal_redo_process_pool_size`
+    /// processes. Only increased while holding the `redo_processes` lock.
+    launching: std::sync::Mutex<usize>,
+    /// Notified when a launch finishes, successfully or not.
+    launched: Condvar,
+}
+
+/// A walredo process in the pool of a [`PostgresRedoManager`].
+struct PooledProcess {
+    process: process::WalRedoProcess,
+    /// Number of requests that are using the process or waiting for it
+    in_flight: AtomicUsize,
+    /// When the last request using the process finished
+    last_used_at: std::sync::Mutex<Instant>,
+}
+
+impl PooledProcess {
+    fn new(process: process::WalRedoProcess) -> Self {
+        WAL_REDO_POOL_PROCESSES.inc();
+        PooledProcess {
+            process,
+            in_flight: AtomicUsize::new(0),
+            last_used_at: std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Register a request that is about to use the process. The request counts
+    /// as queued if the process was already busy.
+    fn start_request(self: &Arc<Self>) -> InFlightRequest {
+        let queued = self.in_flight.fetch_add(1, Ordering::Relaxed) > 0;
+        if queued {
+            WAL_REDO_QUEUED_REQUESTS.inc();
+        }
+        InFlightRequest {
+            process: Arc::clone(self),
+            queued,
+        }
+    }
+
+    /// Like [`Self::start_request`], but only if the process is idle. Concurrent
+    /// callers never get the same idle process.
+    fn try_start_idle_request(self: &Arc<Self>) -> Option<InFlightRequest> {
+        self.in_flight
+            .compare_exchange(0, 1, Ordering::Relaxed, Ordering::Relaxed)
+            .ok()?;
+        Some(InFlightRequest {
+            process: Arc::clone(self),
+            queued: false,
+        })
+    }
+
+    fn is_idle_for(&self, idle_timeout: Duration) -> bool {
+        self.in_flight() == 0 && self.last_used_at.lock().unwrap().elapsed() >= idle_timeout
+    }
+}
+
+impl Drop for PooledProcess {
+    fn drop(&mut self) {
+        WAL_REDO_POOL_PROCESSES.dec();
+    }
+}
+
+/// Guard returned by [`PooledProcess::start_request`]: the request counts as in
+/// flight until it is dropped.
+struct InFlightRequest {
+    process: Arc<PooledProcess>,
+    queued: bool,
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        *self.process.last_used_at.lock().unwrap() = Instant::now();
+        self.process.in_flight.fetch_sub(1, Ordering::Relaxed);
+        if self.queued {
+            WAL_REDO_QUEUED_REQUESTS.dec();
+        }
+    }
+}
+
+/// Starts a request on an idle process in the pool, or, if there is none and the
+/// pool is full, on the least busy process. Returns `None` if another process
+/// should be launched, or if the pool is only full because of the `launching`
+/// processes.
+///
+/// The request is registered before the caller releases the pool lock, so that
+/// concurrent callers see the process as busy.
+fn pick_process(
+    pool: &[Arc<PooledProcess>],
+    launching: usize,
+    max_pool_size: usize,
+) -> Option<InFlightRequest> {
+    if let Some(request) = pool.iter().find_map(|p| p.try_start_idle_request()) {
+        return Some(request);
+    }
+    if pool.len() + launching >= max_pool_size {
+        let least_busy = pool.iter().min_by_key(|p| p.in_flight())?;
+        Some(least_busy.start_request())
+    } else {
+        None
+    }
 }
 
 ///
@@ -83,48 +206,56 @@ impl PostgresRedoManager {
 
         let base_img_lsn = base_img.as_ref().map(|p| p.0).unwrap_or(Lsn::INVALID);
         let mut img = base_img.map(|p| p.1);
-        let mut batch_neon = apply_neon::can_apply_in_neon(&records[0].1);
+        let has_base_img = img.is_some();
+        let batch_kind = |i: usize, record: &NeonWalRecord| {
+            if apply_neon::can_apply_in_neon(record) {
+                BatchKind::Neon
+            } else if self.conf.native_wal_redo
+                // Without a base image, leave it to the walredo process to decide what
+                // the page looks like, unless the record initializes it.
+                && (i > 0 || has_base_img || record.will_init())
+                && native::can_apply(key, record, pg_version)
+            {
+                BatchKind::Native
+            } else {
+                BatchKind::Postgres
+            }
+        };
+        let mut batch = batch_kind(0, &records[0].1);
         let mut batch_start = 0;
         for (i, record) in records.iter().enumerate().skip(1) {
-            let rec_neon = apply_neon::can_apply_in_neon(&record.1);
-
-            if rec_neon != batch_neon {
-                let result = if batch_neon {
-                    self.apply_batch_neon(key, lsn, img, &records[batch_start..i])
-                } else {
-                    self.apply_batch_postgres(
-                        key,
-                        lsn,
-                        img,
-                        base_img_lsn,
-                        &records[batch_start..i],
-                        self.conf.wal_redo_timeout,
-                        pg_version,
-                    )
-                };
+            let rec_batch = batch_kind(i, &record.1);
+
+            if rec_batch != batch {
+                let result = self.apply_batch(
+                    batch,
+                    key,
+                    lsn,
+                    img,
+                    base_img_lsn,
+                    &records[batch_start..i],
+                    pg_version,
+                );
                 img = Some(result?);
 
-                batch_neon = rec_neon;
+                batch = rec_batch;
                 batch_start = i;
             }
         }
         // last batch
-        if batch_neon {
-            self.apply_batch_neon(key, lsn, img, &records[batch_start..])
-        } else {
-            self.apply_batch_postgres(
-                key,
-                lsn,
-                img,
-                base_img_lsn,
-                &records[batch_start..],
-                self.conf.wal_redo_timeout,
-                pg_version,
-            )
-        }
+        self.apply_batch(
+            batch,
+            key,
+            lsn,
+            img,
+            base_img_lsn,
+            &records[batch_start..],
+            pg_version,
+        )
     }
 
     pub(crate) fn status(&self) -> Option<WalRedoManagerStatus> {
+        let pool = self.redo_processes.read().unwrap();
         Some(WalRedoManagerStatus {
             last_redo_at: {
                 let at = *self.last_redo_at.lock().unwrap();
@@ -134,7 +265,9 @@ impl PostgresRedoManager {
                     chrono::Utc::now().checked_sub_signed(chrono::Duration::from_std(age).ok()?)
                 })
             },
-            pid: self.redo_process.read().unwrap().as_ref().map(|p| p.id()),
+            pid: pool.first().map(|p| p.process.id()),
+            pool_size: pool.len(),
+            queue_depth: pool.iter().map(|p| p.in_flight().saturating_sub(1)).sum(),
         })
     }
 }
@@ -152,23 +285,126 @@ impl PostgresRedoManager {
             tenant_shard_id,
             conf,
             last_redo_at: std::sync::Mutex::default(),
-            redo_process: RwLock::new(None),
+            redo_processes: RwLock::new(Vec::new()),
+            launching: std::sync::Mutex::new(0),
+            launched: Condvar::new(),
         }
     }
 
     /// This type doesn't have its own background task to check for idleness: we
     /// rely on our owner calling this function periodically in its own housekeeping
     /// loops.
+    ///
+    /// Shuts down the processes that haven't been used for `idle_timeout`, which
+    /// shrinks the pool back after a burst of load.
     pub(crate) fn maybe_quiesce(&self, idle_timeout: Duration) {
-        if let Ok(g) = self.last_redo_at.try_lock() {
-            if let Some(last_redo_at) = *g {
-                if last_redo_at.elapsed() >= idle_timeout {
-                    drop(g);
-                    let mut guard = self.redo_process.write().unwrap();
-                    *guard = None;
-                }
+        let any_idle = self
+            .redo_processes
+            .read()
+            .unwrap()
+            .iter()
+            .any(|p| p.is_idle_for(idle_timeout));
+        if !any_idle {
+            return;
+        }
+        let idle: Vec<_> = {
+            let mut pool = self.redo_processes.write().unwrap();
+            let (idle, busy) = std::mem::take(&mut *pool)
+                .into_iter()
+                .partition(|p| p.is_idle_for(idle_timeout));
+            *pool = busy;
+            idle
+        };
+        // Drop the processes outside the lock: that waits for them to exit.
+        drop(idle);
+    }
+
+    /// Pick a process from the pool for a request, launching a new one if all
+    /// the existing processes are busy and the pool isn't full yet.
+    ///
+    /// The process is launched without holding the pool lock, so that requests
+    /// can keep using the existing processes meanwhile.
+    fn get_or_launch_process(&self, pg_version: u32) -> anyhow::Result<InFlightRequest> {
+        let max_pool_size = self.conf.wal_redo_process_pool_size.get();
+        {
+            let pool = self.redo_processes.read().unwrap();
+            let launching = *self.launching.lock().unwrap();
+            if let Some(request) = pick_process(&pool, launching, max_pool_size) {
+                return Ok(request);
             }
         }
+        // Reserve a slot in the pool for the new process
+        loop {
+            let pool = self.redo_processes.write().unwrap();
+            let mut launching = self.launching.lock().unwrap();
+            if let Some(request) = pick_process(&pool, *launching, max_pool_size) {
+                return Ok(request);
+            }
+            if pool.len() + *launching < max_pool_size {
+                *launching += 1;
+                break;
+            }
+            // All the slots are taken by launches, there's no process to queue up
+            // behind yet. The launching ones notify while holding `launching`, so
+            // the notification can't be missed after releasing the pool lock.
+            drop(pool);
+            drop(self.launched.wait(launching).unwrap());
+        }
+
+        let start = Instant::now();
+        let launched = process::WalRedoProcess::launch(self.conf, self.tenant_shard_id, pg_version)
+            .context("launch walredo process");
+        let proc = match launched {
+            Ok(process) => Arc::new(PooledProcess::new(process)),
+            Err(e) => {
+                *self.launching.lock().unwrap() -= 1;
+                self.launched.notify_all();
+                return Err(e);
+            }
+        };
+        let duration = start.elapsed();
+        WAL_REDO_PROCESS_LAUNCH_DURATION_HISTOGRAM.observe(duration.as_secs_f64());
+        let request = proc.start_request();
+        let pool_size = {
+            let mut pool = self.redo_processes.write().unwrap();
+            pool.push(Arc::clone(&proc));
+            *self.launching.lock().unwrap() -= 1;
+            self.launched.notify_all();
+            pool.len()
+        };
+        info!(
+            duration_ms = duration.as_millis(),
+            pid = proc.process.id(),
+            pool_size,
+            "launched walredo process"
+        );
+        Ok(request)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn apply_batch(
+        &self,
+        batch: BatchKind,
+        key: Key,
+        lsn: Lsn,
+        base_img: Option<Bytes>,
+        base_img_lsn: Lsn,
+        records: &[(Lsn, NeonWalRecord)],
+        pg_version: u32,
+    ) -> anyhow::Result<Bytes> {
+        match batch {
+            BatchKind::Neon => self.apply_batch_neon(key, lsn, base_img, records),
+            BatchKind::Native => self.apply_batch_native(key, lsn, base_img, records, pg_version),
+            BatchKind::Postgres => self.apply_batch_postgres(
+                key,
+                lsn,
+                base_img,
+                base_img_lsn,
+                records,
+                self.conf.wal_redo_timeout,
+                pg_version,
+            ),
+        }
     }
 
     ///
@@ -191,49 +427,18 @@ impl PostgresRedoManager {
         const MAX_RETRY_ATTEMPTS: u32 = 1;
         let mut n_attempts = 0u32;
         loop {
-            // launch the WAL redo process on first use
-            let proc: Arc<process::WalRedoProcess> = {
-                let proc_guard = self.redo_process.read().unwrap();
-                match &*proc_guard {
-                    None => {
-                        // "upgrade" to write lock to launch the process
-                        drop(proc_guard);
-                        let mut proc_guard = self.redo_process.write().unwrap();
-                        match &*proc_guard {
-                            None => {
-                                let start = Instant::now();
-                                let proc = Arc::new(
-                                    process::WalRedoProcess::launch(
-                                        self.conf,
-                                        self.tenant_shard_id,
-                                        pg_version,
-                                    )
-                                    .context("launch walredo process")?,
-                                );
-                                let duration = start.elapsed();
-                                WAL_REDO_PROCESS_LAUNCH_DURATION_HISTOGRAM
-                                    .observe(duration.as_secs_f64());
-                                info!(
-                                    duration_ms = duration.as_millis(),
-                                    pid = proc.id(),
-                                    "launched walredo process"
-                                );
-                                *proc_guard = Some(Arc::clone(&proc));
-                                proc
-                            }
-                            Some(proc) => Arc::clone(proc),
-                        }
-                    }
-                    Some(proc) => Arc::clone(proc),
-                }
-            };
+            // launch a WAL redo process on first use, or when all of them are busy
+            let in_flight = self.get_or_launch_process(pg_version)?;
+            let proc: Arc<PooledProcess> = Arc::clone(&in_flight.process);
 
             let started_at = std::time::Instant::now();
 
             // Relational WAL records are applied using wal-redo-postgres
             let result = proc
+                .process
                 .apply_wal_records(rel, blknum, &base_img, records, wal_redo_timeout)
                 .context("apply_wal_records");
+            drop(in_flight);
 
             let duration = started_at.elapsed();
 
@@ -275,18 +480,10 @@ impl PostgresRedoManager {
                 // Avoid concurrent callers hitting the same issue.
                 // We can't prevent it from happening because we want to enable parallelism.
                 {
-                    let mut guard = self.redo_process.write().unwrap();
-                    match &*guard {
-                        Some(current_field_value) => {
-                            if Arc::ptr_eq(current_field_value, &proc) {
-                                // We're the first to observe an error from `proc`, it's our job to take it out of rotation.
-                                *guard = None;
-                            }
-                        }
-                        None => {
-                            // Another thread was faster to observe the error, and already took the process out of rotation.
-                        }
-                    }
+                    // If another thread was faster to observe the error, it already took the
+                    // process out of rotation, and this is a no-op.
+                    let mut guard = self.redo_processes.write().unwrap();
+                    guard.retain(|p| !Arc::ptr_eq(p, &proc));
                 }
                 // NB: there may still be other concurrent threads using `proc`.
                 // The last one will send SIGKILL when the underlying Arc reaches refcount 0.
@@ -352,6 +549,45 @@ impl PostgresRedoManager {
         Ok(page.freeze())
     }
 
+    ///
+    /// Process a batch of Postgres WAL records without the wal-redo postgres process.
+    ///
+    fn apply_batch_native(
+        &self,
+        key: Key,
+        lsn: Lsn,
+        base_img: Option<Bytes>,
+        records: &[(Lsn, NeonWalRecord)],
+        pg_version: u32,
+    ) -> anyhow::Result<Bytes> {
+        let start_time = Instant::now();
+
+        let mut page = BytesMut::new();
+        if let Some(fpi) = base_img {
+            page.extend_from_slice(&fpi[..]);
+        } else {
+            // The first record initializes the page, see request_redo
+            page.resize(BLCKSZ as usize, 0);
+        }
+
+        for (record_lsn, record) in records.iter() {
+            native::apply(key, &mut page, *record_lsn, record, pg_version)?;
+        }
+
+        let duration = start_time.elapsed();
+        WAL_REDO_TIME.observe(duration.as_secs_f64());
+        WAL_REDO_NATIVE_RECORD_COUNTER.inc_by(records.len() as u64);
+
+        debug!(
+            "natively applied {} WAL records in {} us to reconstruct page image at LSN {}",
+            records.len(),
+            duration.as_micros(),
+            lsn
+        );
+
+        Ok(page.freeze())
+    }
+
     fn apply_record_neon(
         &self,
         key: Key,
@@ -372,7 +608,9 @@ mod tests {
     use crate::{config::PageServerConf, walrecord::NeonWalRecord};
     use bytes::Bytes;
     use pageserver_api::shard::TenantShardId;
+    use std::num::NonZeroUsize;
     use std::str::FromStr;
+    use std::time::Duration;
     use tracing::Instrument;
     use utils::{id::TenantId, lsn::Lsn};
 
@@ -405,6 +643,123 @@ mod tests {
         assert_eq!(&expected, &*page);
     }
 
+    #[tokio::test]
+    async fn short_v14_redo_native() {
+        let expected = std::fs::read("test_data/short_v14_redo.page").unwrap();
+
+        let h = RedoHarness::with_native_wal_redo(true).unwrap();
+
+        // The first record is replayed natively, the second one is not supported and
+        // needs to be passed on to the walredo process.
+        let records = short_records();
+        let key = Key {
+            field1: 0,
+            field2: 1663,
+            field3: 13010,
+            field4: 1259,
+            field5: 0,
+            field6: 0,
+        };
+        assert!(super::native::can_apply(key, &records[0].1, 14));
+        assert!(!super::native::can_apply(key, &records[1].1, 14));
+
+        let page = h
+            .manager
+            .request_redo(key, Lsn::from_str("0/16E2408").unwrap(), None, records, 14)
+            .instrument(h.span())
+            .await
+            .unwrap();
+
+        assert_eq!(&expected, &*page);
+    }
+
+    #[tokio::test]
+    async fn idle_processes_are_shut_down() {
+        let h = RedoHarness::new().unwrap();
+
+        h.manager
+            .request_redo(
+                Key {
+                    field1: 0,
+                    field2: 1663,
+                    field3: 13010,
+                    field4: 1259,
+                    field5: 0,
+                    field6: 0,
+                },
+                Lsn::from_str("0/16E2408").unwrap(),
+                None,
+                short_records(),
+                14,
+            )
+            .instrument(h.span())
+            .await
+            .unwrap();
+
+        let status = h.manager.status().unwrap();
+        assert_eq!(status.pool_size, 1);
+        assert_eq!(status.queue_depth, 0);
+
+        // Not idle for long enough yet
+        h.manager.maybe_quiesce(Duration::from_secs(3600));
+        assert_eq!(h.manager.status().unwrap().pool_size, 1);
+
+        h.manager.maybe_quiesce(Duration::ZERO);
+        let status = h.manager.status().unwrap();
+        assert_eq!(status.pool_size, 0);
+        assert_eq!(status.pid, None);
+    }
+
+    #[tokio::test]
+    async fn pool_scales_up_under_concurrent_requests() {
+        const POOL_SIZE: usize = 3;
+        const CONCURRENCY: usize = 8;
+        let h = RedoHarness::with_pool_size(POOL_SIZE).unwrap();
+        let expected = std::fs::read("test_data/short_v14_redo.page").unwrap();
+
+        // The redo itself is synchronous, so use threads to make the requests
+        // concurrent, and start them all at once to make them launch processes
+        // at the same time.
+        let runtime = tokio::runtime::Handle::current();
+        let barrier = std::sync::Barrier::new(CONCURRENCY);
+        std::thread::scope(|s| {
+            for _ in 0..CONCURRENCY {
+                s.spawn(|| {
+                    barrier.wait();
+                    for _ in 0..5 {
+                        let page = runtime
+                            .block_on(
+                                h.manager
+                                    .request_redo(
+                                        Key {
+                                            field1: 0,
+                                            field2: 1663,
+                                            field3: 13010,
+                                            field4: 1259,
+                                            field5: 0,
+                                            field6: 0,
+                                        },
+                                        Lsn::from_str("0/16E2408").unwrap(),
+                                        None,
+                                        short_records(),
+                                        14,
+                                    )
+                                    .instrument(h.span()),
+                            )
+                            .unwrap();
+                        assert_eq!(&expected, &*page);
+                    }
+                });
+            }
+        });
+
+        let status = h.manager.status().unwrap();
+        assert!(status.pool_size > 1, "{}", status.pool_size);
+        assert!(status.pool_size <= POOL_SIZE, "{}", status.pool_size);
+        assert_eq!(status.queue_depth, 0);
+        assert_eq!(*h.manager.launching.lock().unwrap(), 0);
+    }
+
     #[tokio::test]
     async fn short_v14_fails_for_wrong_key_but_returns_zero_page() {
         let h = RedoHarness::new().unwrap();
@@ -481,10 +836,22 @@ mod tests {
 
     impl RedoHarness {
         fn new() -> anyhow::Result<Self> {
+            Self::with_native_wal_redo(false)
+        }
+        fn with_native_wal_redo(native_wal_redo: bool) -> anyhow::Result<Self> {
+            Self::with_conf(|conf| conf.native_wal_redo = native_wal_redo)
+        }
+        fn with_pool_size(pool_size: usize) -> anyhow::Result<Self> {
+            Self::with_conf(|conf| {
+                conf.wal_redo_process_pool_size = NonZeroUsize::new(pool_size).unwrap()
+            })
+        }
+        fn with_conf(adjust: impl FnOnce(&mut PageServerConf)) -> anyhow::Result<Self> {
             crate::tenant::harness::setup_logging();
 
             let repo_dir = camino_tempfile::tempdir()?;
-            let conf = PageServerConf::dummy_conf(repo_dir.path().to_path_buf());
+            let mut conf = PageServerConf::dummy_conf(repo_dir.path().to_path_buf());
+            adjust(&mut conf);
             let conf = Box::leak(Box::new(conf));
             let tenant_shard_id = TenantShardId::unsharded(TenantId::generate());
 
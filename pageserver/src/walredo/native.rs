@@ -0,0 +1,1099 @@
+//! Replay of the most common heap and btree WAL records without the walredo process.
+//!
+//! The functions here are ports of the corresponding Postgres redo routines, as
+//! modified in the Neon fork: in PostgreSQL 14 and 15 the heap records carry the
+//! command id of the tuples they modify. Like the walredo process, we only replay
+//! the changes to the one block that is being reconstructed, and skip the rest.
+//!
+//! Records that we don't recognize, or whose full-page image is compressed, are
+//! left to the walredo process: see [`can_apply`].
+
+use anyhow::{bail, ensure, Context};
+use bytes::{Buf, Bytes, BytesMut};
+use pageserver_api::key::{key_to_rel_block, Key};
+use postgres_ffi::pg_constants;
+use postgres_ffi::{page_get_lsn, page_is_new, page_set_lsn, BLCKSZ};
+use utils::lsn::Lsn;
+
+use crate::walrecord::{self, decode_wal_record, DecodedWALRecord, NeonWalRecord};
+
+/// Can this record be replayed by [`apply`] when reconstructing the page at `key`?
+pub(crate) fn can_apply(key: Key, record: &NeonWalRecord, pg_version: u32) -> bool {
+    matches!(parse(key, record, pg_version), Ok(Some(_)))
+}
+
+/// Replay a record that [`can_apply`] accepted on top of `page`.
+pub(crate) fn apply(
+    key: Key,
+    page: &mut BytesMut,
+    lsn: Lsn,
+    record: &NeonWalRecord,
+    pg_version: u32,
+) -> anyhow::Result<()> {
+    let Some(redo) = parse(key, record, pg_version)? else {
+        bail!("cannot replay WAL record natively: {record:?}");
+    };
+    ensure!(
+        page.len() == BLCKSZ as usize,
+        "invalid page size {}",
+        page.len()
+    );
+    redo.apply(page, lsn)
+}
+
+/// A decoded WAL record that we know how to replay against the block at index
+/// `block` of the record.
+struct Redo {
+    decoded: DecodedWALRecord,
+    block: usize,
+    op: Op,
+}
+
+enum Op {
+    /// The record carries a full-page image of the block, which is all there
+    /// is to replay
+    RestoreImage,
+    HeapInsert {
+        init_page: bool,
+        offnum: u16,
+        flags: u8,
+        cid_in_record: bool,
+    },
+    HeapDelete {
+        xmax: u32,
+        offnum: u16,
+        infobits_set: u8,
+        flags: u8,
+        t_cid: u32,
+    },
+    HeapUpdate {
+        hot_update: bool,
+        init_page: bool,
+        old_xmax: u32,
+        old_offnum: u16,
+        old_infobits_set: u8,
+        flags: u8,
+        t_cid: u32,
+        new_xmax: u32,
+        new_offnum: u16,
+        cid_in_record: bool,
+    },
+    BtreeInsertLeaf {
+        offnum: u16,
+    },
+}
+
+/// FirstCommandId, which vanilla Postgres sets on the tuples it replays
+const FIRST_COMMAND_ID: u32 = 0;
+
+/// Returns `None` for the records we can't replay.
+fn parse(key: Key, record: &NeonWalRecord, pg_version: u32) -> anyhow::Result<Option<Redo>> {
+    let NeonWalRecord::Postgres { rec, .. } = record else {
+        return Ok(None);
+    };
+    let Ok((rel, blknum)) = key_to_rel_block(key) else {
+        return Ok(None);
+    };
+
+    let mut decoded = DecodedWALRecord::default();
+    decode_wal_record(rec.clone(), &mut decoded, pg_version)?;
+
+    let Some(block) = decoded.blocks.iter().position(|blk| {
+        blk.rnode_spcnode == rel.spcnode
+            && blk.rnode_dbnode == rel.dbnode
+            && blk.rnode_relnode == rel.relnode
+            && blk.forknum == rel.forknum
+            && blk.blkno == blknum
+    }) else {
+        return Ok(None);
+    };
+
+    let mut main_data = decoded.record.slice(decoded.main_data_offset..);
+    let info = decoded.xl_info & pg_constants::XLR_RMGR_INFO_MASK;
+    let op = match (pg_version, decoded.xl_rmid) {
+        // PostgreSQL 16 logs Neon's flavor of the heap records under RM_NEON_ID,
+        // which we don't replay here yet, and uses the vanilla RM_HEAP_ID records
+        // otherwise.
+        (14..=16, pg_constants::RM_HEAP_ID) => {
+            let cid_in_record = pg_version < 16;
+            let init_page = info & pg_constants::XLOG_HEAP_INIT_PAGE != 0;
+            match info & pg_constants::XLOG_HEAP_OPMASK {
+                pg_constants::XLOG_HEAP_INSERT => {
+                    ensure_len(&main_data, 3)?;
+                    let xlrec = walrecord::v14::XlHeapInsert::decode(&mut main_data);
+                    if xlrec.flags & pg_constants::XLH_INSERT_ALL_FROZEN_SET != 0 {
+                        return Ok(None);
+                    }
+                    Op::HeapInsert {
+                        init_page,
+                        offnum: xlrec.offnum,
+                        flags: xlrec.flags,
+                        cid_in_record,
+                    }
+                }
+                pg_constants::XLOG_HEAP_DELETE if cid_in_record => {
+                    ensure_len(&main_data, 14)?;
+                    let xlrec = walrecord::v14::XlHeapDelete::decode(&mut main_data);
+                    Op::HeapDelete {
+                        xmax: xlrec.xmax,
+                        offnum: xlrec.offnum,
+                        infobits_set: xlrec.infobits_set,
+                        flags: xlrec.flags,
+                        t_cid: xlrec.t_cid,
+                    }
+                }
+                pg_constants::XLOG_HEAP_DELETE => {
+                    ensure_len(&main_data, 8)?;
+                    let xlrec = walrecord::v16::XlHeapDelete::decode(&mut main_data);
+                    Op::HeapDelete {
+                        xmax: xlrec.xmax,
+                        offnum: xlrec.offnum,
+                        infobits_set: xlrec.infobits_set,
+                        flags: xlrec.flags,
+                        t_cid: FIRST_COMMAND_ID,
+                    }
+                }
+                info @ (pg_constants::XLOG_HEAP_UPDATE | pg_constants::XLOG_HEAP_HOT_UPDATE)
+                    if cid_in_record =>
+                {
+                    ensure_len(&main_data, 18)?;
+                    let xlrec = walrecord::v14::XlHeapUpdate::decode(&mut main_data);
+                    Op::HeapUpdate {
+                        hot_update: info == pg_constants::XLOG_HEAP_HOT_UPDATE,
+                        init_page,
+                        old_xmax: xlrec.old_xmax,
+                        old_offnum: xlrec.old_offnum,
+                        old_infobits_set: xlrec.old_infobits_set,
+                        flags: xlrec.flags,
+                        t_cid: xlrec.t_cid,
+                        new_xmax: xlrec.new_xmax,
+                        new_offnum: xlrec.new_offnum,
+                        cid_in_record,
+                    }
+                }
+                info @ (pg_constants::XLOG_HEAP_UPDATE | pg_constants::XLOG_HEAP_HOT_UPDATE) => {
+                    ensure_len(&main_data, 14)?;
+                    let xlrec = walrecord::v16::XlHeapUpdate::decode(&mut main_data);
+                    Op::HeapUpdate {
+                        hot_update: info == pg_constants::XLOG_HEAP_HOT_UPDATE,
+                        init_page,
+                        old_xmax: xlrec.old_xmax,
+                        old_offnum: xlrec.old_offnum,
+                        old_infobits_set: xlrec.old_infobits_set,
+                        flags: xlrec.flags,
+                        t_cid: FIRST_COMMAND_ID,
+                        new_xmax: xlrec.new_xmax,
+                        new_offnum: xlrec.new_offnum,
+                        cid_in_record,
+                    }
+                }
+                _ => return Ok(None),
+            }
+        }
+        (14..=16, pg_constants::RM_BTREE_ID) if info == pg_constants::XLOG_BTREE_INSERT_LEAF => {
+            ensure_len(&main_data, 2)?;
+            Op::BtreeInsertLeaf {
+                offnum: main_data.get_u16_le(),
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    // For the records above, a full-page image replaces all other changes to the
+    // block (see XLogReadBufferForRedo).
+    let blk = &decoded.blocks[block];
+    let op = if blk.apply_image {
+        if postgres_ffi::bkpimage_is_compressed(blk.bimg_info, pg_version)? {
+            return Ok(None);
+        }
+        Op::RestoreImage
+    } else {
+        op
+    };
+
+    Ok(Some(Redo { decoded, block, op }))
+}
+
+fn ensure_len(buf: &Bytes, len: usize) -> anyhow::Result<()> {
+    ensure!(
+        buf.remaining() >= len,
+        "WAL record too short: {} bytes, expected {len}",
+        buf.remaining()
+    );
+    Ok(())
+}
+
+impl Redo {
+    fn apply(&self, page: &mut BytesMut, lsn: Lsn) -> anyhow::Result<()> {
+        let xid = self.decoded.xl_xid;
+        let blkno = self.decoded.blocks[self.block].blkno;
+        match self.op {
+            Op::RestoreImage => self.restore_image(page, lsn),
+            Op::HeapInsert {
+                init_page,
+                offnum,
+                flags,
+                cid_in_record,
+            } => {
+                if init_page {
+                    page_init(page);
+                } else if !needs_redo(page, lsn) {
+                    return Ok(());
+                }
+
+                let mut data = self.block_data()?;
+                let xlhdr = HeapHeader::decode(&mut data, cid_in_record)?;
+                ensure!(
+                    page_max_offset(page) + 1 >= offnum,
+                    "invalid max offset number"
+                );
+
+                let mut tuple = vec![0u8; pg_constants::SIZEOF_HEAP_TUPLE_HEADER];
+                tuple.extend_from_slice(&data);
+                let mut htup = HeapTupleHeader(&mut tuple);
+                htup.set_infomask2(xlhdr.t_infomask2);
+                htup.set_infomask(xlhdr.t_infomask);
+                htup.set_hoff(xlhdr.t_hoff);
+                htup.set_xmin(xid);
+                htup.set_cid(xlhdr.t_cid, false);
+                htup.set_ctid(blkno, offnum);
+
+                page_add_item(page, &tuple, offnum, true, true).context("failed to add tuple")?;
+                page_set_lsn(page, lsn);
+                if flags & pg_constants::XLH_INSERT_ALL_VISIBLE_CLEARED != 0 {
+                    page_clear_all_visible(page);
+                }
+                Ok(())
+            }
+            Op::HeapDelete {
+                xmax,
+                offnum,
+                infobits_set,
+                flags,
+                t_cid,
+            } => {
+                if !needs_redo(page, lsn) {
+                    return Ok(());
+                }
+
+                let mut htup = HeapTupleHeader(page_get_normal_item(page, offnum)?);
+                htup.set_infomask(
+                    htup.infomask() & !(pg_constants::HEAP_XMAX_BITS | pg_constants::HEAP_MOVED),
+                );
+                htup.set_infomask2(
+                    htup.infomask2()
+                        & !(pg_constants::HEAP_KEYS_UPDATED | pg_constants::HEAP_HOT_UPDATED),
+                );
+                htup.fix_infomask_from_infobits(infobits_set);
+                if flags & pg_constants::XLH_DELETE_IS_SUPER == 0 {
+                    htup.set_xmax(xmax);
+                } else {
+                    htup.set_xmin(pg_constants::INVALID_TRANSACTION_ID);
+                }
+                htup.set_cid(t_cid, false);
+                if flags & pg_constants::XLH_DELETE_IS_PARTITION_MOVE != 0 {
+                    // ItemPointerSetMovedPartitions
+                    htup.set_ctid(u32::MAX, 0xfffd);
+                } else {
+                    htup.set_ctid(blkno, offnum);
+                }
+
+                page_set_prunable(page, xid);
+                if flags & pg_constants::XLH_DELETE_ALL_VISIBLE_CLEARED != 0 {
+                    page_clear_all_visible(page);
+                }
+                page_set_lsn(page, lsn);
+                Ok(())
+            }
+            Op::HeapUpdate {
+                hot_update,
+                init_page,
+                old_xmax,
+                old_offnum,
+                old_infobits_set,
+                flags,
+                t_cid,
+                new_xmax,
+                new_offnum,
+                cid_in_record,
+            } => {
+                // Block 0 is the new tuple's, block 1 the old tuple's if it's on a different page
+                let same_block = self.decoded.blocks.len() == 1;
+                let new_blkno = self.decoded.blocks[0].blkno;
+                if self.block == 0 && !same_block && init_page {
+                    page_init(page);
+                } else if !needs_redo(page, lsn) {
+                    return Ok(());
+                }
+
+                let mut old_tuple = None;
+                if self.block == 1 || same_block {
+                    let item = page_get_normal_item(page, old_offnum)?;
+                    let mut htup = HeapTupleHeader(item);
+                    htup.set_infomask(
+                        htup.infomask()
+                            & !(pg_constants::HEAP_XMAX_BITS | pg_constants::HEAP_MOVED),
+                    );
+                    let mut infomask2 = htup.infomask2() & !pg_constants::HEAP_KEYS_UPDATED;
+                    if hot_update {
+                        infomask2 |= pg_constants::HEAP_HOT_UPDATED;
+                    } else {
+                        infomask2 &= !pg_constants::HEAP_HOT_UPDATED;
+                    }
+                    htup.set_infomask2(infomask2);
+                    htup.fix_infomask_from_infobits(old_infobits_set);
+                    htup.set_xmax(old_xmax);
+                    htup.set_cid(t_cid, false);
+                    // Set forward chain link in t_ctid
+                    htup.set_ctid(new_blkno, new_offnum);
+                    if same_block {
+                        old_tuple = Some(htup.0.to_vec());
+                    }
+
+                    page_set_prunable(page, xid);
+                    if flags & pg_constants::XLH_UPDATE_OLD_ALL_VISIBLE_CLEARED != 0 {
+                        page_clear_all_visible(page);
+                    }
+                    page_set_lsn(page, lsn);
+                }
+
+                if self.block == 0 {
+                    let mut data = self.block_data()?;
+                    ensure!(
+                        page_max_offset(page) + 1 >= new_offnum,
+                        "invalid max offset number"
+                    );
+
+                    let mut prefixlen = 0;
+                    let mut suffixlen = 0;
+                    if flags & pg_constants::XLH_UPDATE_PREFIX_FROM_OLD != 0 {
+                        ensure_len(&data, 2)?;
+                        prefixlen = data.get_u16_le() as usize;
+                    }
+                    if flags & pg_constants::XLH_UPDATE_SUFFIX_FROM_OLD != 0 {
+                        ensure_len(&data, 2)?;
+                        suffixlen = data.get_u16_le() as usize;
+                    }
+                    let xlhdr = HeapHeader::decode(&mut data, cid_in_record)?;
+
+                    // Reconstruct the new tuple using the prefix and/or suffix from the
+                    // old tuple, and the data stored in the WAL record.
+                    let mut tuple = vec![0u8; pg_constants::SIZEOF_HEAP_TUPLE_HEADER];
+                    if prefixlen > 0 || suffixlen > 0 {
+                        let Some(old_tuple) = old_tuple.as_deref() else {
+                            bail!("update record copies from an old tuple on another page");
+                        };
+                        let old_data = &old_tuple[HeapTupleHeader::hoff_of(old_tuple)?..];
+                        ensure!(
+                            prefixlen + suffixlen <= old_data.len(),
+                            "prefix and suffix don't fit in old tuple"
+                        );
+                        let bitmap_len = (xlhdr.t_hoff as usize)
+                            .checked_sub(pg_constants::SIZEOF_HEAP_TUPLE_HEADER)
+                            .filter(|len| *len <= data.len())
+                            .context("invalid t_hoff")?;
+                        if prefixlen > 0 {
+                            // bitmap [+ padding] [+ oid] from the WAL record, then the
+                            // prefix from the old tuple
+                            tuple.extend_from_slice(&data[..bitmap_len]);
+                            tuple.extend_from_slice(&old_data[..prefixlen]);
+                            tuple.extend_from_slice(&data[bitmap_len..]);
+                        } else {
+                            tuple.extend_from_slice(&data);
+                        }
+                        tuple.extend_from_slice(&old_data[old_data.len() - suffixlen..]);
+                    } else {
+                        tuple.extend_from_slice(&data);
+                    }
+
+                    let mut htup = HeapTupleHeader(&mut tuple);
+                    htup.set_infomask2(xlhdr.t_infomask2);
+                    htup.set_infomask(xlhdr.t_infomask);
+                    htup.set_hoff(xlhdr.t_hoff);
+                    htup.set_xmin(xid);
+                    htup.set_cid(xlhdr.t_cid, false);
+                    htup.set_xmax(new_xmax);
+                    // Make sure there is no forward chain link in t_ctid
+                    htup.set_ctid(new_blkno, new_offnum);
+
+                    page_add_item(page, &tuple, new_offnum, true, true)
+                        .context("failed to add tuple")?;
+                    if flags & pg_constants::XLH_UPDATE_NEW_ALL_VISIBLE_CLEARED != 0 {
+                        page_clear_all_visible(page);
+                    }
+                    page_set_lsn(page, lsn);
+                }
+                Ok(())
+            }
+            Op::BtreeInsertLeaf { offnum } => {
+                if !needs_redo(page, lsn) {
+                    return Ok(());
+                }
+
+                let data = self.block_data()?;
+                page_add_item(page, &data, offnum, false, false)
+                    .context("failed to add new item")?;
+                page_set_lsn(page, lsn);
+                Ok(())
+            }
+        }
+    }
+
+    /// Port of RestoreBlockImage, and of the LSN update XLogReadBufferForRedo does after it
+    fn restore_image(&self, page: &mut BytesMut, lsn: Lsn) -> anyhow::Result<()> {
+        let blk = &self.decoded.blocks[self.block];
+        let img_offs = blk.bimg_offset as usize;
+        let img_len = blk.bimg_len as usize;
+        ensure!(
+            img_offs + img_len <= self.decoded.record.len()
+                && img_len + blk.hole_length as usize == BLCKSZ as usize
+                && blk.hole_offset as usize <= img_len,
+            "invalid full-page image"
+        );
+
+        page.clear();
+        page.extend_from_slice(&self.decoded.record[img_offs..img_offs + img_len]);
+        if blk.hole_length != 0 {
+            let tail = page.split_off(blk.hole_offset as usize);
+            page.resize(page.len() + blk.hole_length as usize, 0u8);
+            page.unsplit(tail);
+        }
+        if !page_is_new(page) {
+            page_set_lsn(page, lsn);
+        }
+        Ok(())
+    }
+
+    fn block_data(&self) -> anyhow::Result<Bytes> {
+        let blk = &self.decoded.blocks[self.block];
+        ensure!(blk.has_data, "WAL record has no data for the block");
+        let offset = blk.data_offset as usize;
+        let len = blk.data_len as usize;
+        ensure!(
+            offset + len <= self.decoded.record.len(),
+            "invalid block data"
+        );
+        Ok(self.decoded.record.slice(offset..offset + len))
+    }
+}
+
+/// xl_heap_header, which prefixes the tuple data in the insert and update records.
+/// The Neon fork of PostgreSQL 14 and 15 adds the command id to it.
+struct HeapHeader {
+    t_infomask2: u16,
+    t_infomask: u16,
+    t_cid: u32,
+    t_hoff: u8,
+}
+
+impl HeapHeader {
+    fn decode(buf: &mut Bytes, cid_in_record: bool) -> anyhow::Result<HeapHeader> {
+        ensure_len(buf, if cid_in_record { 9 } else { 5 })?;
+        Ok(HeapHeader {
+            t_infomask2: buf.get_u16_le(),
+            t_infomask: buf.get_u16_le(),
+            t_cid: if cid_in_record {
+                buf.get_u32_le()
+            } else {
+                FIRST_COMMAND_ID
+            },
+            t_hoff: buf.get_u8(),
+        })
+    }
+}
+
+/// Accessors for the fields of a HeapTupleHeaderData at the start of a tuple
+struct HeapTupleHeader<'a>(&'a mut [u8]);
+
+impl HeapTupleHeader<'_> {
+    const XMIN: usize = 0;
+    const XMAX: usize = 4;
+    const CID: usize = 8;
+    const CTID: usize = 12;
+    const INFOMASK2: usize = 18;
+    const INFOMASK: usize = 20;
+    const HOFF: usize = 22;
+
+    /// The `t_hoff` of `tuple`, checked to be within it.
+    fn hoff_of(tuple: &[u8]) -> anyhow::Result<usize> {
+        let hoff = *tuple.get(Self::HOFF).context("tuple too short")? as usize;
+        ensure!(
+            hoff >= pg_constants::SIZEOF_HEAP_TUPLE_HEADER && hoff <= tuple.len(),
+            "invalid t_hoff {hoff} in tuple of {} bytes",
+            tuple.len()
+        );
+        Ok(hoff)
+    }
+
+    fn infomask(&self) -> u16 {
+        get_u16(self.0, Self::INFOMASK)
+    }
+
+    fn infomask2(&self) -> u16 {
+        get_u16(self.0, Self::INFOMASK2)
+    }
+
+    fn set_infomask(&mut self, infomask: u16) {
+        set_u16(self.0, Self::INFOMASK, infomask)
+    }
+
+    fn set_infomask2(&mut self, infomask2: u16) {
+        set_u16(self.0, Self::INFOMASK2, infomask2)
+    }
+
+    fn set_hoff(&mut self, hoff: u8) {
+        self.0[Self::HOFF] = hoff;
+    }
+
+    fn set_xmin(&mut self, xid: u32) {
+        set_u32(self.0, Self::XMIN, xid)
+    }
+
+    fn set_xmax(&mut self, xid: u32) {
+        set_u32(self.0, Self::XMAX, xid)
+    }
+
+    /// HeapTupleHeaderSetCmin, and HeapTupleHeaderSetCmax
+    fn set_cid(&mut self, cid: u32, is_combo: bool) {
+        set_u32(self.0, Self::CID, cid);
+        if is_combo {
+            self.set_infomask(self.infomask() | pg_constants::HEAP_COMBOCID);
+        } else {
+            self.set_infomask(self.infomask() & !pg_constants::HEAP_COMBOCID);
+        }
+    }
+
+    fn set_ctid(&mut self, blkno: u32, offnum: u16) {
+        set_u16(self.0, Self::CTID, (blkno >> 16) as u16);
+        set_u16(self.0, Self::CTID + 2, blkno as u16);
+        set_u16(self.0, Self::CTID + 4, offnum);
+    }
+
+    /// Port of fix_infomask_from_infobits
+    fn fix_infomask_from_infobits(&mut self, infobits: u8) {
+        let mut infomask = self.infomask()
+            & !(pg_constants::HEAP_XMAX_IS_MULTI
+                | pg_constants::HEAP_XMAX_LOCK_ONLY
+                | pg_constants::HEAP_XMAX_KEYSHR_LOCK
+                | pg_constants::HEAP_XMAX_EXCL_LOCK);
+        let mut infomask2 = self.infomask2() & !pg_constants::HEAP_KEYS_UPDATED;
+
+        if infobits & pg_constants::XLHL_XMAX_IS_MULTI != 0 {
+            infomask |= pg_constants::HEAP_XMAX_IS_MULTI;
+        }
+        if infobits & pg_constants::XLHL_XMAX_LOCK_ONLY != 0 {
+            infomask |= pg_constants::HEAP_XMAX_LOCK_ONLY;
+        }
+        if infobits & pg_constants::XLHL_XMAX_EXCL_LOCK != 0 {
+            infomask |= pg_constants::HEAP_XMAX_EXCL_LOCK;
+        }
+        // note HEAP_XMAX_SHR_LOCK isn't considered here
+        if infobits & pg_constants::XLHL_XMAX_KEYSHR_LOCK != 0 {
+            infomask |= pg_constants::HEAP_XMAX_KEYSHR_LOCK;
+        }
+        if infobits & pg_constants::XLHL_KEYS_UPDATED != 0 {
+            infomask2 |= pg_constants::HEAP_KEYS_UPDATED;
+        }
+
+        self.set_infomask(infomask);
+        self.set_infomask2(infomask2);
+    }
+}
+
+//
+// Page layout, from bufpage.h and itemid.h
+//
+const PD_FLAGS: usize = 10;
+const PD_LOWER: usize = 12;
+const PD_UPPER: usize = 14;
+const PD_SPECIAL: usize = 16;
+const PD_PAGESIZE_VERSION: usize = 18;
+const PD_PRUNE_XID: usize = 20;
+const SIZE_OF_PAGE_HEADER_DATA: usize = pg_constants::SIZE_OF_PAGE_HEADER as usize;
+const SIZE_OF_ITEM_ID: usize = 4;
+const MAX_OFFSET_NUMBER: u16 = BLCKSZ / SIZE_OF_ITEM_ID as u16;
+
+fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn set_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn set_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The check XLogReadBufferForRedo does: has the page already seen this record?
+fn needs_redo(page: &[u8], lsn: Lsn) -> bool {
+    lsn > page_get_lsn(page)
+}
+
+/// Port of PageInit, for a page without special space
+fn page_init(page: &mut [u8]) {
+    page.fill(0);
+    set_u16(page, PD_LOWER, SIZE_OF_PAGE_HEADER_DATA as u16);
+    set_u16(page, PD_UPPER, BLCKSZ);
+    set_u16(page, PD_SPECIAL, BLCKSZ);
+    set_u16(
+        page,
+        PD_PAGESIZE_VERSION,
+        BLCKSZ | pg_constants::PG_PAGE_LAYOUT_VERSION,
+    );
+}
+
+fn page_max_offset(page: &[u8]) -> u16 {
+    let lower = get_u16(page, PD_LOWER) as usize;
+    (lower.saturating_sub(SIZE_OF_PAGE_HEADER_DATA) / SIZE_OF_ITEM_ID) as u16
+}
+
+/// Returns lp_off, lp_flags and lp_len of the line pointer `offnum`
+fn page_get_item_id(page: &[u8], offnum: u16) -> (usize, u8, usize) {
+    let lp = get_u32(
+        page,
+        SIZE_OF_PAGE_HEADER_DATA + (offnum as usize - 1) * SIZE_OF_ITEM_ID,
+    );
+    (
+        (lp & 0x7fff) as usize,
+        ((lp >> 15) & 0x03) as u8,
+        (lp >> 17) as usize,
+    )
+}
+
+fn page_get_normal_item(page: &mut [u8], offnum: u16) -> anyhow::Result<&mut [u8]> {
+    ensure!(
+        offnum >= 1 && offnum <= page_max_offset(page),
+        "invalid lp: offset {offnum} out of range"
+    );
+    let (lp_off, lp_flags, lp_len) = page_get_item_id(page, offnum);
+    ensure!(
+        lp_flags == pg_constants::LP_NORMAL
+            && lp_len >= pg_constants::SIZEOF_HEAP_TUPLE_HEADER
+            && lp_off + lp_len <= page.len(),
+        "invalid lp: offset {offnum} is not a normal item"
+    );
+    Ok(&mut page[lp_off..lp_off + lp_len])
+}
+
+/// Port of PageAddItemExtended, for a caller-chosen offset number
+fn page_add_item(
+    page: &mut [u8],
+    item: &[u8],
+    offnum: u16,
+    overwrite: bool,
+    is_heap: bool,
+) -> anyhow::Result<()> {
+    let lower = get_u16(page, PD_LOWER) as usize;
+    let upper = get_u16(page, PD_UPPER) as usize;
+    let special = get_u16(page, PD_SPECIAL) as usize;
+    ensure!(
+        lower >= SIZE_OF_PAGE_HEADER_DATA
+            && lower <= upper
+            && upper <= special
+            && special <= BLCKSZ as usize,
+        "corrupted page pointers: lower = {lower}, upper = {upper}, special = {special}"
+    );
+
+    ensure!(
+        offnum >= 1 && offnum <= MAX_OFFSET_NUMBER,
+        "invalid item offset {offnum}"
+    );
+    let limit = page_max_offset(page) + 1;
+    let mut needshuffle = false;
+    if overwrite {
+        if offnum < limit {
+            let (_, lp_flags, lp_len) = page_get_item_id(page, offnum);
+            ensure!(
+                lp_flags == pg_constants::LP_UNUSED && lp_len == 0,
+                "will not overwrite a used ItemId"
+            );
+        }
+    } else if offnum < limit {
+        needshuffle = true;
+    }
+    ensure!(offnum <= limit, "specified item offset is too large");
+    ensure!(
+        !is_heap || offnum <= pg_constants::MAX_HEAP_TUPLES_PER_PAGE,
+        "can't put more than MaxHeapTuplesPerPage items in a heap page"
+    );
+
+    let new_lower = if offnum == limit || needshuffle {
+        lower + SIZE_OF_ITEM_ID
+    } else {
+        lower
+    };
+    let aligned_size = (item.len() + 7) & !7;
+    ensure!(
+        aligned_size <= upper && new_lower <= upper - aligned_size,
+        "not enough free space on page for {} bytes",
+        item.len()
+    );
+    let new_upper = upper - aligned_size;
+
+    let lp_offset = SIZE_OF_PAGE_HEADER_DATA + (offnum as usize - 1) * SIZE_OF_ITEM_ID;
+    if needshuffle {
+        page.copy_within(lp_offset..lower, lp_offset + SIZE_OF_ITEM_ID);
+    }
+    let lp = new_upper as u32 | (pg_constants::LP_NORMAL as u32) << 15 | (item.len() as u32) << 17;
+    set_u32(page, lp_offset, lp);
+    page[new_upper..new_upper + item.len()].copy_from_slice(item);
+    set_u16(page, PD_LOWER, new_lower as u16);
+    set_u16(page, PD_UPPER, new_upper as u16);
+    Ok(())
+}
+
+/// Port of PageSetPrunable
+fn page_set_prunable(page: &mut [u8], xid: u32) {
+    let prune_xid = get_u32(page, PD_PRUNE_XID);
+    if prune_xid == pg_constants::INVALID_TRANSACTION_ID
+        || postgres_ffi::transaction_id_precedes(xid, prune_xid)
+    {
+        set_u32(page, PD_PRUNE_XID, xid);
+    }
+}
+
+fn page_clear_all_visible(page: &mut [u8]) {
+    let flags = get_u16(page, PD_FLAGS) & !pg_constants::PD_ALL_VISIBLE;
+    set_u16(page, PD_FLAGS, flags);
+}
+
+#[cfg(test)]
+mod tests {
+    //! Each test replays synthesized PostgreSQL 14 records both with [`apply`] and
+    //! with the walredo process, and expects the same page from both.
+
+    use super::*;
+    use crate::config::PageServerConf;
+    use crate::walredo::PostgresRedoManager;
+    use bytes::BufMut;
+    use pageserver_api::shard::TenantShardId;
+    use tracing::Instrument;
+    use utils::id::TenantId;
+
+    const PG_VERSION: u32 = 14;
+    const SPCNODE: u32 = 1663;
+    const DBNODE: u32 = 13010;
+    const RELNODE: u32 = 16384;
+    const XID: u32 = 1000;
+    /// MAXALIGN(SizeofHeapTupleHeader), for tuples without nulls
+    const T_HOFF: u8 = 24;
+    const HEAP_ONLY_TUPLE: u16 = 0x8000;
+    const SIZE_OF_XLOG_RECORD: usize = 24;
+
+    fn key(blkno: u32) -> Key {
+        Key {
+            field1: 0,
+            field2: SPCNODE,
+            field3: DBNODE,
+            field4: RELNODE,
+            field5: 0,
+            field6: blkno,
+        }
+    }
+
+    fn lsn(n: u64) -> Lsn {
+        Lsn(0x0100_0000 + n * 0x100)
+    }
+
+    struct Block {
+        blkno: u32,
+        will_init: bool,
+        data: Vec<u8>,
+    }
+
+    /// Assembles a record referencing `blocks` of the main fork of the test relation.
+    fn record(rmid: u8, info: u8, blocks: &[Block], main_data: &[u8]) -> Bytes {
+        let mut body = Vec::new();
+        for (block_id, blk) in blocks.iter().enumerate() {
+            let mut fork_flags = 0;
+            if !blk.data.is_empty() {
+                fork_flags |= pg_constants::BKPBLOCK_HAS_DATA;
+            }
+            if blk.will_init {
+                fork_flags |= pg_constants::BKPBLOCK_WILL_INIT;
+            }
+            if block_id > 0 {
+                fork_flags |= pg_constants::BKPBLOCK_SAME_REL;
+            }
+            body.put_u8(block_id as u8);
+            body.put_u8(fork_flags);
+            body.put_u16_le(blk.data.len() as u16);
+            if block_id == 0 {
+                body.put_u32_le(SPCNODE);
+                body.put_u32_le(DBNODE);
+                body.put_u32_le(RELNODE);
+            }
+            body.put_u32_le(blk.blkno);
+        }
+        body.put_u8(pg_constants::XLR_BLOCK_ID_DATA_SHORT);
+        body.put_u8(main_data.len() as u8);
+        for blk in blocks {
+            body.extend_from_slice(&blk.data);
+        }
+        body.extend_from_slice(main_data);
+
+        let mut rec = Vec::new();
+        rec.put_u32_le((SIZE_OF_XLOG_RECORD + body.len()) as u32);
+        rec.put_u32_le(XID);
+        rec.put_u64_le(0); // xl_prev
+        rec.put_u8(info);
+        rec.put_u8(rmid);
+        rec.put_u16_le(0); // padding
+        let crc = crc32c::crc32c_append(0, &body);
+        let crc = crc32c::crc32c_append(crc, &rec);
+        rec.put_u32_le(crc);
+        rec.extend_from_slice(&body);
+        Bytes::from(rec)
+    }
+
+    fn pg_record(will_init: bool, rec: Bytes) -> NeonWalRecord {
+        NeonWalRecord::Postgres { will_init, rec }
+    }
+
+    /// xl_heap_header of the Neon fork of PostgreSQL 14, followed by the data of a tuple
+    /// without nulls: the padding up to t_hoff, then `user_data`.
+    fn heap_tuple(infomask2: u16, user_data: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.put_u16_le(infomask2);
+        buf.put_u16_le(pg_constants::HEAP_XMAX_INVALID);
+        buf.put_u32_le(0); // t_cid
+        buf.put_u8(T_HOFF);
+        buf.put_u8(0); // padding
+        buf.extend_from_slice(user_data);
+        buf
+    }
+
+    fn heap_insert(blkno: u32, offnum: u16, init_page: bool, user_data: &[u8]) -> NeonWalRecord {
+        let mut info = pg_constants::XLOG_HEAP_INSERT;
+        if init_page {
+            info |= pg_constants::XLOG_HEAP_INIT_PAGE;
+        }
+        let mut xlrec = Vec::new();
+        xlrec.put_u16_le(offnum);
+        xlrec.put_u8(0); // flags
+        let block = Block {
+            blkno,
+            will_init: init_page,
+            data: heap_tuple(2, user_data),
+        };
+        pg_record(
+            init_page,
+            record(pg_constants::RM_HEAP_ID, info, &[block], &xlrec),
+        )
+    }
+
+    /// xl_heap_update of the Neon fork of PostgreSQL 14
+    fn heap_update_xlrec(old_offnum: u16, flags: u8, new_offnum: u16) -> Vec<u8> {
+        let mut xlrec = Vec::new();
+        xlrec.put_u32_le(XID); // old_xmax
+        xlrec.put_u16_le(old_offnum);
+        xlrec.put_u8(pg_constants::XLHL_KEYS_UPDATED); // old_infobits_set
+        xlrec.put_u8(flags);
+        xlrec.put_u32_le(0); // t_cid
+        xlrec.put_u32_le(0); // new_xmax
+        xlrec.put_u16_le(new_offnum);
+        xlrec
+    }
+
+    /// Reconstructs the page at `blkno` both natively and with the walredo process, and
+    /// compares the results.
+    async fn check_against_walredo(
+        blkno: u32,
+        base_img: Option<(Lsn, Bytes)>,
+        records: Vec<(Lsn, NeonWalRecord)>,
+    ) {
+        crate::tenant::harness::setup_logging();
+        let key = key(blkno);
+
+        let mut page = match &base_img {
+            Some((_, img)) => BytesMut::from(&img[..]),
+            None => BytesMut::zeroed(BLCKSZ as usize),
+        };
+        for (lsn, rec) in &records {
+            assert!(can_apply(key, rec, PG_VERSION), "cannot apply {rec:?}");
+            apply(key, &mut page, *lsn, rec, PG_VERSION).unwrap();
+        }
+
+        let repo_dir = camino_tempfile::tempdir().unwrap();
+        let conf = PageServerConf::dummy_conf(repo_dir.path().to_path_buf());
+        assert!(!conf.native_wal_redo);
+        let conf = Box::leak(Box::new(conf));
+        let tenant_shard_id = TenantShardId::unsharded(TenantId::generate());
+        let manager = PostgresRedoManager::new(conf, tenant_shard_id);
+        let request_lsn = records.last().unwrap().0;
+        let expected = manager
+            .request_redo(key, request_lsn, base_img, records, PG_VERSION)
+            .instrument(tracing::info_span!("native_redo_test", tenant_id=%tenant_shard_id.tenant_id, shard_id=%tenant_shard_id.shard_slug()))
+            .await
+            .unwrap();
+
+        assert_eq!(&page[..], &expected[..]);
+    }
+
+    #[tokio::test]
+    async fn heap_delete() {
+        let mut xlrec = Vec::new();
+        xlrec.put_u32_le(XID); // xmax
+        xlrec.put_u16_le(1); // offnum
+        xlrec.put_u16_le(0); // padding
+        xlrec.put_u32_le(0); // t_cid
+        xlrec.put_u8(pg_constants::XLHL_KEYS_UPDATED); // infobits_set
+        xlrec.put_u8(0); // flags
+        let block = Block {
+            blkno: 0,
+            will_init: false,
+            data: Vec::new(),
+        };
+        let delete = record(
+            pg_constants::RM_HEAP_ID,
+            pg_constants::XLOG_HEAP_DELETE,
+            &[block],
+            &xlrec,
+        );
+
+        let records = vec![
+            (lsn(1), heap_insert(0, 1, true, b"\x01\0\0\0\x02\0\0\0")),
+            (lsn(2), heap_insert(0, 2, false, b"\x03\0\0\0\x04\0\0\0")),
+            (lsn(3), pg_record(false, delete)),
+        ];
+        check_against_walredo(0, None, records).await;
+    }
+
+    #[tokio::test]
+    async fn heap_update() {
+        let block = Block {
+            blkno: 0,
+            will_init: false,
+            data: heap_tuple(2, b"\x05\0\0\0\x06\0\0\0"),
+        };
+        let update = record(
+            pg_constants::RM_HEAP_ID,
+            pg_constants::XLOG_HEAP_UPDATE,
+            &[block],
+            &heap_update_xlrec(1, 0, 2),
+        );
+
+        let records = vec![
+            (lsn(1), heap_insert(0, 1, true, b"\x01\0\0\0\x02\0\0\0")),
+            (lsn(2), pg_record(false, update)),
+        ];
+        check_against_walredo(0, None, records).await;
+    }
+
+    #[tokio::test]
+    async fn heap_update_to_other_page() {
+        // Block 0 of the record is the new page, block 1 the old one
+        let blocks = [
+            Block {
+                blkno: 1,
+                will_init: true,
+                data: heap_tuple(2, b"\x05\0\0\0\x06\0\0\0"),
+            },
+            Block {
+                blkno: 0,
+                will_init: false,
+                data: Vec::new(),
+            },
+        ];
+        let update = record(
+            pg_constants::RM_HEAP_ID,
+            pg_constants::XLOG_HEAP_UPDATE | pg_constants::XLOG_HEAP_INIT_PAGE,
+            &blocks,
+            &heap_update_xlrec(1, 0, 1),
+        );
+
+        let old_page_records = vec![
+            (lsn(1), heap_insert(0, 1, true, b"\x01\0\0\0\x02\0\0\0")),
+            (lsn(2), pg_record(false, update.clone())),
+        ];
+        check_against_walredo(0, None, old_page_records).await;
+
+        let new_page_records = vec![(lsn(2), pg_record(true, update))];
+        check_against_walredo(1, None, new_page_records).await;
+    }
+
+    #[tokio::test]
+    async fn heap_hot_update() {
+        // Takes the first and last two bytes of the old tuple's data
+        let mut data = Vec::new();
+        data.put_u16_le(2); // prefixlen
+        data.put_u16_le(2); // suffixlen
+        data.extend(heap_tuple(2 | HEAP_ONLY_TUPLE, b"\x07\0\0\0"));
+        let block = Block {
+            blkno: 0,
+            will_init: false,
+            data,
+        };
+        let flags =
+            pg_constants::XLH_UPDATE_PREFIX_FROM_OLD | pg_constants::XLH_UPDATE_SUFFIX_FROM_OLD;
+        let update = record(
+            pg_constants::RM_HEAP_ID,
+            pg_constants::XLOG_HEAP_HOT_UPDATE,
+            &[block],
+            &heap_update_xlrec(1, flags, 2),
+        );
+
+        let records = vec![
+            (lsn(1), heap_insert(0, 1, true, b"\x01\0\0\0\x02\0\0\0")),
+            (lsn(2), pg_record(false, update)),
+        ];
+        check_against_walredo(0, None, records).await;
+    }
+
+    #[tokio::test]
+    async fn btree_insert_leaf() {
+        // An empty leaf page, with a BTPageOpaqueData as special space
+        const SIZE_OF_BT_PAGE_OPAQUE: u16 = 16;
+        const BTP_LEAF: u16 = 1;
+        let mut base_img = BytesMut::zeroed(BLCKSZ as usize);
+        page_init(&mut base_img);
+        set_u16(&mut base_img, PD_UPPER, BLCKSZ - SIZE_OF_BT_PAGE_OPAQUE);
+        set_u16(&mut base_img, PD_SPECIAL, BLCKSZ - SIZE_OF_BT_PAGE_OPAQUE);
+        set_u16(&mut base_img, BLCKSZ as usize - 4, BTP_LEAF);
+        page_set_lsn(&mut base_img, lsn(0));
+
+        // IndexTupleData with an int4 key: t_tid, t_info with the size, then the key
+        let insert = |offnum: u16, heap_offnum: u16, key: u32| {
+            let mut tuple = Vec::new();
+            tuple.put_u32_le(0); // t_tid block
+            tuple.put_u16_le(heap_offnum); // t_tid offset
+            tuple.put_u16_le(16); // t_info
+            tuple.put_u32_le(key);
+            tuple.put_u32_le(0); // padding
+            let block = Block {
+                blkno: 0,
+                will_init: false,
+                data: tuple,
+            };
+            let rec = record(
+                pg_constants::RM_BTREE_ID,
+                pg_constants::XLOG_BTREE_INSERT_LEAF,
+                &[block],
+                &offnum.to_le_bytes(),
+            );
+            pg_record(false, rec)
+        };
+
+        // The second insert goes before the first item
+        let records = vec![(lsn(1), insert(1, 1, 20)), (lsn(2), insert(1, 2, 10))];
+        check_against_walredo(0, Some((lsn(0), base_img.freeze())), records).await;
+    }
+
+    #[test]
+    fn corrupt_tuple_header() {
+        // A HeapTupleHeaderData, then 4 bytes of data
+        let mut tuple = vec![0u8; T_HOFF as usize + 4];
+        tuple[HeapTupleHeader::HOFF] = T_HOFF;
+        assert_eq!(HeapTupleHeader::hoff_of(&tuple).unwrap(), T_HOFF as usize);
+
+        // Shorter than the header, or with a t_hoff past the end of the tuple
+        assert!(HeapTupleHeader::hoff_of(&tuple[..HeapTupleHeader::HOFF]).is_err());
+        tuple[HeapTupleHeader::HOFF] = T_HOFF + 8;
+        assert!(HeapTupleHeader::hoff_of(&tuple).is_err());
+    }
+}
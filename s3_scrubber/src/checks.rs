@@ -3,14 +3,16 @@ use std::collections::{HashMap, HashSet};
 use anyhow::Context;
 use aws_sdk_s3::{types::ObjectIdentifier, Client};
 use pageserver::tenant::remote_timeline_client::index::IndexLayerMetadata;
-use pageserver_api::shard::ShardIndex;
+use pageserver_api::shard::{ShardIndex, TenantShardId};
 use tracing::{error, info, warn};
 use utils::generation::Generation;
 use utils::id::TimelineId;
 
 use crate::cloud_admin_api::BranchData;
 use crate::metadata_stream::stream_listing;
-use crate::{download_object_with_retries, RootTarget, TenantShardTimelineId};
+use crate::{
+    download_object_with_retries, hash_layer_object_with_retries, RootTarget, TenantShardTimelineId,
+};
 use futures_util::{pin_mut, StreamExt};
 use pageserver::tenant::remote_timeline_client::parse_remote_index_path;
 use pageserver::tenant::storage_layer::LayerFileName;
@@ -161,6 +163,62 @@ pub(crate) fn branch_cleanup_and_check_errors(
     result
 }
 
+/// Download the layers referenced by `index_part` and compare their contents with the hashes
+/// recorded for them. Returns a description of every mismatch.
+///
+/// Layers without a recorded hash, uploaded by older pageservers, are skipped.
+pub(crate) async fn check_layer_hashes(
+    s3_client: &Client,
+    s3_root: &RootTarget,
+    id: &TenantShardTimelineId,
+    index_part: &IndexPart,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    for (layer, metadata) in &index_part.layer_metadata {
+        let Some(expected) = metadata.sha256 else {
+            continue;
+        };
+
+        // After a shard split, layers may still be stored under their ancestor shard's prefix.
+        let layer_owner = TenantShardTimelineId::new(
+            TenantShardId {
+                tenant_id: id.tenant_shard_id.tenant_id,
+                shard_number: metadata.shard.shard_number,
+                shard_count: metadata.shard.shard_count,
+            },
+            id.timeline_id,
+        );
+        let key = format!(
+            "{}{}{}",
+            s3_root.timeline_root(&layer_owner).prefix_in_bucket,
+            layer.file_name(),
+            metadata.generation.get_suffix()
+        );
+
+        match hash_layer_object_with_retries(s3_client, s3_root.bucket_name(), &key).await {
+            Ok(actual) if actual == expected => {}
+            Ok(actual) => {
+                error!("Layer {key} has sha256 {actual}, index_part.json expects {expected}");
+                errors.push(format!(
+                    "layer {}{} (shard {}) has sha256 {actual}, but index_part.json records {expected}",
+                    layer.file_name(),
+                    metadata.generation.get_suffix(),
+                    metadata.shard
+                ));
+            }
+            Err(e) => errors.push(format!(
+                "failed to download layer {}{} (shard {}) to check its hash: {e:#}",
+                layer.file_name(),
+                metadata.generation.get_suffix(),
+                metadata.shard
+            )),
+        }
+    }
+
+    errors
+}
+
 #[derive(Default)]
 pub(crate) struct LayerRef {
     ref_count: usize,
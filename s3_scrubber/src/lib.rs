@@ -24,6 +24,7 @@ use aws_sdk_s3::{Client, Config};
 use aws_smithy_async::rt::sleep::TokioSleep;
 
 use clap::ValueEnum;
+use pageserver::tenant::remote_timeline_client::index::{LayerFileHash, LayerFileHasher};
 use pageserver::tenant::TENANTS_SEGMENT_NAME;
 use pageserver_api::shard::TenantShardId;
 use reqwest::Url;
@@ -396,3 +397,45 @@ async fn download_object_with_retries(
 
     anyhow::bail!("Failed to download objects with key {key} {MAX_RETRIES} times")
 }
+
+/// Like [`download_object_with_retries`], but only hashes the contents as they are streamed,
+/// without holding on to the whole object.
+async fn hash_layer_object_with_retries(
+    s3_client: &Client,
+    bucket_name: &str,
+    key: &str,
+) -> anyhow::Result<LayerFileHash> {
+    'retry: for _ in 0..MAX_RETRIES {
+        let response_stream = match s3_client
+            .get_object()
+            .bucket(bucket_name)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to download object for key {key}: {e}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let mut reader = response_stream.body.into_async_read();
+        let mut hasher = LayerFileHasher::default();
+        let mut buf = vec![0u8; 1024 * 1024];
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) => return Ok(hasher.finish()),
+                Ok(n) => hasher.update(&buf[..n]),
+                Err(e) => {
+                    error!("Failed to stream object body for key {key}: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue 'retry;
+                }
+            }
+        }
+    }
+
+    anyhow::bail!("Failed to download objects with key {key} {MAX_RETRIES} times")
+}
@@ -37,6 +37,9 @@ enum Command {
         json: bool,
         #[arg(long = "tenant-id", num_args = 0..)]
         tenant_ids: Vec<TenantShardId>,
+        /// Download layers and check them against the hashes recorded in their index
+        #[arg(long, default_value_t = false)]
+        verify_layer_hashes: bool,
     },
 }
 
@@ -60,8 +63,12 @@ async fn main() -> anyhow::Result<()> {
     ));
 
     match cli.command {
-        Command::ScanMetadata { json, tenant_ids } => {
-            match scan_metadata(bucket_config.clone(), tenant_ids).await {
+        Command::ScanMetadata {
+            json,
+            tenant_ids,
+            verify_layer_hashes,
+        } => {
+            match scan_metadata(bucket_config.clone(), tenant_ids, verify_layer_hashes).await {
                 Err(e) => {
                     tracing::error!("Failed: {e}");
                     Err(e)
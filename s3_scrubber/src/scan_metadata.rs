@@ -1,8 +1,8 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::checks::{
-    branch_cleanup_and_check_errors, list_timeline_blobs, BlobDataParseResult, S3TimelineBlobData,
-    TenantObjectListing, TimelineAnalysis,
+    branch_cleanup_and_check_errors, check_layer_hashes, list_timeline_blobs, BlobDataParseResult,
+    S3TimelineBlobData, TenantObjectListing, TimelineAnalysis,
 };
 use crate::metadata_stream::{stream_tenant_timelines, stream_tenants};
 use crate::{init_remote, BucketConfig, NodeKind, RootTarget, TenantShardTimelineId};
@@ -195,9 +195,13 @@ Timeline layer count: {}
 }
 
 /// Scan the pageserver metadata in an S3 bucket, reporting errors and statistics.
+///
+/// With `verify_layer_hashes`, also download every layer that has a hash in its index and
+/// verify its contents.
 pub async fn scan_metadata(
     bucket_config: BucketConfig,
     tenant_ids: Vec<TenantShardId>,
+    verify_layer_hashes: bool,
 ) -> anyhow::Result<MetadataSummary> {
     let (s3_client, target) = init_remote(bucket_config, NodeKind::Pageserver)?;
 
@@ -216,16 +220,24 @@ pub async fn scan_metadata(
     let timelines = timelines.try_buffered(CONCURRENCY);
     let timelines = timelines.try_flatten();
 
-    // Generate a stream of S3TimelineBlobData
+    // Generate a stream of S3TimelineBlobData, along with any layer hash mismatches
     async fn report_on_timeline(
         s3_client: &Client,
         target: &RootTarget,
         ttid: TenantShardTimelineId,
-    ) -> anyhow::Result<(TenantShardTimelineId, S3TimelineBlobData)> {
+        verify_layer_hashes: bool,
+    ) -> anyhow::Result<(TenantShardTimelineId, S3TimelineBlobData, Vec<String>)> {
         let data = list_timeline_blobs(s3_client, ttid, target).await?;
-        Ok((ttid, data))
+        let hash_errors = match &data.blob_data {
+            BlobDataParseResult::Parsed { index_part, .. } if verify_layer_hashes => {
+                check_layer_hashes(s3_client, target, &ttid, index_part).await
+            }
+            _ => Vec::new(),
+        };
+        Ok((ttid, data, hash_errors))
     }
-    let timelines = timelines.map_ok(|ttid| report_on_timeline(&s3_client, &target, ttid));
+    let timelines =
+        timelines.map_ok(|ttid| report_on_timeline(&s3_client, &target, ttid, verify_layer_hashes));
     let timelines = timelines.try_buffered(CONCURRENCY);
 
     // We must gather all the TenantShardTimelineId->S3TimelineBlobData for each tenant, because different
@@ -239,13 +251,13 @@ pub async fn scan_metadata(
         tenant_id: TenantId,
         summary: &mut MetadataSummary,
         mut tenant_objects: TenantObjectListing,
-        timelines: Vec<(TenantShardTimelineId, S3TimelineBlobData)>,
+        timelines: Vec<(TenantShardTimelineId, S3TimelineBlobData, Vec<String>)>,
     ) {
         summary.tenant_count += 1;
 
         let mut timeline_ids = HashSet::new();
         let mut timeline_generations = HashMap::new();
-        for (ttid, data) in timelines {
+        for (ttid, data, hash_errors) in timelines {
             timeline_ids.insert(ttid.timeline_id);
             // Stash the generation of each timeline, for later use identifying orphan layers
             if let BlobDataParseResult::Parsed {
@@ -259,8 +271,9 @@ pub async fn scan_metadata(
 
             // Apply checks to this timeline shard's metadata, and in the process update `tenant_objects`
             // reference counts for layers across the tenant.
-            let analysis =
+            let mut analysis =
                 branch_cleanup_and_check_errors(&ttid, &mut tenant_objects, None, None, Some(data));
+            analysis.errors.extend(hash_errors);
             summary.update_analysis(&ttid, &analysis);
         }
 
@@ -311,7 +324,7 @@ pub async fn scan_metadata(
     let mut summary = MetadataSummary::new();
     pin_mut!(timelines);
     while let Some(i) = timelines.next().await {
-        let (ttid, data) = i?;
+        let (ttid, data, hash_errors) = i?;
         summary.update_data(&data);
 
         match tenant_id {
@@ -334,7 +347,7 @@ pub async fn scan_metadata(
         {
             tenant_objects.push(ttid, s3_layers.clone());
         }
-        tenant_timeline_results.push((ttid, data));
+        tenant_timeline_results.push((ttid, data, hash_errors));
     }
 
     if !tenant_timeline_results.is_empty() {
@@ -3701,8 +3701,11 @@ class S3Scrubber:
         assert stdout is not None
         return stdout
 
-    def scan_metadata(self) -> Any:
-        stdout = self.scrubber_cli(["scan-metadata", "--json"], timeout=30)
+    def scan_metadata(self, verify_layer_hashes: bool = False) -> Any:
+        args = ["scan-metadata", "--json"]
+        if verify_layer_hashes:
+            args.append("--verify-layer-hashes")
+        stdout = self.scrubber_cli(args, timeout=30)
 
         try:
             return json.loads(stdout)
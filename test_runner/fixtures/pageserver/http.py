@@ -432,6 +432,7 @@ class PageserverHttpClient(requests.Session):
         ancestor_timeline_id: Optional[TimelineId] = None,
         ancestor_start_lsn: Optional[Lsn] = None,
         existing_initdb_timeline_id: Optional[TimelineId] = None,
+        ancestor_start_timestamp: Optional[datetime] = None,
         **kwargs,
     ) -> Dict[Any, Any]:
         body: Dict[str, Any] = {
@@ -442,6 +443,8 @@ class PageserverHttpClient(requests.Session):
             if existing_initdb_timeline_id
             else None,
         }
+        if ancestor_start_timestamp is not None:
+            body["ancestor_start_timestamp"] = f"{ancestor_start_timestamp.isoformat()}Z"
         if pg_version != PgVersion.NOT_SET:
             body["pg_version"] = int(pg_version)
 
@@ -559,6 +562,17 @@ class PageserverHttpClient(requests.Session):
         )
         self.verbose_error(res)
 
+    def timeline_detach_ancestor(
+        self, tenant_id: Union[TenantId, TenantShardId], timeline_id: TimelineId
+    ):
+        log.info(
+            f"Requesting detach from ancestor for tenant {tenant_id} and timeline {timeline_id}"
+        )
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/detach_ancestor",
+        )
+        self.verbose_error(res)
+
     def timeline_get_lsn_by_timestamp(
         self,
         tenant_id: Union[TenantId, TenantShardId],
@@ -672,6 +686,74 @@ class PageserverHttpClient(requests.Session):
                 assert completed["successful_download_count"] > 0
             return completed
 
+    def timeline_import(
+        self,
+        tenant_id: Union[TenantId, TenantShardId],
+        timeline_id: TimelineId,
+        remote_path: str,
+        base_lsn: Lsn,
+        end_lsn: Lsn,
+        pg_version: PgVersion,
+    ) -> dict[str, Any]:
+        body = {
+            "remote_path": remote_path,
+            "base_lsn": str(base_lsn),
+            "end_lsn": str(end_lsn),
+            "pg_version": int(pg_version),
+        }
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/import",
+            json=body,
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_import_status(
+        self,
+        tenant_id: Union[TenantId, TenantShardId],
+        timeline_id: TimelineId,
+    ) -> dict[str, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/import",
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_relation_sizes(
+        self,
+        tenant_id: Union[TenantId, TenantShardId],
+        timeline_id: TimelineId,
+        lsn: Optional[Lsn] = None,
+    ) -> dict[str, Any]:
+        params = {}
+        if lsn is not None:
+            params["lsn"] = str(lsn)
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/relation_sizes",
+            params=params,
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_key_range_stats(
+        self,
+        tenant_id: Union[TenantId, TenantShardId],
+        timeline_id: TimelineId,
+    ) -> List[Dict[str, Any]]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/key_range_stats",
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, list)
+        return res_json
+
     def get_metrics_str(self) -> str:
         """You probably want to use get_metrics() instead."""
         res = self.get(f"http://localhost:{self.port}/metrics")
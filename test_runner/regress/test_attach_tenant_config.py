@@ -155,6 +155,7 @@ def test_fully_custom_config(positive_env: NeonEnv):
     env = positive_env
 
     fully_custom_config = {
+        "compaction_algorithm": {"kind": "Tiered"},
         "compaction_period": "1h",
         "compaction_threshold": 13,
         "compaction_target_size": 1048576,
@@ -166,16 +167,21 @@ def test_fully_custom_config(positive_env: NeonEnv):
             "threshold": "23h",
         },
         "evictions_low_residence_duration_metric_threshold": "2days",
+        "gc_branch_images": True,
         "gc_feedback": True,
         "gc_horizon": 23 * (1024 * 1024),
         "gc_period": "2h 13m",
         "heatmap_period": "10m",
+        "image_creation_read_heat": False,
         "image_creation_threshold": 7,
         "pitr_interval": "1m",
         "lagging_wal_timeout": "23m",
         "lazy_slru_download": True,
+        "layer_checksums": True,
+        "layer_compression": {"kind": "Zstd", "level": 1},
         "max_lsn_wal_lag": 230000,
         "min_resident_size_override": 23,
+        "resident_size_quota": 1024 * 1024 * 1024,
         "timeline_get_throttle": {
             "task_kinds": ["PageRequestHandler"],
             "fair": True,
@@ -186,6 +192,14 @@ def test_fully_custom_config(positive_env: NeonEnv):
         },
         "trace_read_requests": True,
         "walreceiver_connect_timeout": "13m",
+        "write_throttle": {
+            "task_kinds": ["LayerFlushTask", "Compaction"],
+            "fair": True,
+            "initial": 0,
+            "refill_interval": "1s",
+            "refill_amount": 100 * 1024 * 1024,
+            "max": 1024 * 1024 * 1024,
+        },
     }
 
     ps_http = env.pageserver.http_client()
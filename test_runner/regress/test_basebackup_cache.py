@@ -0,0 +1,71 @@
+import io
+from contextlib import closing
+
+from fixtures.neon_fixtures import NeonEnvBuilder
+from fixtures.pageserver.utils import timeline_delete_wait_completed, wait_for_last_record_lsn
+from fixtures.utils import wait_until
+
+
+#
+# Test that basebackups at the end of a quiescent timeline are served from the
+# basebackup cache, until the timeline advances.
+#
+def test_basebackup_cache(neon_env_builder: NeonEnvBuilder):
+    neon_env_builder.pageserver_config_override = "basebackup_cache_max_size=16777216"
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_basebackup_cache")
+    ps_http = env.pageserver.http_client()
+
+    def write_and_stop(sql: str):
+        endpoint = env.endpoints.create_start("test_basebackup_cache")
+        endpoint.safe_psql(sql)
+        endpoint.stop_and_destroy()
+        # Let the pageserver ingest everything, including the shutdown checkpoint
+        status = env.safekeepers[0].http_client().timeline_status(tenant_id, timeline_id)
+        wait_for_last_record_lsn(ps_http, tenant_id, timeline_id, status.flush_lsn)
+
+    def basebackup() -> bytes:
+        buf = io.BytesIO()
+        with closing(env.pageserver.connect()) as conn:
+            with conn.cursor() as cur:
+                cur.copy_expert(f"basebackup {tenant_id} {timeline_id} --compression=gzip", buf)
+        return buf.getvalue()
+
+    def cache_reads():
+        return {
+            result: ps_http.get_metric_value(
+                "pageserver_basebackup_cache_read_total", {"result": result}
+            )
+            or 0
+            for result in ["hit", "miss"]
+        }
+
+    write_and_stop("CREATE TABLE t (i int)")
+
+    before = cache_reads()
+    first = basebackup()
+    assert basebackup() == first
+    assert basebackup() == first
+    after = cache_reads()
+    assert after["miss"] - before["miss"] == 1
+    assert after["hit"] - before["hit"] == 2
+
+    # The timeline advances: the cached tarball is stale
+    write_and_stop("INSERT INTO t VALUES (1)")
+
+    before = cache_reads()
+    second = basebackup()
+    assert second != first
+    assert basebackup() == second
+    after = cache_reads()
+    assert after["miss"] - before["miss"] == 1
+    assert after["hit"] - before["hit"] == 1
+
+    # Deleting the timeline drops its cached tarball
+    def cache_entries(expected: int):
+        assert ps_http.get_metric_value("pageserver_basebackup_cache_entries") == expected
+
+    wait_until(10, 0.5, lambda: cache_entries(1))
+    timeline_delete_wait_completed(ps_http, tenant_id, timeline_id)
+    cache_entries(0)
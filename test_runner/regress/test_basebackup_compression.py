@@ -0,0 +1,70 @@
+import gzip
+import io
+import tarfile
+from contextlib import closing
+
+import pytest
+import zstandard
+from fixtures.neon_fixtures import NeonEnv
+from fixtures.types import Lsn
+from fixtures.utils import query_scalar
+
+
+#
+# Test the compression and resume options of the 'basebackup' command.
+#
+def test_basebackup_compression_and_resume(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    timeline_id = env.neon_cli.create_branch("test_basebackup_compression", "empty")
+    endpoint = env.endpoints.create_start("test_basebackup_compression")
+
+    endpoint.safe_psql("CREATE TABLE t (i int)")
+    lsn = Lsn(query_scalar(endpoint.connect().cursor(), "SELECT pg_current_wal_insert_lsn()"))
+    # Move the end of the timeline past 'lsn', so that all the basebackups
+    # below are identical.
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1, 1000)")
+    endpoint.stop()
+
+    tenant_id = env.initial_tenant
+
+    def basebackup(options: str) -> bytes:
+        buf = io.BytesIO()
+        with closing(env.pageserver.connect()) as conn:
+            with conn.cursor() as cur:
+                cur.copy_expert(f"basebackup {tenant_id} {timeline_id} {lsn} {options}", buf)
+        return buf.getvalue()
+
+    plain = basebackup("")
+
+    # The first algorithm that the pageserver knows is used
+    compressed = basebackup("--compression=lz4,zstd,gzip")
+    assert compressed[:4] == b"\x28\xb5\x2f\xfd"
+    assert zstandard.ZstdDecompressor().decompressobj().decompress(compressed) == plain
+
+    compressed = basebackup("--compression=gzip")
+    assert compressed[:2] == b"\x1f\x8b"
+    assert gzip.decompress(compressed) == plain
+
+    # The legacy flag
+    assert gzip.decompress(basebackup("--gzip")) == plain
+
+    # Nothing known: uncompressed
+    assert basebackup("--compression=lz4") == plain
+
+    # Resuming picks up exactly at the start of the given entry
+    members = tarfile.open(fileobj=io.BytesIO(plain)).getmembers()
+    assert len(members) > 10
+    for n in [1, len(members) // 2, len(members) - 1]:
+        assert basebackup(f"--resume-from={n}") == plain[members[n].offset :]
+        resumed = basebackup(f"--compression=zstd --resume-from={n}")
+        assert zstandard.ZstdDecompressor().decompressobj().decompress(resumed) == (
+            plain[members[n].offset :]
+        )
+
+    # Resuming requires a fixed LSN
+    with pytest.raises(Exception, match="resuming a basebackup requires an LSN"):
+        with closing(env.pageserver.connect()) as conn:
+            with conn.cursor() as cur:
+                cur.copy_expert(
+                    f"basebackup {tenant_id} {timeline_id} --resume-from=1", io.BytesIO()
+                )
@@ -72,6 +72,7 @@ class EvictionOrder(str, enum.Enum):
     ABSOLUTE_ORDER = "absolute"
     RELATIVE_ORDER_EQUAL = "relative_equal"
     RELATIVE_ORDER_SPARE = "relative_spare"
+    COST_AWARE = "cost_aware"
 
     def config(self) -> Dict[str, Any]:
         if self == EvictionOrder.ABSOLUTE_ORDER:
@@ -86,6 +87,8 @@ class EvictionOrder(str, enum.Enum):
                 "type": "RelativeAccessed",
                 "args": {"highest_layer_count_loses_first": True},
             }
+        elif self == EvictionOrder.COST_AWARE:
+            return {"type": "CostAware", "args": {}}
         else:
             raise RuntimeError(f"not implemented: {self}")
 
@@ -398,7 +401,11 @@ def test_broken_tenants_are_skipped(eviction_env: EvictionEnv):
 
 @pytest.mark.parametrize(
     "order",
-    [EvictionOrder.ABSOLUTE_ORDER, EvictionOrder.RELATIVE_ORDER_EQUAL],
+    [
+        EvictionOrder.ABSOLUTE_ORDER,
+        EvictionOrder.RELATIVE_ORDER_EQUAL,
+        EvictionOrder.COST_AWARE,
+    ],
 )
 def test_pageserver_evicts_until_pressure_is_relieved(
     eviction_env: EvictionEnv, order: EvictionOrder
@@ -507,9 +514,57 @@ def test_pageserver_respects_overridden_resident_size(
     assert du_by_timeline[large_tenant] - later_du_by_timeline[large_tenant] >= target
 
 
+def test_pageserver_enforces_resident_size_quota(eviction_env: EvictionEnv):
+    """
+    A tenant over its resident size quota gets a haircut even without disk pressure,
+    while the other tenant keeps all of its layers.
+    """
+    env = eviction_env
+    ps_http = env.pageserver_http
+
+    du_by_timeline = env.du_by_timeline(env.pageserver)
+    assert len(du_by_timeline) == 2, "this test assumes two tenants"
+    large_tenant = max(du_by_timeline, key=du_by_timeline.__getitem__)
+    small_tenant = min(du_by_timeline, key=du_by_timeline.__getitem__)
+
+    quota = du_by_timeline[large_tenant] // 2
+    ps_http.patch_tenant_config_client_side(large_tenant[0], {"resident_size_quota": quota})
+
+    status = ps_http.tenant_status(large_tenant[0])
+    assert status["resident_size_quota"] == quota
+    assert status["resident_size"] > quota
+    assert ps_http.tenant_status(small_tenant[0])["resident_size_quota"] is None
+    assert (
+        ps_http.get_metric_value(
+            "pageserver_tenant_resident_size_quota_bytes", {"tenant_id": str(large_tenant[0])}
+        )
+        == quota
+    )
+
+    response = ps_http.disk_usage_eviction_run({"evict_bytes": 0})
+    log.info(f"{response}")
+    assert response["Finished"]["assumed"]["failed"]["count"] == 0, "zero failures expected"
+
+    later_du_by_timeline = env.du_by_timeline(env.pageserver)
+    log.info("later_du_by_timeline: %s", later_du_by_timeline)
+    assert later_du_by_timeline[large_tenant] <= quota, "large tenant is trimmed to its quota"
+    assert (
+        later_du_by_timeline[small_tenant] == du_by_timeline[small_tenant]
+    ), "small tenant sees no haircut"
+    assert ps_http.tenant_status(large_tenant[0])["resident_size"] <= quota
+
+    # Within its quota and without pressure, there is nothing to do
+    response = ps_http.disk_usage_eviction_run({"evict_bytes": 0})
+    assert response == "NoPressure"
+
+
 @pytest.mark.parametrize(
     "order",
-    [EvictionOrder.ABSOLUTE_ORDER, EvictionOrder.RELATIVE_ORDER_EQUAL],
+    [
+        EvictionOrder.ABSOLUTE_ORDER,
+        EvictionOrder.RELATIVE_ORDER_EQUAL,
+        EvictionOrder.COST_AWARE,
+    ],
 )
 def test_pageserver_falls_back_to_global_lru(eviction_env: EvictionEnv, order: EvictionOrder):
     """
@@ -543,6 +598,7 @@ def test_pageserver_falls_back_to_global_lru(eviction_env: EvictionEnv, order: E
         EvictionOrder.ABSOLUTE_ORDER,
         EvictionOrder.RELATIVE_ORDER_EQUAL,
         EvictionOrder.RELATIVE_ORDER_SPARE,
+        EvictionOrder.COST_AWARE,
     ],
 )
 def test_partial_evict_tenant(eviction_env: EvictionEnv, order: EvictionOrder):
@@ -589,7 +645,11 @@ def test_partial_evict_tenant(eviction_env: EvictionEnv, order: EvictionOrder):
     warm_size = later_du_by_timeline[warm]
     cold_size = later_du_by_timeline[cold]
 
-    if order == EvictionOrder.ABSOLUTE_ORDER:
+    if order in (EvictionOrder.ABSOLUTE_ORDER, EvictionOrder.COST_AWARE):
+        # The layers are all about env.layer_size, so with cost aware order the layers read
+        # by the warm up cost the most to evict, and the others are evicted oldest first,
+        # just like with absolute order.
+        #
         # bounds for warmed_size
         warm_lower = 0.5 * du_by_timeline[warm]
 
@@ -1,8 +1,15 @@
+import io
 import os
+import re
+import struct
+import tarfile
+from contextlib import closing
 from pathlib import Path
+from typing import Dict
 
 from fixtures.log_helper import log
 from fixtures.neon_fixtures import (
+    NeonEnv,
     NeonEnvBuilder,
     PgBin,
     VanillaPostgres,
@@ -13,6 +20,8 @@ from fixtures.utils import query_scalar, subprocess_capture
 
 num_rows = 1000
 
+BLCKSZ = 8192
+
 
 # Ensure that regular postgres can start from fullbackup
 def test_fullbackup(
@@ -69,3 +78,87 @@ def test_fullbackup(
         vanilla_pg.start()
         num_rows_found = vanilla_pg.safe_psql("select count(*) from tbl;", user="cloud_admin")[0][0]
         assert num_rows == num_rows_found
+
+
+#
+# Test that a fullbackup taken with --incremental-from, applied to a fullbackup at that LSN,
+# gives the same relation files as a fullbackup at the new LSN.
+#
+def test_incremental_fullbackup(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    timeline_id = env.neon_cli.create_branch("test_incremental_fullbackup", "empty")
+    endpoint = env.endpoints.create_start("test_incremental_fullbackup")
+
+    def fullbackup(lsn: Lsn, options: str = "") -> Dict[str, bytes]:
+        buf = io.BytesIO()
+        with closing(env.pageserver.connect()) as conn:
+            with conn.cursor() as cur:
+                cur.copy_expert(
+                    f"fullbackup {env.initial_tenant} {timeline_id} {lsn} {options}", buf
+                )
+        buf.seek(0)
+        with tarfile.open(fileobj=buf) as tar:
+            return {
+                member.name: tar.extractfile(member).read()  # type: ignore
+                for member in tar.getmembers()
+                if member.isfile()
+            }
+
+    def current_lsn() -> Lsn:
+        return Lsn(query_scalar(endpoint.connect().cursor(), "SELECT pg_current_wal_insert_lsn()"))
+
+    endpoint.safe_psql_many(
+        [
+            "CREATE TABLE big AS SELECT g AS i FROM generate_series(1, 100000) g",
+            "CREATE TABLE dropped AS SELECT g AS i FROM generate_series(1, 1000) g",
+        ]
+    )
+    lsn1 = current_lsn()
+    endpoint.safe_psql_many(
+        [
+            "UPDATE big SET i = -i WHERE i % 10000 = 0",
+            "DROP TABLE dropped",
+            "CREATE TABLE created AS SELECT g AS i FROM generate_series(1, 1000) g",
+        ]
+    )
+    lsn2 = current_lsn()
+
+    base = fullbackup(lsn1)
+    full = fullbackup(lsn2)
+    incremental = fullbackup(lsn2, f"--incremental-from={lsn1}")
+
+    # The transactions above modified the first CLOG segment
+    assert "pg_xact/0000" in incremental
+
+    # Apply the incremental backup to the first one
+    restored = {}
+    incremental_size = 0
+    for name, data in incremental.items():
+        dirname, _, file_name = name.rpartition("/")
+        if not file_name.startswith("INCREMENTAL."):
+            # Everything else is sent in full
+            assert data == full[name]
+            continue
+        magic, nblocks, truncate_blocks = struct.unpack_from("<III", data)
+        assert magic == 0xD3AE1F0D
+        incremental_size += len(data)
+        blknums = struct.unpack_from(f"<{nblocks}I", data, 12)
+        # Like in PostgreSQL 17, the pages start at a block boundary
+        header_len = 12 + 4 * nblocks
+        if nblocks > 0:
+            header_len += -header_len % BLCKSZ
+        assert data[12 + 4 * nblocks : header_len] == b"\0" * (header_len - 12 - 4 * nblocks)
+        pages = data[header_len:]
+        assert len(pages) == nblocks * BLCKSZ
+
+        segment_name = f"{dirname}/{file_name[len('INCREMENTAL.'):]}"
+        segment = bytearray(base.get(segment_name, b"")[: truncate_blocks * BLCKSZ])
+        segment.extend(b"\0" * (truncate_blocks * BLCKSZ - len(segment)))
+        for i, blknum in enumerate(blknums):
+            segment[blknum * BLCKSZ : (blknum + 1) * BLCKSZ] = pages[i * BLCKSZ : (i + 1) * BLCKSZ]
+        restored[segment_name] = bytes(segment)
+
+    rel_file = re.compile(r"^(global|base/\d+)/\d+(_\w+)?(\.\d+)?$")
+    assert restored == {name: data for name, data in full.items() if rel_file.match(name)}
+    # Only a few blocks were modified
+    assert incremental_size < sum(map(len, restored.values())) / 10
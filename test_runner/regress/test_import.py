@@ -5,6 +5,7 @@ import shutil
 import tarfile
 from contextlib import closing
 from pathlib import Path
+from typing import Dict
 
 import pytest
 from fixtures.log_helper import log
@@ -14,14 +15,15 @@ from fixtures.neon_fixtures import (
     NeonEnvBuilder,
     PgBin,
 )
+from fixtures.pageserver.http import PageserverApiException
 from fixtures.pageserver.utils import (
     timeline_delete_wait_completed,
     wait_for_last_record_lsn,
     wait_for_upload,
 )
-from fixtures.remote_storage import RemoteStorageKind
+from fixtures.remote_storage import LocalFsStorage, RemoteStorageKind
 from fixtures.types import Lsn, TenantId, TimelineId
-from fixtures.utils import subprocess_capture
+from fixtures.utils import subprocess_capture, wait_until
 
 
 def test_import_from_vanilla(test_output_dir, pg_bin, vanilla_pg, neon_env_builder):
@@ -153,6 +155,69 @@ def test_import_from_vanilla(test_output_dir, pg_bin, vanilla_pg, neon_env_build
     vanilla_pg.stop()
 
 
+def test_import_from_remote_storage(pg_bin, vanilla_pg, neon_env_builder):
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    assert isinstance(env.pageserver_remote_storage, LocalFsStorage)
+
+    vanilla_pg.start()
+    vanilla_pg.safe_psql("create user cloud_admin with password 'postgres' superuser")
+    vanilla_pg.safe_psql("create table t as select g from generate_series(1,10000) g")
+
+    # Put the output of pg_basebackup in the pageserver's remote storage
+    tenant = env.initial_tenant
+    basebackup_dir = env.pageserver_remote_storage.root / "imports" / str(tenant) / "vanilla"
+    basebackup_dir.mkdir(parents=True)
+    pg_bin.run(
+        ["pg_basebackup", "-F", "tar", "-d", vanilla_pg.connstr(), "-D", str(basebackup_dir)]
+    )
+    vanilla_pg.stop()
+    with open(basebackup_dir / "backup_manifest") as f:
+        manifest = json.load(f)
+        start_lsn = Lsn(manifest["WAL-Ranges"][0]["Start-LSN"])
+        end_lsn = Lsn(manifest["WAL-Ranges"][0]["End-LSN"])
+
+    timeline = TimelineId.generate()
+    client = env.pageserver.http_client()
+
+    def import_status(timeline: TimelineId, state: str):
+        status = client.timeline_import_status(tenant, timeline)
+        assert status["state"] == state
+        return status
+
+    # Only the tenant's own imports can be imported from
+    for path in [f"tenants/{tenant}", f"imports/{tenant}/../other/vanilla", "imports/vanilla"]:
+        with pytest.raises(PageserverApiException, match="must be a path below|is not under"):
+            client.timeline_import(tenant, timeline, path, start_lsn, end_lsn, env.pg_version)
+
+    remote_path = f"imports/{tenant}/vanilla"
+    client.timeline_import(tenant, timeline, remote_path, start_lsn, end_lsn, env.pg_version)
+    with pytest.raises(PageserverApiException, match="already being imported"):
+        client.timeline_import(tenant, timeline, remote_path, start_lsn, end_lsn, env.pg_version)
+    status = wait_until(60, 0.5, lambda: import_status(timeline, "Completed"))
+    # The WAL import stops reading at 'end_lsn', so some of pg_wal.tar may be left
+    assert status["downloaded_bytes"] > (basebackup_dir / "base.tar").stat().st_size
+
+    wait_for_last_record_lsn(client, tenant, timeline, end_lsn)
+    env.neon_cli.map_branch("imported", tenant, timeline)
+    endpoint = env.endpoints.create_start("imported", tenant_id=tenant)
+    assert endpoint.safe_psql("select count(*) from t") == [(10000,)]
+
+    # A missing basebackup fails the import, and leaves no timeline behind
+    env.pageserver.allowed_errors.extend(
+        [
+            ".*import failed.*",
+            ".*Timeline got dropped without initializing, cleaning its files.*",
+        ]
+    )
+    missing = TimelineId.generate()
+    remote_path = f"imports/{tenant}/missing"
+    client.timeline_import(tenant, missing, remote_path, start_lsn, start_lsn, env.pg_version)
+    status = wait_until(60, 0.5, lambda: import_status(missing, "Failed"))
+    assert f"{remote_path}/base.tar" in status["error"]
+    assert missing not in [TimelineId(t["timeline_id"]) for t in client.timeline_list(tenant)]
+
+
 def test_import_from_pageserver_small(
     pg_bin: PgBin, neon_env_builder: NeonEnvBuilder, test_output_dir: Path
 ):
@@ -167,6 +232,73 @@ def test_import_from_pageserver_small(
     _import(num_rows, lsn, env, pg_bin, timeline, env.pg_distrib_dir, test_output_dir)
 
 
+def test_import_from_pageserver_aux_files(
+    pg_bin: PgBin, neon_env_builder: NeonEnvBuilder, test_output_dir: Path
+):
+    """Replication slots and pg_logical state survive a fullbackup and an import of it."""
+    env = neon_env_builder.init_start()
+
+    timeline = env.neon_cli.create_branch("test_import_from_pageserver_aux_files")
+    endpoint = env.endpoints.create_start("test_import_from_pageserver_aux_files")
+    endpoint.safe_psql_many(
+        [
+            "select pg_create_logical_replication_slot('my_slot', 'pgoutput')",
+            "select pg_replication_origin_create('my_origin')",
+            "CHECKPOINT",
+        ]
+    )
+    lsn = Lsn(endpoint.safe_psql("select pg_current_wal_insert_lsn()")[0][0])
+    wait_for_last_record_lsn(env.pageserver.http_client(), env.initial_tenant, timeline, lsn)
+
+    def aux_files(tar_path: Path) -> Dict[str, bytes]:
+        files = {}
+        with tarfile.open(tar_path, "r") as tar:
+            for member in tar.getmembers():
+                if member.isfile() and member.name.startswith(("pg_replslot/", "pg_logical/")):
+                    f = tar.extractfile(member)
+                    assert f is not None
+                    files[member.name] = f.read()
+        return files
+
+    tar_output_file = test_output_dir / "fullbackup.tar"
+    _fullbackup(env, pg_bin, env.initial_tenant, timeline, lsn, tar_output_file)
+    expected = aux_files(tar_output_file)
+    assert "pg_replslot/my_slot/state" in expected
+    assert "pg_logical/replorigin_checkpoint" in expected
+
+    # Import into another tenant, and take a fullbackup of that
+    tenant = TenantId.generate()
+    endpoint_id = "ep-import_aux_files"
+    env.pageserver.tenant_create(tenant)
+    env.neon_cli.raw_cli(
+        [
+            "timeline",
+            "import",
+            "--tenant-id",
+            str(tenant),
+            "--timeline-id",
+            str(timeline),
+            "--node-name",
+            endpoint_id,
+            "--base-lsn",
+            str(lsn),
+            "--base-tarfile",
+            str(tar_output_file),
+            "--pg-version",
+            env.pg_version,
+        ]
+    )
+    wait_for_last_record_lsn(env.pageserver.http_client(), tenant, timeline, lsn)
+
+    new_tar_output_file = test_output_dir / "fullbackup-new.tar"
+    _fullbackup(env, pg_bin, tenant, timeline, lsn, new_tar_output_file)
+    assert aux_files(new_tar_output_file) == expected
+
+    # The compute of the imported timeline loads the slot
+    endpoint = env.endpoints.create_start(endpoint_id, tenant_id=tenant)
+    assert endpoint.safe_psql("select slot_name from pg_replication_slots") == [("my_slot",)]
+
+
 @pytest.mark.timeout(1800)
 # TODO: temporarily disable `test_import_from_pageserver_multisegment` test, enable
 # the test back after finding the failure cause.
@@ -230,6 +362,22 @@ def _generate_data(num_rows: int, endpoint: Endpoint) -> Lsn:
             return Lsn(res[0])
 
 
+def _fullbackup(
+    env: NeonEnv,
+    pg_bin: PgBin,
+    tenant: TenantId,
+    timeline: TimelineId,
+    lsn: Lsn,
+    tar_output_file: Path,
+):
+    """Write a fullbackup of the timeline at `lsn` to `tar_output_file`."""
+    # Set LD_LIBRARY_PATH in the env properly, otherwise we may use the wrong libpq.
+    psql_env = {"LD_LIBRARY_PATH": str(env.pg_distrib_dir / "lib")}
+    query = f"fullbackup {tenant} {timeline} {lsn}"
+    cmd = ["psql", "--no-psqlrc", env.pageserver.connstr(), "-c", query, "-o", str(tar_output_file)]
+    pg_bin.run_capture(cmd, env=psql_env)
+
+
 def _import(
     expected_num_rows: int,
     lsn: Lsn,
@@ -0,0 +1,38 @@
+from fixtures.neon_fixtures import NeonEnv, wait_for_last_flush_lsn
+
+
+#
+# Test that WAL ingest and page reads show up in the key range statistics of the timeline
+#
+def test_key_range_stats(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_key_range_stats", "empty")
+    client = env.pageserver.http_client()
+
+    endpoint = env.endpoints.create_start("test_key_range_stats")
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g AS i FROM generate_series(1, 100000) g")
+    relnode = endpoint.safe_psql("SELECT pg_relation_filenode('t')")[0][0]
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    # Keys of relation blocks end with the relnode, fork number and block number
+    def table_stats():
+        return [
+            stats
+            for stats in client.timeline_key_range_stats(tenant_id, timeline_id)
+            if stats["key_start"][18:26] == f"{relnode:08X}"
+        ]
+
+    stats = table_stats()
+    assert sum(s["writes"] for s in stats) > 0
+
+    # Read the table from the pageserver, not from the compute's caches
+    endpoint.stop()
+    endpoint.start()
+    endpoint.safe_psql("SELECT count(*) FROM t")
+
+    stats = table_stats()
+    assert sum(s["reads"] for s in stats) > 0
+    assert sum(s["layers_visited"] for s in stats) > 0
+    for s in stats:
+        assert s["key_start"] < s["key_end"]
@@ -1,10 +1,11 @@
 import time
 from datetime import datetime, timedelta, timezone
 
+import pytest
 from fixtures.log_helper import log
 from fixtures.neon_fixtures import NeonEnvBuilder, wait_for_last_flush_lsn
-from fixtures.pageserver.http import PageserverApiException
-from fixtures.types import Lsn
+from fixtures.pageserver.http import PageserverApiException, TimelineCreate406
+from fixtures.types import Lsn, TimelineId
 from fixtures.utils import query_scalar
 
 
@@ -107,6 +108,82 @@ def test_lsn_mapping(neon_env_builder: NeonEnvBuilder):
         assert Lsn(result["lsn"]) >= last_flush_lsn
 
 
+#
+# Test creating a branch at a timestamp rather than an LSN
+#
+def test_branch_at_timestamp(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+
+    endpoint_main = env.endpoints.create_start("main", tenant_id=tenant_id)
+    cur = endpoint_main.connect().cursor()
+    cur.execute("CREATE TABLE foo (x integer)")
+    tbl = []
+    for i in range(100):
+        cur.execute("INSERT INTO foo VALUES(%s)", (i,))
+        after_timestamp = query_scalar(cur, "SELECT clock_timestamp()").replace(tzinfo=None)
+        tbl.append([i, after_timestamp])
+    wait_for_last_flush_lsn(env, endpoint_main, tenant_id, timeline_id)
+
+    client = env.pageserver.http_client()
+
+    i, probe_timestamp = tbl[50]
+    expected = client.timeline_get_lsn_by_timestamp(tenant_id, timeline_id, probe_timestamp)
+
+    branch_id = TimelineId.generate()
+    result = client.timeline_create(
+        env.pg_version,
+        tenant_id,
+        branch_id,
+        ancestor_timeline_id=timeline_id,
+        ancestor_start_timestamp=probe_timestamp,
+    )
+    log.info(f"branch created at {result['ancestor_start']}")
+    assert result["ancestor_lsn"] == expected["lsn"]
+    assert result["ancestor_start"]["lsn"] == expected["lsn"]
+    commit_timestamp = datetime.strptime(
+        result["ancestor_start"]["commit_timestamp"], "%Y-%m-%dT%H:%M:%S.%f000Z"
+    )
+    assert tbl[i - 1][1] <= commit_timestamp < probe_timestamp
+
+    env.neon_cli.map_branch("at_timestamp", tenant_id, branch_id)
+    endpoint_branch = env.endpoints.create_start("at_timestamp", tenant_id=tenant_id)
+    assert endpoint_branch.safe_psql("SELECT max(x) FROM foo")[0][0] == i
+
+    # Timestamps before the start of the retained history cannot be branched at
+    with pytest.raises(TimelineCreate406):
+        client.timeline_create(
+            env.pg_version,
+            tenant_id,
+            TimelineId.generate(),
+            ancestor_timeline_id=timeline_id,
+            ancestor_start_timestamp=tbl[0][1] - timedelta(hours=10),
+        )
+
+    # An LSN and a timestamp cannot be combined
+    with pytest.raises(PageserverApiException, match="mutually exclusive"):
+        client.timeline_create(
+            env.pg_version,
+            tenant_id,
+            TimelineId.generate(),
+            ancestor_timeline_id=timeline_id,
+            ancestor_start_lsn=Lsn(expected["lsn"]),
+            ancestor_start_timestamp=probe_timestamp,
+        )
+
+    # Branching at an LSN gives the same response, without an ancestor_start
+    result = client.timeline_create(
+        env.pg_version,
+        tenant_id,
+        TimelineId.generate(),
+        ancestor_timeline_id=timeline_id,
+        ancestor_start_lsn=Lsn(expected["lsn"]),
+    )
+    assert result["ancestor_lsn"] == expected["lsn"]
+    assert result["ancestor_start"] is None
+
+
 # Test pageserver get_timestamp_of_lsn API
 def test_ts_of_lsn_api(neon_env_builder: NeonEnvBuilder):
     env = neon_env_builder.init_start()
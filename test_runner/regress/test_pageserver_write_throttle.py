@@ -0,0 +1,44 @@
+from fixtures.neon_fixtures import NeonEnvBuilder, wait_for_last_flush_lsn
+
+
+#
+# Test that the write throttle of a tenant throttles WAL ingest
+#
+def test_pageserver_write_throttle(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+    client = env.pageserver.http_client()
+
+    def throttled_count(kind: str):
+        return client.get_metric_value("pageserver_tenant_throttling_count_global", {"kind": kind})
+
+    assert throttled_count("write") == 0
+
+    # 1 MiB/s of ingest, with an empty bucket to start with
+    client.set_tenant_config(
+        tenant_id,
+        {
+            "write_throttle": {
+                "task_kinds": ["WalReceiverConnectionHandler"],
+                "fair": True,
+                "initial": 0,
+                "refill_interval": "100ms",
+                "refill_amount": 100 * 1024,
+                "max": 1024 * 1024,
+            }
+        },
+    )
+
+    endpoint = env.endpoints.create_start("main", tenant_id=tenant_id)
+    endpoint.safe_psql("CREATE TABLE t AS SELECT g AS i FROM generate_series(1, 20000) g")
+    wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    assert throttled_count("write") > 0
+    wait_usecs = client.get_metric_value(
+        "pageserver_tenant_throttling_wait_usecs_sum_global", {"kind": "write"}
+    )
+    assert wait_usecs is not None and wait_usecs > 0
+
+    # Reads are not affected by the write throttle
+    assert throttled_count("timeline_get") == 0
@@ -7,6 +7,7 @@
 #
 
 import asyncio
+import hashlib
 import os
 from pathlib import Path
 from typing import List, Tuple
@@ -302,3 +303,38 @@ def test_tenant_redownloads_truncated_file_on_startup(
     assert (
         os.stat(remote_layer_path).st_size == expected_size
     ), "truncated file should not had been uploaded after next checkpoint"
+
+
+def test_layer_hashes_recorded_in_index_part(neon_env_builder: NeonEnvBuilder):
+    """
+    Every layer uploaded by the pageserver has the sha256 of its remote object
+    recorded in index_part.json.
+    """
+    env = neon_env_builder.init_start()
+
+    assert isinstance(env.pageserver_remote_storage, LocalFsStorage)
+
+    pageserver_http = env.pageserver.http_client()
+    endpoint = env.endpoints.create_start("main")
+
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+
+    with endpoint.cursor() as cur:
+        cur.execute("CREATE TABLE t1 AS SELECT g, 'foobar' || g FROM generate_series(1, 1000) g;")
+        current_lsn = Lsn(query_scalar(cur, "SELECT pg_current_wal_flush_lsn()"))
+
+    wait_for_last_record_lsn(pageserver_http, tenant_id, timeline_id, current_lsn)
+    pageserver_http.timeline_checkpoint(tenant_id, timeline_id)
+    wait_for_upload(pageserver_http, tenant_id, timeline_id, current_lsn)
+
+    index_part = env.pageserver_remote_storage.index_content(tenant_id, timeline_id)
+    assert len(index_part["layer_metadata"]) > 0
+    for name, metadata in index_part["layer_metadata"].items():
+        remote_path = env.pageserver_remote_storage.remote_layer_path(
+            tenant_id, timeline_id, name, generation=metadata.get("generation")
+        )
+        with open(remote_path, "rb") as f:
+            expected = hashlib.sha256(f.read()).hexdigest()
+        log.info(f"layer {name}: {metadata}")
+        assert metadata["sha256"] == expected
@@ -0,0 +1,113 @@
+import pytest
+from fixtures.log_helper import log
+from fixtures.neon_fixtures import NeonEnvBuilder, wait_for_last_flush_lsn
+from fixtures.pageserver.http import PageserverApiException
+from fixtures.pageserver.utils import timeline_delete_wait_completed
+from fixtures.remote_storage import RemoteStorageKind
+from fixtures.types import Lsn
+from fixtures.utils import query_scalar
+
+
+def test_detach_ancestor_then_delete_parent(neon_env_builder: NeonEnvBuilder):
+    """
+    Detach a branch from its parent, then delete the parent: the branch keeps
+    all of its data, including the part it inherited at the branch point.
+    """
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    pageserver_http = env.pageserver.http_client()
+    tenant_id = env.initial_tenant
+
+    main = env.endpoints.create_start("main", tenant_id=tenant_id)
+    main.safe_psql("CREATE TABLE foo (t text) WITH (autovacuum_enabled = off)")
+    main.safe_psql("INSERT INTO foo SELECT 'main' || g FROM generate_series(1, 10000) g")
+    branch_lsn = Lsn(query_scalar(main.connect().cursor(), "SELECT pg_current_wal_insert_lsn()"))
+
+    # Rows written after the branch point must not show up on the branch.
+    main.safe_psql("INSERT INTO foo SELECT 'main' || g FROM generate_series(1, 1000) g")
+    wait_for_last_flush_lsn(env, main, tenant_id, env.initial_timeline)
+
+    child_timeline_id = env.neon_cli.create_branch(
+        "child", "main", tenant_id=tenant_id, ancestor_start_lsn=branch_lsn
+    )
+    child = env.endpoints.create_start("child", tenant_id=tenant_id)
+    child.safe_psql("INSERT INTO foo SELECT 'child' || g FROM generate_series(1, 5000) g")
+    wait_for_last_flush_lsn(env, child, tenant_id, child_timeline_id)
+    child.stop()
+    main.stop()
+
+    # The root timeline has nothing to detach from.
+    with pytest.raises(PageserverApiException, match="timeline has no ancestor"):
+        pageserver_http.timeline_detach_ancestor(tenant_id, env.initial_timeline)
+
+    pageserver_http.timeline_detach_ancestor(tenant_id, child_timeline_id)
+
+    detail = pageserver_http.timeline_detail(tenant_id, child_timeline_id)
+    log.info(f"detached timeline: {detail}")
+    assert detail["ancestor_timeline_id"] is None
+
+    # The former parent has no children left, so it can be deleted.
+    timeline_delete_wait_completed(pageserver_http, tenant_id, env.initial_timeline)
+
+    child.start()
+    assert query_scalar(child.connect().cursor(), "SELECT count(*) FROM foo") == 15000
+
+    # The detached state survives a restart.
+    child.stop()
+    env.pageserver.stop()
+    env.pageserver.start()
+    detail = pageserver_http.timeline_detail(tenant_id, child_timeline_id)
+    assert detail["ancestor_timeline_id"] is None
+
+    child.start()
+    assert query_scalar(child.connect().cursor(), "SELECT count(*) FROM foo") == 15000
+
+
+def test_detach_ancestor_retry_after_upload_failure(neon_env_builder: NeonEnvBuilder):
+    """
+    If the upload of the detached index part fails, the branch stays attached to
+    its ancestor, and retrying the detach completes it.
+    """
+    neon_env_builder.enable_pageserver_remote_storage(RemoteStorageKind.LOCAL_FS)
+    env = neon_env_builder.init_start()
+    pageserver_http = env.pageserver.http_client()
+    tenant_id = env.initial_tenant
+
+    failpoint = "timeline-detach-ancestor-before-upload-completion"
+    env.pageserver.allowed_errors.append(f".*failpoint: {failpoint}")
+
+    main = env.endpoints.create_start("main", tenant_id=tenant_id)
+    main.safe_psql("CREATE TABLE foo (t text) WITH (autovacuum_enabled = off)")
+    main.safe_psql("INSERT INTO foo SELECT 'main' || g FROM generate_series(1, 10000) g")
+    wait_for_last_flush_lsn(env, main, tenant_id, env.initial_timeline)
+
+    child_timeline_id = env.neon_cli.create_branch("child", "main", tenant_id=tenant_id)
+    child = env.endpoints.create_start("child", tenant_id=tenant_id)
+    child.safe_psql("INSERT INTO foo SELECT 'child' || g FROM generate_series(1, 5000) g")
+    wait_for_last_flush_lsn(env, child, tenant_id, child_timeline_id)
+    child.stop()
+    main.stop()
+
+    pageserver_http.configure_failpoints((failpoint, "return"))
+    with pytest.raises(PageserverApiException, match=failpoint):
+        pageserver_http.timeline_detach_ancestor(tenant_id, child_timeline_id)
+
+    # Still attached, so the parent can't be deleted yet.
+    detail = pageserver_http.timeline_detail(tenant_id, child_timeline_id)
+    assert detail["ancestor_timeline_id"] == str(env.initial_timeline)
+
+    pageserver_http.configure_failpoints((failpoint, "off"))
+    pageserver_http.timeline_detach_ancestor(tenant_id, child_timeline_id)
+
+    detail = pageserver_http.timeline_detail(tenant_id, child_timeline_id)
+    assert detail["ancestor_timeline_id"] is None
+    timeline_delete_wait_completed(pageserver_http, tenant_id, env.initial_timeline)
+
+    # The retry's index part is the one that survives a restart.
+    env.pageserver.stop()
+    env.pageserver.start()
+    detail = pageserver_http.timeline_detail(tenant_id, child_timeline_id)
+    assert detail["ancestor_timeline_id"] is None
+
+    child.start()
+    assert query_scalar(child.connect().cursor(), "SELECT count(*) FROM foo") == 15000
@@ -6,7 +6,7 @@ import threading
 import time
 from contextlib import closing
 from pathlib import Path
-from typing import Optional
+from typing import Dict, Optional
 
 import psycopg2.errors
 import psycopg2.extras
@@ -31,7 +31,7 @@ from fixtures.pageserver.utils import (
 from fixtures.pg_version import PgVersion
 from fixtures.port_distributor import PortDistributor
 from fixtures.remote_storage import RemoteStorageKind
-from fixtures.types import TenantId, TimelineId
+from fixtures.types import Lsn, TenantId, TimelineId
 from fixtures.utils import get_timeline_dir_size, wait_until
 
 
@@ -67,6 +67,63 @@ def test_timeline_size(neon_simple_env: NeonEnv):
             assert res["current_logical_size"] == res["current_logical_size_non_incremental"]
 
 
+def test_timeline_relation_sizes(neon_simple_env: NeonEnv):
+    env = neon_simple_env
+    tenant_id = env.initial_tenant
+    timeline_id = env.neon_cli.create_branch("test_timeline_relation_sizes", "empty")
+    client = env.pageserver.http_client()
+    endpoint = env.endpoints.create_start("test_timeline_relation_sizes")
+
+    def relation_size(cur, table: str):
+        cur.execute(
+            f"""
+            SELECT pg_relation_filenode('{table}'),
+                pg_relation_size('{table}', 'main') + pg_relation_size('{table}', 'fsm')
+                + pg_relation_size('{table}', 'vm')
+            """
+        )
+        return cur.fetchone()
+
+    with closing(endpoint.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE small AS SELECT g FROM generate_series(1, 10) g")
+            cur.execute("CREATE TABLE big AS SELECT g FROM generate_series(1, 100000) g")
+            # Creates the visibility map and FSM forks
+            cur.execute("VACUUM big")
+            cur.execute("SELECT oid FROM pg_database WHERE datname = current_database()")
+            dbnode = cur.fetchone()[0]
+            small_relnode, small_size = relation_size(cur, "small")
+            big_relnode, big_size = relation_size(cur, "big")
+    lsn = wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    def database_sizes(sizes) -> Dict[int, int]:
+        db = next(db for db in sizes["databases"] if db["dbnode"] == dbnode)
+        relations = {rel["relnode"]: rel["size"] for rel in db["relations"]}
+        assert db["size"] == sum(relations.values())
+        return relations
+
+    sizes = client.timeline_relation_sizes(tenant_id, timeline_id)
+    assert Lsn(sizes["lsn"]) >= lsn
+    relations = database_sizes(sizes)
+    assert relations[small_relnode] == small_size
+    assert relations[big_relnode] == big_size
+    # Largest first
+    db = next(db for db in sizes["databases"] if db["dbnode"] == dbnode)
+    assert db["relations"][0]["relnode"] == big_relnode
+
+    endpoint.safe_psql("DROP TABLE big")
+    lsn = wait_for_last_flush_lsn(env, endpoint, tenant_id, timeline_id)
+
+    # The sizes at the end of the timeline were calculated recently, they are
+    # returned as is.
+    assert client.timeline_relation_sizes(tenant_id, timeline_id) == sizes
+
+    # Sizes at a given LSN are calculated on demand
+    relations = database_sizes(client.timeline_relation_sizes(tenant_id, timeline_id, lsn=lsn))
+    assert big_relnode not in relations
+    assert relations[small_relnode] == small_size
+
+
 def test_timeline_size_createdropdb(neon_simple_env: NeonEnv):
     env = neon_simple_env
     new_timeline_id = env.neon_cli.create_branch("test_timeline_size_createdropdb", "empty")
@@ -7,7 +7,9 @@ use std::{
     io::BufReader,
 };
 
-use pageserver_api::models::{PagestreamFeMessage, PagestreamGetPageRequest};
+use pageserver_api::models::{
+    PagestreamFeMessage, PagestreamGetPageRequest, PagestreamProtocolVersion,
+};
 use utils::id::{ConnectionId, TenantId, TimelineId};
 
 use clap::{Parser, Subcommand};
@@ -56,26 +58,38 @@ fn analyze_trace<R: std::io::Read>(mut reader: R) {
     let mut prev: Option<PagestreamGetPageRequest> = None;
 
     // Compute stats
-    while let Ok(msg) = PagestreamFeMessage::parse(&mut reader) {
-        match msg {
-            PagestreamFeMessage::Exists(_) => {}
-            PagestreamFeMessage::Nblocks(_) => {}
-            PagestreamFeMessage::GetSlruSegment(_) => {}
-            PagestreamFeMessage::GetPage(req) => {
-                total += 1;
-
-                if let Some(prev) = prev {
-                    if prev.rel == req.rel {
-                        let delta = (req.blkno as i32) - (prev.blkno as i32);
-                        deltas.entry(delta).and_modify(|c| *c += 1).or_insert(1);
-                    } else {
-                        cross_rel += 1;
-                    }
+    while let Ok(msg) = PagestreamFeMessage::parse(&mut reader, PagestreamProtocolVersion::V2) {
+        let requests = match msg {
+            PagestreamFeMessage::Exists(_) => continue,
+            PagestreamFeMessage::Nblocks(_) => continue,
+            PagestreamFeMessage::GetSlruSegment(_) => continue,
+            PagestreamFeMessage::GetPage(req) => vec![req],
+            PagestreamFeMessage::GetPageBatch(batch) => batch
+                .pages
+                .into_iter()
+                .map(|(rel, blkno)| PagestreamGetPageRequest {
+                    latest: batch.latest,
+                    lsn: batch.lsn,
+                    rel,
+                    blkno,
+                })
+                .collect(),
+            PagestreamFeMessage::DbSize(_) => continue,
+        };
+
+        for req in requests {
+            total += 1;
+
+            if let Some(prev) = prev {
+                if prev.rel == req.rel {
+                    let delta = (req.blkno as i32) - (prev.blkno as i32);
+                    deltas.entry(delta).and_modify(|c| *c += 1).or_insert(1);
+                } else {
+                    cross_rel += 1;
                 }
-                prev = Some(req);
             }
-            PagestreamFeMessage::DbSize(_) => {}
-        };
+            prev = Some(req);
+        }
     }
 
     // Print stats.
@@ -89,7 +103,7 @@ fn analyze_trace<R: std::io::Read>(mut reader: R) {
 }
 
 fn dump_trace<R: std::io::Read>(mut reader: R) {
-    while let Ok(msg) = PagestreamFeMessage::parse(&mut reader) {
+    while let Ok(msg) = PagestreamFeMessage::parse(&mut reader, PagestreamProtocolVersion::V2) {
         println!("{msg:?}");
     }
 }